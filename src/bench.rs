@@ -0,0 +1,279 @@
+use std::{
+    fmt::Display,
+    time::{Duration, Instant},
+};
+
+use log::{info, warn};
+
+use crate::epd::{error::InkyError, inky::Inky};
+
+/** One combination of SPI settings to measure. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Settings {
+    pub clock_hz: u32,
+    pub chunk_size: usize,
+}
+
+/** How transferring frames with some [Settings] went. */
+#[derive(Debug, Clone)]
+pub struct Measurement {
+    pub settings: Settings,
+    pub transfers: u32,
+    pub errors: u32,
+    /** Bytes of the transfers that succeeded. */
+    pub bytes: usize,
+    /** Time spent on the transfers that succeeded. */
+    pub elapsed: Duration,
+}
+
+impl Measurement {
+    /** Bytes per second over the successful transfers. */
+    pub fn throughput(&self) -> Option<f64> {
+        let secs = self.elapsed.as_secs_f64();
+        (self.bytes > 0 && secs > 0.0).then(|| self.bytes as f64 / secs)
+    }
+}
+
+/** Send a frame sized dummy payload `repeats` times for every combination of clock speed and
+ * chunk size. Only image data is transferred: the panel is never powered on or refreshed. A
+ * failed transfer is counted and the next one attempted. */
+pub fn run(
+    inky: &mut Inky,
+    clocks_hz: &[u32],
+    chunk_sizes: &[usize],
+    repeats: u32,
+) -> Result<Vec<Measurement>, InkyError> {
+    let frame_size = (inky.eeprom.width as usize * inky.eeprom.height as usize).div_ceil(2);
+    // Alternating white and black pixels, index 1 in the high nibble of every byte and 0 in
+    // the low one. A payload that is cheap to make and easy to spot on a scope
+    let payload = vec![0x10; frame_size];
+    inky.prepare()?;
+
+    let mut measurements = Vec::new();
+    for &clock_hz in clocks_hz {
+        for &chunk_size in chunk_sizes {
+            let settings = Settings {
+                clock_hz,
+                chunk_size,
+            };
+            inky.set_spi(clock_hz, chunk_size)?;
+            let mut measurement = Measurement {
+                settings,
+                transfers: 0,
+                errors: 0,
+                bytes: 0,
+                elapsed: Duration::ZERO,
+            };
+            for _ in 0..repeats {
+                let started = Instant::now();
+                let result = inky.transmit_only(&payload);
+                measurement.transfers += 1;
+                match result {
+                    Ok(()) => {
+                        measurement.elapsed += started.elapsed();
+                        measurement.bytes += payload.len();
+                    }
+                    Err(error) => {
+                        warn!("Transfer at {settings} failed: {error}");
+                        measurement.errors += 1;
+                    }
+                }
+            }
+            info!("{settings}: {}", format_throughput(&measurement));
+            measurements.push(measurement);
+        }
+    }
+
+    return Ok(measurements);
+}
+
+/** The fastest settings without a single failed transfer. */
+pub fn recommend(measurements: &[Measurement]) -> Option<&Measurement> {
+    measurements
+        .iter()
+        .filter(|m| m.errors == 0)
+        .filter_map(|m| Some((m, m.throughput()?)))
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))
+        .map(|(m, _)| m)
+}
+
+impl Display for Settings {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} MHz with {} byte writes",
+            self.clock_hz as f64 / 1e6,
+            self.chunk_size
+        )
+    }
+}
+
+fn format_throughput(measurement: &Measurement) -> String {
+    match measurement.throughput() {
+        Some(throughput) => format!("{:.1} KiB/s", throughput / 1024.0),
+        None => "-".to_string(),
+    }
+}
+
+/** Table of all measurements followed by the recommended settings. */
+pub struct Report<'a>(pub &'a [Measurement]);
+
+impl Display for Report<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{:>9}  {:>6}  {:>12}  {:>10}  {:>6}",
+            "Clock MHz", "Chunk", "Throughput", "Frame", "Errors"
+        )?;
+        for m in self.0 {
+            let frame = match m.transfers - m.errors {
+                0 => "-".to_string(),
+                frames => format!("{:.0} ms", m.elapsed.as_millis() as f64 / frames as f64),
+            };
+            writeln!(
+                f,
+                "{:>9}  {:>6}  {:>12}  {:>10}  {:>3}/{:<2}",
+                m.settings.clock_hz as f64 / 1e6,
+                m.settings.chunk_size,
+                format_throughput(m),
+                frame,
+                m.errors,
+                m.transfers
+            )?;
+        }
+
+        match recommend(self.0) {
+            Some(best) => write!(
+                f,
+                "\nRecommended: --spi-clock-mhz {} --spi-chunk-size {}",
+                best.settings.clock_hz as f64 / 1e6,
+                best.settings.chunk_size
+            )?,
+            None => write!(f, "\nNo settings transferred a frame without errors")?,
+        }
+        writeln!(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::epd::mock::{self, FakeClock, MockPanel};
+
+    const FRAME_BYTES: usize = 800 * 480 / 2;
+
+    fn measurement(clock_mhz: u32, chunk_size: usize, errors: u32, millis: u64) -> Measurement {
+        let transfers = 4;
+        Measurement {
+            settings: Settings {
+                clock_hz: clock_mhz * 1_000_000,
+                chunk_size,
+            },
+            transfers,
+            errors,
+            bytes: (transfers - errors) as usize * FRAME_BYTES,
+            elapsed: Duration::from_millis(millis),
+        }
+    }
+
+    #[test]
+    fn run_measures_every_combination() {
+        let clock = FakeClock::new();
+        let panel = MockPanel::responsive(&clock);
+        let mut inky = mock::inky(&panel, &clock);
+
+        let measurements = run(&mut inky, &[4_000_000, 8_000_000], &[64, 4096], 3).unwrap();
+        let settings: Vec<(u32, usize)> = measurements
+            .iter()
+            .map(|m| (m.settings.clock_hz, m.settings.chunk_size))
+            .collect();
+        assert_eq!(
+            settings,
+            [
+                (4_000_000, 64),
+                (4_000_000, 4096),
+                (8_000_000, 64),
+                (8_000_000, 4096)
+            ]
+        );
+        for m in &measurements {
+            assert_eq!((m.transfers, m.errors), (3, 0));
+            assert_eq!(m.bytes, 3 * FRAME_BYTES);
+        }
+        assert_eq!(panel.clock_hz(), 8_000_000);
+        assert!(panel.writes().iter().all(|&size| size <= 4096));
+    }
+
+    #[test]
+    fn run_sends_image_data_only() {
+        let clock = FakeClock::new();
+        let panel = MockPanel::responsive(&clock);
+        let mut inky = mock::inky(&panel, &clock);
+
+        run(&mut inky, &[4_000_000], &[64], 2).unwrap();
+        let commands = panel.commands();
+        let transfers: Vec<_> = commands
+            .iter()
+            .filter(|(command, _)| *command == 0x10)
+            .collect();
+        assert_eq!(transfers.len(), 2);
+        assert!(transfers
+            .iter()
+            .all(|(_, data)| *data == vec![0x10; FRAME_BYTES]));
+        // Nothing powers the panel on or refreshes it
+        assert!(!commands
+            .iter()
+            .any(|(command, _)| [0x04, 0x12].contains(command)));
+    }
+
+    #[test]
+    fn run_counts_failed_transfers_and_carries_on() {
+        let clock = FakeClock::new();
+        let panel = MockPanel::responsive(&clock).max_clock_hz(10_000_000);
+        let mut inky = mock::inky(&panel, &clock);
+        inky.prepare().unwrap();
+
+        let measurements = run(&mut inky, &[8_000_000, 16_000_000], &[64], 2).unwrap();
+        assert_eq!(
+            (measurements[0].errors, measurements[0].bytes),
+            (0, 2 * FRAME_BYTES)
+        );
+        assert_eq!((measurements[1].errors, measurements[1].bytes), (2, 0));
+        assert_eq!(measurements[1].elapsed, Duration::ZERO);
+        assert_eq!(measurements[1].throughput(), None);
+    }
+
+    #[test]
+    fn recommend_picks_the_fastest_without_errors() {
+        let measurements = [
+            measurement(4, 64, 0, 400),
+            measurement(8, 4096, 0, 100),
+            measurement(16, 4096, 1, 10),
+            measurement(8, 64, 0, 200),
+        ];
+        let best = recommend(&measurements).unwrap();
+        assert_eq!(best.settings, measurements[1].settings);
+
+        assert!(recommend(&[measurement(16, 64, 4, 0)]).is_none());
+    }
+
+    #[test]
+    fn report_tabulates_and_recommends() {
+        let measurements = [measurement(4, 64, 0, 400), measurement(16, 4096, 4, 0)];
+        let report = Report(&measurements).to_string();
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(
+            lines,
+            [
+                "Clock MHz   Chunk    Throughput       Frame  Errors",
+                "        4      64  1875.0 KiB/s      100 ms    0/4 ",
+                "       16    4096             -           -    4/4 ",
+                "",
+                "Recommended: --spi-clock-mhz 4 --spi-chunk-size 64",
+            ]
+        );
+
+        let failed = Report(&measurements[1..]).to_string();
+        assert!(failed.ends_with("\nNo settings transferred a frame without errors\n"));
+    }
+}
@@ -0,0 +1,125 @@
+use std::{
+    fmt::Display,
+    fs,
+    time::{Duration, Instant},
+};
+
+use log::info;
+use serde::{ser::SerializeStruct, Serialize, Serializer};
+
+/** Timings of one stage of the pipeline over all repeats. */
+#[derive(Debug, Clone)]
+pub struct Stage {
+    pub name: String,
+    pub times: Vec<Duration>,
+    /** Highest resident memory of the whole process during the stage, where Linux can tell. */
+    pub peak_rss_kib: Option<u64>,
+}
+
+impl Stage {
+    pub fn min(&self) -> Duration {
+        self.times.iter().copied().min().unwrap_or_default()
+    }
+
+    pub fn max(&self) -> Duration {
+        self.times.iter().copied().max().unwrap_or_default()
+    }
+
+    /** The middle time, or the mean of the two middle ones for an even number of repeats. */
+    pub fn median(&self) -> Duration {
+        let mut times = self.times.clone();
+        times.sort();
+        match times.len() {
+            0 => Duration::ZERO,
+            len if len % 2 == 1 => times[len / 2],
+            len => (times[len / 2 - 1] + times[len / 2]) / 2,
+        }
+    }
+}
+
+/** Run `step` `repeats` times, timing each run, and return the timings with the result of the
+ * last run for the next stage. */
+pub fn measure<T, E>(
+    name: &str,
+    repeats: u32,
+    mut step: impl FnMut() -> Result<T, E>,
+) -> Result<(Stage, T), E> {
+    reset_peak_rss();
+    let mut times = Vec::new();
+    let mut output = None;
+    for _ in 0..repeats.max(1) {
+        let started = Instant::now();
+        output = Some(step()?);
+        times.push(started.elapsed());
+    }
+    let stage = Stage {
+        name: name.to_string(),
+        times,
+        peak_rss_kib: peak_rss_kib(),
+    };
+    info!("{name}: median {:.2?}", stage.median());
+
+    return Ok((stage, output.unwrap()));
+}
+
+/** Reset the high-water mark of the resident memory, so that it covers the next stage only.
+ * Only Linux has it, and failing is fine: the peak then covers all stages so far. */
+fn reset_peak_rss() {
+    let _ = fs::write("/proc/self/clear_refs", "5");
+}
+
+/** The highest resident memory since the last reset, from `VmHWM` in `/proc/self/status`. */
+fn peak_rss_kib() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmHWM:"))?;
+    return line.split_whitespace().nth(1)?.parse().ok();
+}
+
+fn millis(time: Duration) -> f64 {
+    time.as_secs_f64() * 1000.0
+}
+
+impl Serialize for Stage {
+    /** Times in milliseconds, with the minimum, median and maximum worked out. */
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let times: Vec<f64> = self.times.iter().map(|&time| millis(time)).collect();
+        let mut stage = serializer.serialize_struct("Stage", 6)?;
+        stage.serialize_field("name", &self.name)?;
+        stage.serialize_field("min_ms", &millis(self.min()))?;
+        stage.serialize_field("median_ms", &millis(self.median()))?;
+        stage.serialize_field("max_ms", &millis(self.max()))?;
+        stage.serialize_field("times_ms", &times)?;
+        stage.serialize_field("peak_rss_kib", &self.peak_rss_kib)?;
+        stage.end()
+    }
+}
+
+/** Table of the minimum, median and maximum time of every stage. */
+pub struct Report<'a>(pub &'a [Stage]);
+
+impl Display for Report<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let millis = |time| format!("{:.1} ms", millis(time));
+        writeln!(
+            f,
+            "{:<18}  {:>10}  {:>10}  {:>10}  {:>10}",
+            "Stage", "Min", "Median", "Max", "Peak RSS"
+        )?;
+        for stage in self.0 {
+            let peak = match stage.peak_rss_kib {
+                Some(kib) => format!("{:.1} MiB", kib as f64 / 1024.0),
+                None => "-".to_string(),
+            };
+            writeln!(
+                f,
+                "{:<18}  {:>10}  {:>10}  {:>10}  {:>10}",
+                stage.name,
+                millis(stage.min()),
+                millis(stage.median()),
+                millis(stage.max()),
+                peak
+            )?;
+        }
+        Ok(())
+    }
+}
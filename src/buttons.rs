@@ -0,0 +1,135 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use log::info;
+use rppal::gpio::{self, Gpio, InputPin, Trigger};
+
+use crate::{shutdown, state::Step};
+
+/** How long a button's contacts are left to settle after a press. */
+const DEBOUNCE: Duration = Duration::from_millis(50);
+
+/** The buttons down the side of the Inky Impression, by the letters printed next to them. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Button {
+    /** Back to the image shown before. */
+    A,
+    /** Forward again, or on to a new image once back at the newest one. */
+    B,
+}
+
+impl Button {
+    const ALL: [Button; 2] = [Button::A, Button::B];
+
+    /** The GPIO pin the button pulls low while it is held. */
+    fn pin(self) -> u8 {
+        match self {
+            Button::A => 5,
+            Button::B => 6,
+        }
+    }
+
+    /** Which way the button moves through the history. */
+    pub fn step(self) -> Step {
+        match self {
+            Button::A => Step::Back,
+            Button::B => Step::Forward,
+        }
+    }
+}
+
+/** Listens for presses for as long as it is kept, interrupting the wait for the next refresh. */
+pub struct Buttons {
+    pressed: Arc<Mutex<Option<Button>>>,
+    _pins: Vec<InputPin>,
+}
+
+impl Buttons {
+    pub fn listen() -> Result<Buttons, gpio::Error> {
+        let gpio = Gpio::new()?;
+        let pressed = Arc::new(Mutex::new(None));
+        let mut pins = Vec::new();
+        for button in Button::ALL {
+            let mut pin = gpio.get(button.pin())?.into_input_pullup();
+            let pressed = pressed.clone();
+            pin.set_async_interrupt(Trigger::FallingEdge, Some(DEBOUNCE), move |_| {
+                press(&pressed, button)
+            })?;
+            pins.push(pin);
+        }
+        info!("Listening for buttons A and B");
+        return Ok(Buttons {
+            pressed,
+            _pins: pins,
+        });
+    }
+
+    /** The button pressed last since this was last asked. Earlier presses are dropped, as
+     * someone pressing again while the panel refreshes only wants it to move once. */
+    pub fn take(&self) -> Option<Button> {
+        self.pressed.lock().unwrap().take()
+    }
+}
+
+fn press(pressed: &Mutex<Option<Button>>, button: Button) {
+    info!("Button {button:?} pressed");
+    *pressed.lock().unwrap() = Some(button);
+    shutdown::interrupt();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Instant};
+
+    use chrono::{Local, TimeDelta};
+
+    use super::*;
+    use crate::daemon;
+
+    fn unwired() -> Buttons {
+        Buttons {
+            pressed: Arc::new(Mutex::new(None)),
+            _pins: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn a_goes_back_and_b_forward() {
+        assert!(matches!(Button::A.step(), Step::Back));
+        assert!(matches!(Button::B.step(), Step::Forward));
+        // As wired on the HAT, clear of the panel's pins
+        assert_eq!(Button::ALL.map(Button::pin), [5, 6]);
+    }
+
+    #[test]
+    fn only_the_last_press_is_kept() {
+        let buttons = unwired();
+        assert_eq!(buttons.take(), None);
+        press(&buttons.pressed, Button::A);
+        press(&buttons.pressed, Button::B);
+        assert_eq!(buttons.take(), Some(Button::B));
+        assert_eq!(buttons.take(), None);
+    }
+
+    #[test]
+    fn a_press_ends_the_wait() {
+        let buttons = unwired();
+        let pressed = buttons.pressed.clone();
+        let started = Instant::now();
+        let presser = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            press(&pressed, Button::A);
+        });
+        let reached = daemon::sleep_until(&(Local::now() + TimeDelta::hours(1)));
+        presser.join().unwrap();
+        assert!(!reached);
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert_eq!(buttons.take(), Some(Button::A));
+        // The next wait isn't cut short by the same press
+        assert!(daemon::sleep_until(
+            &(Local::now() + TimeDelta::milliseconds(20))
+        ));
+    }
+}
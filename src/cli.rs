@@ -1,12 +1,819 @@
-use clap::Parser;
+use std::{net::SocketAddr, path::PathBuf, time::Duration};
+
+use chrono::{
+    format::{Item, StrftimeItems},
+    Local,
+};
+use clap::{ArgGroup, Parser, Subcommand};
+use croner::Cron;
+
+use crate::{
+    daemon::QuietHours,
+    emit,
+    epd::{
+        margins::Margins,
+        wiring::{WiringOverrides, MAX_PIN, MAX_SPI_BUS},
+        Model,
+    },
+    error::EXIT_CODES_HELP,
+    fetch,
+    logging::LogFormat,
+    qr::ErrorCorrection,
+    quantize::{
+        caption::Corner, check_saturation, collage::Layout, fidelity, rotate::Rotation, Fit,
+    },
+    render::{Background, Color, Fill, Pattern},
+    select::{glob::Glob, weight::Weight, Selection},
+};
 
 #[derive(Parser)]
-#[command(version, author, about)]
+#[command(
+    version,
+    author,
+    about,
+    subcommand_negates_reqs = true,
+    after_help = EXIT_CODES_HELP,
+    group = ArgGroup::new("sized").args(["dry_run", "model"]).multiple(true)
+)]
 pub struct Cli {
-    /// Directory from which to randomly choose a file to display
-    pub dir: String,
-    #[arg(long, default_value_t = 0.5)]
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// Files to display, directories from which to randomly choose one, - to read an image from
+    /// stdin, or http:// or https:// URLs to download one from. Everything given is chosen
+    /// from as one pool
+    #[arg(required_unless_present_any = ["exec", "from_raw"], value_name = "PATH")]
+    pub paths: Vec<String>,
+    /// Display what a shell command writes to stdout, such as a PNG from a headless renderer,
+    /// instead of files. The command is given to `sh -c` as one argument, so quote it, and
+    /// pipes, variables and redirections work as in a shell. With --interval or --schedule it
+    /// runs again before every refresh
+    #[arg(long, value_name = "COMMAND", conflicts_with = "paths")]
+    pub exec: Option<String>,
+    /// Display a frame saved with --save-raw as it is, without decoding or quantizing anything
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["paths", "exec", "dry_run"])]
+    pub from_raw: Option<PathBuf>,
+    /// Stop the --exec command, along with anything it started, if it runs for longer than
+    /// this
+    #[arg(long, value_name = "DURATION", default_value = "2m", value_parser = humantime::parse_duration, requires = "exec")]
+    pub exec_timeout: Duration,
+    /// Also choose from files in subdirectories of the directories given
+    #[arg(long)]
+    pub recursive: bool,
+    /// How many levels of subdirectories --recursive looks into [default: no limit]
+    #[arg(long, requires = "recursive")]
+    pub max_depth: Option<usize>,
+    /// Also look into subdirectories that are symlinks. Symlinks to files are always followed,
+    /// and dangling ones are skipped
+    #[arg(long)]
+    pub follow_symlinks: bool,
+    /// Also choose from files and subdirectories whose name starts with a dot
+    #[arg(long)]
+    pub include_hidden: bool,
+    /// Choose from every file, not just those with the extension of an image format
+    #[arg(long)]
+    pub all_files: bool,
+    /// Only choose files whose path relative to its directory matches one of these patterns,
+    /// e.g. '**/*.jpg' or 'vacation-*/**'. Patterns without a / match the file name
+    #[arg(long, value_name = "GLOB")]
+    pub include: Vec<Glob>,
+    /// Never choose files whose path relative to its directory matches one of these patterns
+    #[arg(long, value_name = "GLOB")]
+    pub exclude: Vec<Glob>,
+    /// Seed for choosing files, so the same files are always chosen in the same order
+    #[arg(long)]
+    pub seed: Option<u64>,
+    /// How to pick the next file to display
+    #[arg(long, value_enum, default_value_t = Selection::Random)]
+    pub select: Selection,
+    /// Choose files in a subdirectory more or less often, e.g. favorites=5 or drafts=0. Can be
+    /// given more than once, factors of nested subdirectories multiply
+    #[arg(long, value_name = "SUBDIR=FACTOR")]
+    pub weight: Vec<Weight>,
+    /// Never choose files larger than this, in bytes or with a unit like 500K, 30M or 1G. No
+    /// limit by default
+    #[arg(long, value_name = "SIZE", value_parser = parse_file_size)]
+    pub max_file_size: Option<u64>,
+    /// Never choose images smaller than this, such as thumbnails, checked against the image
+    /// turned to hang as --rotate says. Files given by name are always shown. 0x0 turns it off
+    #[arg(long, value_name = "WxH", default_value = "400x240", value_parser = parse_resolution)]
+    pub min_resolution: (u32, u32),
+    /// TOML configuration file
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+    #[arg(long, default_value_t = 0.5, value_parser = parse_saturation)]
     pub saturation: f64,
+    /// Brighten or darken the image, from -100 (black) to 100 (white). Applied before
+    /// --contrast and --gamma
+    #[arg(long, default_value_t = 0, allow_negative_numbers = true, value_parser = clap::value_parser!(i32).range(-100..=100))]
+    pub brightness: i32,
+    /// Raise or lower the contrast, from -100 (flat grey) to 100. Applied after --brightness
+    /// and before --gamma
+    #[arg(long, default_value_t = 0, allow_negative_numbers = true, value_parser = clap::value_parser!(i32).range(-100..=100))]
+    pub contrast: i32,
+    /// Lift the midtones, which the panel shows much darker than a screen, with a power curve:
+    /// above 1.0 brightens, below darkens
+    #[arg(long, default_value_t = 1.0, value_parser = parse_gamma)]
+    pub gamma: f64,
+    /// How to bring images to the panel's size
+    #[arg(long, value_enum, default_value_t = Fit::Cover)]
+    pub fit: Fit,
+    /// Never enlarge an image: one smaller than the panel is shown at its own size in the
+    /// middle over --background, and one smaller only one way is just shrunk the other way
     #[arg(long)]
+    pub no_upscale: bool,
+    /// Deprecated, the same as --fit contain
+    #[arg(long, hide = true, conflicts_with = "fit")]
     pub no_crop: bool,
+    /// Color of the bands left by --fit contain or center: black, white, green, blue, red,
+    /// yellow, orange or #RRGGBB
+    #[arg(long, value_name = "COLOR", default_value = "white")]
+    pub background: Background,
+    /// Turn the image clockwise by this many degrees, for a panel hung in portrait or upside
+    /// down. --margin stays in the panel's own orientation
+    #[arg(long, value_enum, value_name = "DEGREES", default_value_t = Rotation::None)]
+    pub rotate: Rotation,
+    /// Mirror the image left to right, after --rotate and along the panel's own axes
+    #[arg(long)]
+    pub hflip: bool,
+    /// Mirror the image top to bottom, after --rotate and along the panel's own axes
+    #[arg(long)]
+    pub vflip: bool,
+    /// Leave a band around the image, in pixels: either one value or top,right,bottom,left
+    #[arg(long)]
+    pub margin: Option<Margins>,
+    /// Color of the band left by --margin
+    #[arg(long, value_enum, default_value_t = Color::White, requires = "margin")]
+    pub margin_color: Color,
+    /// Show several different files at once, as columns x rows, each fitted to its cell and
+    /// quantized together. Pinned files and ones shown again from the history stay on their own
+    #[arg(long, value_enum, value_name = "LAYOUT")]
+    pub collage: Option<Layout>,
+    /// Width of the band between the cells of a collage, in pixels
+    #[arg(long, value_name = "PX", default_value_t = 8, requires = "collage")]
+    pub collage_gutter: u32,
+    /// Color of the band between the cells of a collage: black, white, green, blue, red,
+    /// yellow, orange or #RRGGBB
+    #[arg(
+        long,
+        value_name = "COLOR",
+        default_value = "white",
+        requires = "collage"
+    )]
+    pub collage_gutter_color: Background,
+    /// Repeat files when there are fewer than the cells of the collage, instead of failing
+    #[arg(long, requires = "collage")]
+    pub collage_repeat: bool,
+    /// Quantize with the built-in dithering instead of libimagequant, so the same image and
+    /// settings always give exactly the same frame
+    #[arg(long)]
+    pub deterministic: bool,
+    /// How much dithering to use, from 0.0 for flat areas of the nearest colors to 1.0 for full
+    /// error diffusion. Lower values suit illustrations
+    #[arg(long, value_name = "0.0-1.0", default_value_t = 1.0, value_parser = parse_dither_strength)]
+    pub dither_strength: f32,
+    /// libimagequant's speed from 1 to 10. Higher is faster, and with the panel's fixed palette
+    /// often looks the same
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u8).range(1..=10))]
+    pub quant_speed: u8,
+    /// Put the panel's controller into deep sleep after each refresh, to save power between
+    /// updates. The next refresh wakes it with a reset
+    #[arg(long)]
+    pub sleep_after: bool,
+    /// Diagnostic for the SPI wiring: reset the controller and transmit the frame, but don't
+    /// power on or refresh the panel, which will not visibly change. Logs how many bytes were
+    /// sent and how long it took
+    #[arg(long, conflicts_with = "emit_only")]
+    pub no_refresh: bool,
+    /// Keep running and display a new image every interval, e.g. 15m or 1h
+    #[arg(long, value_parser = parse_interval)]
+    pub interval: Option<Duration>,
+    /// Refresh on wall-clock multiples of the interval (e.g. on the hour) instead of drifting.
+    /// Multiples are counted from midnight, so the interval must be shorter than a day
+    #[arg(long, requires = "interval")]
+    pub align: bool,
+    /// Keep running and display a new image at the local times matching a cron expression, e.g.
+    /// "0 7,18 * * *" or "30 12 * * 1-5", instead of every --interval. The first one waits for
+    /// the next match
+    #[arg(long, value_name = "CRON", value_parser = parse_schedule, conflicts_with = "interval")]
+    pub schedule: Option<Cron>,
+    /// Daily local time range during which the panel is not refreshed, e.g. 22:30-07:00. The
+    /// config file's `timezone` sets which zone it is in. Images sent to `serve`, `mqtt` or
+    /// `pipe` meanwhile are held back, and the latest one is displayed once it ends
+    #[arg(long)]
+    pub quiet_hours: Option<QuietHours>,
+    /// Refresh even during quiet hours
+    #[arg(long)]
+    pub force: bool,
+    /// With --interval or --schedule, go back to the previous image with button A and forward
+    /// again with button B, like `previous` and `next`. B shows a new image once back at the
+    /// newest one. Presses are acted on during quiet hours too, and the wait for the next
+    /// refresh starts over after them
+    #[arg(long)]
+    pub buttons: bool,
+    /// Release the SPI device and GPIO lines after each refresh instead of holding them
+    #[arg(long)]
+    pub low_footprint: bool,
+    /// POST a JSON summary to this http:// or https:// URL after each refresh, with the time,
+    /// source, panel size and refresh duration. Needs the http feature
+    #[arg(long, value_name = "URL", value_parser = parse_url)]
+    pub webhook: Option<String>,
+    /// Also POST to --webhook when a refresh fails, with the error
+    #[arg(long, requires = "webhook")]
+    pub webhook_on_error: bool,
+    /// TOML file mapping date ranges to subdirectories that should be preferred on those days
+    #[arg(long)]
+    pub season_map: Option<PathBuf>,
+    /// Choose from the subdirectory of each directory named after today, such as mon/ or
+    /// Monday/, or from the whole directory if it has none or it is empty
+    #[arg(long)]
+    pub by_weekday: bool,
+    /// Take the day of the week for --by-weekday in UTC instead of local time
+    #[arg(long, requires = "by_weekday")]
+    pub utc: bool,
+    /// Go round the paths given in order, choosing each image from the next one, instead of from
+    /// all of them as one pool. Paths with nothing to choose from are skipped. Where the round is
+    /// is kept in the state directory
+    #[arg(long)]
+    pub rotate_sources: bool,
+    /// Choose from the photos taken on today's date in any year, going by their EXIF dates, or
+    /// from all files if there are none. The dates are cached in the state directory
+    #[arg(long)]
+    pub on_this_day: bool,
+    /// Skip candidates whose sharpness (variance of Laplacian) is below this value
+    #[arg(long)]
+    pub min_sharpness: Option<f64>,
+    /// Skip candidates whose colors are further than this from the panel's, as an RGB distance
+    /// from 0 to 441, since they come out as noise
+    #[arg(long)]
+    pub max_quant_error: Option<f64>,
+    /// Whether --max-quant-error applies to the mean or the 95th percentile of the color error
+    #[arg(long, value_enum, default_value_t = fidelity::Metric::Mean, requires = "max_quant_error")]
+    pub quant_error_metric: fidelity::Metric,
+    /// Maximum number of candidates to try before settling for the best one, and of files that
+    /// can't be read or decoded to skip before giving up
+    #[arg(long, default_value_t = 5, value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_attempts: u32,
+    /// Log how long each step took, and how long it was until the panel started refreshing
+    #[arg(long)]
+    pub time_budget: bool,
+    /// Where to record which images were shown, so every file is shown once before any repeats
+    /// [default: shown.json in the state directory]
+    #[arg(long)]
+    pub shuffle_state: Option<PathBuf>,
+    /// Choose from every file each time, even ones shown recently
+    #[arg(long, conflicts_with = "shuffle_state")]
+    pub no_state: bool,
+    /// Never choose any of the last N files displayed, unless that leaves nothing to choose
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    pub history: usize,
+    /// Where to record the files displayed, for --history, `previous`, `next` and `stats`
+    /// [default: history.jsonl in the state directory]
+    #[arg(long, global = true)]
+    pub history_file: Option<PathBuf>,
+    /// Where to describe the image on the panel after each refresh, as JSON for other programs
+    /// [default: showing.json in the state directory]
+    #[arg(long, global = true)]
+    pub state_file: Option<PathBuf>,
+    /// Where to cache the panel's EEPROM contents between runs
+    /// [default: eeprom.json in the state directory]
+    #[arg(long)]
+    pub eeprom_cache: Option<PathBuf>,
+    /// Always read the EEPROM over I2C
+    #[arg(long, conflicts_with = "eeprom_cache")]
+    pub no_eeprom_cache: bool,
+    /// Also write every frame to stdout as palette indices, exactly as the panel receives it
+    #[arg(long, value_enum)]
+    pub emit: Option<emit::Format>,
+    /// Only write the frame to stdout and leave the panel as it is, without opening it. The
+    /// frame is the size --model gives, or the cached EEPROM's, or else 800x480
+    #[arg(long, requires = "emit")]
+    pub emit_only: bool,
+    /// Write a caption into a corner of the image, e.g. '{filename}' or '{date} {camera}'. Also
+    /// {stem}, {folder} and {time}. Date, time and camera come from EXIF data and are empty
+    /// without it
+    #[arg(long, value_name = "TEMPLATE")]
+    pub caption: Option<String>,
+    /// Corner of the frame, as it hangs, for the caption
+    #[arg(long, value_enum, default_value_t = Corner::BottomRight, requires = "caption")]
+    pub caption_position: Corner,
+    /// Height of the caption text in pixels. The built-in font only comes in multiples of 8
+    #[arg(long, value_name = "PX", default_value_t = 24, requires = "caption", value_parser = clap::value_parser!(u32).range(8..=400))]
+    pub caption_size: u32,
+    /// TrueType or OpenType font for the caption, the timestamp and text files [default: a
+    /// built-in bitmap font, or DejaVu Sans or another installed font for text files]
+    #[arg(long, value_name = "FILE")]
+    pub caption_font: Option<PathBuf>,
+    /// Fail rather than quantize an image that isn't a paletted PNG of exactly the panel's size
+    /// and colors, to notice when a generator of such images drifts. They are otherwise shown as
+    /// they are anyway
+    #[arg(long, conflicts_with_all = ["caption", "timestamp", "collage"])]
+    pub strict_passthrough: bool,
+    /// Draw every file as plain UTF-8 text in black on white, as is done for files with a .txt
+    /// extension, such as a note or the status report of a script piped into -
+    #[arg(long)]
+    pub as_text: bool,
+    /// Height of the text of text files in pixels
+    #[arg(long, value_name = "PX", default_value_t = 24, value_parser = clap::value_parser!(u32).range(8..=400))]
+    pub text_size: u32,
+    /// Write the time of each refresh into a corner, formatted with strftime specifiers such as
+    /// %Y-%m-%d and %H:%M, as in --timestamp="%H:%M". Drawn after the caption
+    #[arg(long, value_name = "FORMAT", num_args = 0..=1, require_equals = true, default_missing_value = "updated %Y-%m-%d %H:%M", value_parser = parse_timestamp_format)]
+    pub timestamp: Option<String>,
+    /// Corner of the frame, as it hangs, for the timestamp
+    #[arg(long, value_enum, default_value_t = Corner::BottomLeft, requires = "timestamp")]
+    pub timestamp_position: Corner,
+    /// Height of the timestamp text in pixels
+    #[arg(long, value_name = "PX", default_value_t = 16, requires = "timestamp", value_parser = clap::value_parser!(u32).range(8..=400))]
+    pub timestamp_size: u32,
+    /// Give the timestamp in UTC instead of the local time zone
+    #[arg(long, requires = "timestamp")]
+    pub timestamp_utc: bool,
+    /// Also save the frame in the panel's full colors, in the format given by the extension
+    #[arg(long)]
+    pub output: Option<PathBuf>,
+    /// Also save the frame exactly as it is sent to the panel, packed two pixels to a byte
+    /// after a small header, to display it later with --from-raw. With --dry-run, this renders
+    /// frames on a faster machine
+    #[arg(long, value_name = "FILE")]
+    pub save_raw: Option<PathBuf>,
+    /// Draw the quantized frame on the terminal before the panel refreshes
+    #[arg(long, conflicts_with = "emit")]
+    pub preview_term: bool,
+    /// Show the quantized frame in a window at 1:1 scale and wait for a key before going on.
+    /// Needs a build with the `preview` feature
+    #[arg(long)]
+    pub preview_window: bool,
+    /// Print the absolute path of the chosen file to stdout before the panel refreshes
+    #[arg(long, conflicts_with = "emit")]
+    pub print_choice: bool,
+    /// Print every file that could be chosen, sorted, one per line, and exit without touching
+    /// the panel
+    #[arg(long, conflicts_with = "emit")]
+    pub list: bool,
+    /// Render the next image without touching the panel, I2C or GPIO, and print a summary
+    /// instead of displaying it
+    #[arg(long, conflicts_with = "emit")]
+    pub dry_run: bool,
+    /// Panel width for --dry-run or --model [default: 800]
+    #[arg(long, value_name = "PX", requires = "sized", value_parser = clap::value_parser!(u16).range(1..))]
+    pub width: Option<u16>,
+    /// Panel height for --dry-run or --model [default: 480]
+    #[arg(long, value_name = "PX", requires = "sized", value_parser = clap::value_parser!(u16).range(1..))]
+    pub height: Option<u16>,
+    /// Which panel to drive when several are attached: the name or position from 0 of a
+    /// [[device]] profile in the config file, which sets its pins and buses
+    #[arg(long, value_name = "NAME", global = true)]
+    pub device: Option<String>,
+    /// SPI clock in MHz, see `bench-spi` [default: INKY_SPI_HZ, spi_hz in the config's
+    /// [hardware] table, or 5]
+    #[arg(long = "spi-clock-mhz", value_name = "MHZ", global = true, value_parser = parse_mhz)]
+    pub spi_clock_hz: Option<u32>,
+    /// GPIO of the reset line [default: INKY_RESET_PIN, reset_pin in the config's [hardware]
+    /// table, or 27]. The same goes for the other wiring options
+    #[arg(long, value_name = "GPIO", global = true, value_parser = clap::value_parser!(u8).range(..=MAX_PIN as i64))]
+    pub reset_pin: Option<u8>,
+    /// GPIO of the busy line [default: 17]
+    #[arg(long, value_name = "GPIO", global = true, value_parser = clap::value_parser!(u8).range(..=MAX_PIN as i64))]
+    pub busy_pin: Option<u8>,
+    /// GPIO of the data/command line [default: 22]
+    #[arg(long, value_name = "GPIO", global = true, value_parser = clap::value_parser!(u8).range(..=MAX_PIN as i64))]
+    pub dc_pin: Option<u8>,
+    /// GPIO of the chip select line [default: 8]
+    #[arg(long, value_name = "GPIO", global = true, value_parser = clap::value_parser!(u8).range(..=MAX_PIN as i64))]
+    pub cs_pin: Option<u8>,
+    /// SPI bus of the controller [default: 0]
+    #[arg(long, value_name = "BUS", global = true, value_parser = clap::value_parser!(u8).range(..=MAX_SPI_BUS as i64))]
+    pub spi_bus: Option<u8>,
+    /// I2C bus of the EEPROM [default: 1]
+    #[arg(long, value_name = "BUS", global = true)]
+    pub i2c_bus: Option<u8>,
+    /// Bytes per SPI write, see `bench-spi`
+    #[arg(long, default_value_t = 64, value_parser = parse_chunk_size)]
+    pub spi_chunk_size: usize,
+    /// Drive this panel without reading the EEPROM, for boards that don't have one. Takes
+    /// precedence over the EEPROM of boards that do
+    #[arg(long, value_enum, global = true)]
+    pub model: Option<Model>,
+    /// I2C address of the panel's EEPROM, for HATs that don't use 0x50 (see `eeprom scan`)
+    #[arg(long, global = true, default_value = "0x50", value_parser = parse_i2c_address)]
+    pub eeprom_address: u16,
+    /// Log more, -v for what happens on each refresh, -vv for details [default: warnings and
+    /// errors only]. RUST_LOG overrides this
+    #[arg(short, long, global = true, action = clap::ArgAction::Count)]
+    pub verbose: u8,
+    /// Only log errors
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+    /// How log lines on stderr are written
+    #[arg(long, global = true, value_enum, default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+    /// Directory for the history and stats files [default: $XDG_STATE_HOME/inky-rs]
+    #[arg(long, global = true)]
+    pub state_dir: Option<PathBuf>,
+}
+
+impl Cli {
+    /** The wiring options given on the command line. */
+    pub fn wiring(&self) -> WiringOverrides {
+        WiringOverrides {
+            reset_pin: self.reset_pin,
+            busy_pin: self.busy_pin,
+            dc_pin: self.dc_pin,
+            cs_pin: self.cs_pin,
+            spi_bus: self.spi_bus,
+            spi_hz: self.spi_clock_hz,
+            i2c_bus: self.i2c_bus,
+        }
+    }
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Read the panel's EEPROM over I2C and print its size, colors, variants and when it was
+    /// written, to check that the Pi can talk to the HAT. Nothing else is touched
+    Info {
+        /// Print the same as JSON
+        #[arg(long, conflicts_with = "full")]
+        json: bool,
+        /// Print everything useful for a bug report as JSON: EEPROM contents, pins and SPI
+        /// settings. This also claims the SPI and GPIO lines
+        #[arg(long)]
+        full: bool,
+    },
+    /// Send a raw command to the display controller
+    ///
+    /// Meant for bring-up of unusual panel batches. Writing the wrong values to the
+    /// power, booster or VCOM registers can permanently damage the panel.
+    RawCmd {
+        /// Command byte in hex, e.g. 0x82
+        #[arg(value_parser = parse_hex_byte)]
+        command: u8,
+        /// Data bytes in hex, e.g. 1E
+        #[arg(value_parser = parse_hex_byte)]
+        data: Vec<u8>,
+        /// Acknowledge that misuse of this command can damage the panel
+        #[arg(long, required = true)]
+        i_know_what_im_doing: bool,
+    },
+    /// Display the image shown before the current one again
+    Previous,
+    /// Undo `previous`, or display a new image from PATH once back at the newest one
+    Next,
+    /// Put the panel's controller into deep sleep to save power until the next refresh
+    Sleep,
+    /// Fill the whole panel with one color, e.g. to blank it before storing the frame away
+    Clear {
+        /// Color to fill the panel with
+        #[arg(long, value_enum, default_value_t = Color::White)]
+        color: Color,
+    },
+    /// Fill the whole panel with one palette color and refresh, to look for stuck or weak
+    /// regions
+    Color {
+        /// Palette color to fill the panel with
+        #[arg(value_enum)]
+        color: Fill,
+    },
+    /// Refresh the panel with solid white and black a few times to clear ghosts of old images,
+    /// then display the image from before again. This takes several minutes
+    Deghost {
+        /// Number of white and black refresh pairs
+        #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u32).range(1..))]
+        cycles: u32,
+        /// Leave the panel black at the end instead of displaying the image from before
+        #[arg(long)]
+        no_restore: bool,
+    },
+    /// Display a diagnostic pattern drawn at the panel's size, to verify a new panel or new
+    /// wiring. Bars and checkerboards are sent as exact colors, gradients are quantized
+    TestPattern {
+        #[arg(value_enum)]
+        pattern: Pattern,
+    },
+    /// Display the hostname, IP addresses, uptime and other details for headless setup
+    Sysinfo {
+        /// Text file with the layout, using placeholders such as {hostname}, {ips}, {ip eth0},
+        /// {uptime}, {disk}, {panel} and {version}. Lines starting with "# " are headings
+        #[arg(long)]
+        template: Option<PathBuf>,
+    },
+    /// Display a QR code, e.g. 'WIFI:S:mynet;T:WPA;P:secret;;' for guests to join a network.
+    /// Check that it scans with --dry-run and --output first
+    Qr {
+        /// Text or URL to encode
+        data: String,
+        /// How much of the code may be damaged and still scan. Higher levels need a denser code
+        #[arg(long, value_enum, default_value_t = ErrorCorrection::Medium)]
+        error_correction: ErrorCorrection,
+        /// Line of text under the code, e.g. 'Guest WiFi'. Drawn in --caption-font
+        #[arg(long)]
+        caption: Option<String>,
+        /// Height of the caption in pixels
+        #[arg(long, value_name = "PX", default_value_t = 32, requires = "caption", value_parser = clap::value_parser!(u32).range(8..=400))]
+        caption_size: u32,
+    },
+    /// Check the EEPROM, GPIO pins, busy line and SPI one by one, for bring-up of a new build
+    SelfTest {
+        /// Finish by refreshing the panel with bars of every color
+        #[arg(long)]
+        full: bool,
+    },
+    /// Inspect the panel's EEPROM
+    Eeprom {
+        #[command(subcommand)]
+        command: EepromCommand,
+    },
+    /// Render an image at several saturations side by side, to pick --saturation without
+    /// refreshing the panel for each value
+    CalibrateSaturation {
+        /// Image to render
+        image: PathBuf,
+        /// Number of evenly spaced saturations from 0 to 1
+        #[arg(long, default_value_t = 5, value_parser = clap::value_parser!(u32).range(2..))]
+        steps: u32,
+        /// PNG file for the labeled grid, drawn in the colors the panel shows
+        #[arg(long, required_unless_present = "on_panel")]
+        out: Option<PathBuf>,
+        /// Show the renderings on the panel one after the other
+        #[arg(long)]
+        on_panel: bool,
+        /// How long each rendering stays on the panel
+        #[arg(long, default_value = "1m", value_parser = parse_interval, requires = "on_panel")]
+        dwell: Duration,
+        /// Size of each rendering without --on-panel, e.g. 800x480
+        #[arg(long, default_value = "800x480", value_parser = parse_size, conflicts_with = "on_panel")]
+        size: (u32, u32),
+    },
+    /// Render an image without touching the panel and write the resized original and the
+    /// rendering side by side, to judge --fit and dithering
+    Preview {
+        /// Image to render
+        image: PathBuf,
+        /// PNG file for the comparison, with the rendering drawn in the colors the panel shows
+        #[arg(long)]
+        compare: PathBuf,
+        /// Also render the image with the other of --fit cover and contain
+        #[arg(long)]
+        alternative: bool,
+        /// Size of the panel, e.g. 800x480
+        #[arg(long, default_value = "800x480", value_parser = parse_size)]
+        size: (u32, u32),
+    },
+    /// Measure SPI throughput at several clock speeds and chunk sizes, without refreshing
+    BenchSpi {
+        /// Clock speeds to try, in MHz
+        #[arg(long = "clocks", value_name = "MHZ", value_delimiter = ',', default_value = "1,2,5,8,10,16,20", value_parser = parse_mhz)]
+        clocks_hz: Vec<u32>,
+        /// Bytes per write to try
+        #[arg(long, value_delimiter = ',', default_value = "64,256,1024,4096", value_parser = parse_chunk_size)]
+        chunk_sizes: Vec<usize>,
+        /// Frames to transfer with each combination
+        #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u32).range(1..))]
+        repeats: u32,
+    },
+    /// Time decoding, resizing, quantizing and packing an image on this machine, without the
+    /// panel, to compare boards or decide whether to prepare images elsewhere
+    Benchmark {
+        /// Image to render
+        image: PathBuf,
+        /// Times to run each stage
+        #[arg(long, default_value_t = 5, value_parser = clap::value_parser!(u32).range(1..))]
+        repeats: u32,
+        /// Also time packing the frame into the bytes sent to the panel
+        #[arg(long)]
+        packing: bool,
+        /// Size of the panel, e.g. 800x480
+        #[arg(long, default_value = "800x480", value_parser = parse_size)]
+        size: (u32, u32),
+        /// Print the timings as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Display images POSTed to /display over HTTP, one refresh at a time. The answer is JSON
+    /// with the outcome and how long the refresh took. While an upload waits for the panel,
+    /// others are answered with 503 and Retry-After. GET /status tells what is on the panel and
+    /// /preview.png shows it. Needs a build with the `serve` feature
+    Serve {
+        /// Address and port to listen on
+        #[arg(long, default_value = "0.0.0.0:8080")]
+        listen: SocketAddr,
+    },
+    /// Display images sent to an MQTT topic, as the message itself or as JSON like
+    /// {"path": "..."} or {"url": "..."}, and publish how it went to a status topic. Needs a
+    /// build with the `mqtt` feature
+    Mqtt {
+        /// Broker to connect to, e.g. tcp://host:1883
+        #[arg(long, value_parser = parse_broker)]
+        broker: (String, u16),
+        /// Topic to subscribe to for images
+        #[arg(long)]
+        topic: String,
+        /// Topic to publish the outcome of each image to [default: TOPIC/status]
+        #[arg(long)]
+        status_topic: Option<String>,
+    },
+    /// Display every image written to a named pipe, such as with `cat photo.png > FIFO`, one
+    /// per writer. Images written during a refresh replace each other, so only the latest is
+    /// displayed next
+    Pipe {
+        /// Named pipe to read, created if missing and then removed again on exit
+        fifo: PathBuf,
+    },
+    /// Print refresh counts, failures and recently displayed images
+    Stats {
+        /// Print the summary as JSON
+        #[arg(long)]
+        json: bool,
+        /// Only count what happened within this long ago, e.g. 7d or 12h
+        #[arg(long, value_parser = humantime::parse_duration)]
+        since: Option<Duration>,
+        /// Number of recently displayed images to list
+        #[arg(long, default_value_t = 10)]
+        last: usize,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum EepromCommand {
+    /// Look for the EEPROM at 0x50 to 0x57 and try to read panel data wherever something answers
+    Scan,
+}
+
+/** Parse a 7-bit I2C address written in hex, with or without a `0x` prefix. */
+fn parse_i2c_address(s: &str) -> Result<u16, String> {
+    match parse_hex_byte(s) {
+        Ok(address) if (0x03..=0x77).contains(&address) => Ok(address as u16),
+        _ => Err(format!("`{s}` is not an I2C address from 0x03 to 0x77")),
+    }
+}
+
+/** Parse a byte written in hex, with or without a `0x` prefix. Signs, which
+ * [u8::from_str_radix] would accept, are not. */
+fn parse_hex_byte(s: &str) -> Result<u8, String> {
+    let digits = s
+        .strip_prefix("0x")
+        .or_else(|| s.strip_prefix("0X"))
+        .unwrap_or(s);
+    let error = || format!("`{s}` is not a hex byte, e.g. 0x82 or 1E");
+    if !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(error());
+    }
+    u8::from_str_radix(digits, 16).map_err(|_| error())
+}
+
+/** Parse a clock speed in MHz, such as `5` or `0.5`, into Hz. */
+fn parse_mhz(s: &str) -> Result<u32, String> {
+    match s.parse::<f64>() {
+        Ok(mhz) if mhz > 0.0 && mhz <= 125.0 => Ok((mhz * 1e6).round() as u32),
+        _ => Err(format!(
+            "`{s}` is not a clock speed in MHz between 0 and 125"
+        )),
+    }
+}
+
+fn parse_chunk_size(s: &str) -> Result<usize, String> {
+    match s.parse() {
+        Ok(0) | Err(_) => Err(format!("`{s}` is not a positive number of bytes")),
+        Ok(size) => Ok(size),
+    }
+}
+
+/** Parse a number of bytes with an optional binary unit, such as `4096`, `500K` or `1.5G`. */
+fn parse_file_size(s: &str) -> Result<u64, String> {
+    let digits = s.trim_end_matches(|c: char| c.is_ascii_alphabetic());
+    let multiplier = match s[digits.len()..].to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        _ => 0,
+    };
+    match digits.trim().parse::<f64>() {
+        Ok(value) if multiplier > 0 && value > 0.0 && value.is_finite() => {
+            Ok((value * multiplier as f64) as u64)
+        }
+        _ => Err(format!(
+            "`{s}` is not a file size like 4096, 500K, 30M or 1G"
+        )),
+    }
+}
+
+fn parse_url(s: &str) -> Result<String, String> {
+    match fetch::is_url(s) {
+        true => Ok(s.to_string()),
+        false => Err(format!("`{s}` is not an http:// or https:// URL")),
+    }
+}
+
+/** Parse the address of an MQTT broker such as `tcp://host:1883`, `mqtt://host` or `host`,
+ * on port 1883 unless given. */
+fn parse_broker(s: &str) -> Result<(String, u16), String> {
+    let address = s
+        .strip_prefix("tcp://")
+        .or_else(|| s.strip_prefix("mqtt://"))
+        .unwrap_or(s);
+    let (host, port) = match address.rsplit_once(':') {
+        Some((host, port)) => (host, port.parse().ok()),
+        None => (address, Some(1883)),
+    };
+    match port {
+        Some(port) if !host.is_empty() && !host.contains('/') => Ok((host.to_string(), port)),
+        _ => Err(format!(
+            "`{s}` is not a broker address like tcp://host:1883"
+        )),
+    }
+}
+
+/** Parse a size such as `800x480`. */
+fn parse_size(s: &str) -> Result<(u32, u32), String> {
+    s.split_once('x')
+        .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)))
+        .filter(|&(width, height)| width > 0 && height > 0)
+        .ok_or_else(|| format!("`{s}` is not a size like 800x480"))
+}
+
+fn parse_resolution(s: &str) -> Result<(u32, u32), String> {
+    s.split_once('x')
+        .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)))
+        .ok_or_else(|| format!("`{s}` is not a size like 400x240"))
+}
+
+fn parse_saturation(s: &str) -> Result<f64, String> {
+    let saturation = s
+        .parse()
+        .map_err(|_| format!("`{s}` is not a number from 0.0 to 1.0"))?;
+    check_saturation(saturation)
+}
+
+fn parse_gamma(s: &str) -> Result<f64, String> {
+    match s.parse::<f64>() {
+        Ok(gamma) if gamma > 0.0 && gamma.is_finite() => Ok(gamma),
+        _ => Err(format!("`{s}` is not a gamma above 0, such as 1.8")),
+    }
+}
+
+fn parse_dither_strength(s: &str) -> Result<f32, String> {
+    match s.parse::<f32>() {
+        Ok(strength) if (0.0..=1.0).contains(&strength) => Ok(strength),
+        _ => Err(format!("`{s}` is not a dither strength from 0.0 to 1.0")),
+    }
+}
+
+/** Check a strftime format such as `%Y-%m-%d %H:%M`, which chrono would only reject when
+ * formatting. */
+fn parse_timestamp_format(s: &str) -> Result<String, String> {
+    if StrftimeItems::new(s).any(|item| item == Item::Error) {
+        return Err(format!("`{s}` is not a valid strftime format"));
+    }
+    return Ok(s.to_string());
+}
+
+/** Parse a cron expression for `--schedule`, refusing one that never matches, such as for the
+ * 30th of February. */
+fn parse_schedule(s: &str) -> Result<Cron, String> {
+    let cron = Cron::new(s).parse().map_err(|error| error.to_string())?;
+    if cron.find_next_occurrence(&Local::now(), false).is_err() {
+        return Err(format!("`{s}` never matches"));
+    }
+    return Ok(cron);
+}
+
+/** Parse a human readable, non-zero duration such as `15m` or `1h 30m`. */
+fn parse_interval(s: &str) -> Result<Duration, String> {
+    match humantime::parse_duration(s) {
+        Ok(duration) if duration.is_zero() => Err("the interval must not be zero".to_string()),
+        Ok(duration) => Ok(duration),
+        Err(error) => Err(error.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_bytes_parse_with_and_without_prefix() {
+        assert_eq!(parse_hex_byte("1E"), Ok(0x1E));
+        assert_eq!(parse_hex_byte("0x82"), Ok(0x82));
+        assert_eq!(parse_hex_byte("0XfF"), Ok(0xFF));
+        assert_eq!(parse_hex_byte("7"), Ok(0x07));
+        assert_eq!(parse_hex_byte("0x00"), Ok(0x00));
+    }
+
+    #[test]
+    fn hex_bytes_reject_signs_and_junk() {
+        for s in [
+            "+1F", "-1", "0x+1F", "0x-0", "", "0x", "100", "0x1G", " 1F", "1F ",
+        ] {
+            assert!(parse_hex_byte(s).is_err(), "{s:?} was accepted");
+        }
+    }
+
+    #[test]
+    fn i2c_addresses_are_seven_bit() {
+        assert_eq!(parse_i2c_address("0x50"), Ok(0x50));
+        assert!(parse_i2c_address("0x02").is_err());
+        assert!(parse_i2c_address("0x78").is_err());
+        assert!(parse_i2c_address("+50").is_err());
+    }
 }
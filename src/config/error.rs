@@ -0,0 +1,36 @@
+use std::{fmt::Display, io};
+
+use crate::epd::wiring::WiringError;
+
+#[derive(derive_more::From)]
+pub enum ConfigError {
+    Io(io::Error),
+    Parse(toml::de::Error),
+    /** A wiring setting out of range, in the config file or the environment. */
+    Wiring(WiringError),
+    /** `--device` names no profile in the config file. */
+    #[from(ignore)]
+    UnknownDevice {
+        device: String,
+        available: Vec<String>,
+    },
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ConfigError::Io(error) => write!(f, "Config file error: {error}"),
+            ConfigError::Parse(error) => write!(f, "Config file error: {error}"),
+            ConfigError::Wiring(error) => write!(f, "Wiring error: {error}"),
+            ConfigError::UnknownDevice { device, available } if available.is_empty() => write!(
+                f,
+                "No device `{device}`, the config file has no [[device]] profiles"
+            ),
+            ConfigError::UnknownDevice { device, available } => write!(
+                f,
+                "No device `{device}`, the config file has {}",
+                available.join(", ")
+            ),
+        }
+    }
+}
@@ -0,0 +1,273 @@
+use std::{fs, path::Path};
+
+use chrono::{DateTime, FixedOffset, Local, Utc};
+use chrono_tz::Tz;
+use log::info;
+use serde::{de, Deserialize};
+
+use crate::epd::wiring::WiringOverrides;
+
+pub mod error;
+pub mod schedule;
+
+/** Contents of the `--config` file. */
+#[derive(Debug, Default, Deserialize)]
+pub struct Config {
+    #[serde(default)]
+    pub schedule: schedule::Schedule,
+    /** Wiring for HATs or boards that differ from the Inky Impression, such as `reset_pin = 5`.
+     * The command line and `INKY_*` environment variables take precedence. */
+    #[serde(default)]
+    pub hardware: WiringOverrides,
+    /** Wiring of each panel when several are attached, in `[[device]]` tables with a `name`,
+     * chosen with `--device`. Settings a profile leaves out come from `[hardware]`. */
+    #[serde(default, rename = "device")]
+    pub devices: Vec<Device>,
+    /** Time zone `--quiet-hours` are in, such as `Europe/Berlin`, for machines whose clock is
+     * kept in UTC. The system's own by default. */
+    #[serde(default)]
+    pub timezone: Option<Tz>,
+}
+
+/** The wiring of one of several panels attached to the same machine. */
+#[derive(Debug, Deserialize)]
+#[serde(try_from = "toml::Table")]
+pub struct Device {
+    pub name: String,
+    pub wiring: WiringOverrides,
+}
+
+impl TryFrom<toml::Table> for Device {
+    type Error = toml::de::Error;
+
+    /** Take the `name` out and read the rest as wiring settings. The wiring settings can't be
+     * flattened into [Device], as serde would then let unknown settings through. */
+    fn try_from(mut table: toml::Table) -> Result<Self, Self::Error> {
+        let name = match table.remove("name") {
+            Some(toml::Value::String(name)) => name,
+            Some(_) => return Err(de::Error::custom("the name of a device must be a string")),
+            None => return Err(de::Error::custom("every [[device]] needs a name")),
+        };
+        let wiring = table.try_into()?;
+        return Ok(Device { name, wiring });
+    }
+}
+
+impl Config {
+    pub fn load(path: &Path) -> Result<Config, error::ConfigError> {
+        let contents = fs::read_to_string(path)?;
+        let config: Config = toml::from_str(&contents)?;
+        config
+            .hardware
+            .check(|field| format!("hardware.{field} in {}", path.display()))?;
+        for device in &config.devices {
+            let name = &device.name;
+            device
+                .wiring
+                .check(|field| format!("{field} of device {name} in {}", path.display()))?;
+        }
+        return Ok(config);
+    }
+
+    /** The current time in the configured time zone, or the system's. */
+    pub fn now(&self) -> DateTime<FixedOffset> {
+        match self.timezone {
+            Some(timezone) => Utc::now().with_timezone(&timezone).fixed_offset(),
+            None => Local::now().fixed_offset(),
+        }
+    }
+
+    /** The wiring settings for `device`, a profile name or its position from 0, on top of the
+     * `[hardware]` table. Without a device, the `[hardware]` table alone. */
+    pub fn hardware(&self, device: Option<&str>) -> Result<WiringOverrides, error::ConfigError> {
+        let Some(device) = device else {
+            return Ok(self.hardware.clone());
+        };
+        let by_index = || self.devices.get(device.parse::<usize>().ok()?);
+        let Some(profile) = self
+            .devices
+            .iter()
+            .find(|d| d.name == device)
+            .or_else(by_index)
+        else {
+            return Err(error::ConfigError::UnknownDevice {
+                device: device.to_string(),
+                available: self.devices.iter().map(|d| d.name.clone()).collect(),
+            });
+        };
+        info!("Using the wiring of device {}", profile.name);
+        return Ok(profile.wiring.clone().or(&self.hardware));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{NaiveTime, Offset};
+
+    use super::*;
+
+    #[test]
+    fn timezone_is_read_by_name() {
+        let config: Config = toml::from_str("timezone = \"Europe/Berlin\"").unwrap();
+        assert_eq!(config.timezone, Some(chrono_tz::Europe::Berlin));
+        let offset = config.now().offset().local_minus_utc();
+        assert!([3600, 7200].contains(&offset), "{offset}");
+
+        assert!(toml::from_str::<Config>("timezone = \"Mars/Olympus_Mons\"").is_err());
+    }
+
+    #[test]
+    fn time_is_local_without_a_timezone() {
+        let config = Config::default();
+        assert_eq!(config.timezone, None);
+        assert_eq!(config.now().offset(), &Local::now().offset().fix());
+    }
+
+    /** Write `contents` to a config file and load it. */
+    fn load(contents: &str) -> Result<Config, error::ConfigError> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("inky.toml");
+        fs::write(&path, contents).unwrap();
+        return Config::load(&path);
+    }
+
+    fn loaded(contents: &str) -> Config {
+        load(contents).unwrap_or_else(|error| panic!("{error}"))
+    }
+
+    fn load_error(contents: &str) -> String {
+        match load(contents) {
+            Ok(config) => panic!("{contents} was loaded as {config:?}"),
+            Err(error) => error.to_string(),
+        }
+    }
+
+    const DEVICES: &str = r#"
+        [hardware]
+        reset_pin = 27
+        spi_hz = 1000000
+
+        [[device]]
+        name = "hall"
+        cs_pin = 7
+
+        [[device]]
+        name = "kitchen"
+        reset_pin = 5
+        spi_bus = 1
+    "#;
+
+    #[test]
+    fn an_empty_config_has_defaults() {
+        let config = loaded("");
+        assert!(config.devices.is_empty());
+        assert_eq!(config.timezone, None);
+        assert!(config.schedule.resolve(NaiveTime::MIN).is_none());
+        assert_eq!(config.hardware.reset_pin, None);
+    }
+
+    #[test]
+    fn every_section_is_read() {
+        let config = loaded(&format!(
+            "timezone = \"UTC\"\n{DEVICES}\n[schedule.night]\nfrom = \"22:00\"\nto = \"06:00\""
+        ));
+        assert_eq!(config.timezone, Some(chrono_tz::UTC));
+        assert_eq!(config.hardware.reset_pin, Some(27));
+        let names: Vec<_> = config.devices.iter().map(|d| d.name.as_str()).collect();
+        assert_eq!(names, ["hall", "kitchen"]);
+        assert_eq!(config.devices[1].wiring.spi_bus, Some(1));
+        let midnight = NaiveTime::MIN;
+        assert_eq!(
+            config.schedule.resolve(midnight).map(|(name, _)| name),
+            Some("night")
+        );
+    }
+
+    #[test]
+    fn devices_are_found_by_name_or_position() {
+        let config = loaded(DEVICES);
+        let hall = config
+            .hardware(Some("hall"))
+            .unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!(hall.cs_pin, Some(7));
+        // Settings the profile leaves out come from [hardware]
+        assert_eq!((hall.reset_pin, hall.spi_hz), (Some(27), Some(1000000)));
+
+        let kitchen = config.hardware(Some("1")).unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!((kitchen.reset_pin, kitchen.spi_bus), (Some(5), Some(1)));
+        assert_eq!(kitchen.cs_pin, None);
+
+        let hardware = config.hardware(None).unwrap_or_else(|e| panic!("{e}"));
+        assert_eq!((hardware.reset_pin, hardware.cs_pin), (Some(27), None));
+    }
+
+    #[test]
+    fn unknown_devices_are_named_with_the_profiles() {
+        let message = |config: &Config, device| match config.hardware(Some(device)) {
+            Ok(wiring) => panic!("{device} was found as {wiring:?}"),
+            Err(error) => error.to_string(),
+        };
+        let config = loaded(DEVICES);
+        assert_eq!(
+            message(&config, "attic"),
+            "No device `attic`, the config file has hall, kitchen"
+        );
+        assert_eq!(
+            message(&config, "2"),
+            "No device `2`, the config file has hall, kitchen"
+        );
+        assert_eq!(
+            message(&Config::default(), "hall"),
+            "No device `hall`, the config file has no [[device]] profiles"
+        );
+    }
+
+    #[test]
+    fn devices_need_a_name() {
+        let error = load_error("[[device]]\ncs_pin = 7");
+        assert!(error.contains("every [[device]] needs a name"), "{error}");
+        let error = load_error("[[device]]\nname = 2\ncs_pin = 7");
+        assert!(
+            error.contains("the name of a device must be a string"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn unknown_settings_are_rejected() {
+        for contents in [
+            "[hardware]\nreset = 5",
+            "[[device]]\nname = \"hall\"\nreset = 5",
+        ] {
+            let error = load_error(contents);
+            assert!(error.contains("unknown field `reset`"), "{error}");
+        }
+        assert!(load_error("[hardware]\nreset_pin = -1").contains("invalid value"));
+    }
+
+    #[test]
+    fn wiring_out_of_range_is_named_where_it_is() {
+        let error = load_error("[hardware]\nbusy_pin = 40");
+        assert!(
+            error.starts_with("Wiring error: hardware.busy_pin in "),
+            "{error}"
+        );
+        assert!(
+            error.ends_with("is `40`, expected a GPIO number from 0 to 27"),
+            "{error}"
+        );
+
+        let error = load_error("[[device]]\nname = \"hall\"\nspi_hz = 0");
+        assert!(
+            error.starts_with("Wiring error: spi_hz of device hall in "),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn a_missing_config_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let result = Config::load(&dir.path().join("inky.toml"));
+        assert!(matches!(result, Err(error::ConfigError::Io(_))));
+    }
+}
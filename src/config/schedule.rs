@@ -0,0 +1,281 @@
+use std::collections::BTreeMap;
+
+use chrono::{NaiveTime, TimeDelta};
+use serde::{de, Deserialize, Deserializer};
+
+use crate::quantize::{adjust::Adjustments, check_saturation};
+
+/** A time of day written as `HH:MM` in the config file. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+#[serde(try_from = "String")]
+pub struct TimeOfDay(pub NaiveTime);
+
+impl TryFrom<String> for TimeOfDay {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        NaiveTime::parse_from_str(&value, "%H:%M")
+            .map(TimeOfDay)
+            .map_err(|_| format!("`{value}` is not a valid HH:MM time"))
+    }
+}
+
+/** Rendering settings that replace the defaults while a window is active. */
+#[derive(Debug, Default, Clone, Copy, Deserialize)]
+pub struct Overrides {
+    #[serde(default, deserialize_with = "saturation")]
+    pub saturation: Option<f64>,
+    pub brightness: Option<i32>,
+    pub white_balance: Option<[f64; 3]>,
+}
+
+/** Deserialize an optional saturation, rejecting values [check_saturation] doesn't accept. */
+fn saturation<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<f64>, D::Error> {
+    Option::<f64>::deserialize(deserializer)?
+        .map(check_saturation)
+        .transpose()
+        .map_err(de::Error::custom)
+}
+
+impl Overrides {
+    pub fn apply(&self, saturation: &mut f64, adjustments: &mut Adjustments) {
+        if let Some(value) = self.saturation {
+            *saturation = value;
+        }
+        if let Some(value) = self.brightness {
+            adjustments.brightness = value;
+        }
+        if let Some(value) = self.white_balance {
+            adjustments.white_balance = value;
+        }
+    }
+}
+
+/** A time window from `from` (inclusive) to `to` (exclusive), possibly crossing midnight. */
+#[derive(Debug, Deserialize)]
+pub struct Window {
+    pub from: TimeOfDay,
+    pub to: TimeOfDay,
+    #[serde(flatten)]
+    pub overrides: Overrides,
+}
+
+impl Window {
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        let (from, to) = (self.from.0, self.to.0);
+        if from <= to {
+            from <= time && time < to
+        } else {
+            time >= from || time < to
+        }
+    }
+
+    /** How long the window has been open at `time`. */
+    fn elapsed(&self, time: NaiveTime) -> TimeDelta {
+        let elapsed = time - self.from.0;
+        if elapsed < TimeDelta::zero() {
+            elapsed + TimeDelta::days(1)
+        } else {
+            elapsed
+        }
+    }
+}
+
+/** The `[schedule]` section: named windows, each overriding some rendering settings. */
+#[derive(Debug, Default, Deserialize)]
+#[serde(transparent)]
+pub struct Schedule {
+    windows: BTreeMap<String, Window>,
+}
+
+impl Schedule {
+    /** Find the window active at `time`.
+     *
+     * If several windows overlap, the one that opened most recently wins, so a short window
+     * nested in a longer one takes precedence. Remaining ties go to the first name alphabetically. */
+    pub fn resolve(&self, time: NaiveTime) -> Option<(&str, &Window)> {
+        self.windows
+            .iter()
+            .filter(|(_, window)| window.contains(time))
+            .min_by_key(|(_, window)| window.elapsed(time))
+            .map(|(name, window)| (name.as_str(), window))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(time: &str) -> NaiveTime {
+        NaiveTime::parse_from_str(time, "%H:%M").unwrap()
+    }
+
+    fn schedule(toml: &str) -> Schedule {
+        toml::from_str(toml).unwrap_or_else(|error| panic!("{error}"))
+    }
+
+    /** The name of the window active at `time`, if any. */
+    fn active<'a>(schedule: &'a Schedule, time: &str) -> Option<&'a str> {
+        schedule.resolve(at(time)).map(|(name, _)| name)
+    }
+
+    #[test]
+    fn times_are_hours_and_minutes() {
+        let time = |value: &str| TimeOfDay::try_from(value.to_string());
+        assert_eq!(time("07:30"), Ok(TimeOfDay(at("07:30"))));
+        assert_eq!(time("23:59"), Ok(TimeOfDay(at("23:59"))));
+        for wrong in ["24:00", "12:60", "noon", "12:00:30", ""] {
+            let message = format!("`{wrong}` is not a valid HH:MM time");
+            assert_eq!(time(wrong), Err(message));
+        }
+    }
+
+    #[test]
+    fn windows_are_read_with_their_overrides() {
+        let schedule = schedule(
+            r#"
+            [evening]
+            from = "19:00"
+            to = "23:00"
+            saturation = 0.3
+            brightness = -10
+            white_balance = [1.0, 0.95, 0.8]
+
+            [morning]
+            from = "06:00"
+            to = "09:00"
+            "#,
+        );
+        let (name, evening) = schedule.resolve(at("20:00")).unwrap();
+        assert_eq!(name, "evening");
+        assert_eq!(
+            (evening.from, evening.to),
+            (TimeOfDay(at("19:00")), TimeOfDay(at("23:00")))
+        );
+        assert_eq!(evening.overrides.saturation, Some(0.3));
+        assert_eq!(evening.overrides.brightness, Some(-10));
+        assert_eq!(evening.overrides.white_balance, Some([1.0, 0.95, 0.8]));
+
+        let (_, morning) = schedule.resolve(at("07:00")).unwrap();
+        assert_eq!(morning.overrides.saturation, None);
+        assert_eq!(morning.overrides.brightness, None);
+        assert_eq!(morning.overrides.white_balance, None);
+    }
+
+    #[test]
+    fn wrong_windows_are_rejected() {
+        for (toml, message) in [
+            (
+                "[evening]\nfrom = \"19:00\"\nto = \"25:00\"",
+                "`25:00` is not a valid HH:MM time",
+            ),
+            (
+                "[evening]\nfrom = \"19:00\"\nto = \"23:00\"\nsaturation = 1.5",
+                "saturation must be between 0.0 and 1.0, not 1.5",
+            ),
+            ("[evening]\nfrom = \"19:00\"", "missing field `to`"),
+            (
+                "[evening]\nfrom = \"19:00\"\nto = \"23:00\"\nbrightness = \"dim\"",
+                "invalid type",
+            ),
+        ] {
+            match toml::from_str::<Schedule>(toml) {
+                Ok(schedule) => panic!("{toml} was read as {schedule:?}"),
+                Err(error) => assert!(error.to_string().contains(message), "{error}"),
+            }
+        }
+    }
+
+    #[test]
+    fn windows_include_their_start_but_not_their_end() {
+        let schedule = schedule("[day]\nfrom = \"09:00\"\nto = \"17:00\"");
+        assert_eq!(active(&schedule, "08:59"), None);
+        assert_eq!(active(&schedule, "09:00"), Some("day"));
+        assert_eq!(active(&schedule, "16:59"), Some("day"));
+        assert_eq!(active(&schedule, "17:00"), None);
+    }
+
+    #[test]
+    fn windows_cross_midnight() {
+        let schedule = schedule("[night]\nfrom = \"22:00\"\nto = \"06:00\"");
+        assert_eq!(active(&schedule, "21:59"), None);
+        assert_eq!(active(&schedule, "22:00"), Some("night"));
+        assert_eq!(active(&schedule, "00:00"), Some("night"));
+        assert_eq!(active(&schedule, "05:59"), Some("night"));
+        assert_eq!(active(&schedule, "06:00"), None);
+        assert_eq!(active(&schedule, "12:00"), None);
+    }
+
+    #[test]
+    fn the_latest_opened_of_overlapping_windows_wins() {
+        let schedule = schedule(
+            r#"
+            evening = { from = "18:00", to = "23:00", saturation = 0.4 }
+            dinner = { from = "19:00", to = "20:00", saturation = 0.2 }
+            night = { from = "22:00", to = "07:00", saturation = 0.0 }
+            "#,
+        );
+        assert_eq!(active(&schedule, "18:30"), Some("evening"));
+        assert_eq!(active(&schedule, "19:30"), Some("dinner"));
+        assert_eq!(active(&schedule, "20:00"), Some("evening"));
+        assert_eq!(active(&schedule, "22:30"), Some("night"));
+        // The night opened yesterday, but no other window is open
+        assert_eq!(active(&schedule, "03:00"), Some("night"));
+        assert_eq!(active(&schedule, "12:00"), None);
+    }
+
+    #[test]
+    fn a_window_opened_before_midnight_is_older_than_one_opened_after() {
+        let schedule = schedule(
+            r#"
+            night = { from = "22:00", to = "07:00" }
+            dawn = { from = "05:00", to = "08:00" }
+            "#,
+        );
+        assert_eq!(active(&schedule, "04:59"), Some("night"));
+        assert_eq!(active(&schedule, "05:00"), Some("dawn"));
+        assert_eq!(active(&schedule, "07:30"), Some("dawn"));
+    }
+
+    #[test]
+    fn windows_opening_together_go_by_name() {
+        let schedule = schedule(
+            r#"
+            lamp = { from = "19:00", to = "21:00" }
+            candle = { from = "19:00", to = "23:00" }
+            "#,
+        );
+        assert_eq!(active(&schedule, "20:00"), Some("candle"));
+        assert_eq!(active(&schedule, "22:00"), Some("candle"));
+    }
+
+    #[test]
+    fn an_empty_schedule_has_no_window() {
+        assert_eq!(active(&Schedule::default(), "12:00"), None);
+        // A window that closes as it opens is never active
+        let schedule = schedule("[never]\nfrom = \"12:00\"\nto = \"12:00\"");
+        assert_eq!(active(&schedule, "12:00"), None);
+    }
+
+    #[test]
+    fn overrides_replace_only_what_they_set() {
+        let (mut saturation, mut adjustments) = (0.5, Adjustments::default());
+        Overrides::default().apply(&mut saturation, &mut adjustments);
+        assert_eq!((saturation, adjustments), (0.5, Adjustments::default()));
+
+        let overrides = Overrides {
+            saturation: Some(0.2),
+            brightness: None,
+            white_balance: Some([1.0, 0.9, 0.8]),
+        };
+        overrides.apply(&mut saturation, &mut adjustments);
+        assert_eq!(saturation, 0.2);
+        assert_eq!(
+            adjustments,
+            Adjustments {
+                white_balance: [1.0, 0.9, 0.8],
+                ..Adjustments::default()
+            }
+        );
+    }
+}
@@ -0,0 +1,518 @@
+use std::{
+    fmt::Display,
+    str::FromStr,
+    sync::{Condvar, Mutex},
+    time::Duration,
+};
+
+use chrono::{DateTime, Local, NaiveDateTime, NaiveTime, TimeDelta, TimeZone};
+use croner::Cron;
+use log::{debug, info, warn};
+
+use crate::shutdown;
+
+/** Longest single sleep, so that wall-clock jumps (NTP steps, suspend) are noticed quickly. */
+const MAX_SLEEP: Duration = Duration::from_secs(60);
+
+/** Shortest interval that can't be aligned, see [check_aligned]. */
+const ONE_DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/** Whether `interval` can be aligned with [next_aligned]. Its multiples start over every
+ * midnight, so an interval of a day or more would fire daily. */
+pub fn check_aligned(interval: Duration) -> Result<(), String> {
+    if interval >= ONE_DAY {
+        return Err(format!(
+            "--align needs an interval shorter than a day, not {}; use --schedule for daily or \
+             longer cycles",
+            humantime::format_duration(interval)
+        ));
+    }
+    return Ok(());
+}
+
+/** The first wall-clock multiple of `interval` strictly after `now`.
+ *
+ * Multiples are counted from local midnight, so 15 minutes fires at :00, :15, :30 and :45,
+ * while an interval that doesn't divide a day evenly starts over at midnight. The interval must
+ * be shorter than a day, as [check_aligned] makes sure. Local times skipped by a DST change are
+ * skipped, and repeated ones only fire the first time. */
+pub fn next_aligned<Tz: TimeZone>(now: &DateTime<Tz>, interval: TimeDelta) -> DateTime<Tz> {
+    let timezone = now.timezone();
+    let mut day = now.date_naive();
+    loop {
+        let mut slot = day.and_time(NaiveTime::MIN);
+        while slot.date() == day {
+            if let Some(time) = timezone.from_local_datetime(&slot).earliest() {
+                if time > *now {
+                    return time;
+                }
+            }
+            slot += interval;
+        }
+        day = day.succ_opt().unwrap();
+    }
+}
+
+/** Sleep until the wall clock reaches `target`, a signal asks the program to stop, or the sleep
+ * is interrupted by a button press. Returns whether it got there. */
+pub fn sleep_until(target: &DateTime<Local>) -> bool {
+    loop {
+        let remaining = *target - Local::now();
+        if remaining <= TimeDelta::zero() {
+            return true;
+        }
+        let stopped = shutdown::sleep(remaining.to_std().unwrap().min(MAX_SLEEP));
+        if shutdown::interrupted() || stopped {
+            return false;
+        }
+    }
+}
+
+/** Wait for the next cycle: until the next aligned time, or for `interval` from now. */
+pub fn wait(interval: Duration, align: bool) {
+    let now = Local::now();
+    let interval = TimeDelta::from_std(interval).unwrap();
+    let next = if align {
+        next_aligned(&now, interval)
+    } else {
+        now + interval
+    };
+
+    info!("Next refresh at {}", next.format("%Y-%m-%d %H:%M:%S %Z"));
+    sleep_until(&next);
+}
+
+/** Refresh times matching a cron expression in local time, for `--schedule`. */
+pub struct Schedule<'a> {
+    cron: &'a Cron,
+    /** The local time of the last refresh. When clocks go back, that time comes round again an
+     * hour later, and isn't taken as another match. */
+    last: Option<NaiveDateTime>,
+}
+
+impl Schedule<'_> {
+    pub fn new(cron: &Cron) -> Schedule<'_> {
+        Schedule { cron, last: None }
+    }
+
+    /** The first matching time strictly after `now`, or `None` if there is none. */
+    pub fn next<Tz: TimeZone>(&self, now: &DateTime<Tz>) -> Option<DateTime<Tz>> {
+        let mut from = now.clone();
+        loop {
+            let next = self.cron.find_next_occurrence(&from, false).ok()?;
+            if Some(next.naive_local()) != self.last {
+                return Some(next);
+            }
+            from = next;
+        }
+    }
+
+    /** Wait for the next matching time, a signal asking the program to stop, or a button press.
+     * Returns false right away if no time matches any more. */
+    pub fn wait(&mut self) -> bool {
+        let Some(next) = self.next(&Local::now()) else {
+            warn!("The schedule has no more matching times");
+            return false;
+        };
+        info!("Next refresh at {}", next.format("%Y-%m-%d %H:%M:%S %Z"));
+        // Cut short, the same time is waited for again
+        if sleep_until(&next) {
+            self.last = Some(next.naive_local());
+        }
+        return true;
+    }
+}
+
+/** A daily window during which the panel must not refresh, possibly crossing midnight. It is
+ * in wall-clock time, so on the days clocks change it lasts an hour more or less. */
+#[derive(Debug, Clone, Copy)]
+pub struct QuietHours {
+    from: NaiveTime,
+    to: NaiveTime,
+}
+
+impl QuietHours {
+    /** Whether `time` (local, from inclusive, to exclusive) falls within the quiet hours. A
+     * window that ends when it starts is empty. */
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.from <= self.to {
+            self.from <= time && time < self.to
+        } else {
+            time >= self.from || time < self.to
+        }
+    }
+}
+
+impl FromStr for QuietHours {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |time: &str| NaiveTime::parse_from_str(time.trim(), "%H:%M").ok();
+        s.split_once('-')
+            .and_then(|(from, to)| {
+                Some(QuietHours {
+                    from: parse(from)?,
+                    to: parse(to)?,
+                })
+            })
+            .ok_or_else(|| format!("`{s}` is not a time range like 22:30-07:00"))
+    }
+}
+
+impl Display for QuietHours {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{}-{}",
+            self.from.format("%H:%M"),
+            self.to.format("%H:%M")
+        )
+    }
+}
+
+/** Holds back something that couldn't be displayed yet, keeping only the latest one. */
+#[derive(Debug)]
+pub struct Deferred<T> {
+    latest: Option<T>,
+}
+
+impl<T> Default for Deferred<T> {
+    fn default() -> Self {
+        Deferred { latest: None }
+    }
+}
+
+impl<T> Deferred<T> {
+    pub fn defer(&mut self, item: T) {
+        if self.latest.replace(item).is_some() {
+            debug!("Replacing an older deferred item");
+        }
+    }
+
+    pub fn take(&mut self) -> Option<T> {
+        self.latest.take()
+    }
+
+    /** Runs a daemon cycle: within quiet hours the item `choose` picks is held back rather than
+     * shown, and the first cycle after them shows the last one held back. */
+    pub fn cycle<E>(
+        &mut self,
+        quiet: bool,
+        choose: impl FnOnce() -> Result<T, E>,
+    ) -> Result<Cycle<T>, E> {
+        if !quiet {
+            return Ok(Cycle::Show(self.take()));
+        }
+        self.defer(choose()?);
+        return Ok(Cycle::Quiet);
+    }
+}
+
+/** The newest item handed over by another thread and not taken yet. Items that arrive during a
+ * refresh replace each other, so only the latest is displayed once the panel is free. */
+pub struct Latest<T> {
+    pending: Mutex<Option<T>>,
+    ready: Condvar,
+}
+
+impl<T> Default for Latest<T> {
+    fn default() -> Self {
+        Latest {
+            pending: Mutex::new(None),
+            ready: Condvar::new(),
+        }
+    }
+}
+
+impl<T> Latest<T> {
+    pub fn put(&self, item: T) {
+        if self.pending.lock().unwrap().replace(item).is_some() {
+            info!("Skipping an image that a newer one replaced before it was displayed");
+        }
+        self.ready.notify_one();
+    }
+
+    /** The item put last, waiting up to `timeout` for one. */
+    pub fn next(&self, timeout: Duration) -> Option<T> {
+        let pending = self.pending.lock().unwrap();
+        let (mut pending, _) = self
+            .ready
+            .wait_timeout_while(pending, timeout, |pending| pending.is_none())
+            .unwrap();
+        return pending.take();
+    }
+}
+
+/** What a daemon cycle does, see [Deferred::cycle]. */
+#[derive(Debug, PartialEq)]
+pub enum Cycle<T> {
+    /** Within quiet hours, so nothing is shown */
+    Quiet,
+    /** Show the item held back during quiet hours, or a new choice if there is none */
+    Show(Option<T>),
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{FixedOffset, NaiveDate, Offset};
+    use chrono_tz::{Europe::Berlin, Tz};
+
+    use super::*;
+
+    fn minutes(minutes: i64) -> TimeDelta {
+        TimeDelta::minutes(minutes)
+    }
+
+    fn local(date: (i32, u32, u32), time: (u32, u32)) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(date.0, date.1, date.2)
+            .unwrap()
+            .and_hms_opt(time.0, time.1, 0)
+            .unwrap()
+    }
+
+    fn berlin(date: (i32, u32, u32), time: (u32, u32)) -> DateTime<Tz> {
+        Berlin
+            .from_local_datetime(&local(date, time))
+            .single()
+            .unwrap()
+    }
+
+    /** The local time and UTC offset in hours, to tell repeated hours apart. */
+    fn shown(time: DateTime<Tz>) -> (NaiveDateTime, i32) {
+        (
+            time.naive_local(),
+            time.offset().fix().local_minus_utc() / 3600,
+        )
+    }
+
+    #[test]
+    fn aligned_times_are_multiples_from_midnight() {
+        let now = berlin((2024, 6, 1), (10, 7));
+        assert_eq!(
+            next_aligned(&now, minutes(15)),
+            berlin((2024, 6, 1), (10, 15))
+        );
+        assert_eq!(
+            next_aligned(&now, minutes(60)),
+            berlin((2024, 6, 1), (11, 0))
+        );
+        // Strictly after, even right on a multiple
+        let now = berlin((2024, 6, 1), (10, 15));
+        assert_eq!(
+            next_aligned(&now, minutes(15)),
+            berlin((2024, 6, 1), (10, 30))
+        );
+    }
+
+    #[test]
+    fn uneven_intervals_start_over_at_midnight() {
+        let now = berlin((2024, 6, 1), (21, 30));
+        // 7 hours fires at 00:00, 07:00, 14:00 and 21:00, then at midnight again
+        assert_eq!(
+            next_aligned(&now, minutes(7 * 60)),
+            berlin((2024, 6, 2), (0, 0))
+        );
+        let now = berlin((2024, 6, 1), (23, 30));
+        assert_eq!(
+            next_aligned(&now, minutes(23 * 60)),
+            berlin((2024, 6, 2), (0, 0))
+        );
+        let now = berlin((2024, 6, 2), (0, 0));
+        assert_eq!(
+            next_aligned(&now, minutes(23 * 60)),
+            berlin((2024, 6, 2), (23, 0))
+        );
+    }
+
+    #[test]
+    fn aligned_times_skip_the_hour_clocks_go_forward() {
+        // On 2024-03-31 Berlin went from 02:00 CET straight to 03:00 CEST
+        let now = berlin((2024, 3, 31), (1, 45));
+        let next = next_aligned(&now, minutes(30));
+        assert_eq!(shown(next), (local((2024, 3, 31), (3, 0)), 2));
+        assert_eq!(next - now, minutes(15));
+    }
+
+    #[test]
+    fn aligned_times_fire_once_in_the_hour_clocks_go_back() {
+        // On 2024-10-27 Berlin went from 03:00 CEST back to 02:00 CET
+        let first = Berlin
+            .from_local_datetime(&local((2024, 10, 27), (2, 10)))
+            .earliest()
+            .unwrap();
+        let next = next_aligned(&first, minutes(30));
+        assert_eq!(shown(next), (local((2024, 10, 27), (2, 30)), 2));
+
+        // The repeated 02:00 and 02:30 are not taken again
+        let next = next_aligned(&next, minutes(30));
+        assert_eq!(shown(next), (local((2024, 10, 27), (3, 0)), 1));
+        assert_eq!(next - first, minutes(110));
+    }
+
+    #[test]
+    fn aligned_times_follow_the_zone_of_now() {
+        let tokyo = FixedOffset::east_opt(9 * 3600).unwrap();
+        let now = tokyo
+            .from_local_datetime(&local((2024, 6, 1), (8, 59)))
+            .unwrap();
+        let next = next_aligned(&now, minutes(60));
+        assert_eq!(next.naive_local(), local((2024, 6, 1), (9, 0)));
+        assert_eq!(next.offset(), &tokyo);
+    }
+
+    #[test]
+    fn only_intervals_shorter_than_a_day_align() {
+        assert_eq!(check_aligned(Duration::from_secs(15 * 60)), Ok(()));
+        assert_eq!(check_aligned(ONE_DAY - Duration::from_secs(1)), Ok(()));
+        for hours in [24, 25, 48, 24 * 7] {
+            let error = check_aligned(Duration::from_secs(hours * 3600)).unwrap_err();
+            assert!(error.starts_with("--align needs an interval shorter than a day"));
+        }
+    }
+
+    fn quiet(range: &str) -> QuietHours {
+        range.parse().unwrap()
+    }
+
+    fn time(hour: u32, minute: u32) -> NaiveTime {
+        NaiveTime::from_hms_opt(hour, minute, 0).unwrap()
+    }
+
+    /** The Berlin wall-clock time at `hour:minute` UTC on `date`. */
+    fn berlin_time(date: (i32, u32, u32), (hour, minute): (u32, u32)) -> NaiveTime {
+        Berlin
+            .from_utc_datetime(&local(date, (hour, minute)))
+            .time()
+    }
+
+    #[test]
+    fn quiet_hours_within_a_day() {
+        let hours = quiet("12:00-14:30");
+        assert!(!hours.contains(time(11, 59)));
+        assert!(hours.contains(time(12, 0)));
+        assert!(hours.contains(time(14, 29)));
+        assert!(!hours.contains(time(14, 30)));
+        assert!(!hours.contains(time(0, 0)));
+    }
+
+    #[test]
+    fn quiet_hours_across_midnight() {
+        let hours = quiet("22:30-07:00");
+        assert!(!hours.contains(time(22, 29)));
+        assert!(hours.contains(time(22, 30)));
+        assert!(hours.contains(time(23, 59)));
+        assert!(hours.contains(time(0, 0)));
+        assert!(hours.contains(time(6, 59)));
+        assert!(!hours.contains(time(7, 0)));
+        assert!(!hours.contains(time(12, 0)));
+    }
+
+    #[test]
+    fn quiet_hours_ending_when_they_start_are_empty() {
+        let hours = quiet("08:00-08:00");
+        assert!(!hours.contains(time(8, 0)));
+        assert!(!hours.contains(time(20, 0)));
+    }
+
+    #[test]
+    fn quiet_hours_follow_the_wall_clock_when_it_goes_forward() {
+        // At 01:00 UTC on 2024-03-31 Berlin went from 02:00 CET to 03:00 CEST
+        let hours = quiet("23:00-03:00");
+        assert_eq!(berlin_time((2024, 3, 31), (0, 59)), time(1, 59));
+        assert!(hours.contains(berlin_time((2024, 3, 31), (0, 59))));
+        assert!(!hours.contains(berlin_time((2024, 3, 31), (1, 0))));
+    }
+
+    #[test]
+    fn quiet_hours_follow_the_wall_clock_when_it_goes_back() {
+        // At 01:00 UTC on 2024-10-27 Berlin went from 03:00 CEST back to 02:00 CET
+        let hours = quiet("22:00-02:30");
+        assert!(hours.contains(berlin_time((2024, 10, 27), (0, 15))));
+        assert!(!hours.contains(berlin_time((2024, 10, 27), (0, 45))));
+        // 02:15 comes round again, and is quiet again
+        assert_eq!(berlin_time((2024, 10, 27), (1, 15)), time(2, 15));
+        assert!(hours.contains(berlin_time((2024, 10, 27), (1, 15))));
+        assert!(!hours.contains(berlin_time((2024, 10, 27), (1, 30))));
+    }
+
+    #[test]
+    fn quiet_hours_parse_and_display() {
+        assert_eq!(quiet(" 22:30 - 7:00 ").to_string(), "22:30-07:00");
+        for invalid in ["22:30", "22:30-25:00", "night-day", ""] {
+            assert!(invalid.parse::<QuietHours>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn deferring_keeps_only_the_latest() {
+        let mut deferred = Deferred::default();
+        deferred.defer("first");
+        deferred.defer("second");
+        deferred.defer("third");
+        assert_eq!(deferred.take(), Some("third"));
+    }
+
+    #[test]
+    fn taking_drains_the_deferred_item() {
+        let mut deferred = Deferred::default();
+        assert_eq!(deferred.take(), None::<&str>);
+        deferred.defer("photo");
+        assert_eq!(deferred.take(), Some("photo"));
+        assert_eq!(deferred.take(), None);
+    }
+
+    #[test]
+    fn cycles_skipped_in_quiet_hours_come_back_first() {
+        let hours = quiet("22:30-07:00");
+        let mut deferred = Deferred::default();
+        let cycles: Vec<_> = [(22, 0), (23, 0), (3, 0), (7, 30), (8, 0)]
+            .into_iter()
+            .map(|(hour, minute)| {
+                let quiet = hours.contains(time(hour, minute));
+                let chosen = format!("{hour:02}:{minute:02}");
+                deferred.cycle(quiet, || Ok::<_, ()>(chosen)).unwrap()
+            })
+            .collect();
+        assert_eq!(
+            cycles,
+            [
+                Cycle::Show(None),
+                Cycle::Quiet,
+                Cycle::Quiet,
+                // The morning shows what 03:00 would have, and then new choices again
+                Cycle::Show(Some("03:00".to_string())),
+                Cycle::Show(None),
+            ]
+        );
+    }
+
+    #[test]
+    fn images_put_during_a_refresh_collapse_to_the_newest() {
+        let latest = Latest::default();
+        for image in ["first", "second", "third"] {
+            latest.put(image);
+        }
+        assert_eq!(latest.next(Duration::ZERO), Some("third"));
+        assert_eq!(latest.next(Duration::ZERO), None);
+    }
+
+    #[test]
+    fn waiting_for_the_latest_ends_when_one_is_put() {
+        let latest = std::sync::Arc::new(Latest::default());
+        let putting = latest.clone();
+        let putter = std::thread::spawn(move || putting.put("image"));
+        assert_eq!(latest.next(Duration::from_secs(10)), Some("image"));
+        putter.join().unwrap();
+    }
+
+    #[test]
+    fn quiet_cycles_that_cant_choose_keep_the_earlier_item() {
+        let mut deferred = Deferred::default();
+        deferred.defer("photo");
+        assert_eq!(deferred.cycle(true, || Err("no images")), Err("no images"));
+        assert_eq!(
+            deferred.cycle(false, || Err("no images")),
+            Ok(Cycle::Show(Some("photo")))
+        );
+    }
+}
@@ -0,0 +1,123 @@
+use std::io::{self, Write};
+
+use clap::ValueEnum;
+
+/** Highest palette index in a frame, orange. */
+const MAX_INDEX: u8 = 6;
+
+/** Formats for `--emit`. Both carry one palette index per pixel, row by row, in the order of
+ * the palettes in `main.rs`. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// Binary PGM (P5) with a maximum value of 6, so every sample is a palette index
+    Pgm,
+    /// 8-bit paletted PNG whose palette holds the colors the panel was quantized against
+    Png,
+}
+
+/** Write a frame of palette indices in the given format. The PNG palette is made of the first
+ * [MAX_INDEX] + 1 colors of `palette`. */
+pub fn write_frame(
+    mut out: impl Write,
+    format: Format,
+    width: usize,
+    height: usize,
+    pixels: &[u8],
+    palette: &[imagequant::RGBA],
+) -> io::Result<()> {
+    match format {
+        Format::Pgm => {
+            write!(out, "P5\n{width} {height}\n{MAX_INDEX}\n")?;
+            out.write_all(pixels)?;
+        }
+        Format::Png => {
+            let mut encoder = png::Encoder::new(&mut out, width as u32, height as u32);
+            encoder.set_color(png::ColorType::Indexed);
+            encoder.set_depth(png::BitDepth::Eight);
+            encoder.set_palette(
+                palette[..=MAX_INDEX as usize]
+                    .iter()
+                    .flat_map(|c| [c.r, c.g, c.b])
+                    .collect::<Vec<_>>(),
+            );
+            let mut writer = encoder.write_header().map_err(into_io_error)?;
+            writer.write_image_data(pixels).map_err(into_io_error)?;
+            writer.finish().map_err(into_io_error)?;
+        }
+    }
+
+    out.flush()
+}
+
+fn into_io_error(error: png::EncodingError) -> io::Error {
+    match error {
+        png::EncodingError::IoError(error) => error,
+        error => io::Error::other(error),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /** Every index in turn, at an odd width so rows don't line up with them. */
+    fn frame() -> (usize, usize, Vec<u8>) {
+        let (width, height) = (9, 5);
+        let pixels = (0..width * height)
+            .map(|ix| (ix % (MAX_INDEX as usize + 1)) as u8)
+            .collect();
+        (width, height, pixels)
+    }
+
+    #[test]
+    fn pgm_round_trips() {
+        let (width, height, pixels) = frame();
+        let mut out = Vec::new();
+        write_frame(
+            &mut out,
+            Format::Pgm,
+            width,
+            height,
+            &pixels,
+            &crate::get_palette(0.5),
+        )
+        .unwrap();
+
+        let header = b"P5\n9 5\n6\n";
+        assert_eq!(&out[..header.len()], header);
+        assert_eq!(&out[header.len()..], pixels);
+    }
+
+    #[test]
+    fn png_round_trips() {
+        let (width, height, pixels) = frame();
+        let palette = crate::get_palette(0.5);
+        let mut out = Vec::new();
+        write_frame(&mut out, Format::Png, width, height, &pixels, &palette).unwrap();
+
+        let decoder = png::Decoder::new(out.as_slice());
+        let mut reader = decoder.read_info().unwrap();
+        let info = reader.info();
+        assert_eq!((info.width, info.height), (9, 5));
+        assert_eq!(
+            (info.color_type, info.bit_depth),
+            (png::ColorType::Indexed, png::BitDepth::Eight)
+        );
+        let colors: Vec<[u8; 3]> = info
+            .palette
+            .as_ref()
+            .unwrap()
+            .chunks(3)
+            .map(|c| [c[0], c[1], c[2]])
+            .collect();
+        let expected: Vec<[u8; 3]> = palette[..=MAX_INDEX as usize]
+            .iter()
+            .map(|c| [c.r, c.g, c.b])
+            .collect();
+        assert_eq!(colors, expected);
+
+        let mut decoded = vec![0; reader.output_buffer_size()];
+        let frame = reader.next_frame(&mut decoded).unwrap();
+        assert_eq!(&decoded[..frame.buffer_size()], pixels);
+    }
+}
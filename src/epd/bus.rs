@@ -0,0 +1,171 @@
+use std::time::Duration;
+
+use log::info;
+use rppal::gpio::{self, Gpio};
+use rppal::i2c::{self, I2c};
+use rppal::spi::{self, Spi};
+
+use crate::epd::error::InkyError;
+use crate::epd::wiring::Wiring;
+
+const BUSY_DEBOUNCE: Duration = Duration::from_millis(10);
+
+/** The SPI device and GPIO lines used to talk to the controller. */
+pub trait Bus: Send {
+    fn set_chip_select(&mut self, high: bool);
+    fn set_data_command(&mut self, high: bool);
+    fn set_reset(&mut self, high: bool);
+    /** Whether the controller holds the busy line low, which it does while it is busy. */
+    fn is_busy(&self) -> bool;
+    /** Write the start of `data` in one transfer, returning how many bytes that was. */
+    fn write(&mut self, data: &[u8]) -> Result<usize, spi::Error>;
+    fn set_clock_speed(&mut self, clock_hz: u32) -> Result<(), spi::Error>;
+    /** Call `callback` with whether the controller is busy on every debounced edge of the busy
+     * line. */
+    fn set_busy_callback(
+        &mut self,
+        callback: Box<dyn FnMut(bool) + Send>,
+    ) -> Result<(), gpio::Error>;
+    fn clear_busy_callback(&mut self) -> Result<(), gpio::Error>;
+}
+
+/** Claims the [Bus] whenever the driver acquires the hardware. */
+pub trait Connector: Send {
+    fn connect(&mut self, wiring: &Wiring, spi_clock_hz: u32) -> Result<Box<dyn Bus>, InkyError>;
+}
+
+/** The I2C bus the HAT's EEPROM answers on. */
+pub trait I2cBus {
+    fn set_slave_address(&mut self, address: u16) -> Result<(), i2c::Error>;
+    fn block_write(&mut self, command: u8, data: &[u8]) -> Result<(), i2c::Error>;
+    fn block_read(&mut self, command: u8, buffer: &mut [u8]) -> Result<(), i2c::Error>;
+    /** A plain read, without writing a command first. */
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, i2c::Error>;
+}
+
+impl I2cBus for I2c {
+    fn set_slave_address(&mut self, address: u16) -> Result<(), i2c::Error> {
+        I2c::set_slave_address(self, address)
+    }
+
+    fn block_write(&mut self, command: u8, data: &[u8]) -> Result<(), i2c::Error> {
+        I2c::block_write(self, command, data)
+    }
+
+    fn block_read(&mut self, command: u8, buffer: &mut [u8]) -> Result<(), i2c::Error> {
+        I2c::block_read(self, command, buffer)
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, i2c::Error> {
+        I2c::read(self, buffer)
+    }
+}
+
+/** Never connects, for a panel that is only described. */
+pub struct Detached;
+
+impl Connector for Detached {
+    fn connect(&mut self, _wiring: &Wiring, _spi_clock_hz: u32) -> Result<Box<dyn Bus>, InkyError> {
+        Err(InkyError::Detached)
+    }
+}
+
+/** The real hardware, through rppal. */
+pub struct Rppal;
+
+impl Connector for Rppal {
+    fn connect(&mut self, wiring: &Wiring, spi_clock_hz: u32) -> Result<Box<dyn Bus>, InkyError> {
+        Ok(Box::new(Hardware::acquire(wiring, spi_clock_hz)?))
+    }
+}
+
+struct Hardware {
+    spi: Spi,
+    cs_pin: gpio::OutputPin,
+    dc_pin: gpio::OutputPin,
+    reset_pin: gpio::OutputPin,
+    busy_pin: gpio::InputPin,
+}
+
+impl Hardware {
+    fn acquire(wiring: &Wiring, spi_clock_hz: u32) -> Result<Hardware, InkyError> {
+        info!("Initializing GPIO");
+        let gpio = Gpio::new()?;
+        info!("Chip Select @ PIN {}", wiring.cs_pin);
+        let cs_pin = gpio.get(wiring.cs_pin)?.into_output_high();
+        info!("Data/Command @ PIN {}", wiring.dc_pin);
+        let dc_pin = gpio.get(wiring.dc_pin)?.into_output_low();
+        info!("Reset @ PIN {}", wiring.reset_pin);
+        let reset_pin = gpio.get(wiring.reset_pin)?.into_output_high();
+        info!("Busy @ PIN {}", wiring.busy_pin);
+        let mut busy_pin = gpio.get(wiring.busy_pin)?.into_input_pullup();
+        busy_pin.set_interrupt(gpio::Trigger::Both, Some(BUSY_DEBOUNCE))?;
+        info!("Busy pin initial state: {}", busy_pin.read());
+
+        info!("Initializing SPI");
+        let cs_channel = match wiring.cs_pin {
+            0 => spi::SlaveSelect::Ss8,
+            1 => spi::SlaveSelect::Ss7,
+            _ => spi::SlaveSelect::Ss0,
+        };
+        let spi = Spi::new(wiring.spi_bus(), cs_channel, spi_clock_hz, spi::Mode::Mode0)?;
+
+        Ok(Hardware {
+            spi,
+            cs_pin,
+            dc_pin,
+            reset_pin,
+            busy_pin,
+        })
+    }
+}
+
+fn set_level(pin: &mut gpio::OutputPin, high: bool) {
+    if high {
+        pin.set_high();
+    } else {
+        pin.set_low();
+    }
+}
+
+impl Bus for Hardware {
+    fn set_chip_select(&mut self, high: bool) {
+        set_level(&mut self.cs_pin, high);
+    }
+
+    fn set_data_command(&mut self, high: bool) {
+        set_level(&mut self.dc_pin, high);
+    }
+
+    fn set_reset(&mut self, high: bool) {
+        set_level(&mut self.reset_pin, high);
+    }
+
+    fn is_busy(&self) -> bool {
+        self.busy_pin.is_low()
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<usize, spi::Error> {
+        self.spi.write(data)
+    }
+
+    fn set_clock_speed(&mut self, clock_hz: u32) -> Result<(), spi::Error> {
+        self.spi.set_clock_speed(clock_hz)
+    }
+
+    fn set_busy_callback(
+        &mut self,
+        mut callback: Box<dyn FnMut(bool) + Send>,
+    ) -> Result<(), gpio::Error> {
+        self.busy_pin
+            .set_async_interrupt(gpio::Trigger::Both, Some(BUSY_DEBOUNCE), move |event| {
+                callback(event.trigger == gpio::Trigger::FallingEdge)
+            })
+    }
+
+    fn clear_busy_callback(&mut self) -> Result<(), gpio::Error> {
+        self.busy_pin.clear_async_interrupt()?;
+        self.busy_pin
+            .set_interrupt(gpio::Trigger::Both, Some(BUSY_DEBOUNCE))
+    }
+}
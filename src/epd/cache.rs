@@ -0,0 +1,232 @@
+use std::{fs, io, path::Path};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{epd::EPDType, state::write_atomic};
+
+pub const FILE_NAME: &str = "eeprom.json";
+const VERSION: u32 = 1;
+
+/** How long a cached EEPROM is trusted before it is checked against the real one again. */
+const MAX_AGE: TimeDelta = TimeDelta::days(7);
+
+/** Contents of the EEPROM cache file. */
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CachedEeprom {
+    version: u32,
+    /** When the cached contents were last confirmed by a real read. */
+    verified: DateTime<Utc>,
+    pub eeprom: EPDType,
+}
+
+impl CachedEeprom {
+    /** Whether the cache can be used without reading the EEPROM again. */
+    pub fn is_fresh(&self, now: DateTime<Utc>) -> bool {
+        now.signed_duration_since(self.verified) < MAX_AGE
+    }
+}
+
+/** Load the cache, or `None` if it is missing, corrupt or from another version. */
+pub fn load(path: &Path) -> Option<CachedEeprom> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return None,
+        Err(error) => {
+            warn!("Could not read {}: {error}", path.display());
+            return None;
+        }
+    };
+
+    match serde_json::from_str::<CachedEeprom>(&contents) {
+        Ok(cached) if cached.version == VERSION => Some(cached),
+        Ok(cached) => {
+            warn!("Ignoring EEPROM cache of version {}", cached.version);
+            None
+        }
+        Err(error) => {
+            warn!("Ignoring corrupt {}: {error}", path.display());
+            None
+        }
+    }
+}
+
+/** Store EEPROM contents that were just read from the panel. Failures are only logged. */
+pub fn store(path: &Path, eeprom: &EPDType) {
+    let cached = CachedEeprom {
+        version: VERSION,
+        verified: Utc::now(),
+        eeprom: eeprom.clone(),
+    };
+    let result = serde_json::to_vec_pretty(&cached)
+        .map_err(io::Error::from)
+        .and_then(|contents| write_atomic(path, &contents));
+    if let Err(error) = result {
+        warn!("Could not write {}: {error}", path.display());
+    }
+}
+
+/** The EEPROM contents from the cache at `path` if it is fresh at `now`, otherwise from `read`,
+ * which are then stored in the cache. Without a `path`, always `read`. */
+pub fn load_or_read<E>(
+    path: Option<&Path>,
+    now: DateTime<Utc>,
+    read: impl FnOnce() -> Result<EPDType, E>,
+) -> Result<EPDType, E> {
+    let cached = path.and_then(load);
+    if let Some(cached) = cached.as_ref().filter(|cached| cached.is_fresh(now)) {
+        info!("Using cached EPD Type: {:?}", cached.eeprom);
+        return Ok(cached.eeprom.clone());
+    }
+
+    let eeprom = read()?;
+    if let Some(path) = path {
+        if cached.is_some_and(|cached| cached.eeprom != eeprom) {
+            warn!("The EEPROM no longer matches the cached copy, replacing it");
+        }
+        store(path, &eeprom);
+    }
+    return Ok(eeprom);
+}
+
+/** Remove the cache so that the next run reads the EEPROM again. */
+pub fn invalidate(path: &Path) {
+    match fs::remove_file(path) {
+        Ok(()) => warn!("Discarded the cached EEPROM contents"),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => {}
+        Err(error) => warn!("Could not remove {}: {error}", path.display()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+
+    use super::*;
+    use crate::epd::Model;
+
+    fn panel(width: u16) -> EPDType {
+        Model::Ac073tc1.epd_type(Some(width), None)
+    }
+
+    /** Write a cache file confirmed at `verified`, as [store] would have. */
+    fn write(path: &Path, version: u32, verified: DateTime<Utc>, eeprom: &EPDType) {
+        let cached = CachedEeprom {
+            version,
+            verified,
+            eeprom: eeprom.clone(),
+        };
+        fs::write(path, serde_json::to_vec(&cached).unwrap()).unwrap();
+    }
+
+    /** Go through the cache at `path` at `now` with a read that finds `eeprom`, returning what
+     * was used and whether it was read. */
+    fn resolve(path: Option<&Path>, now: DateTime<Utc>, eeprom: &EPDType) -> (EPDType, bool) {
+        let read = Cell::new(false);
+        let result = load_or_read(path, now, || {
+            read.set(true);
+            Ok::<_, ()>(eeprom.clone())
+        });
+        return (result.unwrap(), read.get());
+    }
+
+    #[test]
+    fn a_fresh_cache_is_used_without_reading() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(FILE_NAME);
+        let now = Utc::now();
+        write(&path, VERSION, now - TimeDelta::days(6), &panel(800));
+
+        assert_eq!(resolve(Some(&path), now, &panel(640)), (panel(800), false));
+        // Using it doesn't count as confirming it
+        assert_eq!(load(&path).unwrap().verified, now - TimeDelta::days(6));
+    }
+
+    #[test]
+    fn a_missing_cache_is_read_and_stored() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(FILE_NAME);
+        assert!(load(&path).is_none());
+
+        let before = Utc::now();
+        assert_eq!(
+            resolve(Some(&path), before, &panel(800)),
+            (panel(800), true)
+        );
+        let cached = load(&path).unwrap();
+        assert_eq!((cached.version, &cached.eeprom), (VERSION, &panel(800)));
+        assert!(cached.verified >= before && cached.is_fresh(Utc::now()));
+
+        assert_eq!(
+            resolve(Some(&path), Utc::now(), &panel(640)),
+            (panel(800), false)
+        );
+    }
+
+    #[test]
+    fn a_stale_cache_is_read_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(FILE_NAME);
+        let now = Utc::now();
+        write(&path, VERSION, now - MAX_AGE, &panel(800));
+        assert!(!load(&path).unwrap().is_fresh(now));
+
+        // The same panel, confirmed again
+        assert_eq!(resolve(Some(&path), now, &panel(800)), (panel(800), true));
+        assert!(load(&path).unwrap().is_fresh(now));
+
+        // A panel that no longer matches replaces the cached one
+        write(&path, VERSION, now - MAX_AGE, &panel(800));
+        assert_eq!(resolve(Some(&path), now, &panel(640)), (panel(640), true));
+        assert_eq!(load(&path).unwrap().eeprom, panel(640));
+    }
+
+    #[test]
+    fn a_failed_read_keeps_the_stale_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(FILE_NAME);
+        let now = Utc::now();
+        write(&path, VERSION, now - MAX_AGE, &panel(800));
+
+        let result = load_or_read(Some(&path), now, || Err("no HAT"));
+        assert_eq!(result, Err("no HAT"));
+        assert_eq!(load(&path).unwrap().verified, now - MAX_AGE);
+    }
+
+    #[test]
+    fn corrupt_and_other_versions_are_ignored() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(FILE_NAME);
+        let now = Utc::now();
+        for contents in ["", "{", "{\"version\": 1}", "[1, 2, 3]"] {
+            fs::write(&path, contents).unwrap();
+            assert!(load(&path).is_none(), "{contents}");
+        }
+        // A corrupt file is replaced by what was read
+        assert_eq!(resolve(Some(&path), now, &panel(800)), (panel(800), true));
+        assert_eq!(load(&path).unwrap().eeprom, panel(800));
+
+        write(&path, VERSION + 1, now, &panel(640));
+        assert!(load(&path).is_none());
+        assert_eq!(resolve(Some(&path), now, &panel(800)), (panel(800), true));
+        assert_eq!(load(&path).unwrap().version, VERSION);
+    }
+
+    #[test]
+    fn without_a_cache_the_eeprom_is_always_read() {
+        assert_eq!(resolve(None, Utc::now(), &panel(800)), (panel(800), true));
+    }
+
+    #[test]
+    fn invalidating_removes_the_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(FILE_NAME);
+        store(&path, &panel(800));
+        assert!(load(&path).is_some());
+        invalidate(&path);
+        assert!(!path.exists());
+        // Nothing to remove is fine
+        invalidate(&path);
+    }
+}
@@ -0,0 +1,24 @@
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+/** Source of time for the driver. Every delay and timeout goes through this, so the timing of a
+ * refresh can be driven by something other than the wall clock. */
+pub trait Clock: Send {
+    fn now(&self) -> Instant;
+    fn sleep(&self, duration: Duration);
+}
+
+/** The real time, from `std`. */
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
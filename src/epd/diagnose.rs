@@ -0,0 +1,325 @@
+use std::{fmt::Display, io};
+
+use rppal::{gpio, i2c, spi};
+
+use crate::epd::error::InkyError;
+
+const SPI_DEVICE: &str = "/dev/spidev0.0";
+const I2C_DEVICE: &str = "/dev/i2c-1";
+const GPIO_DEVICE: &str = "/dev/gpiochip0";
+
+/** What the I2C driver reports when nothing acknowledges an address (`EREMOTEIO` on the Pi's
+ * controller, `ENXIO` on others). */
+const NACK_ERRNOS: [i32; 2] = [121, 6];
+
+/** Exit code for a device we aren't allowed to open (`EX_NOPERM` from sysexits.h). */
+pub const EXIT_PERMISSION: u8 = 77;
+/** Exit code for a device that doesn't exist (`EX_UNAVAILABLE` from sysexits.h). */
+pub const EXIT_UNAVAILABLE: u8 = 69;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cause {
+    PermissionDenied,
+    Missing,
+    /** A pin is claimed by the kernel or another program. */
+    PinInUse,
+    /** Nothing acknowledged at the I2C address. */
+    NoResponse,
+    /** The EEPROM answered, but with something other than a description of the panel. */
+    Blank,
+    NotARaspberryPi,
+}
+
+/** What most likely went wrong while opening one of the panel's interfaces, and what to do. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnosis {
+    pub device: String,
+    pub cause: Cause,
+    pub advice: String,
+}
+
+impl Diagnosis {
+    fn new(device: &str, cause: Cause, advice: &str) -> Diagnosis {
+        Diagnosis {
+            device: device.to_string(),
+            cause,
+            advice: advice.to_string(),
+        }
+    }
+
+    pub fn exit_code(&self) -> u8 {
+        match self.cause {
+            Cause::PermissionDenied => EXIT_PERMISSION,
+            Cause::Missing
+            | Cause::PinInUse
+            | Cause::NoResponse
+            | Cause::Blank
+            | Cause::NotARaspberryPi => EXIT_UNAVAILABLE,
+        }
+    }
+}
+
+impl Display for Diagnosis {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let problem = match self.cause {
+            Cause::PermissionDenied => "is not accessible to this user",
+            Cause::Missing => "does not exist",
+            Cause::PinInUse => "is in use",
+            Cause::NoResponse => "did not respond",
+            Cause::Blank => "does not describe the panel",
+            Cause::NotARaspberryPi => "is not supported",
+        };
+        write!(f, "{} {problem}. {}", self.device, self.advice)
+    }
+}
+
+/** Recognize the common setup mistakes behind a driver error. Returns `None` for errors that
+ * aren't about accessing the hardware. */
+pub fn diagnose(error: &InkyError) -> Option<Diagnosis> {
+    match error {
+        InkyError::SpiError(spi::Error::Io(error)) => diagnose_io(error, Interface::Spi),
+        InkyError::I2cError(i2c::Error::Io(error)) => diagnose_io(error, Interface::I2c),
+        InkyError::I2cError(i2c::Error::UnknownModel) => Some(not_a_pi("I2C")),
+        InkyError::GpioError(error) => diagnose_gpio(error),
+        _ => None,
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Interface {
+    Spi,
+    I2c,
+}
+
+fn diagnose_io(error: &io::Error, interface: Interface) -> Option<Diagnosis> {
+    let (device, name, group, dtparam) = match interface {
+        Interface::Spi => (SPI_DEVICE, "SPI", "spi", "dtparam=spi=on"),
+        Interface::I2c => (I2C_DEVICE, "I2C", "i2c", "dtparam=i2c_arm=on"),
+    };
+
+    match error.kind() {
+        io::ErrorKind::PermissionDenied => Some(Diagnosis::new(
+            device,
+            Cause::PermissionDenied,
+            &format!(
+                "Add the user to the {group} group with `sudo usermod -aG {group} $USER` and log in again."
+            ),
+        )),
+        io::ErrorKind::NotFound => Some(Diagnosis::new(
+            device,
+            Cause::Missing,
+            &format!(
+                "Enable {name} under Interface Options in `sudo raspi-config`, or add {dtparam} to config.txt, then reboot."
+            ),
+        )),
+        _ if matches!(interface, Interface::I2c)
+            && error.raw_os_error().is_some_and(|errno| NACK_ERRNOS.contains(&errno)) =>
+        {
+            Some(Diagnosis::new(
+                "The panel's EEPROM",
+                Cause::NoResponse,
+                "Check that the HAT is seated. Some clone HATs put the EEPROM at another address, `inky-rs eeprom scan` looks for it, and boards without one work with `--model ac073tc1`.",
+            ))
+        }
+        io::ErrorKind::InvalidData if matches!(interface, Interface::I2c) => {
+            Some(Diagnosis::new(
+                "The panel's EEPROM",
+                Cause::Blank,
+                "Some clone boards leave it blank, `--model ac073tc1` skips reading it.",
+            ))
+        }
+        _ => None,
+    }
+}
+
+fn diagnose_gpio(error: &gpio::Error) -> Option<Diagnosis> {
+    match error {
+        gpio::Error::PermissionDenied(path) => Some(Diagnosis::new(
+            path,
+            Cause::PermissionDenied,
+            "Add the user to the gpio group with `sudo usermod -aG gpio $USER` and log in again.",
+        )),
+        gpio::Error::Io(error) if error.kind() == io::ErrorKind::PermissionDenied => {
+            Some(Diagnosis::new(
+                GPIO_DEVICE,
+                Cause::PermissionDenied,
+                "Add the user to the gpio group with `sudo usermod -aG gpio $USER` and log in again.",
+            ))
+        }
+        gpio::Error::Io(error) if error.kind() == io::ErrorKind::NotFound => Some(Diagnosis::new(
+            GPIO_DEVICE,
+            Cause::Missing,
+            "Check that this is a Raspberry Pi running a kernel with GPIO character device support.",
+        )),
+        gpio::Error::PinUsed(pin) | gpio::Error::PinNotAvailable(pin) => Some(Diagnosis::new(
+            &format!("GPIO {pin}"),
+            Cause::PinInUse,
+            // The SPI driver claims both chip select lines unless told otherwise
+            if matches!(pin, 7 | 8) {
+                "Add dtoverlay=spi0-0cs to config.txt so the SPI driver leaves the chip select pins alone, then reboot."
+            } else {
+                "Check for another program or a dtoverlay in config.txt that uses this pin."
+            },
+        )),
+        gpio::Error::UnknownModel => Some(not_a_pi("GPIO")),
+        _ => None,
+    }
+}
+
+fn not_a_pi(name: &str) -> Diagnosis {
+    Diagnosis::new(
+        name,
+        Cause::NotARaspberryPi,
+        "The Raspberry Pi model could not be identified; this driver only runs on a Raspberry Pi.",
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{
+        epd::error::{explain, Phase},
+        error::{EXIT_HARDWARE, EXIT_TIMEOUT},
+    };
+
+    fn io_error(kind: io::ErrorKind) -> io::Error {
+        io::Error::new(kind, "from the test")
+    }
+
+    fn spi(kind: io::ErrorKind) -> InkyError {
+        InkyError::SpiError(spi::Error::Io(io_error(kind)))
+    }
+
+    fn i2c(error: io::Error) -> InkyError {
+        InkyError::I2cError(i2c::Error::Io(error))
+    }
+
+    fn gpio(error: gpio::Error) -> InkyError {
+        InkyError::GpioError(error)
+    }
+
+    /** The device and cause `error` is diagnosed with, and its exit code. */
+    fn diagnosed(error: &InkyError) -> (String, Cause, u8) {
+        let diagnosis = diagnose(error).unwrap_or_else(|| panic!("{error} was not diagnosed"));
+        let code = diagnosis.exit_code();
+        assert_eq!(explain(error), code);
+        (diagnosis.device, diagnosis.cause, code)
+    }
+
+    #[test]
+    fn permission_problems_exit_with_77() {
+        let permission = |device: &str| (device.to_string(), Cause::PermissionDenied, 77);
+        assert_eq!(
+            diagnosed(&spi(io::ErrorKind::PermissionDenied)),
+            permission(SPI_DEVICE)
+        );
+        assert_eq!(
+            diagnosed(&i2c(io_error(io::ErrorKind::PermissionDenied))),
+            permission(I2C_DEVICE)
+        );
+        let error = gpio(gpio::Error::PermissionDenied("/dev/gpiomem".to_string()));
+        assert_eq!(diagnosed(&error), permission("/dev/gpiomem"));
+        let error = gpio(gpio::Error::Io(io_error(io::ErrorKind::PermissionDenied)));
+        assert_eq!(diagnosed(&error), permission(GPIO_DEVICE));
+    }
+
+    #[test]
+    fn missing_hardware_exits_with_69() {
+        let missing = |device: &str| (device.to_string(), Cause::Missing, 69);
+        assert_eq!(
+            diagnosed(&spi(io::ErrorKind::NotFound)),
+            missing(SPI_DEVICE)
+        );
+        assert_eq!(
+            diagnosed(&i2c(io_error(io::ErrorKind::NotFound))),
+            missing(I2C_DEVICE)
+        );
+        let error = gpio(gpio::Error::Io(io_error(io::ErrorKind::NotFound)));
+        assert_eq!(diagnosed(&error), missing(GPIO_DEVICE));
+
+        for error in [
+            InkyError::I2cError(i2c::Error::UnknownModel),
+            gpio(gpio::Error::UnknownModel),
+        ] {
+            assert_eq!(diagnosed(&error).1, Cause::NotARaspberryPi);
+            assert_eq!(diagnosed(&error).2, EXIT_UNAVAILABLE);
+        }
+    }
+
+    #[test]
+    fn pins_in_use_are_named() {
+        for error in [gpio::Error::PinUsed(17), gpio::Error::PinNotAvailable(17)] {
+            let error = gpio(error);
+            assert_eq!(
+                diagnosed(&error),
+                ("GPIO 17".to_string(), Cause::PinInUse, 69)
+            );
+            assert!(diagnose(&error).unwrap().advice.contains("another program"));
+        }
+        // The chip select pins are claimed by the SPI driver itself
+        let advice = diagnose(&gpio(gpio::Error::PinUsed(8))).unwrap().advice;
+        assert!(advice.contains("dtoverlay=spi0-0cs"), "{advice}");
+    }
+
+    #[test]
+    fn an_eeprom_that_does_not_answer_is_unavailable() {
+        for errno in NACK_ERRNOS {
+            let error = i2c(io::Error::from_raw_os_error(errno));
+            let (device, cause, code) = diagnosed(&error);
+            assert_eq!(
+                (device.as_str(), cause, code),
+                ("The panel's EEPROM", Cause::NoResponse, 69)
+            );
+        }
+        let error = i2c(io_error(io::ErrorKind::InvalidData));
+        assert_eq!(diagnosed(&error).1, Cause::Blank);
+        // Only I2C can have no one answering
+        let error = InkyError::SpiError(spi::Error::Io(io::Error::from_raw_os_error(121)));
+        assert_eq!(diagnose(&error), None);
+    }
+
+    #[test]
+    fn diagnoses_name_the_device_and_what_to_do() {
+        let diagnosis = diagnose(&spi(io::ErrorKind::PermissionDenied)).unwrap();
+        assert_eq!(
+            diagnosis.to_string(),
+            "/dev/spidev0.0 is not accessible to this user. Add the user to the spi group with \
+             `sudo usermod -aG spi $USER` and log in again."
+        );
+        let diagnosis = diagnose(&i2c(io_error(io::ErrorKind::NotFound))).unwrap();
+        assert_eq!(
+            diagnosis.to_string(),
+            "/dev/i2c-1 does not exist. Enable I2C under Interface Options in `sudo raspi-config`, \
+             or add dtparam=i2c_arm=on to config.txt, then reboot."
+        );
+    }
+
+    #[test]
+    fn other_errors_keep_their_own_exit_codes() {
+        for error in [
+            spi(io::ErrorKind::TimedOut),
+            InkyError::SpiError(spi::Error::ClockSpeedNotSupported(1)),
+            gpio(gpio::Error::ThreadPanic),
+            InkyError::Busy,
+            InkyError::Detached,
+            // Failing part way through a write isn't about opening the device
+            InkyError::Transfer {
+                command: 0x10,
+                phase: Phase::Transmit,
+                written: 0,
+                total: 10,
+                source: spi::Error::Io(io_error(io::ErrorKind::PermissionDenied)),
+            },
+        ] {
+            assert_eq!(diagnose(&error), None, "{error}");
+            assert_eq!(explain(&error), EXIT_HARDWARE, "{error}");
+        }
+        let timeout = InkyError::Timeout {
+            phase: Phase::Refresh,
+            waited: Duration::from_secs(60),
+        };
+        assert_eq!(explain(&timeout), EXIT_TIMEOUT);
+    }
+}
@@ -0,0 +1,152 @@
+use std::{fmt::Display, time::Duration};
+
+use rppal::{gpio, i2c, spi};
+
+use crate::{
+    epd::{diagnose::diagnose, margins::Margins},
+    error::{EXIT_HARDWARE, EXIT_TIMEOUT},
+    shutdown,
+};
+
+/** The step of talking to the controller that was in progress when something failed. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Setup,
+    Transmit,
+    PowerOn,
+    Refresh,
+    PowerOff,
+    Sleep,
+    Raw,
+}
+
+impl Display for Phase {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Phase::Setup => write!(f, "setup"),
+            Phase::Transmit => write!(f, "image transmission"),
+            Phase::PowerOn => write!(f, "power on"),
+            Phase::Refresh => write!(f, "refresh"),
+            Phase::PowerOff => write!(f, "power off"),
+            Phase::Sleep => write!(f, "deep sleep"),
+            Phase::Raw => write!(f, "raw command"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum InkyError {
+    SpiError(spi::Error),
+    GpioError(gpio::Error),
+    I2cError(i2c::Error),
+    Busy,
+    /** An SPI write failed part way through sending a command or its data. */
+    Transfer {
+        command: u8,
+        phase: Phase,
+        written: usize,
+        total: usize,
+        source: spi::Error,
+    },
+    /** The controller stayed busy for `waited` during `phase`, much longer than it should. */
+    Timeout {
+        phase: Phase,
+        waited: Duration,
+    },
+    /** A signal asked the program to stop, so the update was given up before this phase, with
+     * the panel powered off. */
+    Interrupted(Phase),
+    /** The panel was only described with [crate::epd::inky::Inky::detached], so it can't be
+     * talked to. */
+    Detached,
+    /** The margins leave no room for an image on the panel. */
+    InvalidMargins {
+        margins: Margins,
+        width: usize,
+        height: usize,
+    },
+}
+
+impl From<i2c::Error> for InkyError {
+    fn from(value: i2c::Error) -> Self {
+        InkyError::I2cError(value)
+    }
+}
+
+impl From<gpio::Error> for InkyError {
+    fn from(value: gpio::Error) -> Self {
+        InkyError::GpioError(value)
+    }
+}
+
+impl From<spi::Error> for InkyError {
+    fn from(value: spi::Error) -> Self {
+        InkyError::SpiError(value)
+    }
+}
+
+impl Display for InkyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            InkyError::SpiError(error) => write!(f, "SPI error: {error}"),
+            InkyError::GpioError(error) => write!(f, "GPIO error: {error}"),
+            InkyError::I2cError(error) => write!(f, "I2C error: {error}"),
+            InkyError::Busy => write!(f, "The panel is busy"),
+            InkyError::Detached => write!(f, "The panel was not opened"),
+            InkyError::Interrupted(phase) => write!(f, "Stopped by a signal before {phase}"),
+            InkyError::Timeout { phase, waited } => {
+                write!(f, "The panel was still busy after {waited:?} of {phase}")
+            }
+            InkyError::Transfer {
+                command,
+                phase,
+                written,
+                total,
+                source,
+            } => write!(
+                f,
+                "SPI error during {phase} (command 0x{command:02X}, {written} of {total} bytes written): {source}"
+            ),
+            InkyError::InvalidMargins {
+                margins,
+                width,
+                height,
+            } => write!(
+                f,
+                "Margins of {margins} leave no room for an image on the {width}x{height} panel"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for InkyError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            InkyError::SpiError(error) => Some(error),
+            InkyError::GpioError(error) => Some(error),
+            InkyError::I2cError(error) => Some(error),
+            InkyError::Busy
+            | InkyError::Detached
+            | InkyError::Timeout { .. }
+            | InkyError::Interrupted(_)
+            | InkyError::InvalidMargins { .. } => None,
+            InkyError::Transfer { source, .. } => Some(source),
+        }
+    }
+}
+
+/** Print advice for errors caused by a common setup mistake, and pick the exit code. */
+pub fn explain(error: &InkyError) -> u8 {
+    match error {
+        InkyError::Interrupted(_) => return shutdown::exit_code(),
+        InkyError::Timeout { .. } => return EXIT_TIMEOUT,
+        _ => {}
+    }
+    match diagnose(error) {
+        Some(diagnosis) => {
+            eprintln!("{diagnosis}");
+            diagnosis.exit_code()
+        }
+        None => EXIT_HARDWARE,
+    }
+}
@@ -1,21 +1,38 @@
 use std::cmp::min;
-use std::thread;
+use std::path::Path;
 use std::time::Duration;
 
+use chrono::Utc;
 use log::{info, warn};
-use ndarray::{Array1, Array2};
-use rppal::gpio::{self, Gpio};
-use rppal::i2c::{self, I2c};
-use rppal::spi::{self, Spi};
+use rppal::i2c::I2c;
+use rppal::spi;
 
-use crate::epd;
+use crate::epd::bus::{Bus, Connector, Detached, Rppal};
+use crate::epd::clock::{Clock, SystemClock};
+use crate::epd::error::{InkyError, Phase};
+use crate::epd::margins::Margins;
+use crate::epd::version::{PinInfo, SpiInfo, VersionInfo, CRATE_VERSION, DRIVER};
+use crate::epd::wiring::Wiring;
+use crate::epd::{self, cache, raw};
+use crate::shutdown;
 
-const RESET_PIN: u8 = 27;
-const BUSY_PIN: u8 = 17;
-const DC_PIN: u8 = 22;
 const _MOSI_PIN: u8 = 10;
 const _SCLK_PIN: u8 = 11;
-const CS0_PIN: u8 = 8;
+/** Bytes per SPI write. */
+const SPI_CHUNK_SIZE: usize = 64;
+const BUSY_POLL: Duration = Duration::from_millis(1);
+/** How long the controller may stay busy before it is taken to be stuck. The longest phase, a
+ * normal refresh, takes about 30 s. */
+const BUSY_LIMIT: Duration = Duration::from_secs(120);
+/** Length of each reset pulse, and of the pause between the two. The driver always used these,
+ * and no shorter minimum for the AC073TC1 is documented. */
+const RESET_PULSE: Duration = Duration::from_millis(100);
+/** How long to wait for the controller to signal busy after a reset before assuming it is
+ * already ready. A controller that signals busy ends the wait as soon as it is idle again. */
+const RESET_TIMEOUT: Duration = Duration::from_secs(10);
+/** Data byte the controller expects with the deep sleep command, so stray writes can't put it
+ * to sleep. */
+const DSLP_CHECK_CODE: u8 = 0xA5;
 
 const AC073TC1_PSR: u8 = 0x00;
 const AC073TC1_PWR: u8 = 0x01;
@@ -24,7 +41,7 @@ const AC073TC1_POFS: u8 = 0x03;
 const AC073TC1_PON: u8 = 0x04;
 const AC073TC1_BTST1: u8 = 0x05;
 const AC073TC1_BTST2: u8 = 0x06;
-const _AC073TC1_DSLP: u8 = 0x07;
+const AC073TC1_DSLP: u8 = 0x07;
 const AC073TC1_BTST3: u8 = 0x08;
 const AC073TC1_DTM: u8 = 0x10;
 const _AC073TC1_DSP: u8 = 0x11;
@@ -52,208 +69,1232 @@ const AC073TC1_CCSET: u8 = 0xE0;
 const AC073TC1_PWS: u8 = 0xE3;
 const AC073TC1_TSSET: u8 = 0xE6;
 
+/** Commands and their data that set the controller up before every refresh, in order. */
+const INIT: &[(u8, &[u8])] = &[
+    (AC073TC1_CMDH, &[0x49, 0x55, 0x20, 0x08, 0x09, 0x18]),
+    (AC073TC1_PWR, &[0x3F, 0x00, 0x32, 0x2A, 0x0E, 0x2A]),
+    (AC073TC1_PSR, &[0x5F, 0x69]),
+    (AC073TC1_POFS, &[0x00, 0x54, 0x00, 0x44]),
+    (AC073TC1_BTST1, &[0x40, 0x1F, 0x1F, 0x2C]),
+    (AC073TC1_BTST2, &[0x6F, 0x1F, 0x16, 0x25]),
+    (AC073TC1_BTST3, &[0x6F, 0x1F, 0x1F, 0x22]),
+    (AC073TC1_IPC, &[0x00, 0x04]),
+    (AC073TC1_PLL, &[0x02]),
+    (AC073TC1_TSE, &[0x00]),
+    (AC073TC1_CDI, &[0x3F]),
+    (AC073TC1_TCON, &[0x02, 0x00]),
+    (AC073TC1_TRES, &[0x03, 0x20, 0x01, 0xE0]),
+    (AC073TC1_VDCS, &[0x1E]),
+    (AC073TC1_T_VDCS, &[0x00]),
+    (AC073TC1_AGID, &[0x00]),
+    (AC073TC1_PWS, &[0x2F]),
+    (AC073TC1_CCSET, &[0x00]),
+    (AC073TC1_TSSET, &[0x00]),
+];
+
+/** How long the controller may take to power on. */
+const POWER_ON_TIMEOUT: Duration = Duration::from_millis(400);
+/** How long a refresh may take, which is about 30 s. */
+const REFRESH_TIMEOUT: Duration = Duration::from_secs(45);
+/** How long the controller may take to power off. */
+const POWER_OFF_TIMEOUT: Duration = Duration::from_millis(400);
+
+/** Whether the driver currently holds its [Bus]. */
+enum HardwareState {
+    Acquired(Box<dyn Bus>),
+    Released,
+}
+
 pub struct Inky {
-    spi: Spi,
+    hardware: HardwareState,
+    connector: Box<dyn Connector>,
+    clock: Box<dyn Clock>,
     // i2c: I2c,
     // gpio: Gpio,
     pub eeprom: epd::EPDType,
 
-    pub cs_pin: gpio::OutputPin,
-    pub dc_pin: gpio::OutputPin,
-    pub reset_pin: gpio::OutputPin,
-    pub busy_pin: gpio::InputPin,
-
-    buf: Array2<u8>,
+    wiring: Wiring,
+    spi_clock_hz: u32,
+    spi_chunk_size: usize,
+    width: usize,
+    height: usize,
+    margins: Margins,
+    /** Palette index of every pixel, row by row. */
+    buf: Vec<u8>,
+    /** Two pixels per byte as sent to the controller, kept between refreshes. */
+    packed: Vec<u8>,
+    low_footprint: bool,
+    phase: Phase,
+    prepared: bool,
+    /** Whether a callback registered with [Inky::on_busy_change] is active. */
+    busy_callback: bool,
+    timings: Vec<(Phase, Duration)>,
 }
 
-#[derive(Debug)]
-pub enum InkyError {
-    SpiError(spi::Error),
-    GpioError(gpio::Error),
-    I2cError(i2c::Error),
-}
+impl HardwareState {
+    fn acquired(
+        &mut self,
+        connector: &mut dyn Connector,
+        wiring: &Wiring,
+        spi_clock_hz: u32,
+    ) -> Result<&mut dyn Bus, InkyError> {
+        if let HardwareState::Released = self {
+            info!("Re-acquiring SPI and GPIO");
+            *self = HardwareState::Acquired(connector.connect(wiring, spi_clock_hz)?);
+        }
 
-impl From<i2c::Error> for InkyError {
-    fn from(value: i2c::Error) -> Self {
-        InkyError::I2cError(value)
+        match self {
+            HardwareState::Acquired(bus) => Ok(bus.as_mut()),
+            HardwareState::Released => unreachable!(),
+        }
     }
 }
 
-impl From<gpio::Error> for InkyError {
-    fn from(value: gpio::Error) -> Self {
-        InkyError::GpioError(value)
+impl Inky {
+    /** The EEPROM contents from the cache at `eeprom_cache` if it is fresh, otherwise read over
+     * I2C and stored in the cache. */
+    fn load_eeprom(
+        eeprom_address: u16,
+        wiring: &Wiring,
+        eeprom_cache: Option<&Path>,
+    ) -> Result<epd::EPDType, InkyError> {
+        cache::load_or_read(eeprom_cache, Utc::now(), || {
+            info!("Initializing I2C");
+            let mut i2c = I2c::with_bus(wiring.i2c_bus)?;
+            let eeprom = epd::read_eeprom(&mut i2c, eeprom_address)?;
+            info!("EPD Type: {eeprom:?}");
+            Ok(eeprom)
+        })
     }
-}
 
-impl From<spi::Error> for InkyError {
-    fn from(value: spi::Error) -> Self {
-        InkyError::SpiError(value)
+    fn initialize_inky(eeprom: epd::EPDType, wiring: Wiring) -> Result<Inky, InkyError> {
+        Self::with_connector(eeprom, wiring, Rppal, SystemClock)
     }
-}
 
-impl Inky {
-    fn initialize_inky() -> Result<Inky, InkyError> {
-        info!("Initializing I2C");
-        let mut i2c = I2c::new()?;
-        let eeprom = epd::read_eeprom(&mut i2c)?;
-        info!("EPD Type: {eeprom:?}");
-
-        info!("Initializing GPIO");
-        let gpio = Gpio::new()?;
-        info!("Chip Select @ PIN {CS0_PIN}");
-        let cs_pin = gpio.get(CS0_PIN)?.into_output_high();
-        info!("Data/Command @ PIN {DC_PIN}");
-        let dc_pin = gpio.get(DC_PIN)?.into_output_low();
-        info!("Reset @ PIN {RESET_PIN}");
-        let reset_pin = gpio.get(RESET_PIN)?.into_output_high();
-        info!("Busy @ PIN {BUSY_PIN}");
-        let mut busy_pin = gpio.get(BUSY_PIN)?.into_input_pullup();
-        busy_pin.set_interrupt(gpio::Trigger::Both, Some(Duration::from_millis(10)))?;
-        info!("Busy pin initial state: {}", busy_pin.read());
-
-        info!("Initializing SPI");
-        let cs_channel = match CS0_PIN {
-            0 => spi::SlaveSelect::Ss8,
-            1 => spi::SlaveSelect::Ss7,
-            _ => spi::SlaveSelect::Ss0,
-        };
-        let spi = Spi::new(spi::Bus::Spi0, cs_channel, 5000000, spi::Mode::Mode0)?;
+    /** Drive the panel `eeprom` describes over the [Bus] `connector` provides, timing every delay
+     * and timeout with `clock`. */
+    pub fn with_connector(
+        eeprom: epd::EPDType,
+        wiring: Wiring,
+        mut connector: impl Connector + 'static,
+        clock: impl Clock + 'static,
+    ) -> Result<Inky, InkyError> {
+        let bus = connector.connect(&wiring, wiring.spi_hz)?;
 
         info!("Finished initialization");
+        Ok(Self::with_hardware(
+            eeprom,
+            wiring,
+            HardwareState::Acquired(bus),
+            Box::new(connector),
+            Box::new(clock),
+        ))
+    }
+
+    /** The panel `eeprom` describes, without touching any hardware. Frames can be drawn and
+     * read back, but anything that talks to the controller fails with
+     * [InkyError::Detached]. */
+    pub fn detached(eeprom: epd::EPDType, wiring: Wiring) -> Inky {
+        info!("EPD Type: {eeprom:?}, not opening the panel");
+        Self::with_hardware(
+            eeprom,
+            wiring,
+            HardwareState::Released,
+            Box::new(Detached),
+            Box::new(SystemClock),
+        )
+    }
+
+    fn with_hardware(
+        eeprom: epd::EPDType,
+        wiring: Wiring,
+        hardware: HardwareState,
+        connector: Box<dyn Connector>,
+        clock: Box<dyn Clock>,
+    ) -> Inky {
         let width = eeprom.width as usize;
         let height = eeprom.height as usize;
-        Ok(Inky {
-            spi,
+        Inky {
+            hardware,
+            connector,
+            clock,
             // i2c,
             // gpio,
             eeprom,
-            cs_pin,
-            dc_pin,
-            reset_pin,
-            busy_pin,
-            buf: Array2::zeros((height, width)),
-        })
+            wiring,
+            spi_clock_hz: wiring.spi_hz,
+            spi_chunk_size: SPI_CHUNK_SIZE,
+            width,
+            height,
+            margins: Margins::default(),
+            buf: vec![0; width * height],
+            packed: Vec::with_capacity((width * height).div_ceil(2)),
+            low_footprint: false,
+            phase: Phase::Setup,
+            prepared: false,
+            busy_callback: false,
+            timings: Vec::new(),
+        }
     }
 
-    fn setup(&mut self) -> Result<(), InkyError> {
-        info!("Entering setup sequence");
+    /** Get the hardware handles, re-acquiring them if they were released. */
+    fn hardware(&mut self) -> Result<&mut dyn Bus, InkyError> {
+        let connector = self.connector.as_mut();
+        self.hardware
+            .acquired(connector, &self.wiring, self.spi_clock_hz)
+    }
 
-        self.reset_pin.set_low();
-        thread::sleep(Duration::from_millis(100));
-        self.reset_pin.set_high();
-        thread::sleep(Duration::from_millis(100));
-
-        self.reset_pin.set_low();
-        thread::sleep(Duration::from_millis(100));
-        self.reset_pin.set_high();
-
-        self.busy_wait(Duration::from_secs(10))?;
-
-        self.send_command(AC073TC1_CMDH, &[0x49, 0x55, 0x20, 0x08, 0x09, 0x18])?;
-        self.send_command(AC073TC1_PWR, &[0x3F, 0x00, 0x32, 0x2A, 0x0E, 0x2A])?;
-        self.send_command(AC073TC1_PSR, &[0x5F, 0x69])?;
-        self.send_command(AC073TC1_POFS, &[0x00, 0x54, 0x00, 0x44])?;
-        self.send_command(AC073TC1_BTST1, &[0x40, 0x1F, 0x1F, 0x2C])?;
-        self.send_command(AC073TC1_BTST2, &[0x6F, 0x1F, 0x16, 0x25])?;
-        self.send_command(AC073TC1_BTST3, &[0x6F, 0x1F, 0x1F, 0x22])?;
-        self.send_command(AC073TC1_IPC, &[0x00, 0x04])?;
-        self.send_command(AC073TC1_PLL, &[0x02])?;
-        self.send_command(AC073TC1_TSE, &[0x00])?;
-        self.send_command(AC073TC1_CDI, &[0x3F])?;
-        self.send_command(AC073TC1_TCON, &[0x02, 0x00])?;
-        self.send_command(AC073TC1_TRES, &[0x03, 0x20, 0x01, 0xE0])?;
-        self.send_command(AC073TC1_VDCS, &[0x1E])?;
-        self.send_command(AC073TC1_T_VDCS, &[0x00])?;
-        self.send_command(AC073TC1_AGID, &[0x00])?;
-        self.send_command(AC073TC1_PWS, &[0x2F])?;
-        self.send_command(AC073TC1_CCSET, &[0x00])?;
-        self.send_command(AC073TC1_TSSET, &[0x00])?;
+    /** Drop the SPI and GPIO handles so other programs can use them until the next refresh. */
+    fn release(&mut self) {
+        if let HardwareState::Acquired(_) = self.hardware {
+            if let Err(error) = self.clear_busy_callback() {
+                warn!("Could not stop busy notifications: {error}");
+            }
+            info!("Releasing SPI and GPIO");
+            self.hardware = HardwareState::Released;
+            self.prepared = false;
+        }
+    }
 
+    /** Pass on the outcome of talking to the controller, releasing the hardware first in low
+     * footprint mode. That happens after failures too, so the handles are never left claimed
+     * until the next refresh. */
+    fn finish<T>(&mut self, result: Result<T, InkyError>) -> Result<T, InkyError> {
+        if self.low_footprint {
+            self.release();
+        }
+        result
+    }
+
+    /** Run one step of talking to the controller, recording how long it took. */
+    fn run_phase(
+        &mut self,
+        phase: Phase,
+        step: impl FnOnce(&mut Inky) -> Result<(), InkyError>,
+    ) -> Result<(), InkyError> {
+        self.phase = phase;
+        let started = self.clock.now();
+        step(self)?;
+        self.timings.push((phase, self.clock.now() - started));
         Ok(())
     }
 
+    fn setup(&mut self) -> Result<(), InkyError> {
+        info!("Entering setup sequence");
+        self.timings.clear();
+        self.run_phase(Phase::Setup, |inky| {
+            if !inky.reset()? {
+                warn!("Busy Wait: Held high for {RESET_TIMEOUT:?}");
+            }
+
+            for (command, data) in INIT {
+                inky.send_command(*command, data)?;
+            }
+            Ok(())
+        })
+    }
+
+    /** Wait for the controller to become busy and then idle again.
+     *
+     * If the busy line isn't pulled low within `timeout`, this warns and returns `Ok`, taking
+     * the controller to have finished before the line was first read. A busy line that isn't
+     * wired up therefore goes unnoticed, apart from the whole `timeout` being spent. */
     fn busy_wait(&mut self, timeout: Duration) -> Result<(), InkyError> {
-        if self.busy_pin.is_high() {
-            warn!("Busy Wait: Held high. Waiting for {timeout:?}");
-            thread::sleep(timeout);
+        if !self.await_busy(timeout)? {
+            warn!("Busy Wait: Held high for {timeout:?}");
+            return Ok(());
         }
+        self.await_idle()
+    }
 
-        while self.busy_pin.is_low() {}
+    /** Poll until the busy line is pulled low. Returns false if that didn't happen within
+     * `timeout`. */
+    fn await_busy(&mut self, timeout: Duration) -> Result<bool, InkyError> {
+        let connector = self.connector.as_mut();
+        let hardware = self
+            .hardware
+            .acquired(connector, &self.wiring, self.spi_clock_hz)?;
+        let clock = &self.clock;
+        let deadline = clock.now() + timeout;
+        while !hardware.is_busy() {
+            if clock.now() >= deadline {
+                return Ok(false);
+            }
+            clock.sleep(BUSY_POLL);
+        }
+        return Ok(true);
+    }
 
+    /** Poll until the busy line is released, failing if that takes longer than
+     * [BUSY_LIMIT]. */
+    fn await_idle(&mut self) -> Result<(), InkyError> {
+        let phase = self.phase;
+        let connector = self.connector.as_mut();
+        let hardware = self
+            .hardware
+            .acquired(connector, &self.wiring, self.spi_clock_hz)?;
+        let clock = &self.clock;
+        let started = clock.now();
+        while hardware.is_busy() {
+            let waited = clock.now() - started;
+            if waited >= BUSY_LIMIT {
+                return Err(InkyError::Timeout { phase, waited });
+            }
+            clock.sleep(BUSY_POLL);
+        }
         return Ok(());
     }
 
     fn update(&mut self, buf: &[u8]) -> Result<(), InkyError> {
-        self.setup()?;
+        if !self.prepared {
+            self.setup()?;
+        }
+        self.prepared = false;
 
         info!("Transmitting image");
-        self.send_command(AC073TC1_DTM, buf)?;
+        self.run_phase(Phase::Transmit, |inky| inky.send_command(AC073TC1_DTM, buf))?;
+        if shutdown::requested() {
+            return Err(InkyError::Interrupted(Phase::PowerOn));
+        }
 
-        self.send_command(AC073TC1_PON, &[])?;
-        self.busy_wait(Duration::from_millis(400))?;
+        self.run_phase(Phase::PowerOn, |inky| {
+            inky.send_command(AC073TC1_PON, &[])?;
+            inky.busy_wait(POWER_ON_TIMEOUT)
+        })?;
+        // The refresh takes a while, so don't start one when asked to stop with the panel
+        // powered. It must not stay on with high voltage on the gates
+        if shutdown::requested() {
+            warn!("Powering off without refreshing");
+            self.power_off(POWER_OFF_TIMEOUT)?;
+            return Err(InkyError::Interrupted(Phase::Refresh));
+        }
 
-        self.send_command(AC073TC1_DRF, &[0x00])?;
-        self.busy_wait(Duration::from_secs(45))?;
+        self.run_phase(Phase::Refresh, |inky| {
+            inky.send_command(AC073TC1_DRF, &[0x00])?;
+            inky.busy_wait(REFRESH_TIMEOUT)
+        })?;
 
-        self.send_command(AC073TC1_POF, &[0x00])?;
-        self.busy_wait(Duration::from_millis(400))?;
+        self.power_off(POWER_OFF_TIMEOUT)?;
 
-        info!("Update complete");
+        let ms = |phase| -> u64 {
+            let timings = self.timings.iter().filter(|(p, _)| *p == phase);
+            timings
+                .map(|(_, duration)| duration.as_millis() as u64)
+                .sum()
+        };
+        info!(
+            setup_ms = ms(Phase::Setup),
+            transmit_ms = ms(Phase::Transmit),
+            power_on_ms = ms(Phase::PowerOn),
+            refresh_ms = ms(Phase::Refresh),
+            power_off_ms = ms(Phase::PowerOff);
+            "Update complete"
+        );
         return Ok(());
     }
 
-    fn spi_write(&mut self, dc: bool, values: &[u8]) -> Result<(), InkyError> {
-        self.cs_pin.set_low();
-        if dc {
-            self.dc_pin.set_high();
-        } else {
-            self.dc_pin.set_low();
-        }
+    fn power_off(&mut self, timeout: Duration) -> Result<(), InkyError> {
+        self.run_phase(Phase::PowerOff, |inky| {
+            inky.send_command(AC073TC1_POF, &[0x00])?;
+            inky.busy_wait(timeout)
+        })
+    }
+
+    fn spi_write(&mut self, command: u8, dc: bool, values: &[u8]) -> Result<(), InkyError> {
+        let phase = self.phase;
+        let chunk_size = self.spi_chunk_size;
+        let hardware = self.hardware()?;
+        hardware.set_chip_select(false);
+        hardware.set_data_command(dc);
 
         let mut written = 0;
 
         while written != values.len() {
-            written += self
-                .spi
-                .write(&values[written..min(written + 64, values.len())])?;
+            let chunk = &values[written..min(written + chunk_size, values.len())];
+            written += hardware
+                .write(chunk)
+                .map_err(|source| InkyError::Transfer {
+                    command,
+                    phase,
+                    written,
+                    total: values.len(),
+                    source,
+                })?;
         }
-        self.cs_pin.set_high();
+        hardware.set_chip_select(true);
         Ok(())
     }
 
-    fn send_data(&mut self, data: &[u8]) -> Result<(), InkyError> {
-        self.spi_write(true, data)?;
+    fn send_data(&mut self, command: u8, data: &[u8]) -> Result<(), InkyError> {
+        self.spi_write(command, true, data)?;
         Ok(())
     }
 
     fn send_command(&mut self, command: u8, data: &[u8]) -> Result<(), InkyError> {
-        self.spi_write(false, &[command])?;
-        self.send_data(data)
+        self.spi_write(command, false, &[command])?;
+        self.send_data(command, data)
+    }
+
+    /** Read the EEPROM at `eeprom_address` (normally [epd::EEP_ADDRESS]) and claim the SPI and
+     * GPIO lines given by `wiring`. The controller itself is reset and initialized by
+     * [Inky::prepare] or [Inky::show]. */
+    pub fn new(eeprom_address: u16, wiring: Wiring) -> Result<Inky, InkyError> {
+        let eeprom = Self::load_eeprom(eeprom_address, &wiring, None)?;
+        Self::initialize_inky(eeprom, wiring)
+    }
+
+    /** Like [Inky::new], but reuse the EEPROM contents stored at `path` by an earlier run
+     * instead of reading them over I2C. The cache is refreshed every few days. */
+    pub fn with_eeprom_cache(
+        eeprom_address: u16,
+        wiring: Wiring,
+        path: &Path,
+    ) -> Result<Inky, InkyError> {
+        let eeprom = Self::load_eeprom(eeprom_address, &wiring, Some(path))?;
+        Self::initialize_inky(eeprom, wiring)
+    }
+
+    /** Like [Inky::new], but take the panel to be the one `eeprom` describes without reading the
+     * EEPROM, for boards that don't have one. */
+    pub fn without_eeprom(eeprom: epd::EPDType, wiring: Wiring) -> Result<Inky, InkyError> {
+        info!("EPD Type: {eeprom:?}, not read from the EEPROM");
+        Self::initialize_inky(eeprom, wiring)
     }
 
-    pub fn new() -> Result<Inky, InkyError> {
-        let mut inky = Self::initialize_inky()?;
-        inky.setup()?;
-        Ok(inky)
+    /** Pulse the reset line and wait for the controller to come out of reset. Returns whether
+     * it signaled busy while doing so, which a connected controller always does. */
+    pub fn reset(&mut self) -> Result<bool, InkyError> {
+        let connector = self.connector.as_mut();
+        let hardware = self
+            .hardware
+            .acquired(connector, &self.wiring, self.spi_clock_hz)?;
+        let clock = &self.clock;
+
+        hardware.set_reset(false);
+        clock.sleep(RESET_PULSE);
+        hardware.set_reset(true);
+        clock.sleep(RESET_PULSE);
+
+        hardware.set_reset(false);
+        clock.sleep(RESET_PULSE);
+        hardware.set_reset(true);
+
+        if !self.await_busy(RESET_TIMEOUT)? {
+            return Ok(false);
+        }
+        self.await_idle()?;
+        return Ok(true);
+    }
+
+    /** Reset and initialize the controller ahead of the next [Inky::show], so that this can
+     * happen while the image is still being rendered. */
+    pub fn prepare(&mut self) -> Result<(), InkyError> {
+        if let Err(error) = self.setup() {
+            return self.finish(Err(error));
+        }
+        self.prepared = true;
+        Ok(())
     }
 
     pub fn show(&mut self) -> Result<(), InkyError> {
-        let mut internal_buf: Array1<u8> =
-            Array1::zeros(self.eeprom.width as usize * self.eeprom.height as usize / 2);
-        for (ix, px) in self.buf.iter().enumerate() {
-            let actual_px = if *px == 7 { 1 } else { *px } & 0xF;
-            if ix % 2 == 0 {
-                internal_buf[ix / 2] |= actual_px << 4;
-            } else {
-                internal_buf[ix / 2] |= actual_px;
+        let mut packed = std::mem::take(&mut self.packed);
+        pack_pixels(&self.buf, &mut packed);
+        let result = self.update(&packed);
+        self.packed = packed;
+        return self.finish(result);
+    }
+
+    /** Like [Inky::show], for a whole frame that is already packed as [pack_pixels] does, such
+     * as one saved with `--save-raw`. The pixel buffer is left as it is. */
+    pub fn show_packed(&mut self, packed: &[u8]) -> Result<(), InkyError> {
+        debug_assert_eq!(
+            packed.len(),
+            raw::packed_len(self.width, self.height),
+            "the packed frame is not the size of the panel"
+        );
+        let result = self.update(packed);
+        return self.finish(result);
+    }
+
+    /** Like [Inky::show], but stop once the frame is transmitted, without powering on or
+     * refreshing the panel, which keeps what it showed before. This exercises the whole command
+     * and data path in a few seconds for debugging the wiring. Returns the number of bytes of
+     * image data sent. */
+    pub fn transmit(&mut self) -> Result<usize, InkyError> {
+        if !self.prepared {
+            if let Err(error) = self.setup() {
+                return self.finish(Err(error));
             }
         }
-        self.update(internal_buf.as_slice().unwrap())?;
+        self.prepared = false;
 
-        return Ok(());
+        let mut packed = std::mem::take(&mut self.packed);
+        pack_pixels(&self.buf, &mut packed);
+        info!("Transmitting image without refreshing");
+        let result = self.run_phase(Phase::Transmit, |inky| {
+            inky.send_command(AC073TC1_DTM, &packed)
+        });
+        let bytes = packed.len();
+        self.packed = packed;
+        return self.finish(result.map(|()| bytes));
+    }
+
+    /** Call `callback` with `true` when the panel becomes busy and `false` once it is idle again.
+     *
+     * Debounced events are delivered in order from rppal's interrupt thread. [Inky::busy_wait]
+     * reads the pin level directly rather than consuming these events, so both can be used
+     * at the same time. Fails if the GPIO backend can't provide async interrupts.
+     * In low footprint mode the callback is unregistered when the hardware is released after
+     * each refresh. */
+    pub fn on_busy_change(
+        &mut self,
+        callback: impl FnMut(bool) + Send + 'static,
+    ) -> Result<(), InkyError> {
+        let result = self.hardware()?.set_busy_callback(Box::new(callback));
+        if let Err(error) = &result {
+            warn!("Busy notifications unavailable: {error}");
+        }
+        self.busy_callback = result.is_ok();
+        result.map_err(Into::into)
+    }
+
+    /** Stop the notifications registered with [Inky::on_busy_change]. */
+    pub fn clear_busy_callback(&mut self) -> Result<(), InkyError> {
+        if !self.busy_callback {
+            return Ok(());
+        }
+        self.busy_callback = false;
+        match &mut self.hardware {
+            HardwareState::Acquired(hardware) => hardware.clear_busy_callback()?,
+            // The callback went with the GPIO handles
+            HardwareState::Released => {}
+        }
+        Ok(())
+    }
+
+    /** Whether the controller is currently busy. Always false while the hardware is released,
+     * since that only happens once a refresh has fully completed. */
+    pub fn is_busy(&self) -> bool {
+        match &self.hardware {
+            HardwareState::Acquired(hardware) => hardware.is_busy(),
+            HardwareState::Released => false,
+        }
+    }
+
+    /** Use another SPI clock and number of bytes per write, e.g. as suggested by `bench-spi`. */
+    pub fn set_spi(&mut self, clock_hz: u32, chunk_size: usize) -> Result<(), InkyError> {
+        if let HardwareState::Acquired(hardware) = &mut self.hardware {
+            hardware.set_clock_speed(clock_hz)?;
+        }
+        self.spi_clock_hz = clock_hz;
+        self.spi_chunk_size = chunk_size;
+        Ok(())
+    }
+
+    /** Release SPI and GPIO after every refresh and lazily re-acquire them for the next one. */
+    pub fn set_low_footprint(&mut self, enabled: bool) {
+        self.low_footprint = enabled;
+    }
+
+    /** The GPIO pins the panel is wired to. */
+    pub fn pins(wiring: &Wiring) -> PinInfo {
+        PinInfo {
+            reset: wiring.reset_pin,
+            busy: wiring.busy_pin,
+            data_command: wiring.dc_pin,
+            chip_select: wiring.cs_pin,
+        }
+    }
+
+    /** Collect everything worth pasting into a bug report about this panel. */
+    pub fn version_info(&self) -> VersionInfo {
+        VersionInfo {
+            crate_version: CRATE_VERSION,
+            driver: DRIVER,
+            eeprom: self.eeprom.clone(),
+            pins: Self::pins(&self.wiring),
+            spi: SpiInfo {
+                bus: format!("{}", self.wiring.spi_bus()),
+                clock_hz: self.spi_clock_hz,
+                chunk_size: self.spi_chunk_size,
+                mode: format!("{}", spi::Mode::Mode0),
+            },
+            controller_revision: None,
+        }
+    }
+
+    /** How long each phase of the last setup and refresh took. */
+    pub fn timings(&self) -> &[(Phase, Duration)] {
+        &self.timings
+    }
+
+    /** Send an arbitrary command to the controller, bypassing the driver's sequencing.
+     *
+     * This exists for bring-up of unusual panel batches only. Writing the wrong values
+     * to the power, booster or VCOM registers can permanently damage the panel. */
+    pub fn send_raw_command(&mut self, command: u8, data: &[u8]) -> Result<(), InkyError> {
+        if self.is_busy() {
+            return Err(InkyError::Busy);
+        }
+
+        self.setup()?;
+        warn!("Sending raw command 0x{command:02X} with data {data:02X?}");
+        self.phase = Phase::Raw;
+        self.send_command(command, data)
+    }
+
+    /** Keep the image inside `margins`, filling the bands around it with palette index
+     * `color`. Coordinates given to [Inky::set_pixel] are then relative to the inner area,
+     * whose size [Inky::dimensions] reports, while the whole panel is still transmitted. */
+    pub fn set_margins(&mut self, margins: Margins, color: u8) -> Result<(), InkyError> {
+        if margins.inner(self.width, self.height).is_none() {
+            return Err(InkyError::InvalidMargins {
+                margins,
+                width: self.width,
+                height: self.height,
+            });
+        }
+        self.margins = margins;
+        self.buf.fill(color);
+        Ok(())
+    }
+
+    /** Width and height of the area images are drawn in: the panel minus its margins. */
+    pub fn dimensions(&self) -> (usize, usize) {
+        self.margins.inner(self.width, self.height).unwrap()
+    }
+
+    /** Width, height and palette index of every pixel of the whole panel, margins included,
+     * as the next [Inky::show] would display them. */
+    pub fn frame(&self) -> (usize, usize, Vec<u8>) {
+        let pixels = self.buf.iter().map(|&px| displayed_index(px)).collect();
+        (self.width, self.height, pixels)
+    }
+
+    /** Send `data` as image data without powering on or refreshing the panel. The controller
+     * only stores it, and the next [Inky::show] overwrites it, which makes this a safe way to
+     * exercise the SPI link. Call [Inky::prepare] first. */
+    pub fn transmit_only(&mut self, data: &[u8]) -> Result<(), InkyError> {
+        if self.is_busy() {
+            return Err(InkyError::Busy);
+        }
+
+        self.phase = Phase::Transmit;
+        self.send_command(AC073TC1_DTM, data)
+    }
+
+    /** Put the controller into deep sleep, where it draws the least current and ignores
+     * everything but the reset line. The panel has to be powered off, as it is after
+     * [Inky::show]. The reset at the start of the next refresh wakes it up again. */
+    pub fn sleep(&mut self) -> Result<(), InkyError> {
+        info!("Entering deep sleep");
+        let result = self.run_phase(Phase::Sleep, |inky| {
+            inky.send_command(AC073TC1_DSLP, &[DSLP_CHECK_CODE])
+        });
+        if result.is_err() {
+            return self.finish(result);
+        }
+        info!("The controller is in deep sleep until the next reset");
+        return self.finish(Ok(()));
     }
 
+    /** Set every pixel of the panel, margins included, to palette index `color`. */
+    pub fn fill(&mut self, color: u8) {
+        self.buf.fill(color);
+    }
+
+    /** Set the pixel at `(x, y)` of the area inside the margins. */
     pub fn set_pixel(&mut self, x: usize, y: usize, v: u8) {
-        self.buf[[y, x]] = v;
+        debug_assert!(
+            x < self.dimensions().0 && y < self.dimensions().1,
+            "({x}, {y}) is outside the margins"
+        );
+        let (x, y) = (x + self.margins.left, y + self.margins.top);
+        self.buf[pixel_index(self.width, x, y)] = v;
+    }
+}
+
+/** Position of the pixel at `(x, y)` in a row-major buffer. */
+#[inline]
+fn pixel_index(width: usize, x: usize, y: usize) -> usize {
+    y * width + x
+}
+
+/** The color the panel shows for a palette index: transparent pixels are shown as white. */
+#[inline]
+pub fn displayed_index(px: u8) -> u8 {
+    if px == 7 {
+        1
+    } else {
+        px
+    }
+}
+
+/** Pack palette indices two to a byte, the first pixel in the high nibble. */
+#[inline]
+pub fn pack_pixels(pixels: &[u8], packed: &mut Vec<u8>) {
+    let nibble = |px: u8| displayed_index(px) & 0xF;
+    packed.clear();
+    packed.extend(pixels.chunks(2).map(|pair| match *pair {
+        [first, second] => nibble(first) << 4 | nibble(second),
+        [first] => nibble(first) << 4,
+        _ => unreachable!(),
+    }));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+    use crate::epd::mock::{self, Event, FakeClock, MockPanel};
+
+    const FRAME_BYTES: usize = 800 * 480 / 2;
+
+    #[test]
+    fn reset_pulses_twice_and_waits_for_busy() {
+        let clock = FakeClock::new();
+        let panel = MockPanel::new(&clock).busy_after_reset(Duration::from_millis(20));
+        let mut inky = mock::inky(&panel, &clock);
+
+        assert!(inky.reset().unwrap());
+        let pulse = RESET_PULSE;
+        assert_eq!(
+            panel.timed_events(),
+            vec![
+                (Duration::ZERO, Event::Reset(false)),
+                (pulse, Event::Reset(true)),
+                (pulse * 2, Event::Reset(false)),
+                (pulse * 3, Event::Reset(true)),
+            ]
+        );
+        assert_eq!(clock.sleeps()[..3], [pulse; 3]);
+        assert!(clock.sleeps()[3..].iter().all(|&sleep| sleep == BUSY_POLL));
+        assert_eq!(clock.elapsed(), pulse * 3 + Duration::from_millis(20));
+    }
+
+    #[test]
+    fn reset_gives_up_on_a_silent_busy_line() {
+        let clock = FakeClock::new();
+        let panel = MockPanel::new(&clock);
+        let mut inky = mock::inky(&panel, &clock);
+
+        assert!(!inky.reset().unwrap());
+        assert_eq!(clock.elapsed(), RESET_PULSE * 3 + RESET_TIMEOUT);
+    }
+
+    #[test]
+    fn busy_wait_fails_when_the_controller_stays_busy() {
+        let clock = FakeClock::new();
+        let panel =
+            MockPanel::responsive(&clock).busy_after(AC073TC1_DRF, Duration::from_secs(3600));
+        let mut inky = mock::inky(&panel, &clock);
+
+        match inky.show() {
+            Err(InkyError::Timeout { phase, waited }) => {
+                assert_eq!(phase, Phase::Refresh);
+                assert_eq!(waited, BUSY_LIMIT);
+            }
+            other => panic!("expected a timeout, got {other:?}"),
+        }
+        assert_eq!(panel.command_bytes().last(), Some(&AC073TC1_DRF));
+    }
+
+    #[test]
+    fn busy_wait_spends_the_timeout_when_busy_never_asserts() {
+        let clock = FakeClock::new();
+        let panel = MockPanel::new(&clock);
+        let mut inky = mock::inky(&panel, &clock);
+
+        inky.show().unwrap();
+        let waits = RESET_PULSE * 3
+            + RESET_TIMEOUT
+            + POWER_ON_TIMEOUT
+            + REFRESH_TIMEOUT
+            + POWER_OFF_TIMEOUT;
+        assert_eq!(clock.elapsed(), waits);
+    }
+
+    #[test]
+    fn update_sends_the_init_sequence_then_refreshes() {
+        let clock = FakeClock::new();
+        let panel = MockPanel::responsive(&clock);
+        let mut inky = mock::inky(&panel, &clock);
+        inky.fill(2);
+
+        inky.show().unwrap();
+        let mut expected: Vec<(u8, Vec<u8>)> = INIT
+            .iter()
+            .map(|(command, data)| (*command, data.to_vec()))
+            .collect();
+        expected.extend([
+            (AC073TC1_DTM, vec![0x22; FRAME_BYTES]),
+            (AC073TC1_PON, vec![]),
+            (AC073TC1_DRF, vec![0x00]),
+            (AC073TC1_POF, vec![0x00]),
+        ]);
+        assert_eq!(panel.commands(), expected);
+        assert!(panel.writes().iter().all(|&size| size <= SPI_CHUNK_SIZE));
+
+        let phases: Vec<Phase> = inky.timings().iter().map(|(phase, _)| *phase).collect();
+        assert_eq!(
+            phases,
+            [
+                Phase::Setup,
+                Phase::Transmit,
+                Phase::PowerOn,
+                Phase::Refresh,
+                Phase::PowerOff
+            ]
+        );
+        let refresh = inky.timings()[3].1;
+        assert_eq!(refresh, Duration::from_secs(30));
+    }
+
+    #[test]
+    fn update_takes_virtual_time_only() {
+        let clock = FakeClock::new();
+        let panel = MockPanel::responsive(&clock);
+        let mut inky = mock::inky(&panel, &clock);
+
+        let started = std::time::Instant::now();
+        inky.show().unwrap();
+        assert!(clock.elapsed() > Duration::from_secs(30));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    /** The commands of a setup, for comparing with [MockPanel::command_bytes]. */
+    fn init_commands() -> Vec<u8> {
+        INIT.iter().map(|(command, _)| *command).collect()
+    }
+
+    #[test]
+    fn prepare_on_another_thread_overlaps_rendering() {
+        let clock = FakeClock::new();
+        let panel = MockPanel::responsive(&clock);
+        let mut inky = mock::inky(&panel, &clock);
+        let (width, height) = inky.dimensions();
+
+        let rendered: Vec<u8> = std::thread::scope(|scope| {
+            let setup = scope.spawn(|| inky.prepare());
+            let rendered = (0..width * height).map(|i| (i % 7) as u8).collect();
+            setup.join().unwrap().unwrap();
+            rendered
+        });
+        // Preparing resets and initializes the controller, but sends no image
+        let events = panel.events();
+        assert_eq!(
+            events[..4],
+            [
+                Event::Reset(false),
+                Event::Reset(true),
+                Event::Reset(false),
+                Event::Reset(true)
+            ]
+        );
+        assert_eq!(panel.command_bytes(), init_commands());
+
+        for (ix, px) in rendered.iter().enumerate() {
+            inky.set_pixel(ix % width, ix / width, *px);
+        }
+        inky.show().unwrap();
+        let mut expected = init_commands();
+        expected.extend([AC073TC1_DTM, AC073TC1_PON, AC073TC1_DRF, AC073TC1_POF]);
+        assert_eq!(panel.command_bytes(), expected);
+        let resets = panel
+            .events()
+            .iter()
+            .filter(|e| matches!(e, Event::Reset(_)))
+            .count();
+        assert_eq!(resets, 4, "show set the controller up again");
+    }
+
+    #[test]
+    fn show_sets_up_again_after_a_prepared_refresh() {
+        let clock = FakeClock::new();
+        let panel = MockPanel::responsive(&clock);
+        let mut inky = mock::inky(&panel, &clock);
+
+        inky.prepare().unwrap();
+        inky.show().unwrap();
+        inky.show().unwrap();
+        let init = init_commands();
+        let refresh = [AC073TC1_DTM, AC073TC1_PON, AC073TC1_DRF, AC073TC1_POF];
+        let expected: Vec<u8> = [&init[..], &refresh, &init, &refresh].concat();
+        assert_eq!(panel.command_bytes(), expected);
+    }
+
+    #[test]
+    fn setup_sends_the_exact_init_bytes() {
+        let clock = FakeClock::new();
+        let panel = MockPanel::responsive(&clock);
+        let mut inky = mock::inky(&panel, &clock);
+
+        inky.prepare().unwrap();
+        let expected: Vec<(u8, Vec<u8>)> = vec![
+            (0xAA, vec![0x49, 0x55, 0x20, 0x08, 0x09, 0x18]),
+            (0x01, vec![0x3F, 0x00, 0x32, 0x2A, 0x0E, 0x2A]),
+            (0x00, vec![0x5F, 0x69]),
+            (0x03, vec![0x00, 0x54, 0x00, 0x44]),
+            (0x05, vec![0x40, 0x1F, 0x1F, 0x2C]),
+            (0x06, vec![0x6F, 0x1F, 0x16, 0x25]),
+            (0x08, vec![0x6F, 0x1F, 0x1F, 0x22]),
+            (0x13, vec![0x00, 0x04]),
+            (0x30, vec![0x02]),
+            (0x41, vec![0x00]),
+            (0x50, vec![0x3F]),
+            (0x60, vec![0x02, 0x00]),
+            (0x61, vec![0x03, 0x20, 0x01, 0xE0]),
+            (0x82, vec![0x1E]),
+            (0x84, vec![0x00]),
+            (0x86, vec![0x00]),
+            (0xE3, vec![0x2F]),
+            (0xE0, vec![0x00]),
+            (0xE6, vec![0x00]),
+        ];
+        assert_eq!(panel.commands(), expected);
+    }
+
+    #[test]
+    fn raw_commands_are_sent_after_setup() {
+        let clock = FakeClock::new();
+        let panel = MockPanel::responsive(&clock);
+        let mut inky = mock::inky(&panel, &clock);
+
+        inky.send_raw_command(0x82, &[0x1E]).unwrap();
+        let mut expected = init_commands();
+        expected.push(0x82);
+        assert_eq!(panel.command_bytes(), expected);
+        assert_eq!(panel.commands().last(), Some(&(0x82, vec![0x1E])));
+    }
+
+    #[test]
+    fn raw_commands_are_refused_while_busy() {
+        let clock = FakeClock::new();
+        let panel = MockPanel::responsive(&clock).busy_after(0xF0, Duration::from_secs(1));
+        let mut inky = mock::inky(&panel, &clock);
+
+        inky.send_raw_command(0xF0, &[]).unwrap();
+        assert!(inky.is_busy());
+        let sent = panel.commands().len();
+        assert!(matches!(
+            inky.send_raw_command(0x82, &[0x1E]),
+            Err(InkyError::Busy)
+        ));
+        assert_eq!(
+            panel.commands().len(),
+            sent,
+            "something was sent while busy"
+        );
+    }
+
+    /** Register a callback on `inky` that records what it is called with. */
+    fn record_busy(inky: &mut Inky) -> Arc<Mutex<Vec<bool>>> {
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let sink = seen.clone();
+        inky.on_busy_change(move |busy| sink.lock().unwrap().push(busy))
+            .unwrap();
+        seen
+    }
+
+    #[test]
+    fn busy_changes_are_delivered_in_order() {
+        let clock = FakeClock::new();
+        let panel = MockPanel::responsive(&clock);
+        let mut inky = mock::inky(&panel, &clock);
+        let seen = record_busy(&mut inky);
+
+        inky.show().unwrap();
+        // Two resets, power on, refresh and power off
+        assert_eq!(*seen.lock().unwrap(), [true, false].repeat(5));
+    }
+
+    #[test]
+    fn clearing_the_busy_callback_stops_delivery() {
+        let clock = FakeClock::new();
+        let panel = MockPanel::responsive(&clock);
+        let mut inky = mock::inky(&panel, &clock);
+        let seen = record_busy(&mut inky);
+
+        inky.reset().unwrap();
+        let delivered = seen.lock().unwrap().len();
+        inky.clear_busy_callback().unwrap();
+        assert!(!panel.has_callback());
+        inky.show().unwrap();
+        assert_eq!(seen.lock().unwrap().len(), delivered);
+    }
+
+    #[test]
+    fn releasing_the_hardware_clears_the_busy_callback() {
+        let clock = FakeClock::new();
+        let panel = MockPanel::responsive(&clock);
+        let mut inky = mock::inky(&panel, &clock);
+        inky.set_low_footprint(true);
+        let seen = record_busy(&mut inky);
+
+        inky.show().unwrap();
+        assert!(!panel.has_callback());
+        assert_eq!(panel.callbacks_cleared(), 1);
+        assert!(!panel.is_connected());
+        let delivered = seen.lock().unwrap().len();
+        inky.show().unwrap();
+        assert_eq!(seen.lock().unwrap().len(), delivered);
+        assert_eq!(panel.callbacks_cleared(), 1);
+    }
+
+    #[test]
+    fn busy_callbacks_fail_without_async_interrupts() {
+        let clock = FakeClock::new();
+        let panel = MockPanel::responsive(&clock).no_interrupts();
+        let mut inky = mock::inky(&panel, &clock);
+
+        let result = inky.on_busy_change(|_| {});
+        assert!(matches!(result, Err(InkyError::GpioError(_))));
+        inky.show().unwrap();
+        inky.clear_busy_callback().unwrap();
+    }
+
+    #[test]
+    fn low_footprint_releases_and_reacquires_the_bus() {
+        let clock = FakeClock::new();
+        let panel = MockPanel::responsive(&clock);
+        let mut inky = mock::inky(&panel, &clock);
+        inky.set_low_footprint(true);
+        assert!(panel.is_connected());
+
+        inky.show().unwrap();
+        assert!(!panel.is_connected());
+        assert!(!inky.is_busy());
+
+        inky.prepare().unwrap();
+        assert!(panel.is_connected());
+        assert_eq!(panel.connects(), 2);
+        inky.show().unwrap();
+        assert!(!panel.is_connected());
+        assert_eq!(panel.connects(), 2);
+    }
+
+    #[test]
+    fn the_bus_is_kept_without_low_footprint() {
+        let clock = FakeClock::new();
+        let panel = MockPanel::responsive(&clock);
+        let mut inky = mock::inky(&panel, &clock);
+
+        inky.show().unwrap();
+        inky.show().unwrap();
+        assert!(panel.is_connected());
+        assert_eq!(panel.connects(), 1);
+    }
+
+    #[test]
+    fn low_footprint_releases_after_failures() {
+        let clock = FakeClock::new();
+        let panel =
+            MockPanel::responsive(&clock).busy_after(AC073TC1_DRF, Duration::from_secs(3600));
+        let mut inky = mock::inky(&panel, &clock);
+        inky.set_low_footprint(true);
+
+        assert!(matches!(inky.show(), Err(InkyError::Timeout { .. })));
+        assert!(!panel.is_connected());
+        assert!(!inky.is_busy(), "a released panel reads as idle");
+
+        let failing = MockPanel::responsive(&clock).fail_data(AC073TC1_DTM, 0);
+        let mut inky = mock::inky(&failing, &clock);
+        inky.set_low_footprint(true);
+        assert!(matches!(inky.transmit(), Err(InkyError::Transfer { .. })));
+        assert!(!failing.is_connected());
+    }
+
+    #[test]
+    fn reacquiring_fails_like_opening() {
+        let clock = FakeClock::new();
+        let panel = MockPanel::responsive(&clock);
+        let mut inky = mock::inky(&panel, &clock);
+        inky.set_low_footprint(true);
+        inky.show().unwrap();
+
+        panel.refuse_connections(true);
+        assert!(matches!(inky.show(), Err(InkyError::GpioError(_))));
+        assert!(!panel.is_connected());
+
+        panel.refuse_connections(false);
+        panel.clear();
+        inky.show().unwrap();
+        let mut expected = init_commands();
+        expected.extend([AC073TC1_DTM, AC073TC1_PON, AC073TC1_DRF, AC073TC1_POF]);
+        assert_eq!(panel.command_bytes(), expected);
+    }
+
+    #[test]
+    fn a_release_forgets_a_prepared_setup() {
+        let clock = FakeClock::new();
+        let panel = MockPanel::responsive(&clock);
+        let mut inky = mock::inky(&panel, &clock);
+        inky.set_low_footprint(true);
+
+        inky.prepare().unwrap();
+        inky.sleep().unwrap();
+        panel.clear();
+        inky.show().unwrap();
+        assert_eq!(
+            panel.events()[0],
+            Event::Reset(false),
+            "the controller wasn't woken up"
+        );
+    }
+
+    /** The fields of the [InkyError::Transfer] `result` failed with. */
+    fn transfer_error(result: Result<(), InkyError>) -> (u8, Phase, usize, usize) {
+        match result {
+            Err(InkyError::Transfer {
+                command,
+                phase,
+                written,
+                total,
+                ..
+            }) => (command, phase, written, total),
+            other => panic!("expected a transfer error, got {other:?}"),
+        }
+    }
+
+    #[test]
+    fn transfer_errors_report_how_far_the_image_got() {
+        let clock = FakeClock::new();
+        let panel = MockPanel::responsive(&clock).fail_data(AC073TC1_DTM, 100);
+        let mut inky = mock::inky(&panel, &clock);
+
+        let (command, phase, written, total) = transfer_error(inky.show());
+        assert_eq!((command, phase), (AC073TC1_DTM, Phase::Transmit));
+        // Whole chunks up to the first one starting past 100 bytes
+        assert_eq!(written, 2 * SPI_CHUNK_SIZE);
+        assert_eq!(total, FRAME_BYTES);
+        assert_eq!(panel.command_bytes().last(), Some(&AC073TC1_DTM));
+    }
+
+    #[test]
+    fn transfer_errors_follow_the_chunk_size() {
+        let clock = FakeClock::new();
+        let panel = MockPanel::responsive(&clock).fail_data(AC073TC1_DTM, 1000);
+        let mut inky = mock::inky(&panel, &clock);
+        inky.set_spi(8_000_000, 4096).unwrap();
+
+        let (_, _, written, total) = transfer_error(inky.show());
+        assert_eq!((written, total), (4096, FRAME_BYTES));
+        assert_eq!(panel.clock_hz(), 8_000_000);
+    }
+
+    #[test]
+    fn transfer_errors_during_setup_name_the_command() {
+        let clock = FakeClock::new();
+        let panel = MockPanel::responsive(&clock).fail_data(AC073TC1_PWR, 0);
+        let mut inky = mock::inky(&panel, &clock);
+
+        let error = inky.prepare().unwrap_err();
+        let message = error.to_string();
+        assert!(
+            message.starts_with("SPI error during setup (command 0x01, 0 of 6 bytes written): "),
+            "{message}"
+        );
+        assert_eq!(
+            transfer_error(Err(error)),
+            (AC073TC1_PWR, Phase::Setup, 0, 6)
+        );
+    }
+
+    #[test]
+    fn transfer_errors_of_raw_commands_and_sleep() {
+        let clock = FakeClock::new();
+        let panel = MockPanel::responsive(&clock).fail_data(AC073TC1_DSLP, 0);
+        let mut inky = mock::inky(&panel, &clock);
+        assert_eq!(
+            transfer_error(inky.sleep()),
+            (AC073TC1_DSLP, Phase::Sleep, 0, 1)
+        );
+
+        let panel = MockPanel::responsive(&clock).fail_data(0xF0, 0);
+        let mut inky = mock::inky(&panel, &clock);
+        assert_eq!(
+            transfer_error(inky.send_raw_command(0xF0, &[0x1E, 0x00])),
+            (0xF0, Phase::Raw, 0, 2)
+        );
+    }
+
+    #[test]
+    fn detached_draws_frames_without_hardware() {
+        let eeprom = epd::Model::Ac073tc1.epd_type(Some(4), Some(2));
+        let mut inky = Inky::detached(eeprom, Wiring::default());
+        inky.set_margins("0,0,0,1".parse().unwrap(), 1).unwrap();
+        assert_eq!(inky.dimensions(), (3, 2));
+        inky.set_pixel(2, 1, 6);
+        let (width, height, frame) = inky.frame();
+        assert_eq!((width, height), (4, 2));
+        assert_eq!(frame, [1, 1, 1, 1, 1, 1, 1, 6].map(displayed_index));
+
+        assert!(matches!(inky.prepare(), Err(InkyError::Detached)));
+        assert!(matches!(inky.reset(), Err(InkyError::Detached)));
+        assert!(matches!(inky.show(), Err(InkyError::Detached)));
+        inky.set_spi(8_000_000, 64).unwrap();
+    }
+
+    #[test]
+    fn pixels_are_row_major() {
+        assert_eq!(pixel_index(4, 0, 0), 0);
+        assert_eq!(pixel_index(4, 3, 0), 3);
+        assert_eq!(pixel_index(4, 0, 1), 4);
+        assert_eq!(pixel_index(4, 2, 1), 6);
+        assert_eq!(pixel_index(800, 799, 479), 800 * 480 - 1);
+    }
+
+    #[test]
+    fn set_pixel_fills_rows_in_order() {
+        let eeprom = epd::Model::Ac073tc1.epd_type(Some(3), Some(2));
+        let mut inky = Inky::detached(eeprom, Wiring::default());
+        for y in 0..2 {
+            for x in 0..3 {
+                inky.set_pixel(x, y, (y * 3 + x) as u8);
+            }
+        }
+        assert_eq!(inky.frame().2, [0, 1, 2, 3, 4, 5]);
+
+        // Inside margins, the rows are offset but keep their order
+        let eeprom = epd::Model::Ac073tc1.epd_type(Some(4), Some(3));
+        let mut inky = Inky::detached(eeprom, Wiring::default());
+        inky.set_margins("1,0,0,1".parse().unwrap(), 1).unwrap();
+        inky.set_pixel(0, 0, 2);
+        inky.set_pixel(2, 0, 3);
+        inky.set_pixel(0, 1, 4);
+        let frame = inky.frame().2;
+        let rows: Vec<_> = frame.chunks(4).collect();
+        assert_eq!(rows, [[1, 1, 1, 1], [1, 2, 1, 3], [1, 4, 1, 1]]);
+    }
+
+    #[test]
+    fn pixels_are_packed_two_to_a_byte_first_high() {
+        let mut packed = Vec::new();
+        pack_pixels(&[0, 1, 2, 3, 4, 5, 6, 0], &mut packed);
+        assert_eq!(packed, [0x01, 0x23, 0x45, 0x60]);
+
+        // Transparent pixels are sent as white, and a last odd pixel fills a byte
+        pack_pixels(&[7, 4, 6], &mut packed);
+        assert_eq!(packed, [0x14, 0x60]);
+
+        // The buffer is reused, not appended to
+        pack_pixels(&[], &mut packed);
+        assert!(packed.is_empty());
+    }
+
+    #[test]
+    fn packing_keeps_rows_in_order() {
+        let pixels: Vec<u8> = (0..800 * 480).map(|i| (i / 800 % 7) as u8).collect();
+        let mut packed = Vec::new();
+        pack_pixels(&pixels, &mut packed);
+        assert_eq!(packed.len(), FRAME_BYTES);
+        for (row, bytes) in packed.chunks(400).enumerate() {
+            let color = (row % 7) as u8;
+            assert!(
+                bytes.iter().all(|&byte| byte == color << 4 | color),
+                "{row}"
+            );
+        }
     }
 }
@@ -0,0 +1,201 @@
+use std::{fmt::Display, str::FromStr};
+
+/** Bands along the edges of the panel that are left out of the image, e.g. to keep it clear of
+ * a frame's mat. Given in pixels, in the panel's own orientation. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Margins {
+    pub top: usize,
+    pub right: usize,
+    pub bottom: usize,
+    pub left: usize,
+}
+
+impl Margins {
+    /** Size of the area inside the margins, or `None` if they leave nothing of a panel of the
+     * given size. */
+    pub fn inner(&self, width: usize, height: usize) -> Option<(usize, usize)> {
+        let inner_width = width.checked_sub(self.left + self.right)?;
+        let inner_height = height.checked_sub(self.top + self.bottom)?;
+        if inner_width == 0 || inner_height == 0 {
+            return None;
+        }
+        return Some((inner_width, inner_height));
+    }
+}
+
+/** Parse either a single margin for all four edges, or `top,right,bottom,left`. */
+impl FromStr for Margins {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let values: Option<Vec<usize>> = s.split(',').map(|v| v.trim().parse().ok()).collect();
+        match values.as_deref() {
+            Some(&[all]) => Ok(Margins {
+                top: all,
+                right: all,
+                bottom: all,
+                left: all,
+            }),
+            Some(&[top, right, bottom, left]) => Ok(Margins {
+                top,
+                right,
+                bottom,
+                left,
+            }),
+            _ => Err(format!(
+                "`{s}` is not a margin in pixels like 40 or 40,20,40,20 (top,right,bottom,left)"
+            )),
+        }
+    }
+}
+
+impl Display for Margins {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{},{},{},{}",
+            self.top, self.right, self.bottom, self.left
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::epd::{
+        error::InkyError,
+        mock::{self, FakeClock, MockPanel},
+    };
+
+    const WIDTH: usize = 800;
+    const HEIGHT: usize = 480;
+
+    fn margins(top: usize, right: usize, bottom: usize, left: usize) -> Margins {
+        Margins {
+            top,
+            right,
+            bottom,
+            left,
+        }
+    }
+
+    /** The palette index of every pixel in the frame the panel was sent, unpacked. */
+    fn sent_frame(panel: &MockPanel) -> Vec<u8> {
+        let commands = panel.commands();
+        let (_, packed) = commands
+            .iter()
+            .find(|(_, data)| data.len() == WIDTH * HEIGHT / 2)
+            .expect("no frame was sent");
+        return packed
+            .iter()
+            .flat_map(|byte| [byte >> 4, byte & 0xF])
+            .collect();
+    }
+
+    #[test]
+    fn margins_are_one_or_four_values() {
+        assert_eq!("8".parse(), Ok(margins(8, 8, 8, 8)));
+        assert_eq!("1,2,3,4".parse(), Ok(margins(1, 2, 3, 4)));
+        assert_eq!(" 1, 2 ,3,4 ".parse(), Ok(margins(1, 2, 3, 4)));
+        for wrong in ["", "1,2", "1,2,3", "1,2,3,4,5", "-1", "1,2,x,4", "1.5"] {
+            assert_eq!(
+                wrong.parse::<Margins>(),
+                Err(format!(
+                    "`{wrong}` is not a margin in pixels like 40 or 40,20,40,20 (top,right,bottom,left)"
+                ))
+            );
+        }
+        assert_eq!(margins(1, 2, 3, 4).to_string(), "1,2,3,4");
+        assert_eq!("1,2,3,4".parse::<Margins>().unwrap().to_string(), "1,2,3,4");
+    }
+
+    #[test]
+    fn the_inner_area_leaves_out_every_band() {
+        assert_eq!(
+            margins(0, 0, 0, 0).inner(WIDTH, HEIGHT),
+            Some((WIDTH, HEIGHT))
+        );
+        assert_eq!(
+            margins(7, 13, 24, 33).inner(WIDTH, HEIGHT),
+            Some((754, 449))
+        );
+        assert_eq!(
+            margins(0, 400, 0, 399).inner(WIDTH, HEIGHT),
+            Some((1, HEIGHT))
+        );
+        assert_eq!(margins(0, 400, 0, 400).inner(WIDTH, HEIGHT), None);
+        assert_eq!(margins(480, 0, 0, 0).inner(WIDTH, HEIGHT), None);
+        assert_eq!(margins(0, 0, 0, 900).inner(WIDTH, HEIGHT), None);
+    }
+
+    #[test]
+    fn the_packed_frame_has_the_bands_around_the_image() {
+        let clock = FakeClock::new();
+        let panel = MockPanel::responsive(&clock);
+        let mut inky = mock::inky(&panel, &clock);
+        // Odd margins, so that bands and image share bytes
+        let (top, right, bottom, left) = (7, 13, 24, 33);
+        inky.set_margins(margins(top, right, bottom, left), 4)
+            .unwrap();
+        let (width, height) = inky.dimensions();
+        assert_eq!((width, height), (754, 449));
+
+        for y in 0..height {
+            for x in 0..width {
+                inky.set_pixel(x, y, 2);
+            }
+        }
+        inky.set_pixel(0, 0, 3);
+        inky.set_pixel(width - 1, height - 1, 5);
+        inky.show().unwrap();
+
+        let frame = sent_frame(&panel);
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                let inside =
+                    (left..WIDTH - right).contains(&x) && (top..HEIGHT - bottom).contains(&y);
+                let expected = match (x, y) {
+                    (33, 7) => 3,
+                    (786, 455) => 5,
+                    _ if inside => 2,
+                    _ => 4,
+                };
+                assert_eq!(frame[y * WIDTH + x], expected, "({x}, {y})");
+            }
+        }
+    }
+
+    #[test]
+    fn transparent_bands_are_sent_as_white() {
+        let clock = FakeClock::new();
+        let panel = MockPanel::responsive(&clock);
+        let mut inky = mock::inky(&panel, &clock);
+        inky.set_margins(margins(0, 0, 0, 1), 7).unwrap();
+        inky.set_pixel(0, 0, 0);
+        inky.show().unwrap();
+
+        let frame = sent_frame(&panel);
+        assert_eq!(frame[..3], [1, 0, 1]);
+        assert!(frame[WIDTH..].iter().step_by(WIDTH).all(|&px| px == 1));
+    }
+
+    #[test]
+    fn margins_that_leave_no_image_are_refused() {
+        let clock = FakeClock::new();
+        let panel = MockPanel::responsive(&clock);
+        let mut inky = mock::inky(&panel, &clock);
+        inky.set_margins(margins(8, 8, 8, 8), 1).unwrap();
+
+        let result = inky.set_margins(margins(240, 0, 240, 0), 1);
+        assert!(matches!(
+            result,
+            Err(InkyError::InvalidMargins {
+                width: WIDTH,
+                height: HEIGHT,
+                ..
+            })
+        ));
+        // The margins already set are kept
+        assert_eq!(inky.dimensions(), (784, 464));
+    }
+}
@@ -0,0 +1,473 @@
+//! A simulated controller and clock, for testing the driver without a panel.
+
+use std::{
+    collections::HashMap,
+    io,
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant},
+};
+
+use rppal::{gpio, i2c, spi};
+
+use crate::epd::{
+    bus::{Bus, Connector, I2cBus},
+    clock::Clock,
+    error::InkyError,
+    inky::Inky,
+    wiring::Wiring,
+    EPDType, Model,
+};
+
+/** A clock whose time only moves when something sleeps on it or the test advances it. Every
+ * sleep is recorded. Clones share the same time. */
+#[derive(Clone)]
+pub struct FakeClock(Arc<Mutex<VirtualTime>>);
+
+struct VirtualTime {
+    start: Instant,
+    elapsed: Duration,
+    sleeps: Vec<Duration>,
+}
+
+impl FakeClock {
+    pub fn new() -> FakeClock {
+        FakeClock(Arc::new(Mutex::new(VirtualTime {
+            start: Instant::now(),
+            elapsed: Duration::ZERO,
+            sleeps: Vec::new(),
+        })))
+    }
+
+    /** Virtual time since the clock was made. */
+    pub fn elapsed(&self) -> Duration {
+        self.0.lock().unwrap().elapsed
+    }
+
+    /** Every duration slept, in order. */
+    pub fn sleeps(&self) -> Vec<Duration> {
+        self.0.lock().unwrap().sleeps.clone()
+    }
+}
+
+impl Clock for FakeClock {
+    fn now(&self) -> Instant {
+        let time = self.0.lock().unwrap();
+        time.start + time.elapsed
+    }
+
+    fn sleep(&self, duration: Duration) {
+        let mut time = self.0.lock().unwrap();
+        time.sleeps.push(duration);
+        time.elapsed += duration;
+    }
+}
+
+/** What the controller saw, in order. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    /** The reset line was set to this level. */
+    Reset(bool),
+    Command(u8),
+    /** Everything written with the data/command line high while chip select was low. */
+    Data(Vec<u8>),
+}
+
+/** A simulated controller. It goes busy for a set time after a reset or a command, as the
+ * [FakeClock] it shares with the driver counts it. Clones share the same controller, so a test
+ * keeps one to configure it and look at what happened after handing another to the driver. */
+#[derive(Clone)]
+pub struct MockPanel(Arc<Mutex<Panel>>);
+
+struct Panel {
+    clock: FakeClock,
+    events: Vec<(Duration, Event)>,
+    busy_after_reset: Option<Duration>,
+    busy_after: HashMap<u8, Duration>,
+    busy_until: Option<Duration>,
+    /** The busy level last reported to the callback. */
+    reported_busy: bool,
+    callback: Option<Box<dyn FnMut(bool) + Send>>,
+    /** How often the callback was cleared explicitly, rather than dropped with the bus. */
+    callbacks_cleared: usize,
+    /** Command whose data fails to transfer once this many bytes of it were written. */
+    fail_data: Option<(u8, usize)>,
+    fail_connect: bool,
+    /** Transfers fail at SPI clocks above this. */
+    max_clock_hz: Option<u32>,
+    interrupts: bool,
+    connects: usize,
+    connected: usize,
+    clock_hz: u32,
+    writes: Vec<usize>,
+    data_command: bool,
+    last_command: Option<u8>,
+    transaction: Option<Vec<u8>>,
+}
+
+impl MockPanel {
+    /** A controller that never signals busy. */
+    pub fn new(clock: &FakeClock) -> MockPanel {
+        MockPanel(Arc::new(Mutex::new(Panel {
+            clock: clock.clone(),
+            events: Vec::new(),
+            busy_after_reset: None,
+            busy_after: HashMap::new(),
+            busy_until: None,
+            reported_busy: false,
+            callback: None,
+            callbacks_cleared: 0,
+            fail_data: None,
+            fail_connect: false,
+            max_clock_hz: None,
+            interrupts: true,
+            connects: 0,
+            connected: 0,
+            clock_hz: 0,
+            writes: Vec::new(),
+            data_command: false,
+            last_command: None,
+            transaction: None,
+        })))
+    }
+
+    /** A controller that is busy for as long as the real one roughly is: briefly after a reset,
+     * power on and power off, and 30 s for a refresh. */
+    pub fn responsive(clock: &FakeClock) -> MockPanel {
+        MockPanel::new(clock)
+            .busy_after_reset(Duration::from_millis(20))
+            .busy_after(0x04, Duration::from_millis(150))
+            .busy_after(0x12, Duration::from_secs(30))
+            .busy_after(0x02, Duration::from_millis(100))
+    }
+
+    fn panel(&self) -> MutexGuard<'_, Panel> {
+        self.0.lock().unwrap()
+    }
+
+    /** Go busy for `duration` whenever reset is released. */
+    pub fn busy_after_reset(self, duration: Duration) -> MockPanel {
+        self.panel().busy_after_reset = Some(duration);
+        self
+    }
+
+    /** Go busy for `duration` once `command` and its data were sent. */
+    pub fn busy_after(self, command: u8, duration: Duration) -> MockPanel {
+        self.panel().busy_after.insert(command, duration);
+        self
+    }
+
+    /** Fail the transfer of the data of `command` once `after` bytes of it were written. */
+    pub fn fail_data(self, command: u8, after: usize) -> MockPanel {
+        self.panel().fail_data = Some((command, after));
+        self
+    }
+
+    /** Fail every transfer at SPI clocks above `clock_hz`, like long wires would. */
+    pub fn max_clock_hz(self, clock_hz: u32) -> MockPanel {
+        self.panel().max_clock_hz = Some(clock_hz);
+        self
+    }
+
+    /** Refuse to be connected to from now on, like pins another program claimed, or accept
+     * connections again. */
+    pub fn refuse_connections(&self, refuse: bool) {
+        self.panel().fail_connect = refuse;
+    }
+
+    /** The SPI clock last set. */
+    pub fn clock_hz(&self) -> u32 {
+        self.panel().clock_hz
+    }
+
+    /** How many times the driver claimed the bus. */
+    pub fn connects(&self) -> usize {
+        self.panel().connects
+    }
+
+    /** Fail to register busy callbacks, like a GPIO backend without async interrupts. */
+    pub fn no_interrupts(self) -> MockPanel {
+        self.panel().interrupts = false;
+        self
+    }
+
+    pub fn events(&self) -> Vec<Event> {
+        self.panel()
+            .events
+            .iter()
+            .map(|(_, event)| event.clone())
+            .collect()
+    }
+
+    /** Every event with the virtual time it happened at. */
+    pub fn timed_events(&self) -> Vec<(Duration, Event)> {
+        self.panel().events.clone()
+    }
+
+    /** Every command with the data sent after it. */
+    pub fn commands(&self) -> Vec<(u8, Vec<u8>)> {
+        let mut commands: Vec<(u8, Vec<u8>)> = Vec::new();
+        for event in self.events() {
+            match event {
+                Event::Command(command) => commands.push((command, Vec::new())),
+                Event::Data(data) => match commands.last_mut() {
+                    Some((_, sent)) => sent.extend(data),
+                    None => panic!("data sent before any command"),
+                },
+                Event::Reset(_) => {}
+            }
+        }
+        commands
+    }
+
+    /** Just the command bytes of [MockPanel::commands]. */
+    pub fn command_bytes(&self) -> Vec<u8> {
+        self.commands()
+            .iter()
+            .map(|(command, _)| *command)
+            .collect()
+    }
+
+    /** Whether the driver currently holds the bus. */
+    pub fn is_connected(&self) -> bool {
+        self.panel().connected > 0
+    }
+
+    pub fn has_callback(&self) -> bool {
+        self.panel().callback.is_some()
+    }
+
+    pub fn callbacks_cleared(&self) -> usize {
+        self.panel().callbacks_cleared
+    }
+
+    /** The size of every SPI transfer. */
+    pub fn writes(&self) -> Vec<usize> {
+        self.panel().writes.clone()
+    }
+
+    /** Forget what happened so far, keeping the configuration. */
+    pub fn clear(&self) {
+        let mut panel = self.panel();
+        panel.events.clear();
+        panel.writes.clear();
+    }
+}
+
+impl Panel {
+    fn now(&self) -> Duration {
+        self.clock.elapsed()
+    }
+
+    fn record(&mut self, event: Event) {
+        let now = self.now();
+        self.events.push((now, event));
+    }
+
+    fn go_busy(&mut self, duration: Duration) {
+        // Report the end of an earlier busy period first, as the edge would have been
+        self.is_busy();
+        self.busy_until = Some(self.now() + duration);
+        self.is_busy();
+    }
+
+    /** Whether the controller is busy now, telling the callback if that changed. */
+    fn is_busy(&mut self) -> bool {
+        let now = self.now();
+        let busy = self.busy_until.is_some_and(|until| now < until);
+        if busy != self.reported_busy {
+            self.reported_busy = busy;
+            if let Some(callback) = &mut self.callback {
+                callback(busy);
+            }
+        }
+        busy
+    }
+}
+
+/** The [Bus] a [MockPanel] hands out. */
+pub struct MockBus(MockPanel);
+
+impl Connector for MockPanel {
+    fn connect(&mut self, _wiring: &Wiring, spi_clock_hz: u32) -> Result<Box<dyn Bus>, InkyError> {
+        let mut panel = self.panel();
+        if panel.fail_connect {
+            return Err(InkyError::GpioError(gpio::Error::PinUsed(17)));
+        }
+        panel.connects += 1;
+        panel.connected += 1;
+        panel.clock_hz = spi_clock_hz;
+        drop(panel);
+        Ok(Box::new(MockBus(self.clone())))
+    }
+}
+
+impl Drop for MockBus {
+    /** Like closing the pins, which also stops their interrupts. */
+    fn drop(&mut self) {
+        let mut panel = self.0.panel();
+        panel.connected -= 1;
+        panel.callback = None;
+    }
+}
+
+impl Bus for MockBus {
+    fn set_chip_select(&mut self, high: bool) {
+        let mut panel = self.0.panel();
+        if !high {
+            panel.transaction = Some(Vec::new());
+            return;
+        }
+        let Some(bytes) = panel.transaction.take() else {
+            return;
+        };
+        if !panel.data_command {
+            for command in bytes {
+                panel.last_command = Some(command);
+                panel.record(Event::Command(command));
+            }
+            return;
+        }
+        panel.record(Event::Data(bytes));
+        let busy = panel
+            .last_command
+            .and_then(|command| panel.busy_after.get(&command).copied());
+        if let Some(duration) = busy {
+            panel.go_busy(duration);
+        }
+    }
+
+    fn set_data_command(&mut self, high: bool) {
+        self.0.panel().data_command = high;
+    }
+
+    fn set_reset(&mut self, high: bool) {
+        let mut panel = self.0.panel();
+        panel.record(Event::Reset(high));
+        if high {
+            if let Some(duration) = panel.busy_after_reset {
+                panel.go_busy(duration);
+            }
+        }
+    }
+
+    fn is_busy(&self) -> bool {
+        self.0.panel().is_busy()
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<usize, spi::Error> {
+        let mut panel = self.0.panel();
+        let failing = match panel.fail_data {
+            Some((command, after)) => {
+                let written = panel.transaction.as_ref().map_or(0, Vec::len);
+                panel.data_command && panel.last_command == Some(command) && written >= after
+            }
+            None => false,
+        };
+        let too_fast = panel.max_clock_hz.is_some_and(|max| panel.clock_hz > max);
+        if failing || too_fast {
+            return Err(spi::Error::Io(io::Error::other("mock transfer failed")));
+        }
+        panel.writes.push(data.len());
+        panel
+            .transaction
+            .as_mut()
+            .expect("written without chip select")
+            .extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn set_clock_speed(&mut self, clock_hz: u32) -> Result<(), spi::Error> {
+        self.0.panel().clock_hz = clock_hz;
+        Ok(())
+    }
+
+    fn set_busy_callback(
+        &mut self,
+        callback: Box<dyn FnMut(bool) + Send>,
+    ) -> Result<(), gpio::Error> {
+        let mut panel = self.0.panel();
+        if !panel.interrupts {
+            return Err(gpio::Error::Io(io::Error::other("no async interrupts")));
+        }
+        panel.callback = Some(callback);
+        Ok(())
+    }
+
+    fn clear_busy_callback(&mut self) -> Result<(), gpio::Error> {
+        let mut panel = self.0.panel();
+        panel.callback = None;
+        panel.callbacks_cleared += 1;
+        Ok(())
+    }
+}
+
+/** A driver for an 800x480 AC073TC1 talking to `panel`, timed by `clock`. */
+pub fn inky(panel: &MockPanel, clock: &FakeClock) -> Inky {
+    let eeprom = Model::Ac073tc1.epd_type(None, None);
+    Inky::with_connector(eeprom, Wiring::default(), panel.clone(), clock.clone()).unwrap()
+}
+
+/** An I2C bus with EEPROMs at some addresses. Nothing else acknowledges. */
+#[derive(Default)]
+pub struct MockI2c {
+    eeproms: HashMap<u16, Vec<u8>>,
+    address: Option<u16>,
+}
+
+impl MockI2c {
+    pub fn new() -> MockI2c {
+        MockI2c::default()
+    }
+
+    /** An EEPROM at `address` holding `contents`. */
+    pub fn eeprom(mut self, address: u16, contents: Vec<u8>) -> MockI2c {
+        self.eeproms.insert(address, contents);
+        self
+    }
+
+    fn selected(&self) -> Result<&[u8], i2c::Error> {
+        self.address
+            .and_then(|address| self.eeproms.get(&address))
+            .map(Vec::as_slice)
+            .ok_or_else(|| i2c::Error::Io(io::Error::other("no acknowledgement")))
+    }
+}
+
+impl I2cBus for MockI2c {
+    fn set_slave_address(&mut self, address: u16) -> Result<(), i2c::Error> {
+        self.address = Some(address);
+        Ok(())
+    }
+
+    fn block_write(&mut self, _command: u8, _data: &[u8]) -> Result<(), i2c::Error> {
+        self.selected()?;
+        Ok(())
+    }
+
+    fn block_read(&mut self, _command: u8, buffer: &mut [u8]) -> Result<(), i2c::Error> {
+        let contents = self.selected()?;
+        let length = buffer.len().min(contents.len());
+        buffer[..length].copy_from_slice(&contents[..length]);
+        Ok(())
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> Result<usize, i2c::Error> {
+        let contents = self.selected()?;
+        let length = buffer.len().min(contents.len());
+        buffer[..length].copy_from_slice(&contents[..length]);
+        Ok(length)
+    }
+}
+
+/** The bytes an EEPROM holding `eeprom` stores, in the layout [crate::epd::read_eeprom] reads. */
+pub fn eeprom_bytes(eeprom: &EPDType) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(&eeprom.width.to_le_bytes());
+    bytes.extend_from_slice(&eeprom.height.to_le_bytes());
+    bytes.push(eeprom.color as u8);
+    bytes.push(eeprom.pcb_variant);
+    bytes.push(eeprom.display_variant);
+    bytes.push(eeprom.eeprom_write_time_length);
+    bytes.extend_from_slice(&eeprom.eeprom_write_time);
+    bytes
+}
@@ -1,10 +1,27 @@
-use std::mem::transmute;
+use std::{fmt::Display, io, mem::transmute};
 
-use rppal::i2c::{self, I2c};
+use chrono::NaiveDateTime;
+use clap::ValueEnum;
+use rppal::i2c;
+use serde::{Deserialize, Serialize};
 
+use crate::epd::bus::I2cBus;
+
+pub mod bus;
+pub mod cache;
+pub mod clock;
+pub mod diagnose;
+pub mod error;
 pub mod inky;
+pub mod margins;
+#[cfg(test)]
+pub mod mock;
+pub mod raw;
+pub mod scan;
+pub mod version;
+pub mod wiring;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 #[repr(u8)]
 #[allow(dead_code)]
 pub enum EPDColor {
@@ -14,7 +31,18 @@ pub enum EPDColor {
     SevenColour = 0x05,
 }
 
-#[derive(Debug)]
+impl Display for EPDColor {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            EPDColor::Black => write!(f, "black and white"),
+            EPDColor::Red => write!(f, "black, white and red"),
+            EPDColor::Yellow => write!(f, "black, white and yellow"),
+            EPDColor::SevenColour => write!(f, "7 colors"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[repr(C)]
 pub struct EPDType {
     pub width: u16,
@@ -26,15 +54,156 @@ pub struct EPDType {
     pub eeprom_write_time: [u8; 21],
 }
 
-const EEP_ADDRESS: u16 = 0x50;
+impl Display for EPDType {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{}x{} {:?} panel (PCB variant {}, display variant {})",
+            self.width, self.height, self.color, self.pcb_variant, self.display_variant
+        )
+    }
+}
+
+impl EPDType {
+    /** When the EEPROM was written at the factory, as the text stored in it. `None` if that
+     * isn't readable text. */
+    pub fn write_time(&self) -> Option<&str> {
+        let length = (self.eeprom_write_time_length as usize).min(self.eeprom_write_time.len());
+        let text = std::str::from_utf8(&self.eeprom_write_time[..length]).ok()?;
+        let text = text.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+        return (!text.is_empty()).then_some(text);
+    }
+
+    /** [EPDType::write_time] to the second, if it is in the usual `YYYY-MM-DD HH:MM:SS.ffff`
+     * form. Otherwise the text as it is. */
+    pub fn write_time_readable(&self) -> Option<String> {
+        let text = self.write_time()?;
+        let readable = NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M:%S%.f")
+            .map(|time| time.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_else(|_| text.to_string());
+        return Some(readable);
+    }
+}
+
+/** Panels that can be driven without reading their EEPROM, for boards that don't have one. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Model {
+    /// The 7.3" 7-color panel of the Inky Impression, 800x480
+    Ac073tc1,
+}
+
+impl Model {
+    /** What the EEPROM of a genuine HAT with this panel would hold, with the panel's own size
+     * unless `width` or `height` are given. */
+    pub fn epd_type(self, width: Option<u16>, height: Option<u16>) -> EPDType {
+        let (default_width, default_height, color, display_variant) = match self {
+            Model::Ac073tc1 => (800, 480, EPDColor::SevenColour, 20),
+        };
+        EPDType {
+            width: width.unwrap_or(default_width),
+            height: height.unwrap_or(default_height),
+            color,
+            pcb_variant: 0,
+            display_variant,
+            eeprom_write_time_length: 0,
+            eeprom_write_time: [0; 21],
+        }
+    }
+}
+
+/** Where the EEPROM of a genuine HAT answers. */
+pub const EEP_ADDRESS: u16 = 0x50;
+
+/** Offset of [EPDType::color] in the EEPROM. */
+const COLOR_OFFSET: usize = 4;
 
-pub fn read_eeprom(i2c: &mut I2c) -> Result<EPDType, i2c::Error> {
-    i2c.set_slave_address(EEP_ADDRESS)?;
+pub fn read_eeprom(i2c: &mut impl I2cBus, address: u16) -> Result<EPDType, i2c::Error> {
+    i2c.set_slave_address(address)?;
     i2c.block_write(0x00, &[0x00])?;
 
     let mut buffer: [u8; 30] = [0; 30];
     i2c.block_read(0x00, &mut buffer[..29])?;
 
+    // Anything else would not be a valid EPDColor
+    if !matches!(buffer[COLOR_OFFSET], 0x01 | 0x02 | 0x03 | 0x05) {
+        return Err(i2c::Error::Io(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "0x{address:02X} does not hold panel data (color byte 0x{:02X})",
+                buffer[COLOR_OFFSET]
+            ),
+        )));
+    }
+
     let epd_type: EPDType = unsafe { transmute(buffer) };
     return Ok(epd_type);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::epd::mock::{eeprom_bytes, MockI2c};
+
+    fn panel() -> EPDType {
+        let mut eeprom = Model::Ac073tc1.epd_type(None, None);
+        let written = b"2023-04-05 06:07:08.9";
+        eeprom.eeprom_write_time_length = written.len() as u8;
+        eeprom.eeprom_write_time.copy_from_slice(written);
+        eeprom
+    }
+
+    fn with_color(color: u8) -> Vec<u8> {
+        let mut bytes = eeprom_bytes(&panel());
+        bytes[COLOR_OFFSET] = color;
+        bytes
+    }
+
+    #[test]
+    fn read_eeprom_decodes_panel_data() {
+        let mut i2c = MockI2c::new().eeprom(0x52, eeprom_bytes(&panel()));
+        let eeprom = read_eeprom(&mut i2c, 0x52).unwrap();
+        assert_eq!(eeprom, panel());
+        assert_eq!(
+            eeprom.write_time_readable().as_deref(),
+            Some("2023-04-05 06:07:08")
+        );
+    }
+
+    #[test]
+    fn read_eeprom_accepts_every_known_color() {
+        for (byte, color) in [
+            (0x01, EPDColor::Black),
+            (0x02, EPDColor::Red),
+            (0x03, EPDColor::Yellow),
+            (0x05, EPDColor::SevenColour),
+        ] {
+            let mut i2c = MockI2c::new().eeprom(EEP_ADDRESS, with_color(byte));
+            assert_eq!(read_eeprom(&mut i2c, EEP_ADDRESS).unwrap().color, color);
+        }
+    }
+
+    #[test]
+    fn read_eeprom_rejects_unknown_colors() {
+        for byte in [0x00, 0x04, 0x06, 0xFF] {
+            let mut i2c = MockI2c::new().eeprom(EEP_ADDRESS, with_color(byte));
+            match read_eeprom(&mut i2c, EEP_ADDRESS) {
+                Err(i2c::Error::Io(error)) => {
+                    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+                    let expected =
+                        format!("0x50 does not hold panel data (color byte 0x{byte:02X})");
+                    assert_eq!(error.to_string(), expected);
+                }
+                other => panic!("color byte 0x{byte:02X} gave {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn read_eeprom_fails_without_a_device() {
+        let mut i2c = MockI2c::new().eeprom(0x51, eeprom_bytes(&panel()));
+        assert!(matches!(
+            read_eeprom(&mut i2c, EEP_ADDRESS),
+            Err(i2c::Error::Io(_))
+        ));
+    }
+}
@@ -0,0 +1,245 @@
+use std::{
+    fmt::Display,
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::epd::inky::pack_pixels;
+
+/** Start of a file written by `--save-raw`. */
+const MAGIC: &[u8; 4] = b"INKY";
+/** Raised whenever the layout of the file changes. */
+const VERSION: u16 = 1;
+/** Magic, version, width and height. */
+const HEADER_LEN: usize = 10;
+
+/** A whole frame packed two pixels to a byte, exactly as the panel receives it, so it can be
+ * rendered on a faster machine and displayed later without decoding or quantizing. */
+pub struct RawFrame {
+    pub width: usize,
+    pub height: usize,
+    pub packed: Vec<u8>,
+}
+
+pub enum RawError {
+    Io(PathBuf, io::Error),
+    /** The file doesn't start with [MAGIC]. */
+    NotRaw(PathBuf),
+    /** The file was written by a version of the format this build doesn't know. */
+    Version(PathBuf, u16),
+    /** The header doesn't agree with the length of the file. */
+    Truncated {
+        path: PathBuf,
+        expected: usize,
+        actual: usize,
+    },
+    /** The frame is too large for the 16-bit width and height in the header. */
+    TooLarge(PathBuf, (usize, usize)),
+    /** The frame was rendered for a panel of another size. */
+    Size {
+        path: PathBuf,
+        frame: (usize, usize),
+        panel: (usize, usize),
+    },
+}
+
+/** Bytes of a `width` × `height` frame packed two pixels to a byte. */
+pub fn packed_len(width: usize, height: usize) -> usize {
+    (width * height).div_ceil(2)
+}
+
+/** Pack `width` × `height` palette indices like the panel receives them and write them to
+ * `path`, after a header with the size. */
+pub fn save(path: &Path, width: usize, height: usize, frame: &[u8]) -> Result<(), RawError> {
+    let (Ok(header_width), Ok(header_height)) = (u16::try_from(width), u16::try_from(height))
+    else {
+        return Err(RawError::TooLarge(path.to_path_buf(), (width, height)));
+    };
+    let mut packed = Vec::new();
+    pack_pixels(frame, &mut packed);
+    let mut contents = Vec::with_capacity(HEADER_LEN + packed.len());
+    contents.write_all(MAGIC).unwrap();
+    for value in [VERSION, header_width, header_height] {
+        contents.write_all(&value.to_le_bytes()).unwrap();
+    }
+    contents.extend(packed);
+    return fs::write(path, contents).map_err(|error| RawError::Io(path.to_path_buf(), error));
+}
+
+/** Read a frame written by [save] and check that it is meant for a panel of `width` ×
+ * `height`. */
+pub fn load(path: &Path, width: usize, height: usize) -> Result<RawFrame, RawError> {
+    let contents = fs::read(path).map_err(|error| RawError::Io(path.to_path_buf(), error))?;
+    if contents.len() < HEADER_LEN || !contents.starts_with(MAGIC) {
+        return Err(RawError::NotRaw(path.to_path_buf()));
+    }
+    let field = |i: usize| u16::from_le_bytes([contents[4 + 2 * i], contents[5 + 2 * i]]);
+    let version = field(0);
+    if version != VERSION {
+        return Err(RawError::Version(path.to_path_buf(), version));
+    }
+    let frame = RawFrame {
+        width: field(1) as usize,
+        height: field(2) as usize,
+        packed: contents[HEADER_LEN..].to_vec(),
+    };
+    let expected = packed_len(frame.width, frame.height);
+    if frame.packed.len() != expected {
+        return Err(RawError::Truncated {
+            path: path.to_path_buf(),
+            expected,
+            actual: frame.packed.len(),
+        });
+    }
+    if (frame.width, frame.height) != (width, height) {
+        return Err(RawError::Size {
+            path: path.to_path_buf(),
+            frame: (frame.width, frame.height),
+            panel: (width, height),
+        });
+    }
+    return Ok(frame);
+}
+
+impl Display for RawError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RawError::Io(path, error) => write!(f, "{}: {error}", path.display()),
+            RawError::NotRaw(path) => {
+                write!(f, "{} is not a frame saved with --save-raw", path.display())
+            }
+            RawError::Version(path, version) => write!(
+                f,
+                "{} is in version {version} of the raw format, this build reads version {VERSION}",
+                path.display()
+            ),
+            RawError::TooLarge(path, (width, height)) => write!(
+                f,
+                "Could not save {}: a {width}x{height} frame doesn't fit the raw format, which \
+                 allows up to {}x{}",
+                path.display(),
+                u16::MAX,
+                u16::MAX
+            ),
+            RawError::Truncated {
+                path,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{} should have {expected} bytes of pixels but has {actual}",
+                path.display()
+            ),
+            RawError::Size {
+                path,
+                frame: (frame_width, frame_height),
+                panel: (panel_width, panel_height),
+            } => write!(
+                f,
+                "{} was rendered at {frame_width}x{frame_height}, but the panel is \
+                 {panel_width}x{panel_height}",
+                path.display()
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /** A 3 × 3 frame with every color, an odd number of pixels to pack. */
+    const FRAME: [u8; 9] = [0, 1, 2, 3, 4, 5, 6, 1, 0];
+
+    fn saved(dir: &Path) -> PathBuf {
+        let path = dir.join("frame.raw");
+        save(&path, 3, 3, &FRAME).unwrap_or_else(|error| panic!("{error}"));
+        return path;
+    }
+
+    fn load_error(path: &Path, width: usize, height: usize) -> RawError {
+        match load(path, width, height) {
+            Ok(_) => panic!("{} was loaded", path.display()),
+            Err(error) => error,
+        }
+    }
+
+    #[test]
+    fn frames_load_as_they_were_saved() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = saved(dir.path());
+        assert!(fs::read(&path)
+            .unwrap()
+            .starts_with(b"INKY\x01\x00\x03\x00\x03\x00"));
+
+        let frame = load(&path, 3, 3).unwrap_or_else(|error| panic!("{error}"));
+        let mut packed = Vec::new();
+        pack_pixels(&FRAME, &mut packed);
+        assert_eq!((frame.width, frame.height), (3, 3));
+        assert_eq!(frame.packed, packed);
+        assert_eq!(frame.packed.len(), packed_len(3, 3));
+    }
+
+    #[test]
+    fn other_files_are_not_raw_frames() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("photo.png");
+        for contents in [b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR".as_slice(), b"INKY"] {
+            fs::write(&path, contents).unwrap();
+            assert!(matches!(load_error(&path, 3, 3), RawError::NotRaw(_)));
+        }
+    }
+
+    #[test]
+    fn unknown_versions_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = saved(dir.path());
+        let mut contents = fs::read(&path).unwrap();
+        contents[4] = 2;
+        fs::write(&path, contents).unwrap();
+        assert!(matches!(load_error(&path, 3, 3), RawError::Version(_, 2)));
+    }
+
+    #[test]
+    fn truncated_frames_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = saved(dir.path());
+        let mut contents = fs::read(&path).unwrap();
+        contents.pop();
+        fs::write(&path, contents).unwrap();
+        assert!(matches!(
+            load_error(&path, 3, 3),
+            RawError::Truncated {
+                expected: 5,
+                actual: 4,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn frames_for_another_panel_are_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = saved(dir.path());
+        assert_eq!(
+            load_error(&path, 4, 3).to_string(),
+            format!(
+                "{} was rendered at 3x3, but the panel is 4x3",
+                path.display()
+            )
+        );
+    }
+
+    #[test]
+    fn frames_too_large_for_the_header_are_not_saved() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("frame.raw");
+        let frame = vec![0; 65_536];
+        let Err(error) = save(&path, 65_536, 1, &frame) else {
+            panic!("a frame 65536 pixels wide was saved");
+        };
+        assert!(matches!(error, RawError::TooLarge(_, (65_536, 1))));
+        assert!(!path.exists());
+    }
+}
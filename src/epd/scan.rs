@@ -0,0 +1,150 @@
+use std::{fmt::Display, ops::RangeInclusive};
+
+use rppal::i2c;
+
+use crate::epd::{self, bus::I2cBus, EPDType};
+
+/** Where 24Cxx EEPROMs can be strapped to answer. Genuine HATs use 0x50, some clones others. */
+pub const EEPROM_ADDRESSES: RangeInclusive<u16> = 0x50..=0x57;
+
+/** What was found at one address. */
+pub struct Probe {
+    pub address: u16,
+    /** `None` if nothing acknowledged, otherwise the outcome of reading panel data there. */
+    pub contents: Option<Result<EPDType, String>>,
+}
+
+/** Look for the panel's EEPROM at every plausible address.
+ *
+ * Presence is probed with a one byte read, like `i2cdetect -r` does for this range, because a
+ * quick write can corrupt some EEPROMs. Only addresses that answer are then read like a normal
+ * start would, whose address write just moves the read pointer of the 16-bit addressed EEPROMs
+ * these HATs use. */
+pub fn scan(i2c: &mut impl I2cBus) -> Result<Vec<Probe>, i2c::Error> {
+    let mut probes = Vec::new();
+    for address in EEPROM_ADDRESSES {
+        i2c.set_slave_address(address)?;
+        let acked = i2c.read(&mut [0; 1]).is_ok();
+        let contents = acked.then(|| epd::read_eeprom(i2c, address).map_err(|e| e.to_string()));
+        probes.push(Probe { address, contents });
+    }
+    return Ok(probes);
+}
+
+/** The first address holding panel data. */
+pub fn suggest(probes: &[Probe]) -> Option<u16> {
+    probes
+        .iter()
+        .find(|probe| matches!(probe.contents, Some(Ok(_))))
+        .map(|probe| probe.address)
+}
+
+/** One line per address, followed by a suggestion. */
+pub struct Report<'a>(pub &'a [Probe]);
+
+impl Display for Report<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        for probe in self.0 {
+            write!(f, "0x{:02X}  ", probe.address)?;
+            match &probe.contents {
+                None => writeln!(f, "no response")?,
+                Some(Ok(eeprom)) => writeln!(f, "{eeprom}")?,
+                Some(Err(error)) => writeln!(f, "responds, but could not be read: {error}")?,
+            }
+        }
+
+        match suggest(self.0) {
+            Some(epd::EEP_ADDRESS) => writeln!(f, "\nThe EEPROM is at the default address"),
+            Some(address) => writeln!(f, "\nUse --eeprom-address 0x{address:02X}"),
+            None => writeln!(f, "\nNo panel EEPROM found"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::epd::{
+        mock::{eeprom_bytes, MockI2c},
+        Model,
+    };
+
+    fn panel() -> Vec<u8> {
+        eeprom_bytes(&Model::Ac073tc1.epd_type(None, None))
+    }
+
+    /** Something that answers but holds no panel data, like another HAT's EEPROM. */
+    fn blank() -> Vec<u8> {
+        vec![0xFF; 32]
+    }
+
+    fn found(probes: &[Probe]) -> Vec<(u16, Option<bool>)> {
+        probes
+            .iter()
+            .map(|probe| (probe.address, probe.contents.as_ref().map(Result::is_ok)))
+            .collect()
+    }
+
+    #[test]
+    fn scan_probes_every_address() {
+        let mut i2c = MockI2c::new().eeprom(0x50, panel());
+        let probes = scan(&mut i2c).unwrap();
+        let addresses: Vec<u16> = probes.iter().map(|probe| probe.address).collect();
+        assert_eq!(addresses, EEPROM_ADDRESSES.collect::<Vec<_>>());
+        assert_eq!(found(&probes)[0], (0x50, Some(true)));
+        assert!(found(&probes)[1..]
+            .iter()
+            .all(|(_, contents)| contents.is_none()));
+        assert_eq!(
+            probes[0].contents,
+            Some(Ok(Model::Ac073tc1.epd_type(None, None)))
+        );
+    }
+
+    #[test]
+    fn scan_tells_panel_data_from_other_devices() {
+        let mut i2c = MockI2c::new().eeprom(0x51, blank()).eeprom(0x53, panel());
+        let probes = scan(&mut i2c).unwrap();
+        assert_eq!(
+            found(&probes)[..4],
+            [
+                (0x50, None),
+                (0x51, Some(false)),
+                (0x52, None),
+                (0x53, Some(true))
+            ]
+        );
+        let Some(Err(error)) = &probes[1].contents else {
+            unreachable!()
+        };
+        assert!(error.contains("0x51 does not hold panel data (color byte 0xFF)"));
+    }
+
+    #[test]
+    fn suggest_prefers_the_first_panel() {
+        let mut i2c = MockI2c::new()
+            .eeprom(0x50, blank())
+            .eeprom(0x55, panel())
+            .eeprom(0x57, panel());
+        assert_eq!(suggest(&scan(&mut i2c).unwrap()), Some(0x55));
+
+        let mut i2c = MockI2c::new().eeprom(0x52, blank());
+        assert_eq!(suggest(&scan(&mut i2c).unwrap()), None);
+    }
+
+    #[test]
+    fn report_suggests_an_address() {
+        let mut i2c = MockI2c::new().eeprom(0x50, panel());
+        let report = Report(&scan(&mut i2c).unwrap()).to_string();
+        assert!(report.starts_with("0x50  800x480 SevenColour panel (PCB variant 0, display variant 20)\n0x51  no response\n"));
+        assert!(report.ends_with("\nThe EEPROM is at the default address\n"));
+
+        let mut i2c = MockI2c::new().eeprom(0x51, blank()).eeprom(0x56, panel());
+        let report = Report(&scan(&mut i2c).unwrap()).to_string();
+        assert!(report.contains("\n0x51  responds, but could not be read: "));
+        assert!(report.ends_with("\nUse --eeprom-address 0x56\n"));
+
+        let report = Report(&scan(&mut MockI2c::new()).unwrap()).to_string();
+        assert!(report.ends_with("\nNo panel EEPROM found\n"));
+    }
+}
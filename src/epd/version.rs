@@ -0,0 +1,165 @@
+use std::fmt::Display;
+
+use serde::Serialize;
+
+use crate::epd::{EPDColor, EPDType};
+
+pub const CRATE_VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const DRIVER: &str = "ac073tc1";
+
+/** Everything needed to identify the software, panel and wiring in a bug report. */
+#[derive(Debug, Serialize)]
+pub struct VersionInfo {
+    pub crate_version: &'static str,
+    pub driver: &'static str,
+    pub eeprom: EPDType,
+    pub pins: PinInfo,
+    pub spi: SpiInfo,
+    /** Not available until the controller revision can be read back. */
+    pub controller_revision: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PinInfo {
+    pub reset: u8,
+    pub busy: u8,
+    pub data_command: u8,
+    pub chip_select: u8,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SpiInfo {
+    pub bus: String,
+    pub clock_hz: u32,
+    pub chunk_size: usize,
+    pub mode: String,
+}
+
+/** The abbreviated form, short enough for a single log line. */
+impl Display for VersionInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "inky-rs {} ({} driver), {}",
+            self.crate_version, self.driver, self.eeprom
+        )
+    }
+}
+
+/** What the panel's EEPROM says about it, for the `info` subcommand. */
+#[derive(Debug, Serialize)]
+pub struct PanelInfo {
+    pub width: u16,
+    pub height: u16,
+    pub color: EPDColor,
+    pub pcb_variant: u8,
+    pub display_variant: u8,
+    /** When the EEPROM was written, see [EPDType::write_time_readable]. */
+    pub eeprom_write_time: Option<String>,
+}
+
+impl From<&EPDType> for PanelInfo {
+    fn from(eeprom: &EPDType) -> Self {
+        PanelInfo {
+            width: eeprom.width,
+            height: eeprom.height,
+            color: eeprom.color,
+            pcb_variant: eeprom.pcb_variant,
+            display_variant: eeprom.display_variant,
+            eeprom_write_time: eeprom.write_time_readable(),
+        }
+    }
+}
+
+impl Display for PanelInfo {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "Size:            {}x{}", self.width, self.height)?;
+        writeln!(f, "Colors:          {}", self.color)?;
+        writeln!(f, "PCB variant:     {}", self.pcb_variant)?;
+        writeln!(f, "Display variant: {}", self.display_variant)?;
+        match &self.eeprom_write_time {
+            Some(time) => writeln!(f, "EEPROM written:  {time}"),
+            None => writeln!(f, "EEPROM written:  unknown"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::epd::{
+        inky::Inky,
+        mock::{eeprom_bytes, FakeClock, MockI2c, MockPanel},
+        read_eeprom,
+        wiring::Wiring,
+        Model, EEP_ADDRESS,
+    };
+
+    /** The version info of a driver for the panel a synthetic EEPROM describes, on pins moved
+     * away from the HAT's. */
+    fn version_info() -> VersionInfo {
+        let mut written = Model::Ac073tc1.epd_type(None, None);
+        written.pcb_variant = 12;
+        let time = b"2023-04-05 06:07:08.9";
+        written.eeprom_write_time_length = time.len() as u8;
+        written.eeprom_write_time.copy_from_slice(time);
+        let mut i2c = MockI2c::new().eeprom(EEP_ADDRESS, eeprom_bytes(&written));
+        let eeprom = read_eeprom(&mut i2c, EEP_ADDRESS).unwrap();
+
+        let wiring = Wiring {
+            busy_pin: 24,
+            spi_bus: 1,
+            ..Wiring::default()
+        };
+        let clock = FakeClock::new();
+        let panel = MockPanel::new(&clock);
+        let mut inky = Inky::with_connector(eeprom, wiring, panel, clock).unwrap();
+        inky.set_spi(8_000_000, 128).unwrap();
+        return inky.version_info();
+    }
+
+    #[test]
+    fn version_info_json_keeps_its_shape() {
+        let written: Vec<u8> = b"2023-04-05 06:07:08.9".to_vec();
+        let expected = json!({
+            "crate_version": CRATE_VERSION,
+            "driver": "ac073tc1",
+            "eeprom": {
+                "width": 800,
+                "height": 480,
+                "color": "SevenColour",
+                "pcb_variant": 12,
+                "display_variant": 20,
+                "eeprom_write_time_length": 21,
+                "eeprom_write_time": written,
+            },
+            "pins": {
+                "reset": 27,
+                "busy": 24,
+                "data_command": 22,
+                "chip_select": 8,
+            },
+            "spi": {
+                "bus": "Spi1",
+                "clock_hz": 8_000_000,
+                "chunk_size": 128,
+                "mode": "Mode0",
+            },
+            "controller_revision": null,
+        });
+        assert_eq!(serde_json::to_value(version_info()).unwrap(), expected);
+    }
+
+    #[test]
+    fn version_info_abbreviates_to_one_line() {
+        assert_eq!(
+            version_info().to_string(),
+            format!(
+                "inky-rs {CRATE_VERSION} (ac073tc1 driver), 800x480 SevenColour panel \
+                 (PCB variant 12, display variant 20)"
+            )
+        );
+    }
+}
@@ -0,0 +1,243 @@
+use std::{env, fmt::Display};
+
+use log::info;
+use rppal::spi;
+use serde::{Deserialize, Serialize};
+
+const RESET_PIN: u8 = 27;
+const BUSY_PIN: u8 = 17;
+const DC_PIN: u8 = 22;
+const CS_PIN: u8 = 8;
+const SPI_BUS: u8 = 0;
+const SPI_HZ: u32 = 5_000_000;
+const I2C_BUS: u8 = 1;
+
+/** The highest GPIO number on the 40-pin header. */
+pub const MAX_PIN: u8 = 27;
+/** The highest SPI bus rppal knows. */
+pub const MAX_SPI_BUS: u8 = 6;
+
+/** The GPIO lines and buses the panel is wired to. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Wiring {
+    pub reset_pin: u8,
+    pub busy_pin: u8,
+    pub dc_pin: u8,
+    pub cs_pin: u8,
+    pub spi_bus: u8,
+    pub spi_hz: u32,
+    pub i2c_bus: u8,
+}
+
+impl Default for Wiring {
+    /** The wiring of the Inky Impression HAT. */
+    fn default() -> Wiring {
+        Wiring {
+            reset_pin: RESET_PIN,
+            busy_pin: BUSY_PIN,
+            dc_pin: DC_PIN,
+            cs_pin: CS_PIN,
+            spi_bus: SPI_BUS,
+            spi_hz: SPI_HZ,
+            i2c_bus: I2C_BUS,
+        }
+    }
+}
+
+impl Wiring {
+    /** The SPI bus as rppal names it. The number was checked when the wiring was resolved. */
+    pub fn spi_bus(&self) -> spi::Bus {
+        match self.spi_bus {
+            0 => spi::Bus::Spi0,
+            1 => spi::Bus::Spi1,
+            2 => spi::Bus::Spi2,
+            3 => spi::Bus::Spi3,
+            4 => spi::Bus::Spi4,
+            5 => spi::Bus::Spi5,
+            6 => spi::Bus::Spi6,
+            bus => unreachable!("SPI bus {bus} passed the checks"),
+        }
+    }
+
+    /** The device file of the I2C bus, as the kernel names it. */
+    pub fn i2c_device(&self) -> String {
+        format!("/dev/i2c-{}", self.i2c_bus)
+    }
+}
+
+/** Wiring settings from one source, any of which may be missing: the command line, the
+ * environment or the `[hardware]` table of the config file. */
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WiringOverrides {
+    pub reset_pin: Option<u8>,
+    pub busy_pin: Option<u8>,
+    pub dc_pin: Option<u8>,
+    pub cs_pin: Option<u8>,
+    pub spi_bus: Option<u8>,
+    pub spi_hz: Option<u32>,
+    pub i2c_bus: Option<u8>,
+}
+
+/** A wiring setting that isn't a usable value, named after where it came from. */
+#[derive(Debug)]
+pub struct WiringError {
+    pub setting: String,
+    pub value: String,
+    pub expected: &'static str,
+}
+
+impl WiringOverrides {
+    /** Read `INKY_RESET_PIN`, `INKY_BUSY_PIN`, `INKY_DC_PIN`, `INKY_CS_PIN`, `INKY_SPI_BUS`,
+     * `INKY_SPI_HZ` and `INKY_I2C_BUS`. */
+    pub fn from_env() -> Result<WiringOverrides, WiringError> {
+        let overrides = WiringOverrides {
+            reset_pin: var("INKY_RESET_PIN")?,
+            busy_pin: var("INKY_BUSY_PIN")?,
+            dc_pin: var("INKY_DC_PIN")?,
+            cs_pin: var("INKY_CS_PIN")?,
+            spi_bus: var("INKY_SPI_BUS")?,
+            spi_hz: var("INKY_SPI_HZ")?,
+            i2c_bus: var("INKY_I2C_BUS")?,
+        };
+        overrides.check(|field| format!("INKY_{}", field.to_ascii_uppercase()))?;
+        return Ok(overrides);
+    }
+
+    /** These settings, with the ones that are missing taken from `fallback`. */
+    pub fn or(self, fallback: &WiringOverrides) -> WiringOverrides {
+        WiringOverrides {
+            reset_pin: self.reset_pin.or(fallback.reset_pin),
+            busy_pin: self.busy_pin.or(fallback.busy_pin),
+            dc_pin: self.dc_pin.or(fallback.dc_pin),
+            cs_pin: self.cs_pin.or(fallback.cs_pin),
+            spi_bus: self.spi_bus.or(fallback.spi_bus),
+            spi_hz: self.spi_hz.or(fallback.spi_hz),
+            i2c_bus: self.i2c_bus.or(fallback.i2c_bus),
+        }
+    }
+
+    /** Check that the settings are in range, naming a wrong one with `name` of its field. */
+    pub fn check(&self, name: impl Fn(&str) -> String) -> Result<(), WiringError> {
+        let error = |field: &str, value: &dyn Display, expected| WiringError {
+            setting: name(field),
+            value: value.to_string(),
+            expected,
+        };
+        for (field, pin) in [
+            ("reset_pin", self.reset_pin),
+            ("busy_pin", self.busy_pin),
+            ("dc_pin", self.dc_pin),
+            ("cs_pin", self.cs_pin),
+        ] {
+            if let Some(pin) = pin.filter(|&pin| pin > MAX_PIN) {
+                return Err(error(field, &pin, "a GPIO number from 0 to 27"));
+            }
+        }
+        if let Some(bus) = self.spi_bus.filter(|&bus| bus > MAX_SPI_BUS) {
+            return Err(error("spi_bus", &bus, "an SPI bus from 0 to 6"));
+        }
+        if let Some(hz) = self.spi_hz.filter(|&hz| hz == 0) {
+            return Err(error("spi_hz", &hz, "a clock speed in Hz above 0"));
+        }
+        return Ok(());
+    }
+}
+
+/** The value of an environment variable, if it is set. */
+fn var<T: std::str::FromStr>(name: &str) -> Result<Option<T>, WiringError> {
+    let Ok(value) = env::var(name) else {
+        return Ok(None);
+    };
+    match value.trim().parse() {
+        Ok(parsed) => Ok(Some(parsed)),
+        Err(_) => Err(WiringError {
+            setting: name.to_string(),
+            value,
+            expected: "a whole number",
+        }),
+    }
+}
+
+/** Put together the wiring from the command line, the environment and the config file, in that
+ * order of precedence, falling back to the HAT's own wiring. The result and where each value
+ * came from are logged. */
+pub fn resolve(cli: &WiringOverrides, env: &WiringOverrides, config: &WiringOverrides) -> Wiring {
+    let defaults = Wiring::default();
+    let mut sources = Vec::new();
+    let wiring = Wiring {
+        reset_pin: pick(
+            &mut sources,
+            ("reset pin", "INKY_RESET_PIN"),
+            [cli.reset_pin, env.reset_pin, config.reset_pin],
+            defaults.reset_pin,
+        ),
+        busy_pin: pick(
+            &mut sources,
+            ("busy pin", "INKY_BUSY_PIN"),
+            [cli.busy_pin, env.busy_pin, config.busy_pin],
+            defaults.busy_pin,
+        ),
+        dc_pin: pick(
+            &mut sources,
+            ("data/command pin", "INKY_DC_PIN"),
+            [cli.dc_pin, env.dc_pin, config.dc_pin],
+            defaults.dc_pin,
+        ),
+        cs_pin: pick(
+            &mut sources,
+            ("chip select pin", "INKY_CS_PIN"),
+            [cli.cs_pin, env.cs_pin, config.cs_pin],
+            defaults.cs_pin,
+        ),
+        spi_bus: pick(
+            &mut sources,
+            ("SPI bus", "INKY_SPI_BUS"),
+            [cli.spi_bus, env.spi_bus, config.spi_bus],
+            defaults.spi_bus,
+        ),
+        spi_hz: pick(
+            &mut sources,
+            ("SPI clock Hz", "INKY_SPI_HZ"),
+            [cli.spi_hz, env.spi_hz, config.spi_hz],
+            defaults.spi_hz,
+        ),
+        i2c_bus: pick(
+            &mut sources,
+            ("I2C bus", "INKY_I2C_BUS"),
+            [cli.i2c_bus, env.i2c_bus, config.i2c_bus],
+            defaults.i2c_bus,
+        ),
+    };
+    info!("Wiring: {}", sources.join(", "));
+    return wiring;
+}
+
+/** The first of the command line, environment and config file `layers` that has a value, or
+ * the default, noting in `sources` which one it was. */
+fn pick<T: Copy + Display>(
+    sources: &mut Vec<String>,
+    (label, variable): (&str, &str),
+    layers: [Option<T>; 3],
+    default: T,
+) -> T {
+    let [cli, env, config] = layers;
+    let (value, source) = match (cli, env, config) {
+        (Some(value), _, _) => (value, "command line"),
+        (None, Some(value), _) => (value, variable),
+        (None, None, Some(value)) => (value, "config file"),
+        (None, None, None) => (default, "built in"),
+    };
+    sources.push(format!("{label} {value} ({source})"));
+    return value;
+}
+
+impl Display for WiringError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} is `{}`, expected {}",
+            self.setting, self.value, self.expected
+        )
+    }
+}
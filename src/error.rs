@@ -0,0 +1,166 @@
+use std::{fmt::Display, io, path::PathBuf, process::ExitCode};
+
+use crate::{
+    config::error::ConfigError,
+    epd::{error::InkyError, raw::RawError},
+    mqtt::MqttError,
+    pipe::PipeError,
+    qr::QrCodeError,
+    quantize::error::QuantizeError,
+    select::error::SelectError,
+    serve::ServeError,
+    window::WindowError,
+};
+
+/** Any other failure, such as a file that can't be written. */
+pub const EXIT_FAILURE: u8 = 1;
+/** The command line or the configuration file is wrong. Clap uses the same code. */
+pub const EXIT_USAGE: u8 = 2;
+/** There is no image to choose. */
+pub const EXIT_NO_CANDIDATES: u8 = 3;
+/** The chosen image couldn't be downloaded, decoded or quantized. */
+pub const EXIT_IMAGE: u8 = 4;
+/** Opening or talking to the panel failed. */
+pub const EXIT_HARDWARE: u8 = 5;
+/** The panel stayed busy for much longer than a refresh takes. */
+pub const EXIT_TIMEOUT: u8 = 6;
+
+/** The exit codes as listed at the end of `--help`. */
+pub const EXIT_CODES_HELP: &str = "\
+Exit status:
+    0      Success
+    1      Any other failure, such as a file that can't be written
+    2      Wrong command line or configuration file
+    3      No image to choose, e.g. an empty directory
+    4      The image couldn't be downloaded, decoded, captioned or quantized
+    5      Opening or talking to the panel failed
+    6      The panel stayed busy for much longer than a refresh takes
+    69     A device of the panel is missing or in use
+    77     No permission to open a device of the panel
+    128+N  Stopped by signal N, e.g. 143 for SIGTERM";
+
+/** Anything that can go wrong while choosing, rendering and displaying an image. */
+#[derive(derive_more::From)]
+pub enum RunError {
+    Select(SelectError),
+    Quantize(QuantizeError),
+    Display(InkyError),
+    Config(ConfigError),
+    /** Options that can't be used together in ways clap doesn't check. */
+    #[from(ignore)]
+    Usage(String),
+    /** Reading the `sysinfo` template failed. */
+    #[from(ignore)]
+    Template(PathBuf, io::Error),
+    /** Writing the frame for `--emit` failed. */
+    Emit(io::Error),
+    /** Saving the frame for `--output` failed. */
+    #[from(ignore)]
+    Output(PathBuf, image::ImageError),
+    /** Saving a frame for `--save-raw` or reading one for `--from-raw` failed. */
+    Raw(RawError),
+    /** Showing the frame for `--preview-window` failed. */
+    Window(WindowError),
+    /** Starting the server for `serve` failed. */
+    Serve(ServeError),
+    /** Subscribing for `mqtt` failed. */
+    Mqtt(MqttError),
+    /** Setting up the named pipe for `pipe` failed. */
+    Pipe(PipeError),
+    /** Writing an image sent to `serve`, `mqtt` or `pipe` to the state directory failed. */
+    #[from(ignore)]
+    Upload(PathBuf, io::Error),
+    /** The data for `qr` doesn't fit in a code on the panel. */
+    Qr(QrCodeError),
+}
+
+impl RunError {
+    /** Short name of the kind of failure, as counted in the stats file. */
+    pub fn class(&self) -> &'static str {
+        match self {
+            RunError::Select(_) => "selection",
+            RunError::Quantize(QuantizeError::Quantize(_)) => "quantize",
+            RunError::Quantize(QuantizeError::Fetch(_)) => "download",
+            RunError::Quantize(QuantizeError::Exec(_)) => "command",
+            RunError::Quantize(QuantizeError::Caption(_)) => "caption",
+            RunError::Quantize(QuantizeError::Passthrough(..)) => "passthrough",
+            RunError::Quantize(_) => "decode",
+            RunError::Display(InkyError::Interrupted(_)) => "interrupted",
+            RunError::Display(_) => "display",
+            RunError::Config(_) => "config",
+            RunError::Usage(_) => "usage",
+            RunError::Template(..) => "template",
+            RunError::Emit(_) => "emit",
+            RunError::Output(..) => "output",
+            RunError::Raw(_) => "raw",
+            RunError::Window(_) => "window",
+            RunError::Serve(_) => "serve",
+            RunError::Mqtt(_) => "mqtt",
+            RunError::Pipe(_) => "pipe",
+            RunError::Upload(..) => "upload",
+            RunError::Qr(_) => "qr",
+        }
+    }
+}
+
+impl RunError {
+    /** Whether the next run would most likely fail the same way. Problems with the panel are,
+     * while a file that can't be decoded or an empty directory may not be next time. */
+    pub fn is_fatal(&self) -> bool {
+        matches!(
+            self,
+            RunError::Display(_) | RunError::Config(_) | RunError::Usage(_)
+        )
+    }
+}
+
+impl Display for RunError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RunError::Select(error) => write!(f, "{error}"),
+            RunError::Quantize(error) => write!(f, "{error}"),
+            RunError::Display(error) => write!(f, "Display error: {error}"),
+            RunError::Config(error) => write!(f, "{error}"),
+            RunError::Usage(message) => write!(f, "{message}"),
+            RunError::Template(path, error) => {
+                write!(f, "Template error: {}: {error}", path.display())
+            }
+            RunError::Emit(error) => write!(f, "Could not write the frame: {error}"),
+            RunError::Output(path, error) => {
+                write!(f, "Could not write {}: {error}", path.display())
+            }
+            RunError::Raw(error) => write!(f, "Raw frame error: {error}"),
+            RunError::Window(error) => write!(f, "Could not show the preview window: {error}"),
+            RunError::Serve(error) => write!(f, "Could not start the server: {error}"),
+            RunError::Mqtt(error) => write!(f, "Could not subscribe: {error}"),
+            RunError::Pipe(error) => write!(f, "{error}"),
+            RunError::Upload(path, error) => {
+                write!(f, "Could not write {}: {error}", path.display())
+            }
+            RunError::Qr(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+/** Print the error, with advice for common setup mistakes, and pick the exit code (see
+ * [EXIT_CODES_HELP]). */
+pub fn report(error: RunError) -> ExitCode {
+    eprintln!("{error}");
+    let code = match &error {
+        RunError::Select(_) => EXIT_NO_CANDIDATES,
+        RunError::Quantize(_) => EXIT_IMAGE,
+        RunError::Raw(RawError::Io(..)) => EXIT_FAILURE,
+        RunError::Raw(_) => EXIT_IMAGE,
+        RunError::Display(error) => crate::epd::error::explain(error),
+        RunError::Config(_) | RunError::Usage(_) | RunError::Qr(_) => EXIT_USAGE,
+        RunError::Template(..)
+        | RunError::Emit(_)
+        | RunError::Output(..)
+        | RunError::Window(_)
+        | RunError::Serve(_)
+        | RunError::Mqtt(_)
+        | RunError::Pipe(_)
+        | RunError::Upload(..) => EXIT_FAILURE,
+    };
+    return ExitCode::from(code);
+}
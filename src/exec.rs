@@ -0,0 +1,258 @@
+use std::{
+    fmt::Display,
+    io::{self, Read},
+    os::unix::process::CommandExt,
+    process::{Child, Command, ExitStatus, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::debug;
+
+use crate::fetch::MAX_BYTES;
+
+/** Bytes at the end of the command's stderr kept for error messages. */
+const MAX_STDERR: usize = 4096;
+/** How often the command is checked on while it runs. */
+const POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+pub enum ExecError {
+    /** `sh` couldn't be started. */
+    Spawn(io::Error),
+    Io(io::Error),
+    /** The command exited with an error, or was killed. */
+    Failed {
+        status: ExitStatus,
+        stderr: String,
+    },
+    /** The command was stopped after running for this long. */
+    TimedOut(Duration),
+    /** The command wrote more than [MAX_BYTES] to stdout. */
+    TooLarge,
+    /** The command succeeded, but what it wrote to stdout isn't an image. */
+    NotAnImage {
+        stderr: String,
+    },
+}
+
+/** Run `command` with `sh -c`, as a shell would run it on a line of its own, and return what it
+ * wrote to stdout, which should be an image unless `expect_image` is false. Stdin is empty.
+ *
+ * The command runs in a process group of its own. Once it has finished, or after `timeout`,
+ * anything it left running in the background is killed, so a hung renderer can't hold up the
+ * next refresh. */
+pub fn run(command: &str, timeout: Duration, expect_image: bool) -> Result<Vec<u8>, ExecError> {
+    debug!("Running {command}");
+    let started = Instant::now();
+    let mut child = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0)
+        .spawn()
+        .map_err(ExecError::Spawn)?;
+
+    let stdout = child.stdout.take().unwrap();
+    let mut stderr = child.stderr.take().unwrap();
+    let stdout = thread::spawn(move || {
+        let mut bytes = Vec::new();
+        stdout.take(MAX_BYTES + 1).read_to_end(&mut bytes)?;
+        return Ok::<_, io::Error>(bytes);
+    });
+    let stderr = thread::spawn(move || {
+        let bytes = read_tail(&mut stderr, MAX_STDERR)?;
+        return Ok::<_, io::Error>(String::from_utf8_lossy(&bytes).trim().to_string());
+    });
+
+    let status = loop {
+        if let Some(status) = child.try_wait().map_err(ExecError::Io)? {
+            break status;
+        }
+        if started.elapsed() >= timeout {
+            kill_group(&child);
+            child.wait().map_err(ExecError::Io)?;
+            return Err(ExecError::TimedOut(timeout));
+        }
+        thread::sleep(POLL_INTERVAL);
+    };
+    // Background processes would keep the pipes open
+    kill_group(&child);
+    debug!("{command} finished in {:.2?}", started.elapsed());
+
+    let stdout = stdout.join().unwrap().map_err(ExecError::Io)?;
+    let stderr = stderr.join().unwrap().map_err(ExecError::Io)?;
+    // Stdout is closed once the limit is reached, which usually kills the command
+    if stdout.len() as u64 > MAX_BYTES {
+        return Err(ExecError::TooLarge);
+    }
+    if !status.success() {
+        return Err(ExecError::Failed { status, stderr });
+    }
+    if expect_image && image::guess_format(&stdout).is_err() {
+        return Err(ExecError::NotAnImage { stderr });
+    }
+    return Ok(stdout);
+}
+
+/** Read `reader` to the end, so a command writing to it never blocks on a full pipe, keeping
+ * only the last `limit` bytes. */
+fn read_tail(reader: &mut impl Read, limit: usize) -> io::Result<Vec<u8>> {
+    let mut tail = Vec::with_capacity(2 * limit);
+    let mut chunk = [0; 4096];
+    loop {
+        let read = match reader.read(&mut chunk) {
+            Ok(0) => return Ok(tail),
+            Ok(read) => read,
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+            Err(error) => return Err(error),
+        };
+        tail.extend_from_slice(&chunk[..read]);
+        if tail.len() > limit {
+            tail.drain(..tail.len() - limit);
+        }
+    }
+}
+
+/** Kill every process in the group of `child`, which leads it. */
+fn kill_group(child: &Child) {
+    // Fails harmlessly once all of them have exited
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+}
+
+/** Append what the command wrote to stderr, if anything. */
+fn write_stderr(f: &mut std::fmt::Formatter, stderr: &str) -> std::fmt::Result {
+    if stderr.is_empty() {
+        return Ok(());
+    }
+    write!(f, ", its stderr ends with:\n{stderr}")
+}
+
+impl Display for ExecError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ExecError::Spawn(error) => write!(f, "could not start sh: {error}"),
+            ExecError::Io(error) => write!(f, "{error}"),
+            ExecError::Failed { status, stderr } => {
+                write!(f, "the command failed with {status}")?;
+                write_stderr(f, stderr)
+            }
+            ExecError::TimedOut(timeout) => write!(
+                f,
+                "the command was stopped after {}, raise --exec-timeout if it needs longer",
+                humantime::format_duration(*timeout)
+            ),
+            ExecError::TooLarge => write!(
+                f,
+                "the command wrote more than {} MiB",
+                MAX_BYTES / 1024 / 1024
+            ),
+            ExecError::NotAnImage { stderr } => {
+                write!(
+                    f,
+                    "the command did not write an image such as a PNG to stdout"
+                )?;
+                write_stderr(f, stderr)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PNG_SIGNATURE: &str = "\\211PNG\\r\\n\\032\\n";
+
+    #[test]
+    fn images_on_stdout_are_returned() {
+        let command = format!("printf '{PNG_SIGNATURE}'");
+        let Ok(stdout) = run(&command, Duration::from_secs(10), true) else {
+            panic!("{command} failed");
+        };
+        assert_eq!(stdout, b"\x89PNG\r\n\x1a\n");
+
+        let Ok(stdout) = run("echo hello", Duration::from_secs(10), false) else {
+            panic!("echo failed");
+        };
+        assert_eq!(stdout, b"hello\n");
+    }
+
+    #[test]
+    fn failures_keep_the_end_of_stderr() {
+        let result = run(
+            "echo starting >&2; echo no such album >&2; exit 3",
+            Duration::from_secs(10),
+            true,
+        );
+        let Err(ExecError::Failed { status, stderr }) = result else {
+            panic!("the command should have failed");
+        };
+        assert_eq!(status.code(), Some(3));
+        assert_eq!(stderr, "starting\nno such album");
+    }
+
+    #[test]
+    fn stderr_is_bounded() {
+        // A megabyte of noise, then the line that matters
+        let command =
+            "head -c 1048576 /dev/zero | tr '\\0' x >&2; echo >&2; echo the end >&2; exit 1";
+        let Err(ExecError::Failed { stderr, .. }) = run(command, Duration::from_secs(10), true)
+        else {
+            panic!("the command should have failed");
+        };
+        assert!(stderr.len() <= MAX_STDERR);
+        assert!(
+            stderr.ends_with("xxx\nthe end"),
+            "{}",
+            &stderr[stderr.len() - 20..]
+        );
+    }
+
+    #[test]
+    fn read_tail_keeps_the_last_bytes() {
+        let data: Vec<u8> = (0..10_000u32).map(|i| i as u8).collect();
+        assert_eq!(read_tail(&mut data.as_slice(), 100).unwrap(), data[9_900..]);
+        assert_eq!(read_tail(&mut &data[..50], 100).unwrap(), data[..50]);
+        assert!(read_tail(&mut io::empty(), 100).unwrap().is_empty());
+    }
+
+    #[test]
+    fn slow_commands_are_stopped() {
+        let started = Instant::now();
+        let result = run("sleep 30", Duration::from_millis(200), true);
+        assert!(matches!(
+            result,
+            Err(ExecError::TimedOut(timeout)) if timeout == Duration::from_millis(200)
+        ));
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn background_processes_do_not_hold_up_the_result() {
+        let started = Instant::now();
+        let result = run("sleep 30 & echo done", Duration::from_secs(20), false);
+        assert!(matches!(result, Ok(stdout) if stdout == b"done\n"));
+        assert!(started.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn other_output_is_not_an_image() {
+        let result = run(
+            "echo '<html>'; echo oops >&2",
+            Duration::from_secs(10),
+            true,
+        );
+        let Err(error @ ExecError::NotAnImage { .. }) = result else {
+            panic!("text should not pass for an image");
+        };
+        assert_eq!(
+            error.to_string(),
+            "the command did not write an image such as a PNG to stdout, its stderr ends \
+             with:\noops"
+        );
+    }
+}
@@ -0,0 +1,120 @@
+use std::{fmt::Display, io};
+
+/** Downloads larger than this are refused, so a misbehaving server can't exhaust the memory of
+ * a small board. */
+pub const MAX_BYTES: u64 = 32 * 1024 * 1024;
+
+/** Large images on a slow connection take a while to download. */
+#[cfg(feature = "http")]
+const TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+/** Why an HTTP request failed. */
+#[cfg_attr(not(feature = "http"), allow(dead_code))]
+pub enum RequestError {
+    /** The server answered with something other than success. */
+    Status(u16, String),
+    /** Connecting or reading the response failed, or timed out. */
+    Transport(String),
+}
+
+#[cfg_attr(not(feature = "http"), allow(dead_code))]
+pub enum FetchError {
+    Request(RequestError),
+    Io(io::Error),
+    TooLarge,
+    /** Built without the `http` feature. */
+    #[cfg(not(feature = "http"))]
+    Unsupported,
+}
+
+/** Whether the positional argument is a URL rather than a path. */
+pub fn is_url(source: &str) -> bool {
+    source.starts_with("http://") || source.starts_with("https://")
+}
+
+/** An HTTP client that allows `timeout` for connecting and for the whole response. */
+#[cfg(feature = "http")]
+pub fn agent(timeout: std::time::Duration) -> ureq::Agent {
+    return ureq::AgentBuilder::new()
+        .timeout_connect(timeout)
+        .timeout(timeout)
+        .build();
+}
+
+#[cfg(feature = "http")]
+impl From<ureq::Error> for RequestError {
+    fn from(error: ureq::Error) -> RequestError {
+        match error {
+            ureq::Error::Status(code, response) => {
+                RequestError::Status(code, response.status_text().to_string())
+            }
+            ureq::Error::Transport(transport) => RequestError::Transport(transport.to_string()),
+        }
+    }
+}
+
+/** Download the body of `url`, up to [MAX_BYTES]. The content type is ignored: the image crate
+ * tells the format from the bytes. */
+#[cfg(feature = "http")]
+pub fn download(url: &str) -> Result<Vec<u8>, FetchError> {
+    use std::io::Read;
+
+    let response = agent(TIMEOUT)
+        .get(url)
+        .call()
+        .map_err(|error| FetchError::Request(error.into()))?;
+    if response.status() != 200 {
+        let status = response.status_text().to_string();
+        let error = RequestError::Status(response.status(), status);
+        return Err(FetchError::Request(error));
+    }
+
+    let mut body = Vec::new();
+    response
+        .into_reader()
+        .take(MAX_BYTES + 1)
+        .read_to_end(&mut body)
+        .map_err(FetchError::Io)?;
+    if body.len() as u64 > MAX_BYTES {
+        return Err(FetchError::TooLarge);
+    }
+
+    return Ok(body);
+}
+
+#[cfg(not(feature = "http"))]
+pub fn download(_url: &str) -> Result<Vec<u8>, FetchError> {
+    Err(FetchError::Unsupported)
+}
+
+impl Display for RequestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RequestError::Status(code, text) => write!(f, "the server answered {code} {text}"),
+            RequestError::Transport(error) => write!(f, "{error}"),
+        }
+    }
+}
+
+impl Display for FetchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            FetchError::Request(error) => write!(f, "{error}"),
+            FetchError::Io(error) => write!(f, "{error}"),
+            FetchError::TooLarge => {
+                write!(
+                    f,
+                    "the image is larger than {} MiB",
+                    MAX_BYTES / 1024 / 1024
+                )
+            }
+            #[cfg(not(feature = "http"))]
+            FetchError::Unsupported => {
+                write!(
+                    f,
+                    "this build can't download images, rebuild with --features http"
+                )
+            }
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use std::io::Write;
+
+use chrono::{SecondsFormat, Utc};
+use clap::ValueEnum;
+use env_logger::{Builder, Env};
+use log::{
+    kv::{self, Key, Value, VisitSource},
+    LevelFilter, Record,
+};
+use serde_json::{Map, Number};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    /// Human readable lines
+    Text,
+    /// One JSON object per line, with the timestamp, level, message and any fields
+    Json,
+}
+
+/** The level for `-v` given `verbose` times: warnings by default, up to everything. */
+pub fn level(verbose: u8, quiet: bool) -> LevelFilter {
+    if quiet {
+        return LevelFilter::Error;
+    }
+    match verbose {
+        0 => LevelFilter::Warn,
+        1 => LevelFilter::Info,
+        2 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
+
+/** Set up logging to stderr at `level`. `RUST_LOG` still takes precedence when set, so
+ * individual modules can be turned up or down. */
+pub fn init(level: LevelFilter, format: LogFormat) {
+    let mut builder = Builder::new();
+    builder.filter_level(level);
+    builder.parse_env(Env::default());
+    if format == LogFormat::Json {
+        builder.format(|buf, record| {
+            let line = serde_json::Value::Object(json_record(record));
+            writeln!(buf, "{line}")
+        });
+    }
+    builder.init();
+}
+
+fn json_record(record: &Record) -> Map<String, serde_json::Value> {
+    let mut object = Map::new();
+    object.insert(
+        "timestamp".to_string(),
+        Utc::now()
+            .to_rfc3339_opts(SecondsFormat::Millis, true)
+            .into(),
+    );
+    object.insert("level".to_string(), record.level().as_str().into());
+    object.insert("target".to_string(), record.target().into());
+    object.insert("message".to_string(), record.args().to_string().into());
+    let mut fields = Fields(object);
+    // Fields are collected into a map, which can't fail
+    let _ = record.key_values().visit(&mut fields);
+    return fields.0;
+}
+
+/** Adds the key-value pairs of a record next to the message, keeping numbers and booleans as
+ * such. */
+struct Fields(Map<String, serde_json::Value>);
+
+impl<'kvs> VisitSource<'kvs> for Fields {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        let value = if let Some(n) = value.to_u64() {
+            n.into()
+        } else if let Some(n) = value.to_i64() {
+            n.into()
+        } else if let Some(n) = value.to_f64().and_then(Number::from_f64) {
+            n.into()
+        } else if let Some(b) = value.to_bool() {
+            b.into()
+        } else {
+            value.to_string().into()
+        };
+        self.0.insert(key.to_string(), value);
+        Ok(())
+    }
+}
@@ -1,14 +1,85 @@
-use std::{fs, path::Path};
+use std::{
+    fs,
+    io::{self, BufWriter, Cursor, IsTerminal, Read, Write as _},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    thread,
+    time::{Duration, Instant},
+};
 
-use clap::Parser as _;
-use cli::Cli;
-use epd::inky::Inky;
-use image::ImageReader;
-use quantize::{crop_resize, error::QuantizeError, fit_resize, image_buffer_into_vec, quantize};
+use chrono::{Datelike, Local, Utc};
 
+use buttons::{Button, Buttons};
+use clap::{Parser as _, ValueEnum as _};
+use cli::{Cli, Command, EepromCommand};
+use config::{error::ConfigError, Config};
+use daemon::{Cycle, Deferred, Schedule};
+use epd::{
+    error::{InkyError, Phase},
+    inky::{displayed_index, pack_pixels, Inky},
+    raw,
+    version::{PanelInfo, CRATE_VERSION, DRIVER},
+    wiring::{Wiring, WiringOverrides},
+    EPDType, Model,
+};
+use error::RunError;
+use image::{imageops::FilterType, DynamicImage, ImageFormat, RgbImage};
+use log::{debug, info, warn};
+use quantize::{
+    adjust::Adjustments,
+    caption::{self, CaptionFont, Corner},
+    collage::Layout,
+    decode,
+    dither::dither,
+    error::QuantizeError,
+    fidelity, image_buffer_into_vec, indexed, quantize, quantize_rgb, resize, rgb_image_into_vec,
+    rotate::{Orientation, Rotation},
+    sharpness, shrink, shrink_fit, text, visible_region, xmp, Fit, Settings,
+};
+use rand::{rngs::StdRng, SeedableRng};
+use render::{Canvas, Color, Pattern};
+use report::{DryRun, RunReport, Source, Summary, TimeBudget};
+use rppal::i2c::I2c;
+use select::{
+    choose::Chooser,
+    criteria::{Criteria, Scores},
+    error::SelectError,
+    gather::Sources,
+    pin::Pinned,
+    season::MonthDay,
+    Candidate, Filter, Listing, Selection, STDIN,
+};
+use state::{Shown, Step};
+use sysinfo::SystemInfo;
+use webhook::Payload;
+
+mod bench; // SPI throughput benchmark
+mod benchmark; // Timing the rendering pipeline
+mod buttons; // The buttons on the side of the panel
 mod cli; // Cli options
+mod config; // Configuration file
+mod daemon; // Timing of repeated refreshes
+mod emit; // Writing frames to stdout
 mod epd; // Driver for the e-paper display
+mod error; // Errors of a whole run
+mod exec; // Running a command for an image
+mod fetch; // Downloading images over HTTP
+mod logging; // Log level and format
+mod mqtt; // Receiving images over MQTT
+mod pipe; // Receiving images through a named pipe
+mod preview; // Drawing frames on the terminal
+mod qr; // QR codes for the qr command
 mod quantize; // Image quantization
+mod render; // Drawing generated screens
+mod report; // Summary of a run
+mod select; // Choosing which file to display
+mod selftest; // Hardware bring-up checks
+mod serve; // Receiving images over HTTP
+mod shutdown; // Stopping cleanly on SIGINT and SIGTERM
+mod state; // History and stats kept between runs
+mod sysinfo; // System information dashboard
+mod webhook; // Notifying a URL after each refresh
+mod window; // Previewing frames in a desktop window
 
 const DESATURATED_PALETTE: &[[u8; 4]] = &[
     [0, 0, 0, 255],       // Black
@@ -51,56 +122,1920 @@ fn get_palette(saturation: f64) -> Vec<imagequant::RGBA> {
         .collect()
 }
 
-fn palettize_file(
+/** Height in pixels of the labels of `preview --compare`. */
+const COMPARISON_LABEL_SIZE: u32 = 24;
+
+/** Panel size for `--dry-run`, that of the AC073TC1. */
+const DRY_RUN_WIDTH: usize = 800;
+const DRY_RUN_HEIGHT: usize = 480;
+
+/** Read an image piped into stdin. */
+fn read_stdin() -> Result<Vec<u8>, QuantizeError> {
+    let mut stdin = io::stdin().lock();
+    if stdin.is_terminal() {
+        return Err(QuantizeError::TerminalInput);
+    }
+    let mut bytes = Vec::new();
+    stdin.read_to_end(&mut bytes)?;
+
+    return Ok(bytes);
+}
+
+/** Decode the image at `path` and bring it to `width` × `height` in the panel's orientation as
+ * `fit` says, with its caption and the timestamp. The aspect ratio is matched upright and the
+ * result turned and mirrored to the panel afterwards. Bands left by fitting get the
+ * `--background` color from `palette`. Also returns the decoded size, the part of the image that
+ * was used and how long it took. */
+fn load_file(
+    cli: &Cli,
+    fit: Fit,
+    palette: &[imagequant::RGBA],
+    width: u32,
+    height: u32,
+    path: &Path,
+) -> Result<(DynamicImage, Source), QuantizeError> {
+    let (width, height) = cli.rotate.upright_size(width, height);
+    let (image, source) = load_upright(cli, fit, palette, width, height, path)?;
+    let image = draw_timestamp(cli, image)?;
+    return Ok((orientation(cli).apply(image), source));
+}
+
+/** Like [load_file], but leave the image upright at `width` × `height` and without the
+ * timestamp, such as for a cell of a collage. */
+fn load_upright(
+    cli: &Cli,
+    fit: Fit,
     palette: &[imagequant::RGBA],
-    no_crop: bool,
     width: u32,
     height: u32,
     path: &Path,
+) -> Result<(DynamicImage, Source), QuantizeError> {
+    let started = Instant::now();
+    let url = path.to_str().filter(|source| fetch::is_url(source));
+    let bytes = if path == Path::new(STDIN) {
+        read_stdin()?
+    } else if let Some(command) = cli.exec.as_deref().filter(|&c| path == Path::new(c)) {
+        exec::run(command, cli.exec_timeout, !cli.as_text)?
+    } else if let Some(url) = url {
+        fetch::download(url)?
+    } else {
+        fs::read(path)?
+    };
+    let original_image = if text::is_text(cli.as_text, path) {
+        let font = text::font(cli.caption_font.as_deref())?;
+        let contents = String::from_utf8_lossy(&bytes);
+        text::render(&contents, &font, cli.text_size, width, height)
+    } else {
+        decode::decode(bytes, path)?
+    };
+    let decode = started.elapsed();
+    let (original_width, original_height) = (original_image.width(), original_image.height());
+    let (original_image, (x, y, ..)) = xmp::apply_sidecar_crop(path, original_image);
+
+    let started = Instant::now();
+    let background = cli.background.rgba(palette);
+    let (image_width, image_height) = (original_image.width(), original_image.height());
+    let (mut image, fit) = if cli.no_upscale {
+        let image = shrink(fit, width, height, background, &original_image);
+        (
+            image,
+            shrink_fit(fit, width, height, image_width, image_height),
+        )
+    } else {
+        (resize(fit, width, height, background, &original_image), fit)
+    };
+    let (crop_x, crop_y, crop_width, crop_height) =
+        visible_region(fit, width, height, image_width, image_height);
+    let source = Source {
+        width: original_width,
+        height: original_height,
+        crop: (x + crop_x, y + crop_y, crop_width, crop_height),
+        decode,
+        resize: started.elapsed(),
+    };
+    if let Some(template) = &cli.caption {
+        let font = CaptionFont::load(cli.caption_font.as_deref())?;
+        let text = caption::expand(template, path);
+        image = caption::draw(image, &text, &font, cli.caption_size, cli.caption_position);
+    }
+    return Ok((image, source));
+}
+
+/** Write the time into a corner of an upright image for `--timestamp`. It is drawn after the
+ * caption, so it stays readable where they overlap. */
+fn draw_timestamp(cli: &Cli, image: DynamicImage) -> Result<DynamicImage, QuantizeError> {
+    let Some(format) = &cli.timestamp else {
+        return Ok(image);
+    };
+    let font = CaptionFont::load(cli.caption_font.as_deref())?;
+    let text = caption::timestamp(format, cli.timestamp_utc);
+    return Ok(caption::draw(
+        image,
+        &text,
+        &font,
+        cli.timestamp_size,
+        cli.timestamp_position,
+    ));
+}
+
+/** Quantize an image into palette indices, with libimagequant or, if `deterministic`, with
+ * the crate's own dithering whose output never changes between runs or versions. */
+fn palettize_image(
+    palette: &[imagequant::RGBA],
+    adjustments: &Adjustments,
+    settings: Settings,
+    image: DynamicImage,
+    deterministic: bool,
 ) -> Result<Vec<u8>, QuantizeError> {
-    let original_image = ImageReader::open(path)?.decode()?;
-    let image = if no_crop {
-        fit_resize(width, height, &original_image)
+    let width = image.width() as usize;
+    let height = image.height() as usize;
+
+    if deterministic {
+        let mut in_buffer = image_buffer_into_vec(image.into_rgba8());
+        adjustments.apply(&mut in_buffer);
+        return Ok(dither(
+            palette,
+            width,
+            height,
+            settings.dither_strength,
+            |i| in_buffer[i],
+        ));
+    }
+
+    // Most photos have no alpha channel, so skip widening them to RGBA
+    let started = Instant::now();
+    let out_buffer = if image.color().has_alpha() {
+        let mut in_buffer = image_buffer_into_vec(image.into_rgba8());
+        adjustments.apply(&mut in_buffer);
+        quantize(palette, settings, width, height, in_buffer.into())?
     } else {
-        crop_resize(width, height, &original_image)
+        let mut in_buffer = rgb_image_into_vec(image.into_rgb8());
+        adjustments.apply_rgb(&mut in_buffer);
+        quantize_rgb(palette, settings, width, height, &in_buffer)?
     };
-    let width = image.width();
-    let height = image.height();
-    let in_buffer = image_buffer_into_vec(image.into_rgba8());
-    let out_buffer = quantize(&palette, width as usize, height as usize, in_buffer.into())?;
+    info!(
+        "Quantized at speed {} in {:.2?}",
+        settings.speed,
+        started.elapsed()
+    );
 
     return Ok(out_buffer);
 }
 
-fn main() {
-    env_logger::init();
+/** Pick a random candidate, re-rolling ones that miss the sharpness or color error thresholds,
+ * if set. A single candidate is used as it is. Files that can't be read are skipped. */
+fn choose_image(
+    cli: &Cli,
+    candidates: &mut Vec<Candidate>,
+    rng: &mut StdRng,
+    width: u32,
+    height: u32,
+    palette: &[imagequant::RGBA],
+) -> Result<(PathBuf, DynamicImage, Scores, Source), QuantizeError> {
+    let criteria = Criteria {
+        min_sharpness: cli.min_sharpness,
+        max_quant_error: cli.max_quant_error,
+    };
+    let load = |path: &Path| load_file(cli, fit(cli), palette, width, height, path);
+    let score = |image: &DynamicImage| Scores {
+        sharpness: criteria.min_sharpness.map(|_| sharpness(image)),
+        quant_error: criteria.max_quant_error.map(|_| {
+            cli.quant_error_metric
+                .of(&fidelity::measure(palette, image))
+        }),
+    };
+    return chooser(cli).choose(criteria, candidates, rng, load, score);
+}
+
+/** Take the files for a collage in `layout` from the candidates and put them together at
+ * `width` × `height` in the panel's orientation, with the timestamp drawn once over the whole.
+ * Files are repeated to fill the cells when there are too few and `--collage-repeat` is given.
+ * Returns the distinct files, and the first one's source with the time spent on all of them.
+ * The sharpness and color error thresholds don't apply, as they judge single images. */
+fn choose_collage(
+    cli: &Cli,
+    layout: Layout,
+    candidates: &mut Vec<Candidate>,
+    rng: &mut StdRng,
+    width: u32,
+    height: u32,
+    palette: &[imagequant::RGBA],
+) -> Result<(Vec<PathBuf>, DynamicImage, Source), RunError> {
+    let cells = layout.cells();
+    let (width, height) = cli.rotate.upright_size(width, height);
+    let gutter = cli.collage_gutter;
+    let (cell_width, cell_height) = layout.cell_size(width, height, gutter);
+    let load = |path: &Path| load_upright(cli, fit(cli), palette, cell_width, cell_height, path);
+    let (files, loaded) =
+        chooser(cli).take_cells::<_, RunError>(cells, cli.collage_repeat, candidates, rng, load)?;
+    let (images, sources): (Vec<DynamicImage>, Vec<Source>) = loaded.into_iter().unzip();
+    let source = Source {
+        decode: sources.iter().map(|source| source.decode).sum(),
+        resize: sources.iter().map(|source| source.resize).sum(),
+        ..sources[0]
+    };
+    let cells: Vec<DynamicImage> = images.into_iter().cycle().take(cells).collect();
+    let gutter_color = cli.collage_gutter_color.rgba(palette);
+    let image = layout.compose(width, height, gutter, gutter_color, &cells);
+    let image = draw_timestamp(cli, image)?;
+    return Ok((files, orientation(cli).apply(image), source));
+}
 
-    let cli = Cli::parse();
+/** Quantize the image at `path` at `steps` evenly spaced saturations from 0 to 1. */
+fn render_saturations(
+    cli: &Cli,
+    path: &Path,
+    width: usize,
+    height: usize,
+    steps: u32,
+) -> Result<Vec<(f64, Canvas)>, QuantizeError> {
     let palette = get_palette(cli.saturation);
+    let (width, height) = (width as u32, height as u32);
+    let (image, _) = load_file(cli, fit(cli), &palette, width, height, path)?;
+    let mut renderings = Vec::new();
+    for step in 0..steps {
+        let saturation = step as f64 / (steps - 1) as f64;
+        info!("Rendering at saturation {saturation:.2}");
+        let pixels = palettize_image(
+            &get_palette(saturation),
+            &cli_adjustments(cli),
+            quantize_settings(cli),
+            image.clone(),
+            cli.deterministic,
+        )?;
+        let canvas = Canvas {
+            width: width as usize,
+            height: height as usize,
+            pixels,
+        };
+        renderings.push((saturation, canvas));
+    }
+    return Ok(renderings);
+}
 
-    let mut inky = Inky::new().unwrap();
-    let width = inky.eeprom.width as usize;
-    let height = inky.eeprom.height as usize;
-
-    let dir: Vec<fs::DirEntry> = fs::read_dir(Path::new(&cli.dir))
-        .unwrap()
-        .map(|e| e.unwrap())
+/** Lay renderings at different saturations out in a grid about as wide as it is tall, turned
+ * upright and labeled with their saturation. */
+fn saturation_sheet(rotation: Rotation, renderings: &[(f64, Canvas)]) -> Canvas {
+    let cells: Vec<(Canvas, String)> = renderings
+        .iter()
+        .map(|(saturation, canvas)| {
+            let cell = upright(rotation, canvas.clone());
+            (cell, format!("saturation {saturation:.2}"))
+        })
         .collect();
+    let columns = (cells.len() as f64).sqrt().ceil() as usize;
+    return render::contact_sheet(&cells, columns);
+}
 
-    let infile = &dir[rand::random_range(0..dir.len())];
+/** Render the image at `path` for a `width` × `height` panel and put the resized original and
+ * the rendering next to each other as they hang, labeled with the fit. With `alternative`, the
+ * rendering with the other of cover and contain is added. Renderings are drawn in the saturated
+ * palette, which is closer to what the panel shows. */
+fn render_comparison(
+    cli: &Cli,
+    path: &Path,
+    width: u32,
+    height: u32,
+    alternative: bool,
+) -> Result<RgbImage, QuantizeError> {
+    let palette = get_palette(cli.saturation);
+    let font = CaptionFont::load(cli.caption_font.as_deref())?;
+    let undo = Orientation {
+        rotation: cli.rotate.inverse(),
+        ..Orientation::default()
+    };
+    let label = |image: DynamicImage, text: &str| {
+        caption::draw(image, text, &font, COMPARISON_LABEL_SIZE, Corner::TopLeft).into_rgb8()
+    };
+
+    let fit = fit(cli);
+    let mut fits = vec![fit];
+    if alternative {
+        fits.push(if fit == Fit::Cover {
+            Fit::Contain
+        } else {
+            Fit::Cover
+        });
+    }
+    let mut panes = Vec::new();
+    for (i, &fit) in fits.iter().enumerate() {
+        let (image, _) = load_file(cli, fit, &palette, width, height, path)?;
+        if i == 0 {
+            panes.push(label(undo.apply(image.clone()), "original"));
+        }
+        let pixels = palettize_image(
+            &palette,
+            &cli_adjustments(cli),
+            quantize_settings(cli),
+            image,
+            cli.deterministic,
+        )?;
+        let canvas = Canvas {
+            width: width as usize,
+            height: height as usize,
+            pixels,
+        };
+        let rendering = upright(cli.rotate, canvas).to_rgb(SATURATED_PALETTE);
+        let name = fit.to_possible_value().unwrap();
+        panes.push(label(rendering.into(), name.get_name()));
+    }
+    return Ok(render::side_by_side(&panes));
+}
+
+/** Time every stage of rendering the image at `path` for a `width` × `height` panel `repeats`
+ * times: decoding, resizing with cover and contain and, for comparison, with cover in a single
+ * pass, quantizing at speeds 1, 5 and 10, and, if
+ * `packing`, packing the frame into the bytes sent to the panel. */
+fn run_benchmark(
+    cli: &Cli,
+    path: &Path,
+    (width, height): (u32, u32),
+    repeats: u32,
+    packing: bool,
+) -> Result<Vec<benchmark::Stage>, QuantizeError> {
+    let palette = get_palette(cli.saturation);
+    let background = cli.background.rgba(&palette);
+    let bytes = fs::read(path)?;
+    let mut stages = Vec::new();
+
+    let (stage, image) =
+        benchmark::measure("decode", repeats, || decode::decode(bytes.clone(), path))?;
+    stages.push(stage);
+    info!("Decoded {}x{}", image.width(), image.height());
+
+    let mut resized = None;
+    for fit in [Fit::Cover, Fit::Contain] {
+        let name = format!("resize {}", fit.to_possible_value().unwrap().get_name());
+        let (stage, image) = benchmark::measure(&name, repeats, || {
+            Ok::<_, QuantizeError>(resize(fit, width, height, background, &image))
+        })?;
+        stages.push(stage);
+        resized.get_or_insert(image);
+    }
+
+    // What cover takes without reducing the image first, to weigh what that saves
+    let (stage, _) = benchmark::measure("resize single pass", repeats, || {
+        let (x, y, crop_width, crop_height) =
+            visible_region(Fit::Cover, width, height, image.width(), image.height());
+        let cropped = image.crop_imm(x, y, crop_width, crop_height);
+        Ok::<_, QuantizeError>(cropped.resize_exact(width, height, FilterType::Lanczos3))
+    })?;
+    stages.push(stage);
+
+    let resized = resized.unwrap();
+    let mut pixels = Vec::new();
+    for speed in [1, 5, 10] {
+        let settings = Settings {
+            speed,
+            ..quantize_settings(cli)
+        };
+        let name = format!("quantize speed {speed}");
+        let (stage, quantized) = benchmark::measure(&name, repeats, || {
+            let adjustments = cli_adjustments(cli);
+            palettize_image(&palette, &adjustments, settings, resized.clone(), false)
+        })?;
+        stages.push(stage);
+        pixels = quantized;
+    }
+
+    if packing {
+        let mut packed = Vec::new();
+        let (stage, ()) = benchmark::measure("pack", repeats, || {
+            pack_pixels(&pixels, &mut packed);
+            Ok::<_, QuantizeError>(())
+        })?;
+        stages.push(stage);
+    }
+
+    return Ok(stages);
+}
+
+/** Where candidates are gathered from and which are kept, as the command line sets it up. */
+fn sources(cli: &Cli) -> Sources {
+    let weekday = match cli.utc {
+        true => Utc::now().weekday(),
+        false => Local::now().weekday(),
+    };
+    return Sources {
+        paths: cli.paths.clone(),
+        exec: cli.exec.clone(),
+        listing: Listing {
+            max_depth: match cli.recursive {
+                true => cli.max_depth.unwrap_or(usize::MAX),
+                false => 0,
+            },
+            follow_symlinks: cli.follow_symlinks,
+            include_hidden: cli.include_hidden,
+        },
+        filter: cli_filter(cli),
+        weights: cli.weight.clone(),
+        season_map: cli.season_map.clone(),
+        today: MonthDay::from_date(&Local::now()),
+        weekday: cli.by_weekday.then_some(weekday),
+    };
+}
+
+/** The filter set up by the command line. */
+fn cli_filter(cli: &Cli) -> Filter {
+    return Filter {
+        all_files: cli.all_files,
+        include: cli.include.clone(),
+        exclude: cli.exclude.clone(),
+        max_file_size: cli.max_file_size,
+        // The image is turned to hang the way the panel does, so it is compared the same way
+        min_resolution: Some(cli.min_resolution)
+            .filter(|&(width, height)| width > 0 || height > 0)
+            .map(|(width, height)| cli.rotate.upright_size(width, height)),
+    };
+}
+
+/** How files are taken from the candidates, as the command line sets it up. */
+fn chooser(cli: &Cli) -> Chooser {
+    return Chooser {
+        selection: cli.select,
+        max_attempts: cli.max_attempts,
+    };
+}
+
+/** The candidates to pick a new image from: those gathered from the command line, or from the
+ * next of its paths with `--rotate-sources`, narrowed down to the photos taken on this day with
+ * `--on-this-day` and to the ones not shown yet unless that is turned off. */
+fn candidate_pool(cli: &Cli, state_dir: &Path) -> Result<Vec<Candidate>, SelectError> {
+    let mut candidates = match cli.rotate_sources {
+        true => sources(cli).next_source(&state_dir.join(state::sources::FILE_NAME), true)?,
+        false => sources(cli).candidates()?,
+    };
+    if cli.on_this_day {
+        let cache = (!cli.no_state).then(|| state_dir.join(select::on_this_day::FILE_NAME));
+        candidates = taken_on_this_day(cache.as_deref(), candidates);
+    }
+    let needed = cli.collage.map_or(1, Layout::cells);
+    if cli.history > 0 {
+        let path = history_file(cli, state_dir);
+        candidates = narrow(candidates, needed, |candidates| {
+            state::history::exclude_recent(&path, cli.history, candidates)
+        });
+    }
+    if let Some(path) = shuffle_state(cli, state_dir) {
+        candidates = narrow(candidates, needed, |candidates| {
+            state::shuffle::unseen(&path, candidates)
+        });
+    }
+    return Ok(candidates);
+}
+
+/** The `candidates` narrowed down by `by`, unless there is no choice to begin with or it would
+ * leave fewer than the `needed` files, such as for the cells of a collage. */
+fn narrow(
+    candidates: Vec<Candidate>,
+    needed: usize,
+    by: impl FnOnce(Vec<Candidate>) -> Vec<Candidate>,
+) -> Vec<Candidate> {
+    if candidates.len() <= needed {
+        return candidates;
+    }
+    let narrowed = by(candidates.clone());
+    if narrowed.len() < needed {
+        debug!(
+            "Choosing from all {} files to fill {needed}",
+            candidates.len()
+        );
+        return candidates;
+    }
+    return narrowed;
+}
+
+/** The photos among the `candidates` taken on today's date, for `--on-this-day`, keeping their
+ * dates in the `cache` file if given. */
+fn taken_on_this_day(cache: Option<&Path>, candidates: Vec<Candidate>) -> Vec<Candidate> {
+    let today = MonthDay::from_date(&Local::now());
+    return select::on_this_day::narrow(cache, today, candidates);
+}
+
+/** The candidates for a run: the given file (such as a deferred or pinned one) if it still
+ * exists, otherwise the [candidate_pool]. */
+fn candidates_for(
+    cli: &Cli,
+    state_dir: &Path,
+    chosen: Option<PathBuf>,
+) -> Result<Vec<Candidate>, SelectError> {
+    match chosen {
+        Some(path) if path.exists() => Ok(vec![Candidate::new(path)]),
+        Some(path) => {
+            warn!("{} no longer exists", path.display());
+            candidate_pool(cli, state_dir)
+        }
+        None => candidate_pool(cli, state_dir),
+    }
+}
+
+/** Choose an image from the candidates, or the files of a `collage`, and quantize it into
+ * palette indices. Returns the files shown, the first one for a single image. The time spent
+ * choosing excludes decoding and resizing the chosen images, which are budgeted on their own. */
+#[allow(clippy::too_many_arguments)]
+fn render_next(
+    cli: &Cli,
+    candidates: &mut Vec<Candidate>,
+    collage: Option<Layout>,
+    rng: &mut StdRng,
+    width: u32,
+    height: u32,
+    palette: &[imagequant::RGBA],
+    adjustments: &Adjustments,
+    budget: &mut TimeBudget,
+) -> Result<(Vec<PathBuf>, Vec<u8>, Scores, Source), RunError> {
+    let step = Instant::now();
+    let (files, image, scores, source) = match collage {
+        Some(layout) => {
+            let (files, image, source) =
+                choose_collage(cli, layout, candidates, rng, width, height, palette)?;
+            (files, image, Scores::default(), source)
+        }
+        None => {
+            let (infile, image, scores, source) =
+                choose_image(cli, candidates, rng, width, height, palette)?;
+            (vec![infile], image, scores, source)
+        }
+    };
+    let choose = step.elapsed().saturating_sub(source.decode + source.resize);
+    budget.steps.push(("choose".to_string(), choose));
+    budget.steps.push(("decode".to_string(), source.decode));
+    budget.steps.push(("resize".to_string(), source.resize));
+    if cli.print_choice {
+        for file in &files {
+            println!("{}", absolute(file).display());
+        }
+    }
+
+    let step = Instant::now();
+    let infile = &files[0];
+    // Text has to be drawn over the image, so an already indexed one is quantized again
+    let indexed = if cli.caption.is_some() || cli.timestamp.is_some() || collage.is_some() {
+        None
+    } else {
+        let (upright_width, upright_height) = cli.rotate.upright_size(width, height);
+        let size = (upright_width, upright_height);
+        indexed::passthrough(infile, palette, size, cli.strict_passthrough)?
+    };
+    let buffer = match indexed {
+        Some(indexed) => {
+            info!(
+                "{} already uses the panel's colors, skipping quantization",
+                infile.display()
+            );
+            let background = cli.background.index(palette);
+            let orientation = orientation(cli);
+            let (upright_width, upright_height) = cli.rotate.upright_size(width, height);
+            let indices = indexed::resize_indices(
+                indexed,
+                upright_width,
+                upright_height,
+                fit(cli),
+                !cli.no_upscale,
+                background,
+            );
+            orientation
+                .apply_indices(upright_width, upright_height, indices)
+                .2
+        }
+        None => {
+            let mut settings = quantize_settings(cli);
+            // Dithering would only scatter specks around the letters
+            if collage.is_none() && text::is_text(cli.as_text, infile) {
+                settings.dither_strength = 0.0;
+            }
+            palettize_image(palette, adjustments, settings, image, cli.deterministic)?
+        }
+    };
+    budget.steps.push(("quantize".to_string(), step.elapsed()));
+
+    return Ok((files, buffer, scores, source));
+}
+
+/** `path` made absolute with symlinks resolved, or as it is if that fails, such as for URLs
+ * and stdin. */
+fn absolute(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/** The tone adjustments given on the command line, before any schedule window. */
+fn cli_adjustments(cli: &Cli) -> Adjustments {
+    return Adjustments {
+        brightness: cli.brightness,
+        contrast: cli.contrast,
+        gamma: cli.gamma,
+        ..Adjustments::default()
+    };
+}
+
+/** The palette and adjustments to use now, after applying the active schedule window, whose
+ * name is returned as well. */
+fn resolve_schedule(
+    cli: &Cli,
+    config: &Config,
+) -> (Vec<imagequant::RGBA>, f64, Adjustments, Option<String>) {
+    let mut saturation = cli.saturation;
+    let mut adjustments = cli_adjustments(cli);
+    let window = config.schedule.resolve(Local::now().time());
+    if let Some((name, window)) = window {
+        info!("Schedule window {name} is active");
+        window.overrides.apply(&mut saturation, &mut adjustments);
+    }
+    let name = window.map(|(name, _)| name.to_string());
+    return (get_palette(saturation), saturation, adjustments, name);
+}
+
+/** How to bring images to the panel's size, with `--no-crop` standing for `--fit contain`. */
+fn fit(cli: &Cli) -> Fit {
+    if cli.no_crop {
+        return Fit::Contain;
+    }
+    return cli.fit;
+}
+
+/** The quantizer settings given on the command line. */
+fn quantize_settings(cli: &Cli) -> Settings {
+    return Settings {
+        dither_strength: cli.dither_strength,
+        speed: cli.quant_speed,
+    };
+}
+
+/** How images are turned and mirrored onto the panel. */
+fn orientation(cli: &Cli) -> Orientation {
+    return Orientation {
+        rotation: cli.rotate,
+        hflip: cli.hflip,
+        vflip: cli.vflip,
+    };
+}
+
+/** A canvas in the panel's orientation turned back upright, the way the frame hangs. Flips
+ * are kept, so it shows what is on the glass. */
+fn upright(rotation: Rotation, canvas: Canvas) -> Canvas {
+    let undo = Orientation {
+        rotation: rotation.inverse(),
+        ..Orientation::default()
+    };
+    let (width, height, pixels) =
+        undo.apply_indices(canvas.width as u32, canvas.height as u32, canvas.pixels);
+    return Canvas {
+        width: width as usize,
+        height: height as usize,
+        pixels,
+    };
+}
+
+/** A whole frame of palette indices turned upright, as it is seen on the wall. */
+fn upright_frame(cli: &Cli, width: usize, height: usize, frame: &[u8]) -> Canvas {
+    let canvas = Canvas {
+        width,
+        height,
+        pixels: frame.to_vec(),
+    };
+    return upright(cli.rotate, canvas);
+}
+
+/** Save, emit or preview a whole frame of palette indices, as asked for on the command line.
+ * The saved image and the preview are turned upright, the emitted frame is exactly what the
+ * panel gets. */
+fn write_outputs(
+    cli: &Cli,
+    width: usize,
+    height: usize,
+    frame: &[u8],
+    palette: &[imagequant::RGBA],
+) -> Result<(), RunError> {
+    let shown = || upright_frame(cli, width, height, frame);
+    if let Some(path) = &cli.output {
+        shown()
+            .to_rgb(DESATURATED_PALETTE)
+            .save(path)
+            .map_err(|error| RunError::Output(path.clone(), error))?;
+        info!("Wrote {}", path.display());
+    }
+    if let Some(path) = &cli.save_raw {
+        raw::save(path, width, height, frame)?;
+        info!("Wrote {}", path.display());
+    }
 
-    let buffer = palettize_file(
+    if let Some(format) = cli.emit {
+        let stdout = BufWriter::new(io::stdout().lock());
+        match emit::write_frame(stdout, format, width, height, frame, palette) {
+            Err(error) if error.kind() == io::ErrorKind::BrokenPipe => {
+                warn!("Stdout was closed, the frame was not emitted")
+            }
+            result => result?,
+        }
+    }
+    if cli.preview_term {
+        let mode = preview::Mode::detect();
+        let canvas = shown();
+        let text = preview::render(
+            canvas.width,
+            canvas.height,
+            &canvas.pixels,
+            palette,
+            preview::columns(),
+            mode,
+        );
+        // Written in one go so log lines from other threads can't end up inside the picture
+        if let Err(error) = io::stdout().lock().write_all(text.as_bytes()) {
+            warn!("Could not draw the preview: {error}");
+        }
+    }
+    if cli.preview_window {
+        let canvas = shown();
+        window::show(canvas.width, canvas.height, &canvas.pixels, palette)?;
+    }
+    return Ok(());
+}
+
+/** Render the next image at the size given on the command line without touching any hardware,
+ * and print what would have been displayed. */
+fn dry_run(
+    cli: &Cli,
+    config: &Config,
+    state_dir: &Path,
+    rng: &mut StdRng,
+) -> Result<DryRun, RunError> {
+    let (palette, _, adjustments, _) = resolve_schedule(cli, config);
+    let width = cli.width.map_or(DRY_RUN_WIDTH, usize::from);
+    let height = cli.height.map_or(DRY_RUN_HEIGHT, usize::from);
+    let margins = cli.margin.unwrap_or_default();
+    let (inner_width, inner_height) =
+        margins
+            .inner(width, height)
+            .ok_or(InkyError::InvalidMargins {
+                margins,
+                width,
+                height,
+            })?;
+
+    // A dry run shows the next source, but leaves it next
+    let mut candidates = match cli.rotate_sources {
+        true => sources(cli).next_source(&state_dir.join(state::sources::FILE_NAME), false)?,
+        false => sources(cli).candidates()?,
+    };
+    if cli.on_this_day {
+        // A dry run leaves the state directory alone, so the dates are read every time
+        candidates = taken_on_this_day(None, candidates);
+    }
+    let mut budget = TimeBudget::default();
+    let (mut files, pixels, scores, _) = render_next(
+        cli,
+        &mut candidates,
+        cli.collage,
+        rng,
+        inner_width as u32,
+        inner_height as u32,
         &palette,
-        cli.no_crop,
-        width as u32,
-        height as u32,
-        infile.path().as_path(),
-    )
-    .unwrap_or_else(quantize::error::handle_error);
+        &adjustments,
+        &mut budget,
+    )?;
+
+    // Put together the frame like the panel's buffer would be
+    let mut canvas = Canvas::new(width, height, cli.margin_color.index());
+    let image = Canvas {
+        width: inner_width,
+        height: inner_height,
+        pixels,
+    };
+    canvas.blit(margins.left, margins.top, &image);
+    let frame: Vec<u8> = canvas
+        .pixels
+        .iter()
+        .map(|&px| displayed_index(px))
+        .collect();
+
+    let mut summary = DryRun::new(files.remove(0), scores, width, height, &frame);
+    summary.collage = files;
+    write_outputs(cli, width, height, &frame, &palette)?;
+    return Ok(summary);
+}
+
+/** Choose, render and display the next image or `collage`, or the given one (such as a
+ * deferred or pinned file). `started` is when the run began, for the time budget.
+ *
+ * The panel is reset and initialized on another thread while the image is being rendered. */
+fn display_next(
+    cli: &Cli,
+    config: &Config,
+    inky: &mut Inky,
+    mut candidates: Vec<Candidate>,
+    collage: Option<Layout>,
+    rng: &mut StdRng,
+    started: Instant,
+) -> Result<RunReport, RunError> {
+    let mut budget = TimeBudget::default();
+    budget
+        .steps
+        .push(("startup".to_string(), started.elapsed()));
+
+    let (palette, saturation, adjustments, window) = resolve_schedule(cli, config);
+    let (width, height) = inky.dimensions();
+
+    let (rendered, prepared) = thread::scope(|scope| {
+        let setup = scope.spawn(|| {
+            if cli.emit_only {
+                Ok(())
+            } else {
+                inky.prepare()
+            }
+        });
+
+        let rendered = render_next(
+            cli,
+            &mut candidates,
+            collage,
+            rng,
+            width as u32,
+            height as u32,
+            &palette,
+            &adjustments,
+            &mut budget,
+        );
+        (rendered, setup.join().unwrap())
+    });
+    let (mut files, buffer, scores, source) = rendered?;
+    prepared?;
+
+    let mut report = RunReport::new(files.remove(0));
+    report.collage = files;
+    report.scores = scores;
+    report.schedule_window = window;
+    report.saturation = saturation;
+    report.fit = fit(cli);
+    report.source = Some(source);
+    report.histogram = report::histogram(&buffer);
 
     for (ix, px) in buffer.iter().enumerate() {
         inky.set_pixel(ix % width, ix / width, *px);
     }
 
-    inky.show().unwrap();
+    let (width, height, frame) = inky.frame();
+    report.panel = (width, height);
+    write_outputs(cli, width, height, &frame, &palette)?;
+    if cli.emit_only {
+        report.time_budget = budget;
+        return Ok(report);
+    }
+
+    let before_show = started.elapsed();
+    let show_started = Instant::now();
+    if cli.no_refresh {
+        let bytes = inky.transmit()?;
+        let elapsed = show_started.elapsed();
+        warn!(
+            "Transmitted {bytes} bytes in {elapsed:.2?} ({:.0} KiB/s) without refreshing, the \
+             panel was left as it was",
+            bytes as f64 / 1024.0 / elapsed.as_secs_f64().max(f64::EPSILON)
+        );
+    } else {
+        inky.show()?;
+        report.refresh_duration = Some(show_started.elapsed());
+    }
+    if cli.sleep_after {
+        inky.sleep()?;
+    }
+
+    budget.until_refresh = before_show;
+    for &(phase, duration) in inky.timings() {
+        match phase {
+            Phase::Setup => budget.parallel.push((phase.to_string(), duration)),
+            Phase::Transmit | Phase::PowerOn => {
+                budget.until_refresh += duration;
+                budget.steps.push((phase.to_string(), duration));
+            }
+            _ => budget.steps.push((phase.to_string(), duration)),
+        }
+    }
+    report.time_budget = budget;
+
+    return Ok(report);
+}
+
+fn load_config(cli: &Cli) -> Result<Config, ConfigError> {
+    match &cli.config {
+        Some(path) => Config::load(path),
+        None => Ok(Config::default()),
+    }
+}
+
+/** The file recording which images were shown this round, unless disabled. `--select newest`
+ * doesn't use it, as it should pick the newest file every time. */
+fn shuffle_state(cli: &Cli, state_dir: &Path) -> Option<PathBuf> {
+    if cli.no_state || cli.select == Selection::Newest {
+        return None;
+    }
+    let path = cli.shuffle_state.clone();
+    return Some(path.unwrap_or_else(|| state_dir.join(state::shuffle::FILE_NAME)));
+}
+
+/** The file describing what is on the panel, for other programs. */
+fn showing_file(cli: &Cli, state_dir: &Path) -> PathBuf {
+    let path = cli.state_file.clone();
+    return path.unwrap_or_else(|| state_dir.join(state::showing::FILE_NAME));
+}
+
+/** The file listing displayed images, for `--history`, `previous`, `next` and `stats`. */
+fn history_file(cli: &Cli, state_dir: &Path) -> PathBuf {
+    let path = cli.history_file.clone();
+    return path.unwrap_or_else(|| state_dir.join(state::history::FILE_NAME));
+}
+
+/** The random number generator for choosing files, seeded by `--seed` if given. */
+fn selection_rng(cli: &Cli) -> StdRng {
+    match cli.seed {
+        Some(seed) => StdRng::seed_from_u64(seed),
+        None => StdRng::from_os_rng(),
+    }
+}
+
+/** The EEPROM cache file to use, unless disabled. */
+fn eeprom_cache(cli: &Cli, state_dir: &Path) -> Option<PathBuf> {
+    if cli.no_eeprom_cache {
+        return None;
+    }
+    let path = cli.eeprom_cache.clone();
+    return Some(path.unwrap_or_else(|| state_dir.join(epd::cache::FILE_NAME)));
+}
+
+/** How the panel is wired, from the command line, the `INKY_*` environment variables and the
+ * config file in that order, using the config's profile for `--device` if given. */
+fn wiring(cli: &Cli) -> Result<Wiring, ConfigError> {
+    let env = WiringOverrides::from_env()?;
+    let config = load_config(cli)?.hardware(cli.device.as_deref())?;
+    return Ok(epd::wiring::resolve(&cli.wiring(), &env, &config));
+}
+
+/** The panel `--model` describes, with `--width` and `--height` if given. The cached contents of
+ * a real EEPROM are only compared with it, to warn that they are overridden. */
+fn model_eeprom(cli: &Cli, eeprom_cache: Option<&Path>) -> Option<EPDType> {
+    let eeprom = cli.model?.epd_type(cli.width, cli.height);
+    let cached = eeprom_cache
+        .and_then(epd::cache::load)
+        .map(|cached| cached.eeprom);
+    if let Some(cached) = cached.filter(|cached| {
+        (cached.width, cached.height, cached.color) != (eeprom.width, eeprom.height, eeprom.color)
+    }) {
+        warn!("The EEPROM describes a {cached}, driving it as a {eeprom} as --model says");
+    }
+    return Some(eeprom);
+}
+
+/** The panel, from `--model` if given and otherwise from its EEPROM, read through the cache at
+ * `eeprom_cache` if given. Its version info is logged, so the versions and the panel are in
+ * every pasted log of a run that uses it. */
+fn new_inky(cli: &Cli, wiring: Wiring, eeprom_cache: Option<&Path>) -> Result<Inky, InkyError> {
+    let inky = match (model_eeprom(cli, eeprom_cache), eeprom_cache) {
+        (Some(eeprom), _) => Inky::without_eeprom(eeprom, wiring)?,
+        (None, Some(path)) => Inky::with_eeprom_cache(cli.eeprom_address, wiring, path)?,
+        (None, None) => Inky::new(cli.eeprom_address, wiring)?,
+    };
+    info!("{}", inky.version_info());
+    return Ok(inky);
+}
+
+/** Open the panel as configured on the command line. From then on, SIGINT and SIGTERM wait
+ * for the panel to be powered off before exiting. */
+fn open_inky(cli: &Cli, state_dir: &Path) -> Result<Inky, RunError> {
+    shutdown::install();
+    let wiring = wiring(cli)?;
+    let mut inky = new_inky(cli, wiring, eeprom_cache(cli, state_dir).as_deref())?;
+    if let Some(margins) = cli.margin {
+        inky.set_margins(margins, cli.margin_color.index())?;
+    }
+    inky.set_spi(wiring.spi_hz, cli.spi_chunk_size)?;
+    inky.set_low_footprint(cli.low_footprint);
+    let _ = inky.on_busy_change(|busy| debug!("Panel busy: {busy}"));
+    return Ok(inky);
+}
+
+/** The panel described without opening it, for `--emit-only`. It is the one `--model` gives,
+ * or the one in the EEPROM cache however old, or else the AC073TC1 this program drives. */
+fn detached_inky(cli: &Cli, state_dir: &Path) -> Result<Inky, RunError> {
+    let eeprom_cache = eeprom_cache(cli, state_dir);
+    let eeprom = model_eeprom(cli, eeprom_cache.as_deref())
+        .or_else(|| {
+            eeprom_cache
+                .as_deref()
+                .and_then(epd::cache::load)
+                .map(|cached| cached.eeprom)
+        })
+        .unwrap_or_else(|| Model::Ac073tc1.epd_type(cli.width, cli.height));
+    let mut inky = Inky::detached(eeprom, wiring(cli)?);
+    info!("{}", inky.version_info());
+    if let Some(margins) = cli.margin {
+        inky.set_margins(margins, cli.margin_color.index())?;
+    }
+    return Ok(inky);
+}
+
+/** The panel to draw the next frame on: opened, unless it is only emitted. */
+fn frame_inky(cli: &Cli, state_dir: &Path) -> Result<Inky, RunError> {
+    match cli.emit_only {
+        true => detached_inky(cli, state_dir),
+        false => open_inky(cli, state_dir),
+    }
+}
+
+/** Whether the program keeps displaying images with `--interval` or `--schedule`, rather than
+ * one and exiting. */
+fn keeps_running(cli: &Cli) -> bool {
+    cli.interval.is_some() || cli.schedule.is_some()
+}
+
+/** Log and record the outcome of a run, passing on the error if it failed. With `--interval`
+ * or `--schedule`, only failures that would repeat on the next run are passed on, others are
+ * logged and the loop goes on. */
+fn conclude(
+    cli: &Cli,
+    state_dir: &Path,
+    result: Result<RunReport, RunError>,
+    shown: Shown,
+) -> Result<(), RunError> {
+    match result {
+        Ok(report) if cli.emit_only => {
+            // Nothing was displayed, so there is nothing to record
+            info!("Emitted {}", report.file.display());
+        }
+        Ok(report) if cli.no_refresh => {
+            info!("Transmitted {} without refreshing", report.file.display());
+        }
+        Ok(report) => {
+            let refresh_ms = report.refresh_duration.unwrap_or_default().as_millis() as u64;
+            info!(file:% = report.file.display(), refresh_ms; "{report}");
+            if cli.time_budget {
+                info!("Time budget: {}", report.time_budget);
+            }
+            // Stdout is for the frame with --emit and for the path with --print-choice
+            if !cli.quiet && cli.emit.is_none() && !cli.print_choice {
+                println!("{}", Summary(&report));
+            }
+            if let (Shown::New, Some(path)) = (shown, shuffle_state(cli, state_dir)) {
+                for file in report.files() {
+                    state::shuffle::mark_shown(&path, file);
+                }
+            }
+            state::record(state_dir, &history_file(cli, state_dir), &report, shown);
+            state::showing::write(&showing_file(cli, state_dir), &report);
+            if let Some(url) = &cli.webhook {
+                webhook::notify(url, &Payload::displayed(&report));
+            }
+        }
+        Err(error) => {
+            state::record_failure(state_dir, error.class());
+            if let (Some(url), true) = (&cli.webhook, cli.webhook_on_error) {
+                webhook::notify(url, &Payload::failed(&error));
+            }
+            let interrupted = matches!(error, RunError::Display(InkyError::Interrupted(_)));
+            if let (RunError::Display(_), Some(path)) = (&error, eeprom_cache(cli, state_dir)) {
+                if !interrupted {
+                    epd::cache::invalidate(&path);
+                }
+            }
+            if keeps_running(cli) && !error.is_fatal() {
+                warn!("{error}, trying again at the next refresh");
+                return Ok(());
+            }
+            return Err(error);
+        }
+    }
+    return Ok(());
+}
+
+/** `canvas` in the panel's full colors, as a PNG file. */
+fn frame_png(canvas: &Canvas) -> Vec<u8> {
+    let mut png = Vec::new();
+    // Encoding into memory can't fail
+    canvas
+        .to_rgb(DESATURATED_PALETTE)
+        .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+        .unwrap();
+    return png;
+}
+
+/** An image sent by `serve`, `mqtt` or `pipe` rather than chosen from PATH. */
+enum Sent {
+    /** The image itself, written to the state directory to be displayed. */
+    Image(Vec<u8>),
+    /** The path or URL of one. */
+    Source(PathBuf),
+}
+
+/** Whether `--quiet-hours` hold refreshing back right now, unless `--force` overrides them. */
+fn is_quiet(cli: &Cli, config: &Config) -> bool {
+    let quiet_hours = cli.quiet_hours.filter(|_| !cli.force);
+    return quiet_hours.is_some_and(|q| q.contains(config.now().time()));
+}
+
+/** Display the images sent by `serve`, `mqtt` or `pipe` until asked to stop. `next` waits a
+ * moment for one, along with what `answer` needs to tell its sender how displaying it went.
+ * Images are written to `upload_file_name` in the state directory. During quiet hours only the
+ * latest one is kept, and `answer` hears of it without a result; it is displayed once they
+ * end. */
+fn display_sent<T>(
+    cli: &Cli,
+    state_dir: &Path,
+    config: &Config,
+    inky: &mut Inky,
+    upload_file_name: &str,
+    mut next: impl FnMut() -> Option<(Sent, T)>,
+    mut answer: impl FnMut(&mut Inky, &mut T, Option<&Result<RunReport, RunError>>),
+) -> Result<ExitCode, RunError> {
+    let mut deferred = Deferred::default();
+    while !shutdown::requested() {
+        let received = next();
+        let (sent, mut sender) = match (received, is_quiet(cli, config)) {
+            (Some(mut received), true) => {
+                info!("Quiet hours, holding the image back until they end");
+                answer(inky, &mut received.1, None);
+                deferred.defer(received);
+                continue;
+            }
+            (Some(received), false) => {
+                // Newer than anything held back
+                deferred.take();
+                received
+            }
+            (None, true) => continue,
+            (None, false) => match deferred.take() {
+                Some(received) => received,
+                None => continue,
+            },
+        };
+        let upload = state_dir.join(upload_file_name);
+        let path = match sent {
+            Sent::Source(path) => Ok(path),
+            Sent::Image(bytes) => fs::create_dir_all(state_dir)
+                .and_then(|_| fs::write(&upload, bytes))
+                .map(|_| upload.clone())
+                .map_err(|error| RunError::Upload(upload, error)),
+        };
+        let result = path.and_then(|path| display_path(cli, config, inky, path));
+        answer(inky, &mut sender, Some(&result));
+        conclude_sent(cli, state_dir, result)?;
+    }
+    return Ok(shutdown::stop_code().unwrap_or(ExitCode::SUCCESS));
+}
+
+/** Display the image at `path` on its own, with nothing else to choose from. */
+fn display_path(
+    cli: &Cli,
+    config: &Config,
+    inky: &mut Inky,
+    path: PathBuf,
+) -> Result<RunReport, RunError> {
+    let candidates = vec![Candidate::new(path)];
+    let mut rng = selection_rng(cli);
+    return display_next(
+        cli,
+        config,
+        inky,
+        candidates,
+        None,
+        &mut rng,
+        Instant::now(),
+    );
+}
+
+/** Log and record the outcome of displaying a sent image, passing on only errors that would
+ * repeat with the next one. Sent images are counted but kept out of the history, as uploads
+ * are gone once displayed. */
+fn conclude_sent(
+    cli: &Cli,
+    state_dir: &Path,
+    result: Result<RunReport, RunError>,
+) -> Result<(), RunError> {
+    match result {
+        Ok(report) => {
+            info!("{report}");
+            let history_path = history_file(cli, state_dir);
+            state::record(state_dir, &history_path, &report, Shown::Pinned);
+        }
+        Err(error) => {
+            state::record_failure(state_dir, error.class());
+            if error.is_fatal() {
+                return Err(error);
+            }
+            warn!("{error}");
+        }
+    }
+    return Ok(());
+}
+
+/** Fill the whole panel with the color at palette `index` and refresh, then log `done`. */
+fn fill_panel(cli: &Cli, state_dir: &Path, index: u8, done: &str) -> Result<ExitCode, RunError> {
+    let mut inky = open_inky(cli, state_dir)?;
+    inky.fill(index);
+    inky.show()?;
+    info!("{done}");
+    return Ok(ExitCode::SUCCESS);
+}
+
+fn main() -> ExitCode {
+    let started = Instant::now();
+    let cli = Cli::parse();
+    logging::init(logging::level(cli.verbose, cli.quiet), cli.log_format);
+
+    if cli.no_crop {
+        warn!("--no-crop is deprecated, use --fit contain");
+    }
+
+    return run(&cli, started).unwrap_or_else(error::report);
+}
+
+/** Carry out the command line, returning the code to exit with unless something failed. */
+fn run(cli: &Cli, started: Instant) -> Result<ExitCode, RunError> {
+    let state_dir = cli.state_dir.clone().unwrap_or_else(state::default_dir);
+    match &cli.command {
+        Some(Command::Info {
+            json: _,
+            full: true,
+        }) => {
+            let inky = new_inky(cli, wiring(cli)?, None)?;
+            let version_info = inky.version_info();
+            println!("{}", serde_json::to_string_pretty(&version_info).unwrap());
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::Info { json, full: false }) => {
+            let address = cli.eeprom_address;
+            let wiring = wiring(cli)?;
+            let eeprom = match model_eeprom(cli, None) {
+                Some(eeprom) => eeprom,
+                None => I2c::with_bus(wiring.i2c_bus)
+                    .and_then(|mut i2c| epd::read_eeprom(&mut i2c, address))
+                    .map_err(|error| {
+                        eprintln!(
+                            "Could not read the EEPROM at 0x{address:02X} on {}",
+                            wiring.i2c_device()
+                        );
+                        InkyError::from(error)
+                    })?,
+            };
+            let panel = PanelInfo::from(&eeprom);
+            if *json {
+                println!("{}", serde_json::to_string_pretty(&panel).unwrap());
+            } else {
+                println!("inky-rs {CRATE_VERSION} ({DRIVER} driver)");
+                print!("{panel}");
+            }
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::RawCmd { command, data, .. }) => {
+            let mut inky = new_inky(cli, wiring(cli)?, None)?;
+            inky.send_raw_command(*command, data)?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::Sleep) => {
+            let mut inky = open_inky(cli, &state_dir)?;
+            // A sleeping controller only listens to the reset line
+            if !inky.reset()? {
+                warn!("The controller didn't signal busy after a reset, is it connected?");
+            }
+            inky.sleep()?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::Clear { color }) => {
+            let done = format!("Cleared the panel to {color:?}");
+            return fill_panel(cli, &state_dir, color.index(), &done);
+        }
+        Some(Command::Color { color }) => {
+            let done = format!("Filled the panel with {color:?}");
+            return fill_panel(cli, &state_dir, color.index(), &done);
+        }
+        Some(Command::Deghost { cycles, no_restore }) => {
+            let mut inky = open_inky(cli, &state_dir)?;
+            let refreshes = cycles * 2;
+            for refresh in 0..refreshes {
+                // Stopping between refreshes leaves the panel powered off
+                if shutdown::requested() {
+                    break;
+                }
+                let color = if refresh % 2 == 0 {
+                    Color::White
+                } else {
+                    Color::Black
+                };
+                if !cli.quiet {
+                    println!(
+                        "Cycle {}/{cycles}: refreshing with {color:?} ({}/{refreshes})",
+                        refresh / 2 + 1,
+                        refresh + 1
+                    );
+                }
+                inky.fill(color.index());
+                inky.show()?;
+            }
+
+            let history_path = history_file(cli, &state_dir);
+            let restore = state::current(&history_path);
+            if let Some((cursor, path)) = restore.filter(|_| !no_restore && !shutdown::requested())
+            {
+                let candidates = candidates_for(cli, &state_dir, Some(path))?;
+                let config = load_config(cli)?;
+                let mut rng = selection_rng(cli);
+                let result =
+                    display_next(cli, &config, &mut inky, candidates, None, &mut rng, started);
+                conclude(cli, &state_dir, result, Shown::Revisit(cursor))?;
+            }
+            return Ok(shutdown::stop_code().unwrap_or(ExitCode::SUCCESS));
+        }
+        Some(Command::TestPattern { pattern }) => {
+            let mut inky = open_inky(cli, &state_dir)?;
+            let (width, height) = inky.dimensions();
+            let pixels = match pattern {
+                Pattern::Bars => render::color_bars(width, height).pixels,
+                Pattern::Checker => render::checker(width, height).pixels,
+                Pattern::Gradient => palettize_image(
+                    &get_palette(cli.saturation),
+                    &cli_adjustments(cli),
+                    quantize_settings(cli),
+                    render::gradient(width, height).into(),
+                    cli.deterministic,
+                )?,
+            };
+            for (ix, px) in pixels.iter().enumerate() {
+                inky.set_pixel(ix % width, ix / width, *px);
+            }
+            inky.show()?;
+            info!("Displayed the {pattern:?} test pattern");
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::Sysinfo { template }) => {
+            let template = match template {
+                Some(path) => fs::read_to_string(path)
+                    .map_err(|error| RunError::Template(path.clone(), error))?,
+                None => sysinfo::DEFAULT_TEMPLATE.to_string(),
+            };
+            let mut inky = open_inky(cli, &state_dir)?;
+            let eeprom = &inky.eeprom;
+            let panel = format!("{}x{} {:?}", eeprom.width, eeprom.height, eeprom.color);
+            let info = SystemInfo::gather(cli.paths.first().map(Path::new), panel);
+            let text = info.fill_template(&template);
+            let (width, height) = inky.dimensions();
+            let canvas = sysinfo::render(&text, width, height);
+            for (ix, px) in canvas.pixels.iter().enumerate() {
+                inky.set_pixel(ix % canvas.width, ix / canvas.width, *px);
+            }
+            inky.show()?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::Qr {
+            data,
+            error_correction,
+            caption,
+            caption_size,
+        }) => {
+            let mut inky = (!cli.dry_run)
+                .then(|| frame_inky(cli, &state_dir))
+                .transpose()?;
+            let (width, height) = match &inky {
+                Some(inky) => inky.dimensions(),
+                None => (
+                    cli.width.map_or(DRY_RUN_WIDTH, usize::from),
+                    cli.height.map_or(DRY_RUN_HEIGHT, usize::from),
+                ),
+            };
+            let font = caption
+                .as_ref()
+                .map(|_| text::font(cli.caption_font.as_deref()))
+                .transpose()?;
+            let caption = caption
+                .as_deref()
+                .zip(font.as_ref())
+                .map(|(line, font)| (line, font, *caption_size));
+            let (upright_width, upright_height) = cli.rotate.upright_size(width, height);
+            let canvas = qr::render(
+                data,
+                *error_correction,
+                caption,
+                upright_width,
+                upright_height,
+            )?;
+            let (_, _, pixels) = orientation(cli).apply_indices(
+                canvas.width as u32,
+                canvas.height as u32,
+                canvas.pixels,
+            );
+
+            let palette = get_palette(cli.saturation);
+            let Some(inky) = &mut inky else {
+                write_outputs(cli, width, height, &pixels, &palette)?;
+                println!("Would display a QR code\nFrame: {width}x{height}");
+                return Ok(ExitCode::SUCCESS);
+            };
+            for (ix, px) in pixels.iter().enumerate() {
+                inky.set_pixel(ix % width, ix / width, *px);
+            }
+            let (width, height, frame) = inky.frame();
+            write_outputs(cli, width, height, &frame, &palette)?;
+            if !cli.emit_only {
+                inky.show()?;
+                info!("Displayed the QR code");
+            }
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::SelfTest { full }) => {
+            let checks = selftest::checks(*full, cli.eeprom_address, wiring(cli)?);
+            if !selftest::run(&checks, &mut selftest::Hardware) {
+                return Ok(ExitCode::FAILURE);
+            }
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::Eeprom {
+            command: EepromCommand::Scan,
+        }) => {
+            let probes = I2c::with_bus(wiring(cli)?.i2c_bus)
+                .and_then(|mut i2c| epd::scan::scan(&mut i2c))
+                .map_err(InkyError::from)?;
+            print!("{}", epd::scan::Report(&probes));
+            if epd::scan::suggest(&probes).is_none() {
+                return Ok(ExitCode::FAILURE);
+            }
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::CalibrateSaturation {
+            image,
+            steps,
+            out,
+            on_panel,
+            dwell,
+            size,
+        }) => {
+            let mut inky = on_panel.then(|| open_inky(cli, &state_dir)).transpose()?;
+            let (width, height) = match &inky {
+                Some(inky) => inky.dimensions(),
+                None => (size.0 as usize, size.1 as usize),
+            };
+            let renderings = render_saturations(cli, image, width, height, *steps)?;
+
+            if let Some(out) = out {
+                let sheet = saturation_sheet(cli.rotate, &renderings).to_rgb(SATURATED_PALETTE);
+                sheet
+                    .save(out)
+                    .map_err(|error| RunError::Output(out.clone(), error))?;
+                info!("Wrote {}", out.display());
+            }
+
+            if let Some(inky) = &mut inky {
+                for (i, (saturation, canvas)) in renderings.iter().enumerate() {
+                    info!("Showing saturation {saturation:.2}");
+                    for (ix, px) in canvas.pixels.iter().enumerate() {
+                        inky.set_pixel(ix % width, ix / width, *px);
+                    }
+                    inky.show()?;
+                    if i + 1 < renderings.len() {
+                        thread::sleep(*dwell);
+                    }
+                }
+            }
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::Preview {
+            image,
+            compare,
+            alternative,
+            size,
+        }) => {
+            let (width, height) = *size;
+            let comparison = render_comparison(cli, image, width, height, *alternative)?;
+            comparison
+                .save(compare)
+                .map_err(|error| RunError::Output(compare.clone(), error))?;
+            info!("Wrote {}", compare.display());
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::BenchSpi {
+            clocks_hz,
+            chunk_sizes,
+            repeats,
+        }) => {
+            let mut inky = open_inky(cli, &state_dir)?;
+            let measurements = bench::run(&mut inky, clocks_hz, chunk_sizes, *repeats)?;
+            print!("{}", bench::Report(&measurements));
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::Benchmark {
+            image,
+            repeats,
+            packing,
+            size,
+            json,
+        }) => {
+            let stages = run_benchmark(cli, image, *size, *repeats, *packing)?;
+            if *json {
+                println!("{}", serde_json::to_string_pretty(&stages).unwrap());
+            } else {
+                print!("{}", benchmark::Report(&stages));
+            }
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::Serve { listen }) => {
+            let queue = serve::start(*listen)?;
+            let config = load_config(cli)?;
+            let mut inky = open_inky(cli, &state_dir)?;
+            queue.set_panel((inky.eeprom.width as usize, inky.eeprom.height as usize));
+            let next = || {
+                let mut job = queue.next(Duration::from_secs(1))?;
+                return Some((Sent::Image(job.take_body()), job));
+            };
+            let answer = |inky: &mut Inky, job: &mut serve::Job, result: Option<&Result<_, _>>| {
+                let Some(result) = result else {
+                    queue.done(job, None);
+                    job.defer();
+                    return;
+                };
+                // Made from the panel's buffer, so it is exactly what was displayed
+                let preview = result.is_ok().then(|| {
+                    let (width, height, frame) = inky.frame();
+                    frame_png(&upright_frame(cli, width, height, &frame))
+                });
+                queue.done(job, preview);
+                job.finish(result);
+            };
+            let upload = serve::UPLOAD_FILE_NAME;
+            return display_sent(cli, &state_dir, &config, &mut inky, upload, next, answer);
+        }
+        Some(Command::Mqtt {
+            broker,
+            topic,
+            status_topic,
+        }) => {
+            let status_topic = status_topic.clone();
+            let status_topic = status_topic.unwrap_or_else(|| format!("{topic}/status"));
+            let subscriber = mqtt::start(broker.clone(), topic, status_topic)?;
+            let config = load_config(cli)?;
+            let mut inky = open_inky(cli, &state_dir)?;
+            let next = || {
+                let sent = match subscriber.next(Duration::from_secs(1))? {
+                    mqtt::Request::Source(source) => Sent::Source(PathBuf::from(source)),
+                    mqtt::Request::Image(bytes) => Sent::Image(bytes),
+                };
+                return Some((sent, ()));
+            };
+            let answer = |inky: &mut Inky, _: &mut (), result: Option<&_>| {
+                let status = match result {
+                    Some(result) => mqtt::Status::new(result, inky.dimensions()),
+                    None => mqtt::Status::deferred(inky.dimensions()),
+                };
+                subscriber.publish(&status);
+            };
+            let upload = mqtt::UPLOAD_FILE_NAME;
+            return display_sent(cli, &state_dir, &config, &mut inky, upload, next, answer);
+        }
+        Some(Command::Pipe { fifo }) => {
+            let pipe = pipe::open(fifo)?;
+            let config = load_config(cli)?;
+            let mut inky = open_inky(cli, &state_dir)?;
+            // An image cut short by its writer fails to decode, and the next one is awaited
+            let next = || Some((Sent::Image(pipe.next(Duration::from_secs(1))?), ()));
+            let answer = |_: &mut Inky, _: &mut (), _: Option<&_>| {};
+            let upload = pipe::UPLOAD_FILE_NAME;
+            return display_sent(cli, &state_dir, &config, &mut inky, upload, next, answer);
+        }
+        Some(Command::Stats { json, since, last }) => {
+            let summary =
+                state::summarize(&state_dir, &history_file(cli, &state_dir), *since, *last);
+            if *json {
+                println!("{}", serde_json::to_string_pretty(&summary).unwrap());
+            } else {
+                print!("{summary}");
+            }
+            return Ok(ExitCode::SUCCESS);
+        }
+        Some(Command::Previous) => {
+            let Some((cursor, path)) = state::step(&history_file(cli, &state_dir), Step::Back)
+            else {
+                println!("There is no earlier image in the history");
+                return Ok(ExitCode::FAILURE);
+            };
+            let candidates = candidates_for(cli, &state_dir, Some(path))?;
+            let result = display_next(
+                cli,
+                &load_config(cli)?,
+                &mut open_inky(cli, &state_dir)?,
+                candidates,
+                None,
+                &mut selection_rng(cli),
+                started,
+            );
+            conclude(cli, &state_dir, result, Shown::Revisit(cursor))?;
+            return Ok(shutdown::stop_code().unwrap_or(ExitCode::SUCCESS));
+        }
+        Some(Command::Next) => {
+            let revisit = state::step(&history_file(cli, &state_dir), Step::Forward);
+            if revisit.is_none() && cli.paths.is_empty() {
+                println!("Already at the newest image in the history, give a file or directory to show a new one");
+                return Ok(ExitCode::FAILURE);
+            }
+            let (cursor, path) = revisit.unzip();
+            // A file from the history is shown on its own, even if it was part of a collage
+            let collage = cli.collage.filter(|_| path.is_none());
+            let candidates = candidates_for(cli, &state_dir, path)?;
+            let result = display_next(
+                cli,
+                &load_config(cli)?,
+                &mut open_inky(cli, &state_dir)?,
+                candidates,
+                collage,
+                &mut selection_rng(cli),
+                started,
+            );
+            let shown = cursor.map_or(Shown::New, Shown::Revisit);
+            conclude(cli, &state_dir, result, shown)?;
+            return Ok(shutdown::stop_code().unwrap_or(ExitCode::SUCCESS));
+        }
+        None => {}
+    }
+
+    if cli.buttons && !keeps_running(cli) {
+        return Err(RunError::Usage(
+            "--buttons needs --interval or --schedule to keep running".to_string(),
+        ));
+    }
+
+    if cli.paths.iter().any(|path| path == STDIN) && keeps_running(cli) {
+        return Err(RunError::Usage(
+            "An image from stdin can only be displayed once, so --interval and --schedule can't \
+             be used"
+                .to_string(),
+        ));
+    }
+
+    if let Some(interval) = cli.interval.filter(|_| cli.align) {
+        daemon::check_aligned(interval).map_err(RunError::Usage)?;
+    }
+
+    if let Some(path) = &cli.from_raw {
+        let mut inky = open_inky(cli, &state_dir)?;
+        let (width, height, _) = inky.frame();
+        let frame = raw::load(path, width, height)?;
+        inky.show_packed(&frame.packed)?;
+        info!("Displayed {}", path.display());
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if cli.list {
+        let candidates = sources(cli).candidates()?;
+        let mut paths: Vec<PathBuf> = candidates.iter().map(|c| absolute(&c.path)).collect();
+        paths.sort();
+        paths.dedup();
+        for path in paths {
+            println!("{}", path.display());
+        }
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let config = load_config(cli)?;
+    if cli.dry_run {
+        let summary = dry_run(cli, &config, &state_dir, &mut selection_rng(cli))?;
+        println!("{summary}");
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if !keeps_running(cli) && is_quiet(cli, &config) {
+        info!("Not refreshing during quiet hours (use --force to override)");
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    // Opening the panel takes seconds and resets it, so it waits until there is something to
+    // show. A directory without images then fails without touching the hardware
+    let mut inky = None;
+    let mut rng = selection_rng(cli);
+    let mut show = |chosen: Option<PathBuf>,
+                    rng: &mut StdRng,
+                    started: Instant|
+     -> Result<RunReport, RunError> {
+        // Pinned and deferred files are shown on their own
+        let collage = cli.collage.filter(|_| chosen.is_none());
+        let candidates = candidates_for(cli, &state_dir, chosen)?;
+        if inky.is_none() {
+            inky = Some(frame_inky(cli, &state_dir)?);
+        }
+        let inky = inky.as_mut().unwrap();
+        display_next(cli, &config, inky, candidates, collage, rng, started)
+    };
+
+    let buttons = match cli.buttons {
+        true => Some(Buttons::listen().map_err(InkyError::from)?),
+        false => None,
+    };
+    let mut deferred = Deferred::default();
+    let mut shown_pin = None;
+    let mut run_started = started;
+    let mut schedule = cli.schedule.as_ref().map(Schedule::new);
+    // Only the matching times refresh, starting up doesn't
+    let mut wait = schedule.is_some();
+    loop {
+        // A button press cuts the wait short, and it starts over once the press is dealt with
+        while wait {
+            match (&mut schedule, cli.interval) {
+                (Some(schedule), _) => {
+                    if !schedule.wait() {
+                        return Ok(ExitCode::SUCCESS);
+                    }
+                }
+                (None, Some(interval)) => daemon::wait(interval, cli.align),
+                (None, None) => return Ok(ExitCode::SUCCESS),
+            }
+            if let Some(code) = shutdown::stop_code() {
+                return Ok(code);
+            }
+            let Some(button) = buttons.as_ref().and_then(Buttons::take) else {
+                wait = false;
+                run_started = Instant::now();
+                continue;
+            };
+            let history_path = history_file(cli, &state_dir);
+            match (state::step(&history_path, button.step()), button) {
+                (Some((cursor, path)), _) => {
+                    let result = show(Some(path), &mut rng, Instant::now());
+                    conclude(cli, &state_dir, result, Shown::Revisit(cursor))?;
+                }
+                (None, Button::B) => {
+                    let result = show(None, &mut rng, Instant::now());
+                    conclude(cli, &state_dir, result, Shown::New)?;
+                }
+                (None, Button::A) => info!("There is no earlier image in the history"),
+            }
+            shown_pin = None;
+            if let Some(code) = shutdown::stop_code() {
+                return Ok(code);
+            }
+        }
+        // Quiet hours hold back pins as well
+        let quiet = is_quiet(cli, &config);
+        let pinned = match quiet {
+            true => Pinned::None,
+            false => select::pin::check(&cli.paths, shown_pin.as_ref()),
+        };
+        match pinned {
+            Pinned::Unchanged(pin) => info!(
+                "Still pinned to {}, leaving the panel as it is",
+                pin.path.display()
+            ),
+            Pinned::Show(pin) => {
+                info!("Pinned to {}, skipping selection", pin.path.display());
+                let result = show(Some(pin.path.clone()), &mut rng, run_started);
+                conclude(cli, &state_dir, result, Shown::Pinned)?;
+                shown_pin = Some(pin);
+            }
+            Pinned::None => {
+                let cycle = deferred.cycle(quiet, || -> Result<PathBuf, RunError> {
+                    let mut candidates = candidate_pool(cli, &state_dir)?;
+                    let path = select::take(&mut candidates, cli.select, &mut rng);
+                    info!("Quiet hours, deferring {} until they end", path.display());
+                    return Ok(path);
+                });
+                match cycle {
+                    Ok(Cycle::Quiet) => {}
+                    Ok(Cycle::Show(chosen)) => {
+                        let result = show(chosen, &mut rng, run_started);
+                        conclude(cli, &state_dir, result, Shown::New)?;
+                        shown_pin = None;
+                    }
+                    Err(error) => warn!("{error}, trying again at the next refresh"),
+                }
+            }
+        }
+        if let Some(code) = shutdown::stop_code() {
+            return Ok(code);
+        }
+        wait = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn colors(palette: &[[u8; 4]]) -> Vec<imagequant::RGBA> {
+        palette
+            .iter()
+            .map(|&[r, g, b, a]| imagequant::RGBA::new(r, g, b, a))
+            .collect()
+    }
+
+    #[test]
+    fn saturation_ends_are_the_palettes() {
+        assert_eq!(get_palette(0.0), colors(DESATURATED_PALETTE));
+        assert_eq!(get_palette(1.0), colors(SATURATED_PALETTE));
+    }
+
+    #[test]
+    fn saturation_blends_between_the_palettes() {
+        let desaturated = colors(DESATURATED_PALETTE);
+        let saturated = colors(SATURATED_PALETTE);
+        for (ix, color) in get_palette(0.5).iter().enumerate() {
+            let (from, to) = (desaturated[ix], saturated[ix]);
+            for (channel, (from, to)) in [
+                (color.r, (from.r, to.r)),
+                (color.g, (from.g, to.g)),
+                (color.b, (from.b, to.b)),
+                (color.a, (from.a, to.a)),
+            ] {
+                assert!(from.min(to) <= channel && channel <= from.max(to));
+            }
+        }
+        // Red is halfway from 255 to 0x9F
+        assert_eq!(get_palette(0.5)[4].r, 207);
+    }
+
+    #[test]
+    fn opaque_images_quantize_the_same_with_or_without_alpha() {
+        let rgb = RgbImage::from_fn(48, 32, |x, y| {
+            let texture = ((x * 7 + y * 13) % 17) as u8;
+            image::Rgb([(x * 5) as u8 ^ texture, (y * 8) as u8, ((x + y) * 3) as u8])
+        });
+        let rgba = DynamicImage::ImageRgb8(rgb.clone()).into_rgba8();
+        let palette = get_palette(0.5);
+        for adjustments in [
+            Adjustments::default(),
+            Adjustments {
+                brightness: 20,
+                white_balance: [1.0, 0.9, 0.8],
+                contrast: -30,
+                gamma: 1.4,
+            },
+        ] {
+            let run = |image| {
+                palettize_image(&palette, &adjustments, Settings::default(), image, false)
+                    .unwrap_or_else(|error| panic!("{error}"))
+            };
+            let without_alpha = run(DynamicImage::ImageRgb8(rgb.clone()));
+            let with_alpha = run(DynamicImage::ImageRgba8(rgba.clone()));
+            assert_eq!(without_alpha.len(), 48 * 32);
+            assert!(
+                without_alpha == with_alpha,
+                "different with {adjustments:?}"
+            );
+        }
+    }
+
+    /** Renderings of a `width` × `height` panel at `steps` saturations, each in its own color. */
+    fn saturations(steps: u8, width: usize, height: usize) -> Vec<(f64, Canvas)> {
+        return (0..steps)
+            .map(|step| {
+                let saturation = step as f64 / (steps - 1) as f64;
+                (saturation, Canvas::new(width, height, 2 + step % 5))
+            })
+            .collect();
+    }
+
+    #[test]
+    fn saturation_sheet_labels_a_grid_of_renderings() {
+        let sheet = saturation_sheet(Rotation::None, &saturations(5, 100, 60));
+
+        // Three columns and two rows of 100 × 60 cells, with 24 between them and around them
+        // and the labels, one font pixel to a pixel, half a gap below each cell
+        let mut expected = Canvas::new(396, 288, Color::White.index());
+        let cells = [(24, 24), (148, 24), (272, 24), (24, 156), (148, 156)];
+        let labels = ["0.00", "0.25", "0.50", "0.75", "1.00"];
+        for (i, ((x, y), label)) in cells.into_iter().zip(labels).enumerate() {
+            expected.fill_rect(x, y, 100, 60, 2 + i as u8);
+            let label = format!("saturation {label}");
+            expected.draw_text(x + 5, y + 72, &label, 1, Color::Black.index());
+        }
+
+        assert_eq!((sheet.width, sheet.height), (396, 288));
+        assert!(sheet.pixels == expected.pixels);
+    }
+
+    #[test]
+    fn saturation_sheet_turns_renderings_upright() {
+        let mut renderings = saturations(2, 100, 60);
+        renderings[0].1.fill_rect(0, 0, 1, 1, Color::Black.index());
+        let sheet = saturation_sheet(Rotation::Half, &renderings);
+
+        assert_eq!((sheet.width, sheet.height), (272, 156));
+        let at = |x: usize, y: usize| sheet.pixels[y * sheet.width + x];
+        assert_eq!(at(24 + 99, 24 + 59), Color::Black.index());
+        assert_eq!(at(24, 24), 2);
+    }
+
+    fn sources_for(args: &[&str]) -> Sources {
+        let cli = Cli::try_parse_from(["inky-rs", "photos"].iter().chain(args)).unwrap();
+        return sources(&cli);
+    }
+
+    #[test]
+    fn sources_follow_the_command_line() {
+        let sources = sources_for(&[]);
+        assert_eq!(sources.paths, ["photos"]);
+        assert_eq!(sources.listing.max_depth, 0);
+        assert_eq!(sources.filter.min_resolution, Some((400, 240)));
+        assert_eq!(sources.weekday, None);
+
+        assert_eq!(sources_for(&["--recursive"]).listing.max_depth, usize::MAX);
+        let sources = sources_for(&["--recursive", "--max-depth", "2"]);
+        assert_eq!(sources.listing.max_depth, 2);
+        // Compared with the image as it hangs
+        let sources = sources_for(&["--rotate", "90", "--min-resolution", "600x400"]);
+        assert_eq!(sources.filter.min_resolution, Some((400, 600)));
+        let sources = sources_for(&["--min-resolution", "0x0"]);
+        assert_eq!(sources.filter.min_resolution, None);
+        assert!(sources_for(&["--by-weekday"]).weekday.is_some());
+    }
 }
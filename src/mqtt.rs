@@ -0,0 +1,324 @@
+use std::{fmt::Display, sync::Arc, time::Duration};
+
+#[cfg(feature = "mqtt")]
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{daemon::Latest, error::RunError, report::RunReport};
+
+/** The file in the state directory that images sent as the payload are written to. */
+pub const UPLOAD_FILE_NAME: &str = "mqtt-upload";
+
+/** Wait before the first attempt to reconnect to the broker, doubled after each failure. */
+#[cfg_attr(not(feature = "mqtt"), allow(dead_code))]
+const BACKOFF_MIN: Duration = Duration::from_secs(1);
+#[cfg_attr(not(feature = "mqtt"), allow(dead_code))]
+const BACKOFF_MAX: Duration = Duration::from_secs(60);
+
+#[cfg_attr(not(feature = "mqtt"), allow(dead_code))]
+pub enum MqttError {
+    /** A topic that can't be subscribed or published to. */
+    Topic(String),
+    /** Built without the `mqtt` feature. */
+    #[cfg(not(feature = "mqtt"))]
+    Unsupported,
+}
+
+/** What a message asks to display. */
+pub enum Request {
+    /** The payload is the image itself. */
+    Image(Vec<u8>),
+    /** A path or URL to display, from a payload like `{"path": "..."}` or `{"url": "..."}`. */
+    Source(String),
+}
+
+#[derive(Deserialize)]
+struct Payload {
+    path: Option<String>,
+    url: Option<String>,
+}
+
+impl Request {
+    #[cfg_attr(not(feature = "mqtt"), allow(dead_code))]
+    fn parse(payload: &[u8]) -> Request {
+        let source = serde_json::from_slice::<Payload>(payload)
+            .ok()
+            .and_then(|payload| payload.url.or(payload.path));
+        match source {
+            Some(source) => Request::Source(source),
+            None => Request::Image(payload.to_vec()),
+        }
+    }
+}
+
+/** The outcome of a message, published as JSON to the status topic. */
+#[derive(Serialize)]
+#[cfg_attr(not(feature = "mqtt"), allow(dead_code))]
+pub struct Status {
+    /** `displayed`, `deferred` until quiet hours end, or `failed` */
+    outcome: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file: Option<String>,
+    width: usize,
+    height: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    refresh_seconds: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl Status {
+    /** The status after displaying a message on a panel of `width` × `height`. */
+    pub fn new(result: &Result<RunReport, RunError>, (width, height): (usize, usize)) -> Status {
+        match result {
+            Ok(report) => Status {
+                outcome: "displayed",
+                file: Some(report.file.display().to_string()),
+                width,
+                height,
+                refresh_seconds: report.refresh_duration.map(|d| d.as_secs_f64()),
+                error: None,
+            },
+            Err(error) => Status::failed(error, (width, height)),
+        }
+    }
+
+    /** The status of a message held back until quiet hours end. */
+    pub fn deferred((width, height): (usize, usize)) -> Status {
+        Status {
+            outcome: "deferred",
+            file: None,
+            width,
+            height,
+            refresh_seconds: None,
+            error: None,
+        }
+    }
+
+    /** The status of a message that couldn't be displayed. */
+    fn failed(error: impl Display, (width, height): (usize, usize)) -> Status {
+        Status {
+            outcome: "failed",
+            file: None,
+            width,
+            height,
+            refresh_seconds: None,
+            error: Some(error.to_string()),
+        }
+    }
+}
+
+/** A subscription to the topic with images, and where to publish how displaying them went. */
+#[cfg_attr(not(feature = "mqtt"), allow(dead_code))]
+pub struct Subscriber {
+    latest: Arc<Latest<Request>>,
+    #[cfg(feature = "mqtt")]
+    client: rumqttc::Client,
+    status_topic: String,
+}
+
+impl Subscriber {
+    /** The next message to display, waiting up to `timeout` for one. */
+    pub fn next(&self, timeout: Duration) -> Option<Request> {
+        self.latest.next(timeout)
+    }
+
+    /** Publish `status` to the status topic. Failing to is only logged, as the broker may be
+     * away for a moment. */
+    #[cfg(feature = "mqtt")]
+    pub fn publish(&self, status: &Status) {
+        let payload = serde_json::to_vec(status).unwrap();
+        let published = self.client.try_publish(
+            &self.status_topic,
+            rumqttc::QoS::AtLeastOnce,
+            false,
+            payload,
+        );
+        if let Err(error) = published {
+            warn!("Could not publish to {}: {error}", self.status_topic);
+        }
+    }
+
+    #[cfg(not(feature = "mqtt"))]
+    pub fn publish(&self, _status: &Status) {}
+}
+
+/** Connect to the broker at `host`:`port` and subscribe to `topic` on another thread, which
+ * keeps reconnecting with growing waits in between when the broker goes away. */
+#[cfg(feature = "mqtt")]
+pub fn start(
+    (host, port): (String, u16),
+    topic: &str,
+    status_topic: String,
+) -> Result<Subscriber, MqttError> {
+    use std::thread;
+
+    use rumqttc::{Client, Event, MqttOptions, Packet, QoS};
+
+    use crate::{fetch::MAX_BYTES, shutdown};
+
+    if !rumqttc::valid_filter(topic) {
+        return Err(MqttError::Topic(topic.to_string()));
+    }
+    if !rumqttc::valid_topic(&status_topic) {
+        return Err(MqttError::Topic(status_topic));
+    }
+    let client_id = format!("inky-rs-{}", std::process::id());
+    let mut options = MqttOptions::new(client_id, &host, port);
+    options.set_keep_alive(Duration::from_secs(30));
+    // Images are much larger than the default limit of 10 KiB
+    options.set_max_packet_size(MAX_BYTES as usize + 1024, 64 * 1024);
+    let (client, mut connection) = Client::new(options, 10);
+
+    let latest = Arc::new(Latest::default());
+    let subscription = client.clone();
+    let received = latest.clone();
+    let topic = topic.to_string();
+    info!("Connecting to {host}:{port} for {topic}");
+    thread::spawn(move || {
+        let mut backoff = BACKOFF_MIN;
+        for notification in connection.iter() {
+            match notification {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    info!("Connected to {host}:{port}, subscribing to {topic}");
+                    backoff = BACKOFF_MIN;
+                    // Subscriptions don't survive a reconnection with a clean session
+                    if let Err(error) = subscription.try_subscribe(&topic, QoS::AtLeastOnce) {
+                        warn!("Could not subscribe to {topic}: {error}");
+                    }
+                }
+                Ok(Event::Incoming(Packet::Publish(publish))) => {
+                    info!(
+                        "Received {} bytes on {}",
+                        publish.payload.len(),
+                        publish.topic
+                    );
+                    received.put(Request::parse(&publish.payload));
+                }
+                Ok(_) => {}
+                Err(error) => {
+                    warn!("Lost the connection to {host}:{port}: {error}, retrying in {backoff:?}");
+                    if shutdown::sleep(backoff) {
+                        return;
+                    }
+                    backoff = (backoff * 2).min(BACKOFF_MAX);
+                }
+            }
+        }
+    });
+
+    return Ok(Subscriber {
+        latest,
+        client,
+        status_topic,
+    });
+}
+
+#[cfg(not(feature = "mqtt"))]
+pub fn start(
+    _broker: (String, u16),
+    _topic: &str,
+    _status_topic: String,
+) -> Result<Subscriber, MqttError> {
+    Err(MqttError::Unsupported)
+}
+
+impl Display for MqttError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            MqttError::Topic(topic) => write!(f, "{topic} is not a valid MQTT topic"),
+            #[cfg(not(feature = "mqtt"))]
+            MqttError::Unsupported => {
+                write!(
+                    f,
+                    "this build has no MQTT client, rebuild with --features mqtt"
+                )
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io, path::PathBuf};
+
+    use serde_json::json;
+
+    use super::*;
+
+    fn source(payload: &[u8]) -> Option<String> {
+        match Request::parse(payload) {
+            Request::Source(source) => Some(source),
+            Request::Image(_) => None,
+        }
+    }
+
+    #[test]
+    fn payloads_naming_a_url_or_path_are_fetched() {
+        let url = br#"{"url": "https://example.com/photo.jpg"}"#;
+        assert_eq!(
+            source(url).as_deref(),
+            Some("https://example.com/photo.jpg")
+        );
+        let path = br#"{"path": "/srv/photos/beach.png"}"#;
+        assert_eq!(source(path).as_deref(), Some("/srv/photos/beach.png"));
+        let both = br#"{"path": "/srv/photos/beach.png", "url": "https://example.com/a.jpg"}"#;
+        assert_eq!(source(both).as_deref(), Some("https://example.com/a.jpg"));
+    }
+
+    #[test]
+    fn other_payloads_are_the_image_itself() {
+        let png = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR".as_slice();
+        let json_without_source = br#"{"name": "beach.png"}"#.as_slice();
+        let json_string = br#""/srv/photos/beach.png""#.as_slice();
+        for payload in [png, json_without_source, json_string, b""] {
+            match Request::parse(payload) {
+                Request::Image(bytes) => assert_eq!(bytes, payload),
+                Request::Source(source) => panic!("{source} was taken from {payload:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn displayed_status_has_the_file_and_refresh() {
+        let mut report = RunReport::new(PathBuf::from("/var/lib/inky-rs/mqtt-upload"));
+        report.refresh_duration = Some(Duration::from_millis(31_250));
+        let status = Status::new(&Ok(report), (800, 480));
+        assert_eq!(
+            serde_json::to_value(&status).unwrap(),
+            json!({
+                "outcome": "displayed",
+                "file": "/var/lib/inky-rs/mqtt-upload",
+                "width": 800,
+                "height": 480,
+                "refresh_seconds": 31.25,
+            })
+        );
+    }
+
+    #[test]
+    fn failed_status_has_the_error() {
+        let error = RunError::Upload(
+            PathBuf::from("/var/lib/inky-rs/mqtt-upload"),
+            io::Error::other("disk full"),
+        );
+        let status = Status::new(&Err(error), (800, 480));
+        assert_eq!(
+            serde_json::to_value(&status).unwrap(),
+            json!({
+                "outcome": "failed",
+                "width": 800,
+                "height": 480,
+                "error": "Could not write /var/lib/inky-rs/mqtt-upload: disk full",
+            })
+        );
+    }
+
+    #[test]
+    fn deferred_status_has_only_the_panel() {
+        assert_eq!(
+            serde_json::to_value(Status::deferred((800, 480))).unwrap(),
+            json!({"outcome": "deferred", "width": 800, "height": 480})
+        );
+    }
+}
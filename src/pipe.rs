@@ -0,0 +1,183 @@
+use std::{
+    ffi::CString,
+    fmt::Display,
+    fs::{self, File},
+    io::{self, Read},
+    os::unix::{ffi::OsStrExt, fs::FileTypeExt},
+    path::{Path, PathBuf},
+    sync::Arc,
+    thread,
+    time::Duration,
+};
+
+use log::{debug, info, warn};
+
+use crate::{daemon::Latest, fetch::MAX_BYTES};
+
+/** The file in the state directory that images read from the pipe are written to. */
+pub const UPLOAD_FILE_NAME: &str = "pipe-upload";
+
+/** Permissions of a pipe created by the `pipe` command: the owner and the group may write. */
+const MODE: libc::mode_t = 0o660;
+
+pub enum PipeError {
+    /** Something other than a named pipe is in the way. */
+    NotAFifo(PathBuf),
+    Create(PathBuf, io::Error),
+}
+
+/** A named pipe read on another thread. It is removed when dropped if it was created for it. */
+pub struct Pipe {
+    path: PathBuf,
+    created: bool,
+    latest: Arc<Latest<Vec<u8>>>,
+}
+
+impl Pipe {
+    /** The next image to display, waiting up to `timeout` for one. */
+    pub fn next(&self, timeout: Duration) -> Option<Vec<u8>> {
+        self.latest.next(timeout)
+    }
+}
+
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        if !self.created {
+            return;
+        }
+        match fs::remove_file(&self.path) {
+            Ok(()) => debug!("Removed {}", self.path.display()),
+            Err(error) => warn!("Could not remove {}: {error}", self.path.display()),
+        }
+    }
+}
+
+/** Read images from the named pipe at `path`, creating it if there is nothing there. Each
+ * writer sends one image, which ends when it closes the pipe. */
+pub fn open(path: &Path) -> Result<Pipe, PipeError> {
+    let created = match fs::metadata(path) {
+        Ok(metadata) if metadata.file_type().is_fifo() => false,
+        Ok(_) => return Err(PipeError::NotAFifo(path.to_path_buf())),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => {
+            mkfifo(path).map_err(|error| PipeError::Create(path.to_path_buf(), error))?;
+            info!("Created {}", path.display());
+            true
+        }
+        Err(error) => return Err(PipeError::Create(path.to_path_buf(), error)),
+    };
+
+    let latest = Arc::new(Latest::default());
+    let received = latest.clone();
+    let reading = path.to_path_buf();
+    info!("Waiting for images written to {}", path.display());
+    thread::spawn(move || loop {
+        match read_image(&reading) {
+            Ok(image) if image.is_empty() => debug!("A writer closed the pipe without writing"),
+            Ok(image) => {
+                info!("Received {} bytes", image.len());
+                received.put(image);
+            }
+            Err(error) => {
+                warn!("Could not read {}: {error}", reading.display());
+                // Such as after the pipe was deleted, which would fail again at once
+                thread::sleep(Duration::from_secs(1));
+            }
+        }
+    });
+
+    return Ok(Pipe {
+        path: path.to_path_buf(),
+        created,
+        latest,
+    });
+}
+
+/** Wait for a writer to open the pipe and read until it closes it. Opening blocks until there
+ * is one. */
+fn read_image(path: &Path) -> io::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    File::open(path)?
+        .take(MAX_BYTES + 1)
+        .read_to_end(&mut bytes)?;
+    if bytes.len() as u64 > MAX_BYTES {
+        return Err(io::Error::other(format!(
+            "the image is larger than {} MiB",
+            MAX_BYTES / 1024 / 1024
+        )));
+    }
+    return Ok(bytes);
+}
+
+fn mkfifo(path: &Path) -> io::Result<()> {
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    // SAFETY: `path` is a NUL-terminated string that outlives the call, and mkfifo only reads it
+    if unsafe { libc::mkfifo(path.as_ptr(), MODE) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    return Ok(());
+}
+
+impl Display for PipeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PipeError::NotAFifo(path) => {
+                write!(f, "{} exists and is not a named pipe", path.display())
+            }
+            PipeError::Create(path, error) => {
+                write!(f, "Could not create {}: {error}", path.display())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn files_in_the_way_are_not_pipes() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("image.png");
+        fs::write(&file, b"not a pipe").unwrap();
+        for path in [file.as_path(), dir.path()] {
+            match open(path) {
+                Err(PipeError::NotAFifo(reported)) => assert_eq!(reported, path),
+                Err(error) => panic!("{error}"),
+                Ok(_) => panic!("{} was opened as a pipe", path.display()),
+            }
+        }
+        // Left as they were
+        assert_eq!(fs::read(&file).unwrap(), b"not a pipe");
+    }
+
+    #[test]
+    fn pipes_that_cant_be_created_say_where() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("missing").join("frame.fifo");
+        let Err(error) = open(&path) else {
+            panic!("{} was created", path.display());
+        };
+        assert!(error
+            .to_string()
+            .starts_with(&format!("Could not create {}: ", path.display())));
+    }
+
+    #[test]
+    fn created_pipes_are_removed_when_done() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("frame.fifo");
+        let pipe = open(&path).unwrap_or_else(|error| panic!("{error}"));
+        assert!(fs::metadata(&path).unwrap().file_type().is_fifo());
+        drop(pipe);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn existing_pipes_are_left_in_place() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("frame.fifo");
+        mkfifo(&path).unwrap();
+        drop(open(&path).unwrap_or_else(|error| panic!("{error}")));
+        assert!(fs::metadata(&path).unwrap().file_type().is_fifo());
+    }
+}
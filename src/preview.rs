@@ -0,0 +1,209 @@
+use std::{
+    env,
+    fmt::Write as _,
+    io::{self, IsTerminal},
+};
+
+/** Letters standing in for the palette entries without color support, by index. */
+const ASCII_COLORS: &[u8] = b"# gbryo";
+
+/** Columns to fill when the terminal width is unknown. */
+const DEFAULT_COLUMNS: usize = 80;
+
+/** How colors can be shown on the terminal. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /** 24-bit colors. */
+    TrueColor,
+    /** The xterm 256 color palette. */
+    Ansi256,
+    /** No escapes at all, one letter per palette entry. */
+    Ascii,
+}
+
+impl Mode {
+    /** Pick the richest mode stdout supports. Anything but a terminal, and terminals with
+     * `NO_COLOR` set or `TERM=dumb`, get plain ASCII. */
+    pub fn detect() -> Mode {
+        let term = env::var("TERM").unwrap_or_default();
+        if !io::stdout().is_terminal() || env::var_os("NO_COLOR").is_some() || term == "dumb" {
+            return Mode::Ascii;
+        }
+        let colorterm = env::var("COLORTERM").unwrap_or_default();
+        if colorterm == "truecolor" || colorterm == "24bit" {
+            return Mode::TrueColor;
+        }
+        return Mode::Ansi256;
+    }
+}
+
+/** Width of the terminal from `COLUMNS`, which shells set for interactive sessions. */
+pub fn columns() -> usize {
+    env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.parse().ok())
+        .filter(|&columns| columns > 0)
+        .unwrap_or(DEFAULT_COLUMNS)
+}
+
+/** Draw a `width` × `height` frame of palette indices at most `columns` characters wide.
+ *
+ * In color, every character is an upper half block with the foreground set to one pixel and
+ * the background to the one below it, which keeps pixels roughly square. In ASCII a character
+ * covers two rows. Pixels are sampled, not averaged, so every one shown is a panel color. */
+pub fn render(
+    width: usize,
+    height: usize,
+    pixels: &[u8],
+    palette: &[imagequant::RGBA],
+    columns: usize,
+    mode: Mode,
+) -> String {
+    let columns = columns.min(width).max(1);
+    // Two pixel rows per line in every mode: half blocks hold two, and text cells are about
+    // twice as tall as they are wide
+    let lines = (height * columns / width.max(1)).div_ceil(2).max(1);
+    let sample = |column: usize, row: usize| {
+        let x = column * width / columns;
+        let y = (row * height / (lines * 2)).min(height.saturating_sub(1));
+        pixels.get(y * width + x).copied().unwrap_or(0)
+    };
+
+    let mut out = String::new();
+    for line in 0..lines {
+        for column in 0..columns {
+            let top = sample(column, line * 2);
+            let bottom = sample(column, line * 2 + 1);
+            match mode {
+                Mode::Ascii => {
+                    out.push(*ASCII_COLORS.get(top as usize).unwrap_or(&b'?') as char);
+                }
+                Mode::TrueColor | Mode::Ansi256 => {
+                    let _ = write!(
+                        out,
+                        "\x1b[{};{}m\u{2580}",
+                        escape(mode, palette, top, 38),
+                        escape(mode, palette, bottom, 48)
+                    );
+                }
+            }
+        }
+        if mode != Mode::Ascii {
+            out.push_str("\x1b[0m");
+        }
+        out.push('\n');
+    }
+    return out;
+}
+
+/** SGR parameters for the color of palette entry `index`, as a foreground with `base` 38 or a
+ * background with 48. */
+fn escape(mode: Mode, palette: &[imagequant::RGBA], index: u8, base: u8) -> String {
+    let color = palette
+        .get(index as usize)
+        .copied()
+        .unwrap_or(rgb::Rgba::new(0, 0, 0, 0));
+    match mode {
+        Mode::TrueColor => format!("{base};2;{};{};{}", color.r, color.g, color.b),
+        _ => {
+            // Nearest entry of the 6×6×6 color cube
+            let level = |c: u8| (c as u16 * 5 + 127) / 255;
+            let cube = 16 + 36 * level(color.r) + 6 * level(color.g) + level(color.b);
+            format!("{base};5;{cube}")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /** The palette without the saturation blended in, so colors are easy to tell apart. */
+    fn palette() -> Vec<imagequant::RGBA> {
+        crate::get_palette(0.0)
+    }
+
+    /** A `width` × `height` frame with `index(x, y)` at every pixel. */
+    fn frame(width: usize, height: usize, index: impl Fn(usize, usize) -> u8) -> Vec<u8> {
+        (0..width * height)
+            .map(|ix| index(ix % width, ix / width))
+            .collect()
+    }
+
+    #[test]
+    fn ascii_has_a_letter_per_color() {
+        let pixels = frame(7, 2, |x, _| x as u8);
+        let out = render(7, 2, &pixels, &palette(), 80, Mode::Ascii);
+        assert_eq!(out, "# gbryo\n");
+        // Transparent and unknown entries
+        let out = render(2, 1, &[7, 200], &palette(), 80, Mode::Ascii);
+        assert_eq!(out, "??\n");
+    }
+
+    #[test]
+    fn ascii_samples_every_other_row() {
+        // Black, white, green and blue quadrants
+        let pixels = frame(16, 8, |x, y| (x / 8 + 2 * (y / 4)) as u8);
+        let out = render(16, 8, &pixels, &palette(), 8, Mode::Ascii);
+        assert_eq!(out, "####    \nggggbbbb\n");
+        let out = render(16, 8, &pixels, &palette(), 16, Mode::Ascii);
+        assert_eq!(
+            out,
+            "########        \n########        \nggggggggbbbbbbbb\nggggggggbbbbbbbb\n"
+        );
+    }
+
+    #[test]
+    fn true_color_has_a_pixel_in_each_half() {
+        // Black above red, white above green
+        let out = render(2, 2, &[0, 1, 4, 2], &palette(), 80, Mode::TrueColor);
+        assert_eq!(
+            out,
+            "\x1b[38;2;0;0;0;48;2;255;0;0m\u{2580}\
+             \x1b[38;2;255;255;255;48;2;0;255;0m\u{2580}\x1b[0m\n"
+        );
+    }
+
+    #[test]
+    fn ansi256_uses_the_color_cube() {
+        let out = render(2, 2, &[0, 1, 4, 2], &palette(), 80, Mode::Ansi256);
+        assert_eq!(
+            out,
+            "\x1b[38;5;16;48;5;196m\u{2580}\x1b[38;5;231;48;5;46m\u{2580}\x1b[0m\n"
+        );
+        let cube: Vec<String> = (0..7)
+            .map(|i| escape(Mode::Ansi256, &palette(), i, 38))
+            .collect();
+        assert_eq!(
+            cube,
+            ["38;5;16", "38;5;231", "38;5;46", "38;5;21", "38;5;196", "38;5;226", "38;5;214"]
+        );
+    }
+
+    #[test]
+    fn odd_heights_are_spread_over_the_lines() {
+        // Blue, red and yellow rows, over the four halves of two lines
+        let pixels = frame(1, 3, |_, y| y as u8 + 3);
+        let out = render(1, 3, &pixels, &palette(), 80, Mode::TrueColor);
+        assert_eq!(
+            out,
+            "\x1b[38;2;0;0;255;48;2;0;0;255m\u{2580}\x1b[0m\n\
+             \x1b[38;2;255;0;0;48;2;255;255;0m\u{2580}\x1b[0m\n"
+        );
+    }
+
+    #[test]
+    fn output_fits_the_columns() {
+        let pixels = frame(800, 480, |x, y| ((x / 100 + y / 100) % 7) as u8);
+        for columns in [1, 40, 80, 200] {
+            let out = render(800, 480, &pixels, &palette(), columns, Mode::Ascii);
+            let lines: Vec<&str> = out.lines().collect();
+            assert!(lines.iter().all(|line| line.len() == columns), "{columns}");
+            // Half as many lines as rows, to keep the aspect ratio
+            assert_eq!(lines.len(), (480 * columns / 800).div_ceil(2).max(1));
+        }
+        // Never wider than the frame, and never empty
+        assert_eq!(render(3, 2, &[0; 6], &palette(), 80, Mode::Ascii), "###\n");
+        assert_eq!(render(3, 2, &[0; 6], &palette(), 0, Mode::Ascii), "#\n");
+    }
+}
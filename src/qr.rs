@@ -0,0 +1,224 @@
+use std::fmt::Display;
+
+use clap::ValueEnum;
+use qrcode::{types::QrError, EcLevel, QrCode};
+
+use crate::{
+    quantize::{caption::CaptionFont, text},
+    render::{Canvas, Color},
+};
+
+/** Light modules kept around the code, as the standard asks for. Scanners struggle without. */
+const QUIET_ZONE: usize = 4;
+
+/** How much of a QR code may be damaged or covered and still scan. Higher levels make the code
+ * denser, so there is less room for data. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ErrorCorrection {
+    /// About 7% of the code
+    Low,
+    /// About 15% of the code
+    Medium,
+    /// About 25% of the code
+    Quartile,
+    /// About 30% of the code
+    High,
+}
+
+impl From<ErrorCorrection> for EcLevel {
+    fn from(level: ErrorCorrection) -> EcLevel {
+        match level {
+            ErrorCorrection::Low => EcLevel::L,
+            ErrorCorrection::Medium => EcLevel::M,
+            ErrorCorrection::Quartile => EcLevel::Q,
+            ErrorCorrection::High => EcLevel::H,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum QrCodeError {
+    /** The data can't be encoded, usually because there is too much of it. */
+    Encode(QrError),
+    /** The code has more modules than the panel has pixels for. */
+    TooSmall { modules: usize, side: usize },
+}
+
+/** Draw `data` as a QR code scaled up to the largest square that fits in `width` × `height`,
+ * centered on white with `caption` underneath if given. Every module is a whole number of pixels
+ * of exactly black or white, so the canvas must be sent without dithering. */
+pub fn render(
+    data: &str,
+    level: ErrorCorrection,
+    caption: Option<(&str, &CaptionFont, u32)>,
+    width: usize,
+    height: usize,
+) -> Result<Canvas, QrCodeError> {
+    let code =
+        QrCode::with_error_correction_level(data, level.into()).map_err(QrCodeError::Encode)?;
+    let caption = caption.map(|(line, font, size)| {
+        let line = if font.width(line, size) > width as u32 {
+            text::with_ellipsis(line, font, size, width as u32)
+        } else {
+            line.to_string()
+        };
+        (font.rasterize(&line, size), font.line_height(size) as usize)
+    });
+    // The quiet zone keeps the caption clear of the code, a margin keeps it off the edge
+    let caption_height = caption
+        .as_ref()
+        .map_or(0, |(mask, _)| mask.height() as usize * 3 / 2);
+
+    let modules = code.width() + 2 * QUIET_ZONE;
+    let side = width.min(height.saturating_sub(caption_height));
+    let scale = side / modules;
+    if scale == 0 {
+        return Err(QrCodeError::TooSmall { modules, side });
+    }
+    let size = modules * scale;
+    let left = (width - size) / 2;
+    let top = (height - size - caption_height) / 2;
+
+    let mut canvas = Canvas::new(width, height, Color::White.index());
+    for (i, color) in code.to_colors().iter().enumerate() {
+        if *color == qrcode::Color::Dark {
+            let x = left + (QUIET_ZONE + i % code.width()) * scale;
+            let y = top + (QUIET_ZONE + i / code.width()) * scale;
+            canvas.fill_rect(x, y, scale, scale, Color::Black.index());
+        }
+    }
+
+    if let Some((mask, line_height)) = caption {
+        let x = width.saturating_sub(mask.width() as usize) / 2;
+        let y = top + size + line_height.saturating_sub(mask.height() as usize) / 2;
+        for (mx, my, pixel) in mask.enumerate_pixels() {
+            // Gray edges would come out in whichever color is nearest, so they are decided here
+            if pixel.0[0] >= 128 {
+                canvas.fill_rect(x + mx as usize, y + my as usize, 1, 1, Color::Black.index());
+            }
+        }
+    }
+    return Ok(canvas);
+}
+
+impl Display for QrCodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            QrCodeError::Encode(QrError::DataTooLong) => write!(
+                f,
+                "Too much data for a QR code, shorten it or lower --error-correction"
+            ),
+            QrCodeError::Encode(error) => write!(f, "Could not encode the QR code: {error}"),
+            QrCodeError::TooSmall { modules, side } => write!(
+                f,
+                "The QR code is {modules} modules wide, which does not fit in {side} pixels; \
+                 shorten the data, lower --error-correction or leave out the caption"
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DATA: &str = "https://example.com/frame";
+
+    fn code() -> QrCode {
+        return QrCode::with_error_correction_level(DATA, EcLevel::M).unwrap();
+    }
+
+    /** The smallest rectangle around the black pixels as `(left, top, right, bottom)`, the
+     * right and bottom edges exclusive. */
+    fn dark_bounds(canvas: &Canvas) -> (usize, usize, usize, usize) {
+        let black = Color::Black.index();
+        let dark: Vec<(usize, usize)> = (canvas.pixels.iter().enumerate())
+            .filter(|(_, &px)| px == black)
+            .map(|(i, _)| (i % canvas.width, i / canvas.width))
+            .collect();
+        let xs = || dark.iter().map(|&(x, _)| x);
+        let ys = || dark.iter().map(|&(_, y)| y);
+        return (
+            xs().min().unwrap(),
+            ys().min().unwrap(),
+            xs().max().unwrap() + 1,
+            ys().max().unwrap() + 1,
+        );
+    }
+
+    #[test]
+    fn codes_are_only_black_and_white() {
+        let font = CaptionFont::Builtin;
+        let caption = Some(("Guest Wi-Fi", &font, 16));
+        let canvas = render(DATA, ErrorCorrection::Medium, caption, 200, 150)
+            .unwrap_or_else(|error| panic!("{error}"));
+        let (black, white) = (Color::Black.index(), Color::White.index());
+        assert!(canvas.pixels.iter().all(|&px| px == black || px == white));
+        assert!(canvas.pixels.contains(&black));
+        assert!(canvas.pixels.contains(&white));
+    }
+
+    #[test]
+    fn modules_are_scaled_up_whole() {
+        let canvas = render(DATA, ErrorCorrection::Medium, None, 200, 150)
+            .unwrap_or_else(|error| panic!("{error}"));
+        let code = code();
+        let modules = code.width() + 2 * QUIET_ZONE;
+        let scale = 150 / modules;
+        let size = modules * scale;
+        let (left, top) = ((200 - size) / 2, (150 - size) / 2);
+
+        let colors = code.to_colors();
+        let mut expected = Canvas::new(200, 150, Color::White.index());
+        for y in top..top + size {
+            for x in left..left + size {
+                let (mx, my) = ((x - left) / scale, (y - top) / scale);
+                let in_code = QUIET_ZONE..QUIET_ZONE + code.width();
+                if !in_code.contains(&mx) || !in_code.contains(&my) {
+                    continue;
+                }
+                let module = (my - QUIET_ZONE) * code.width() + (mx - QUIET_ZONE);
+                if colors[module] == qrcode::Color::Dark {
+                    expected.fill_rect(x, y, 1, 1, Color::Black.index());
+                }
+            }
+        }
+        assert!(scale > 1);
+        assert!(canvas.pixels == expected.pixels);
+    }
+
+    #[test]
+    fn codes_are_centred() {
+        let canvas = render(DATA, ErrorCorrection::Medium, None, 200, 150)
+            .unwrap_or_else(|error| panic!("{error}"));
+        let (left, top, right, bottom) = dark_bounds(&canvas);
+        assert!(
+            left.abs_diff(200 - right) <= 1,
+            "{left} and {}",
+            200 - right
+        );
+        assert!(
+            top.abs_diff(150 - bottom) <= 1,
+            "{top} and {}",
+            150 - bottom
+        );
+        // The corner finder patterns are dark, so the bounds are square
+        assert_eq!(right - left, bottom - top);
+    }
+
+    #[test]
+    fn captions_can_leave_no_room_for_the_code() {
+        let font = CaptionFont::Builtin;
+        assert!(render(DATA, ErrorCorrection::Low, None, 40, 40).is_ok());
+        let caption = Some(("Guest Wi-Fi", &font, 16));
+        match render(DATA, ErrorCorrection::Low, caption, 40, 40) {
+            Err(QrCodeError::TooSmall { modules, side }) => {
+                let code = QrCode::with_error_correction_level(DATA, EcLevel::L).unwrap();
+                assert_eq!(modules, code.width() + 2 * QUIET_ZONE);
+                assert!(side < modules, "{side}");
+            }
+            Err(error) => panic!("{error}"),
+            Ok(_) => panic!("the code was drawn"),
+        }
+    }
+}
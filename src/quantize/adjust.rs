@@ -0,0 +1,170 @@
+/** Tone adjustments applied to the resized image before quantization. */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Adjustments {
+    /** Offset added to every channel, from -100 (black) to 100 (white) */
+    pub brightness: i32,
+    /** Gain applied to the red, green and blue channels */
+    pub white_balance: [f64; 3],
+    /** Spread of the channels around mid-grey, from -100 (all grey) to 100 */
+    pub contrast: i32,
+    /** Power curve for the red, green and blue channels, above 1 to lift the midtones */
+    pub gamma: f64,
+}
+
+impl Default for Adjustments {
+    fn default() -> Self {
+        Adjustments {
+            brightness: 0,
+            white_balance: [1.0, 1.0, 1.0],
+            contrast: 0,
+            gamma: 1.0,
+        }
+    }
+}
+
+impl Adjustments {
+    /** Build a lookup table per color channel: white balance first, then brightness, contrast
+     * and gamma. */
+    fn luts(&self) -> [[u8; 256]; 3] {
+        let offset = self.brightness as f64 * 255.0 / 100.0;
+        // Scaled like the image crate's contrast, so -100 is flat grey
+        let spread = ((100.0 + self.contrast as f64) / 100.0).powi(2);
+        let mut luts = [[0; 256]; 3];
+        for (lut, gain) in luts.iter_mut().zip(self.white_balance) {
+            for (value, out) in lut.iter_mut().enumerate() {
+                let brightened = (value as f64 * gain + offset).clamp(0.0, 255.0);
+                let contrasted = ((brightened / 255.0 - 0.5) * spread + 0.5).clamp(0.0, 1.0);
+                let curved = 255.0 * contrasted.powf(1.0 / self.gamma);
+                *out = curved.round() as u8;
+            }
+        }
+        luts
+    }
+
+    /** Apply the adjustments in place. The alpha channel is left untouched. */
+    pub fn apply(&self, buffer: &mut [imagequant::RGBA]) {
+        self.apply_channels(bytemuck::cast_slice_mut(buffer), 4);
+    }
+
+    /** Apply the adjustments in place to an image without alpha. */
+    pub fn apply_rgb(&self, buffer: &mut [rgb::RGB8]) {
+        self.apply_channels(bytemuck::cast_slice_mut(buffer), 3);
+    }
+
+    /** Apply the adjustments to interleaved pixels of `stride` bytes, red, green and blue first. */
+    fn apply_channels(&self, bytes: &mut [u8], stride: usize) {
+        if *self == Adjustments::default() {
+            return;
+        }
+
+        let [r, g, b] = self.luts();
+        for px in bytes.chunks_exact_mut(stride) {
+            px[0] = r[px[0] as usize];
+            px[1] = g[px[1] as usize];
+            px[2] = b[px[2] as usize];
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quantize::{quantize, Settings};
+
+    const WIDTH: usize = 16;
+    const HEIGHT: usize = 16;
+    const BLACK: u8 = 0;
+    const WHITE: u8 = 1;
+
+    fn grey(level: u8) -> Vec<imagequant::RGBA> {
+        vec![imagequant::RGBA::new(level, level, level, 255); WIDTH * HEIGHT]
+    }
+
+    /** A horizontal ramp from black to white. */
+    fn ramp() -> Vec<imagequant::RGBA> {
+        (0..WIDTH * HEIGHT)
+            .map(|ix| {
+                let level = (ix % WIDTH * 255 / (WIDTH - 1)) as u8;
+                imagequant::RGBA::new(level, level, level, 255)
+            })
+            .collect()
+    }
+
+    fn quantized(adjustments: Adjustments, mut pixels: Vec<imagequant::RGBA>) -> Vec<u8> {
+        adjustments.apply(&mut pixels);
+        let palette = crate::get_palette(0.5);
+        quantize(
+            &palette,
+            Settings::default(),
+            WIDTH,
+            HEIGHT,
+            pixels.into_boxed_slice(),
+        )
+        .unwrap()
+    }
+
+    fn count(indices: &[u8], index: u8) -> usize {
+        indices.iter().filter(|&&i| i == index).count()
+    }
+
+    /** The average lightness of the palette colors the pixels were given, from 0 to 765. */
+    fn lightness(indices: &[u8]) -> f64 {
+        let palette = crate::get_palette(0.5);
+        let sum: u32 = indices
+            .iter()
+            .map(|&i| palette[i as usize])
+            .map(|px| px.r as u32 + px.g as u32 + px.b as u32)
+            .sum();
+        sum as f64 / indices.len() as f64
+    }
+
+    fn brightness(brightness: i32) -> Adjustments {
+        Adjustments {
+            brightness,
+            ..Adjustments::default()
+        }
+    }
+
+    #[test]
+    fn brightness_moves_grey_towards_white() {
+        let runs: Vec<Vec<u8>> = [0, 25, 50, 75, 100]
+            .into_iter()
+            .map(|level| quantized(brightness(level), grey(128)))
+            .collect();
+        let lightness: Vec<f64> = runs.iter().map(|indices| lightness(indices)).collect();
+        assert!(lightness.windows(2).all(|w| w[0] <= w[1]), "{lightness:?}");
+        assert!(count(&runs[0], WHITE) < WIDTH * HEIGHT);
+        assert_eq!(count(&runs[4], WHITE), WIDTH * HEIGHT);
+
+        // And towards black the other way
+        let darkened = quantized(brightness(-100), grey(128));
+        assert_eq!(count(&darkened, BLACK), WIDTH * HEIGHT);
+    }
+
+    #[test]
+    fn no_brightness_or_contrast_changes_nothing() {
+        let adjustments = Adjustments {
+            brightness: 0,
+            contrast: 0,
+            ..Adjustments::default()
+        };
+        // Without relying on apply skipping the defaults
+        let identity: [u8; 256] = std::array::from_fn(|value| value as u8);
+        assert_eq!(adjustments.luts(), [identity; 3]);
+
+        let mut pixels = ramp();
+        adjustments.apply(&mut pixels);
+        assert!(pixels == ramp());
+        let mut rgb: Vec<rgb::RGB8> = ramp().iter().map(|px| px.rgb()).collect();
+        let original = rgb.clone();
+        adjustments.apply_rgb(&mut rgb);
+        assert!(rgb == original);
+
+        let unadjusted = {
+            let palette = crate::get_palette(0.5);
+            let buffer = ramp().into_boxed_slice();
+            quantize(&palette, Settings::default(), WIDTH, HEIGHT, buffer).unwrap()
+        };
+        assert!(quantized(adjustments, ramp()) == unadjusted);
+    }
+}
@@ -0,0 +1,291 @@
+use std::{fs, fs::File, io::BufReader, path::Path};
+
+use ab_glyph::{point, Font, FontVec, PxScale, ScaleFont};
+use chrono::{Local, Utc};
+use clap::ValueEnum;
+use image::{DynamicImage, GrayImage, Rgba};
+
+use crate::{
+    quantize::error::QuantizeError,
+    render::{font::GLYPH_HEIGHT, text_width, Canvas},
+};
+
+/** Where the caption goes, in the frame as it hangs. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+/** The font captions are drawn in: the bitmap font built in, scaled up to whole pixels, or a
+ * TrueType or OpenType font. */
+pub enum CaptionFont {
+    Builtin,
+    Outline(FontVec),
+}
+
+impl CaptionFont {
+    /** Load the font file at `path`, or use the built-in font if there is none. */
+    pub fn load(path: Option<&Path>) -> Result<CaptionFont, QuantizeError> {
+        let Some(path) = path else {
+            return Ok(CaptionFont::Builtin);
+        };
+        let font = FontVec::try_from_vec(fs::read(path)?).map_err(|error| {
+            QuantizeError::Caption(format!("{} is not a usable font: {error}", path.display()))
+        })?;
+        return Ok(CaptionFont::Outline(font));
+    }
+
+    /** What is put in place of the end of a caption that is too long. The built-in font is
+     * ASCII only. */
+    pub fn ellipsis(&self) -> &'static str {
+        match self {
+            CaptionFont::Builtin => "...",
+            CaptionFont::Outline(_) => "…",
+        }
+    }
+
+    /** The largest whole-pixel scale of the built-in font at most `size` pixels tall. */
+    fn builtin_scale(size: u32) -> usize {
+        (size as usize / GLYPH_HEIGHT).max(1)
+    }
+
+    /** Distance from one line of text to the next, `size` pixels tall. */
+    pub fn line_height(&self, size: u32) -> u32 {
+        match self {
+            CaptionFont::Builtin => ((GLYPH_HEIGHT + 1) * Self::builtin_scale(size)) as u32,
+            CaptionFont::Outline(font) => {
+                let font = font.as_scaled(PxScale::from(size as f32));
+                (font.height() + font.line_gap()).ceil() as u32
+            }
+        }
+    }
+
+    pub fn width(&self, text: &str, size: u32) -> u32 {
+        match self {
+            CaptionFont::Builtin => text_width(text, Self::builtin_scale(size)) as u32,
+            CaptionFont::Outline(font) => {
+                let font = font.as_scaled(PxScale::from(size as f32));
+                let mut width = 0.0;
+                let mut previous = None;
+                for c in text.chars() {
+                    let id = font.glyph_id(c);
+                    if let Some(previous) = previous {
+                        width += font.kern(previous, id);
+                    }
+                    width += font.h_advance(id);
+                    previous = Some(id);
+                }
+                width.ceil() as u32
+            }
+        }
+    }
+
+    /** Draw `text` as coverage from 0 to 255, `size` pixels tall. */
+    pub fn rasterize(&self, text: &str, size: u32) -> GrayImage {
+        match self {
+            CaptionFont::Builtin => {
+                let scale = Self::builtin_scale(size);
+                let width = text_width(text, scale);
+                let mut canvas = Canvas::new(width, GLYPH_HEIGHT * scale, 0);
+                canvas.draw_text(0, 0, text, scale, 255);
+                GrayImage::from_raw(width as u32, canvas.height as u32, canvas.pixels).unwrap()
+            }
+            CaptionFont::Outline(font) => {
+                let scale = PxScale::from(size as f32);
+                let scaled = font.as_scaled(scale);
+                let height = scaled.height().ceil() as u32;
+                let mut mask = GrayImage::new(self.width(text, size).max(1), height.max(1));
+                let mut x = 0.0;
+                let mut previous = None;
+                for c in text.chars() {
+                    let id = scaled.glyph_id(c);
+                    if let Some(previous) = previous {
+                        x += scaled.kern(previous, id);
+                    }
+                    let glyph = id.with_scale_and_position(scale, point(x, scaled.ascent()));
+                    x += scaled.h_advance(id);
+                    previous = Some(id);
+                    let Some(outline) = font.outline_glyph(glyph) else {
+                        continue;
+                    };
+                    let bounds = outline.px_bounds();
+                    outline.draw(|gx, gy, coverage| {
+                        let px = bounds.min.x as i64 + gx as i64;
+                        let py = bounds.min.y as i64 + gy as i64;
+                        if px < 0
+                            || py < 0
+                            || px >= mask.width() as i64
+                            || py >= mask.height() as i64
+                        {
+                            return;
+                        }
+                        let pixel = mask.get_pixel_mut(px as u32, py as u32);
+                        pixel.0[0] = pixel.0[0].max((coverage * 255.0).round() as u8);
+                    });
+                }
+                mask
+            }
+        }
+    }
+}
+
+/** Fill in the placeholders of a caption template for the file at `path`: `{filename}`,
+ * `{stem}` and `{folder}` from its path, and `{date}`, `{time}` and `{camera}` from its EXIF
+ * data. Placeholders without a value, such as the date of a photo without EXIF data, become
+ * empty, and unknown ones are left as they are. */
+pub fn expand(template: &str, path: &Path) -> String {
+    let exif = read_exif(path);
+    let exif_text = |tag| {
+        let field = exif.as_ref()?.get_field(tag, exif::In::PRIMARY)?;
+        match &field.value {
+            exif::Value::Ascii(values) => values.first().map(|v| String::from_utf8_lossy(v)),
+            _ => None,
+        }
+        .map(|text| text.trim().to_string())
+    };
+    let taken = [exif::Tag::DateTimeOriginal, exif::Tag::DateTime]
+        .into_iter()
+        .find_map(|tag| exif::DateTime::from_ascii(exif_text(tag)?.as_bytes()).ok());
+    let name = |name: Option<&std::ffi::OsStr>| {
+        name.map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    };
+
+    let mut caption = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        caption.push_str(&rest[..start]);
+        let Some(end) = rest[start..].find('}') else {
+            caption.push_str(&rest[start..]);
+            rest = "";
+            break;
+        };
+        let placeholder = &rest[start + 1..start + end];
+        let value = match placeholder {
+            "filename" => Some(name(path.file_name())),
+            "stem" => Some(name(path.file_stem())),
+            "folder" => Some(name(path.parent().and_then(Path::file_name))),
+            "date" => Some(taken.as_ref().map_or(String::new(), |t| {
+                format!("{:04}-{:02}-{:02}", t.year, t.month, t.day)
+            })),
+            "time" => Some(
+                taken
+                    .as_ref()
+                    .map_or(String::new(), |t| format!("{:02}:{:02}", t.hour, t.minute)),
+            ),
+            "camera" => Some(exif_text(exif::Tag::Model).unwrap_or_default()),
+            _ => None,
+        };
+        match value {
+            Some(value) => caption.push_str(&value),
+            None => caption.push_str(&rest[start..=start + end]),
+        }
+        rest = &rest[start + end + 1..];
+    }
+    caption.push_str(rest);
+    return caption.trim().to_string();
+}
+
+/** The current time in `format`, in UTC or the local time zone. The format has been checked
+ * when parsing the command line. */
+pub fn timestamp(format: &str, utc: bool) -> String {
+    if utc {
+        return Utc::now().format(format).to_string();
+    }
+    return Local::now().format(format).to_string();
+}
+
+fn read_exif(path: &Path) -> Option<exif::Exif> {
+    let file = File::open(path).ok()?;
+    exif::Reader::new()
+        .read_from_container(&mut BufReader::new(file))
+        .ok()
+}
+
+/** Draw `text` in white with a black outline into `corner` of the image, `size` pixels tall.
+ * Text wider than the image is cut short with an ellipsis. */
+pub fn draw(
+    image: DynamicImage,
+    text: &str,
+    font: &CaptionFont,
+    size: u32,
+    corner: Corner,
+) -> DynamicImage {
+    let mut image = image.into_rgba8();
+    let outline = (size / 12).max(1);
+    let margin = size / 2 + outline;
+    let max_width = image.width().saturating_sub(2 * margin);
+    let text = fit_width(text, font, size, max_width);
+    if text.is_empty() {
+        return DynamicImage::from(image);
+    }
+
+    let mask = font.rasterize(&text, size);
+    let (width, height) = (mask.width() + 2 * outline, mask.height() + 2 * outline);
+    let left = match corner {
+        Corner::TopLeft | Corner::BottomLeft => margin - outline,
+        Corner::TopRight | Corner::BottomRight => {
+            image.width().saturating_sub(margin - outline + width)
+        }
+    };
+    let top = match corner {
+        Corner::TopLeft | Corner::TopRight => margin - outline,
+        Corner::BottomLeft | Corner::BottomRight => {
+            image.height().saturating_sub(margin - outline + height)
+        }
+    };
+
+    let coverage = |x: i64, y: i64| -> u8 {
+        if x < 0 || y < 0 || x >= mask.width() as i64 || y >= mask.height() as i64 {
+            return 0;
+        }
+        mask.get_pixel(x as u32, y as u32).0[0]
+    };
+    let reach = outline as i64;
+    for y in 0..height {
+        for x in 0..width {
+            let (px, py) = (left + x, top + y);
+            if px >= image.width() || py >= image.height() {
+                continue;
+            }
+            let (mx, my) = (x as i64 - reach, y as i64 - reach);
+            let mut edge = 0;
+            for dy in -reach..=reach {
+                for dx in -reach..=reach {
+                    edge = edge.max(coverage(mx + dx, my + dy));
+                }
+            }
+            let pixel = image.get_pixel_mut(px, py);
+            blend(pixel, [0, 0, 0], edge);
+            blend(pixel, [255, 255, 255], coverage(mx, my));
+        }
+    }
+    return DynamicImage::from(image);
+}
+
+/** `text`, or as much of it as fits in `max_width` followed by an ellipsis. */
+fn fit_width(text: &str, font: &CaptionFont, size: u32, max_width: u32) -> String {
+    if font.width(text, size) <= max_width {
+        return text.to_string();
+    }
+    let mut chars: Vec<char> = text.chars().collect();
+    while !chars.is_empty() {
+        chars.pop();
+        let shortened: String = chars.iter().collect::<String>().trim_end().to_string();
+        let shortened = shortened + font.ellipsis();
+        if font.width(&shortened, size) <= max_width {
+            return shortened;
+        }
+    }
+    return String::new();
+}
+
+fn blend(pixel: &mut Rgba<u8>, color: [u8; 3], alpha: u8) {
+    let alpha = alpha as u32;
+    for (channel, target) in pixel.0.iter_mut().zip(color) {
+        *channel = ((*channel as u32 * (255 - alpha) + target as u32 * alpha) / 255) as u8;
+    }
+}
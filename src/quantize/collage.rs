@@ -0,0 +1,143 @@
+use clap::ValueEnum;
+use image::{imageops, DynamicImage, Rgba, RgbaImage};
+
+/** How `--collage` arranges images on the panel, as columns × rows. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Layout {
+    /// One image above the other
+    #[value(name = "1x2")]
+    Stacked,
+    /// Two images next to each other
+    #[value(name = "2x1")]
+    SideBySide,
+    /// Four images in two rows
+    #[value(name = "2x2")]
+    Grid,
+}
+
+impl Layout {
+    /** Number of columns and rows. */
+    pub fn grid(self) -> (u32, u32) {
+        match self {
+            Layout::Stacked => (1, 2),
+            Layout::SideBySide => (2, 1),
+            Layout::Grid => (2, 2),
+        }
+    }
+
+    /** Number of images the layout holds. */
+    pub fn cells(self) -> usize {
+        let (columns, rows) = self.grid();
+        (columns * rows) as usize
+    }
+
+    /** Size of each cell of a `width` × `height` collage with `gutter` pixels between cells.
+     * Pixels that don't divide evenly are left over at the right and bottom edges. */
+    pub fn cell_size(self, width: u32, height: u32, gutter: u32) -> (u32, u32) {
+        let (columns, rows) = self.grid();
+        let cell_width = width.saturating_sub((columns - 1) * gutter) / columns;
+        let cell_height = height.saturating_sub((rows - 1) * gutter) / rows;
+        (cell_width.max(1), cell_height.max(1))
+    }
+
+    /** Put `cells`, each already of [Layout::cell_size], into a `width` × `height` image,
+     * row by row, on a background of `gutter_color`. */
+    pub fn compose(
+        self,
+        width: u32,
+        height: u32,
+        gutter: u32,
+        gutter_color: Rgba<u8>,
+        cells: &[DynamicImage],
+    ) -> DynamicImage {
+        let (columns, _) = self.grid();
+        let (cell_width, cell_height) = self.cell_size(width, height, gutter);
+        let mut collage = RgbaImage::from_pixel(width, height, gutter_color);
+        for (i, cell) in cells.iter().enumerate() {
+            let (column, row) = (i as u32 % columns, i as u32 / columns);
+            let x = column * (cell_width + gutter);
+            let y = row * (cell_height + gutter);
+            imageops::replace(&mut collage, &cell.to_rgba8(), x as i64, y as i64);
+        }
+        return DynamicImage::from(collage);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GUTTER: Rgba<u8> = Rgba([255, 255, 255, 255]);
+
+    /** A cell filled with the shade `value`. */
+    fn cell(width: u32, height: u32, value: u8) -> DynamicImage {
+        DynamicImage::from(RgbaImage::from_pixel(
+            width,
+            height,
+            Rgba([value, value, value, 255]),
+        ))
+    }
+
+    #[test]
+    fn layouts_hold_columns_times_rows() {
+        assert_eq!(Layout::Stacked.grid(), (1, 2));
+        assert_eq!(Layout::SideBySide.grid(), (2, 1));
+        assert_eq!(Layout::Grid.grid(), (2, 2));
+        assert_eq!(Layout::Stacked.cells(), 2);
+        assert_eq!(Layout::SideBySide.cells(), 2);
+        assert_eq!(Layout::Grid.cells(), 4);
+    }
+
+    #[test]
+    fn cells_share_what_the_gutters_leave() {
+        assert_eq!(Layout::Grid.cell_size(800, 480, 0), (400, 240));
+        assert_eq!(Layout::Grid.cell_size(800, 480, 10), (395, 235));
+        assert_eq!(Layout::SideBySide.cell_size(800, 480, 10), (395, 480));
+        assert_eq!(Layout::Stacked.cell_size(800, 480, 10), (800, 235));
+        // The odd pixel is left over
+        assert_eq!(Layout::SideBySide.cell_size(801, 480, 0), (400, 480));
+    }
+
+    #[test]
+    fn cells_are_never_empty() {
+        assert_eq!(Layout::Grid.cell_size(10, 10, 20), (1, 1));
+        assert_eq!(Layout::Grid.cell_size(1, 1, 0), (1, 1));
+    }
+
+    #[test]
+    fn cells_are_placed_row_by_row_between_gutters() {
+        let (width, height, gutter) = (22, 14, 3);
+        let (cell_width, cell_height) = Layout::Grid.cell_size(width, height, gutter);
+        assert_eq!((cell_width, cell_height), (9, 5));
+        let cells: Vec<DynamicImage> = (1..=4)
+            .map(|value| cell(cell_width, cell_height, value))
+            .collect();
+        let collage = Layout::Grid
+            .compose(width, height, gutter, GUTTER, &cells)
+            .to_rgba8();
+
+        assert_eq!(collage.dimensions(), (22, 14));
+        let at = |x, y| collage.get_pixel(x, y).0[0];
+        // The corners of each cell
+        for (value, x, y) in [(1, 0, 0), (2, 12, 0), (3, 0, 8), (4, 12, 8)] {
+            assert_eq!(at(x, y), value);
+            assert_eq!(at(x + cell_width - 1, y + cell_height - 1), value);
+        }
+        // The gutters, and the pixels left over at the right and bottom
+        assert_eq!(at(9, 0), 255);
+        assert_eq!(at(11, 4), 255);
+        assert_eq!(at(0, 5), 255);
+        assert_eq!(at(10, 6), 255);
+        assert_eq!(at(21, 13), 255);
+    }
+
+    #[test]
+    fn missing_cells_are_left_as_gutter() {
+        let (cell_width, cell_height) = Layout::SideBySide.cell_size(20, 10, 0);
+        let collage = Layout::SideBySide
+            .compose(20, 10, 0, GUTTER, &[cell(cell_width, cell_height, 0)])
+            .to_rgba8();
+        assert_eq!(collage.get_pixel(9, 9).0, [0, 0, 0, 255]);
+        assert_eq!(collage.get_pixel(10, 0).0, GUTTER.0);
+    }
+}
@@ -0,0 +1,206 @@
+use std::{
+    fs::File,
+    io::{Cursor, Read},
+    path::Path,
+};
+
+use image::{metadata::Orientation, DynamicImage, ImageDecoder, ImageFormat, ImageReader};
+
+use crate::quantize::error::QuantizeError;
+
+/** Brands of HEIF files with HEVC coded images, as phones write them. `mif1` and `msf1` are
+ * left out, as AVIF files list them too. */
+const HEIC_BRANDS: &[&[u8; 4]] = &[b"heic", b"heix", b"hevc", b"hevx", b"heim", b"heis"];
+const AVIF_BRANDS: &[&[u8; 4]] = &[b"avif", b"avis"];
+
+/** Formats in an ISO base media file, which the image crate doesn't recognize by itself. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Container {
+    Heic,
+    Avif,
+}
+
+impl Container {
+    /** Recognize the format from the brands in the `ftyp` box at the start of `bytes`, or else
+     * from the extension of `path` if the image crate doesn't recognize the contents either. */
+    fn detect(bytes: &[u8], path: &Path) -> Option<Container> {
+        if let Some(container) = Container::from_brands(bytes) {
+            return Some(container);
+        }
+        if image::guess_format(bytes).is_ok() {
+            return None;
+        }
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "heic" | "heif" => Some(Container::Heic),
+            "avif" => Some(Container::Avif),
+            _ => None,
+        }
+    }
+
+    fn from_brands(bytes: &[u8]) -> Option<Container> {
+        if bytes.get(4..8)? != b"ftyp" {
+            return None;
+        }
+        let size = u32::from_be_bytes(bytes[..4].try_into().unwrap()) as usize;
+        // The major brand, the minor version and then the compatible brands
+        let brands = bytes.get(8..size.min(bytes.len()))?;
+        let brands = brands
+            .chunks_exact(4)
+            .enumerate()
+            .filter(|(i, _)| *i != 1)
+            .map(|(_, brand)| brand);
+        for brand in brands {
+            if AVIF_BRANDS.iter().any(|avif| avif.as_slice() == brand) {
+                return Some(Container::Avif);
+            }
+            if HEIC_BRANDS.iter().any(|heic| heic.as_slice() == brand) {
+                return Some(Container::Heic);
+            }
+        }
+        return None;
+    }
+}
+
+/** Decode an image in memory, turned upright as its EXIF orientation says. HEIC and AVIF are
+ * recognized from their contents or the extension of `path`, other formats are guessed from the
+ * contents alone, as uploads and downloads often have no extension. */
+pub fn decode(bytes: Vec<u8>, path: &Path) -> Result<DynamicImage, QuantizeError> {
+    let reader = match Container::detect(&bytes, path) {
+        Some(Container::Heic) => return decode_heic(&bytes),
+        Some(Container::Avif) if cfg!(not(feature = "avif")) => {
+            return Err(QuantizeError::Unsupported {
+                format: "AVIF",
+                feature: "avif",
+            });
+        }
+        Some(Container::Avif) => ImageReader::with_format(Cursor::new(bytes), ImageFormat::Avif),
+        None => ImageReader::new(Cursor::new(bytes)).with_guessed_format()?,
+    };
+
+    let mut decoder = reader.into_decoder()?;
+    let orientation = decoder.orientation()?;
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    image.apply_orientation(orientation);
+
+    return Ok(image);
+}
+
+/** The width and height of the image in the file at `path` as it will be shown, turned upright
+ * as its EXIF orientation says. Only the header is read, the image isn't decoded. */
+pub fn dimensions(path: &Path) -> Result<(u32, u32), QuantizeError> {
+    // Enough for the signature of any format and the brands of an ISO base media file
+    let mut header = Vec::with_capacity(64);
+    File::open(path)?.take(64).read_to_end(&mut header)?;
+    let reader = match Container::detect(&header, path) {
+        Some(Container::Heic) => return heic_dimensions(path),
+        Some(Container::Avif) if cfg!(not(feature = "avif")) => {
+            return Err(QuantizeError::Unsupported {
+                format: "AVIF",
+                feature: "avif",
+            });
+        }
+        Some(Container::Avif) => {
+            let mut reader = ImageReader::open(path)?;
+            reader.set_format(ImageFormat::Avif);
+            reader
+        }
+        None => ImageReader::open(path)?.with_guessed_format()?,
+    };
+
+    let mut decoder = reader.into_decoder()?;
+    let (width, height) = decoder.dimensions();
+    let swapped = matches!(
+        decoder.orientation()?,
+        Orientation::Rotate90
+            | Orientation::Rotate270
+            | Orientation::Rotate90FlipH
+            | Orientation::Rotate270FlipH
+    );
+
+    return Ok(if swapped {
+        (height, width)
+    } else {
+        (width, height)
+    });
+}
+
+/** Decode the primary image of a HEIC file with libheif. The rotation and mirroring in the file
+ * are applied while decoding, and the EXIF orientation, which only repeats them, is ignored as
+ * the HEIF standard asks. */
+#[cfg(feature = "heic")]
+fn decode_heic(bytes: &[u8]) -> Result<DynamicImage, QuantizeError> {
+    use image::{
+        error::{DecodingError, ImageFormatHint},
+        ImageError, RgbImage, RgbaImage,
+    };
+    use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
+
+    let error = |error: libheif_rs::HeifError| {
+        let hint = ImageFormatHint::Name("HEIC".to_string());
+        QuantizeError::Image(ImageError::Decoding(DecodingError::new(hint, error)))
+    };
+    let context = HeifContext::read_from_bytes(bytes).map_err(error)?;
+    let handle = context.primary_image_handle().map_err(error)?;
+    let alpha = handle.has_alpha_channel();
+    let chroma = if alpha {
+        RgbChroma::Rgba
+    } else {
+        RgbChroma::Rgb
+    };
+    let decoded = LibHeif::new()
+        .decode(&handle, ColorSpace::Rgb(chroma), None)
+        .map_err(error)?;
+
+    // Interleaved RGB was asked for, and its rows may be padded, so they are copied one by one
+    let plane = decoded.planes().interleaved.unwrap();
+    let channels = if alpha { 4 } else { 3 };
+    let row_length = plane.width as usize * channels;
+    let mut pixels = Vec::with_capacity(row_length * plane.height as usize);
+    for row in plane.data.chunks(plane.stride).take(plane.height as usize) {
+        pixels.extend_from_slice(&row[..row_length]);
+    }
+
+    let image = if alpha {
+        RgbaImage::from_raw(plane.width, plane.height, pixels).map(DynamicImage::from)
+    } else {
+        RgbImage::from_raw(plane.width, plane.height, pixels).map(DynamicImage::from)
+    };
+    // The buffer was sized for exactly these dimensions
+    return Ok(image.unwrap());
+}
+
+#[cfg(not(feature = "heic"))]
+fn decode_heic(_bytes: &[u8]) -> Result<DynamicImage, QuantizeError> {
+    Err(QuantizeError::Unsupported {
+        format: "HEIC",
+        feature: "heic",
+    })
+}
+
+/** The size of the primary image of a HEIC file, with its rotation applied like [decode_heic]
+ * does. */
+#[cfg(feature = "heic")]
+fn heic_dimensions(path: &Path) -> Result<(u32, u32), QuantizeError> {
+    use image::{
+        error::{DecodingError, ImageFormatHint},
+        ImageError,
+    };
+    use libheif_rs::HeifContext;
+
+    let error = |error: libheif_rs::HeifError| {
+        let hint = ImageFormatHint::Name("HEIC".to_string());
+        QuantizeError::Image(ImageError::Decoding(DecodingError::new(hint, error)))
+    };
+    let context = HeifContext::read_from_file(&path.to_string_lossy()).map_err(error)?;
+    let handle = context.primary_image_handle().map_err(error)?;
+    return Ok((handle.width(), handle.height()));
+}
+
+#[cfg(not(feature = "heic"))]
+fn heic_dimensions(_path: &Path) -> Result<(u32, u32), QuantizeError> {
+    Err(QuantizeError::Unsupported {
+        format: "HEIC",
+        feature: "heic",
+    })
+}
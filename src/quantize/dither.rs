@@ -0,0 +1,69 @@
+/** Pixels with less alpha than this are mapped to the transparent palette entry, if any. */
+const ALPHA_THRESHOLD: u8 = 128;
+
+/** Quantize `width` × `height` pixels, read through `pixel` by their row-major position, to
+ * indices into `palette`, using the nearest palette color with Floyd-Steinberg dithering.
+ * `strength` is the share of the error that is diffused, rounded to sixteenths.
+ *
+ * Unlike libimagequant, this only depends on its input: it runs in integer arithmetic on a
+ * single thread, so the same image and palette give the same indices on every run, machine
+ * and library version. */
+pub fn dither(
+    palette: &[imagequant::RGBA],
+    width: usize,
+    height: usize,
+    strength: f32,
+    pixel: impl Fn(usize) -> imagequant::RGBA,
+) -> Vec<u8> {
+    let transparent = palette.iter().position(|c| c.a < ALPHA_THRESHOLD);
+    let opaque: Vec<(u8, [i32; 3])> = palette
+        .iter()
+        .enumerate()
+        .filter(|(_, c)| c.a >= ALPHA_THRESHOLD)
+        .map(|(i, c)| (i as u8, [c.r as i32, c.g as i32, c.b as i32]))
+        .collect();
+
+    let strength = (strength.clamp(0.0, 1.0) * 16.0).round() as i32;
+
+    // Error carried to the current and the next row, in 1/256ths (the weights' sixteenths
+    // times the strength's), with a pixel of padding on both sides so the edges need no
+    // special cases
+    let mut current = vec![[0i32; 3]; width + 2];
+    let mut next = vec![[0i32; 3]; width + 2];
+    let mut out = vec![0; width * height];
+    for y in 0..height {
+        for x in 0..width {
+            let px = pixel(y * width + x);
+            if let (Some(index), true) = (transparent, px.a < ALPHA_THRESHOLD) {
+                out[y * width + x] = index as u8;
+                continue;
+            }
+
+            let channels = [px.r, px.g, px.b].map(|c| c as i32);
+            let error = current[x + 1];
+            let wanted: [i32; 3] =
+                std::array::from_fn(|c| (channels[c] + error[c] / 256).clamp(0, 255));
+            let &(index, color) = opaque
+                .iter()
+                .min_by_key(|(_, color)| distance(color, &wanted))
+                .unwrap();
+            out[y * width + x] = index;
+
+            for channel in 0..3 {
+                let e = (wanted[channel] - color[channel]) * strength;
+                current[x + 2][channel] += e * 7;
+                next[x][channel] += e * 3;
+                next[x + 1][channel] += e * 5;
+                next[x + 2][channel] += e;
+            }
+        }
+        std::mem::swap(&mut current, &mut next);
+        next.fill([0; 3]);
+    }
+
+    return out;
+}
+
+fn distance(a: &[i32; 3], b: &[i32; 3]) -> i32 {
+    a.iter().zip(b).map(|(a, b)| (a - b) * (a - b)).sum()
+}
@@ -1,10 +1,43 @@
-use std::{fmt::Display, io, process};
+use std::{fmt::Display, io, path::PathBuf};
+
+use crate::{exec::ExecError, fetch::FetchError, quantize::indexed::Requantize};
 
 #[derive(derive_more::From)]
 pub enum QuantizeError {
     Io(io::Error),
     Image(image::ImageError),
     Quantize(imagequant::Error),
+    Fetch(FetchError),
+    Exec(ExecError),
+    /** The caption couldn't be drawn, such as with a font file that isn't one. */
+    #[from(ignore)]
+    Caption(String),
+    /** An image was to be read from stdin, but it is a terminal. */
+    #[from(ignore)]
+    TerminalInput,
+    /** `--strict-passthrough` was given, but the image would have to be quantized. */
+    #[from(ignore)]
+    Passthrough(PathBuf, Requantize),
+    /** The image is in a format this build can't decode without the given feature. */
+    #[from(ignore)]
+    Unsupported {
+        format: &'static str,
+        feature: &'static str,
+    },
+    /** Every file tried in turn failed to be read or decoded, each with its error. */
+    #[from(ignore)]
+    Unreadable(Vec<(PathBuf, QuantizeError)>),
+}
+
+impl QuantizeError {
+    /** Whether the file itself is the problem, such as a corrupt or truncated image, so another
+     * one might do. */
+    pub fn is_unreadable(&self) -> bool {
+        matches!(
+            self,
+            QuantizeError::Io(_) | QuantizeError::Image(_) | QuantizeError::Unsupported { .. }
+        )
+    }
 }
 
 impl Display for QuantizeError {
@@ -13,14 +46,37 @@ impl Display for QuantizeError {
             QuantizeError::Io(error) => write!(f, "File error: {error}"),
             QuantizeError::Image(error) => write!(f, "File error: {error}"),
             QuantizeError::Quantize(error) => write!(f, "Quantization error: {error}"),
+            QuantizeError::Fetch(error) => write!(f, "Download error: {error}"),
+            QuantizeError::Exec(error) => write!(f, "Command error: {error}"),
+            QuantizeError::Caption(error) => write!(f, "Caption error: {error}"),
+            QuantizeError::TerminalInput => {
+                write!(
+                    f,
+                    "Stdin is a terminal, pipe an image into it to display it"
+                )
+            }
+            QuantizeError::Passthrough(path, reason) => write!(
+                f,
+                "Passthrough error: {} would have to be quantized, as {reason}",
+                path.display()
+            ),
+            QuantizeError::Unsupported { format, feature } => {
+                write!(
+                    f,
+                    "{format} support not enabled in this build, rebuild with --features {feature}"
+                )
+            }
+            QuantizeError::Unreadable(failures) => {
+                write!(
+                    f,
+                    "None of the {} files tried could be read",
+                    failures.len()
+                )?;
+                for (path, error) in failures {
+                    write!(f, "; {}: {error}", path.display())?;
+                }
+                Ok(())
+            }
         }
     }
 }
-
-pub fn handle_error<T, E>(error: E) -> T
-where
-    E: Into<QuantizeError>,
-{
-    println!("{}", error.into());
-    process::exit(1);
-}
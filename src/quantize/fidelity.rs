@@ -0,0 +1,66 @@
+use clap::ValueEnum;
+use image::DynamicImage;
+
+/** Size the image is reduced to before measuring, so judging a candidate costs a fraction of
+ * quantizing it. */
+const PROXY_SIZE: u32 = 200;
+
+/** How far an image's colors are from the palette, as the RGB distance (0–441) from each pixel
+ * to the nearest opaque palette color. Dithering can mix colors the palette lacks only so far:
+ * images that score high come out noisy. */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ColorError {
+    pub mean: f64,
+    pub p95: f64,
+}
+
+/** Which figure of a [ColorError] `--max-quant-error` applies to. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Metric {
+    /// Average over all pixels
+    Mean,
+    /// 95th percentile, which catches images with large badly matched areas
+    P95,
+}
+
+impl Metric {
+    pub fn of(self, error: &ColorError) -> f64 {
+        match self {
+            Metric::Mean => error.mean,
+            Metric::P95 => error.p95,
+        }
+    }
+}
+
+/** Measure the [ColorError] of a downscaled copy of `image`. Pixels with less than half alpha
+ * are left out, as they end up transparent anyway. */
+pub fn measure(palette: &[imagequant::RGBA], image: &DynamicImage) -> ColorError {
+    let proxy = image.thumbnail(PROXY_SIZE, PROXY_SIZE).into_rgba8();
+    let opaque: Vec<_> = palette.iter().filter(|c| c.a >= 128).collect();
+
+    let mut distances: Vec<f64> = proxy
+        .pixels()
+        .filter(|px| px[3] >= 128)
+        .map(|px| {
+            opaque
+                .iter()
+                .map(|c| {
+                    let d = |a: u8, b: u8| (a as f64 - b as f64).powi(2);
+                    d(px[0], c.r) + d(px[1], c.g) + d(px[2], c.b)
+                })
+                .fold(f64::INFINITY, f64::min)
+                .sqrt()
+        })
+        .collect();
+    if distances.is_empty() || opaque.is_empty() {
+        return ColorError {
+            mean: 0.0,
+            p95: 0.0,
+        };
+    }
+
+    distances.sort_by(f64::total_cmp);
+    let mean = distances.iter().sum::<f64>() / distances.len() as f64;
+    let p95 = distances[(distances.len() - 1) * 95 / 100];
+    return ColorError { mean, p95 };
+}
@@ -0,0 +1,463 @@
+use std::{fmt::Display, fs::File, io::BufReader, path::Path};
+
+use image::{imageops::FilterType, DynamicImage, GrayImage, Rgba};
+use log::debug;
+
+use crate::quantize::{error::QuantizeError, resize_with, shrink_with, Fit};
+
+/** How far each channel of a PNG palette entry may be from a display color to count as it. */
+const TOLERANCE: u8 = 8;
+
+/** Why an image has to be quantized rather than mapped straight to display indices. */
+#[derive(Debug)]
+pub enum Requantize {
+    /** The file couldn't be read as a PNG, such as one in another format. */
+    NotPng(String),
+    /** The PNG holds colors rather than palette indices. */
+    NotIndexed,
+    /** This entry of the PNG's palette has no display color within [TOLERANCE]. */
+    Palette { entry: usize, color: [u8; 4] },
+    /** The image isn't the size of the panel, which only `--strict-passthrough` minds. */
+    Size {
+        image: (u32, u32),
+        panel: (u32, u32),
+    },
+}
+
+/** Find every entry of a PNG palette in the display palette. Entries with less than half alpha
+ * map to the display palette's transparent color. Fails on the first entry without a display
+ * color within [TOLERANCE], as the image then needs quantizing after all. */
+pub fn match_palette(
+    entries: &[[u8; 4]],
+    display: &[imagequant::RGBA],
+) -> Result<Vec<u8>, Requantize> {
+    let close = |a: u8, b: u8| a.abs_diff(b) <= TOLERANCE;
+    entries
+        .iter()
+        .enumerate()
+        .map(|(entry, &[r, g, b, a])| {
+            let opaque = a >= 128;
+            display
+                .iter()
+                .position(|c| {
+                    if opaque {
+                        c.a >= 128 && close(c.r, r) && close(c.g, g) && close(c.b, b)
+                    } else {
+                        c.a < 128
+                    }
+                })
+                .map(|index| index as u8)
+                .ok_or(Requantize::Palette {
+                    entry,
+                    color: [r, g, b, a],
+                })
+        })
+        .collect()
+}
+
+/** Read a paletted PNG whose palette is made of display colors, as display palette indices.
+ * Fails for any other file, or if it can't be read this way. */
+pub fn load_indexed(
+    path: &Path,
+    display: &[imagequant::RGBA],
+) -> Result<(u32, u32, Vec<u8>), Requantize> {
+    let not_png = |error: &dyn Display| Requantize::NotPng(error.to_string());
+    let file = File::open(path).map_err(|error| not_png(&error))?;
+    let mut decoder = png::Decoder::new(BufReader::new(file));
+    decoder.set_transformations(png::Transformations::IDENTITY);
+    let mut reader = decoder.read_info().map_err(|error| not_png(&error))?;
+
+    let info = reader.info();
+    if info.color_type != png::ColorType::Indexed {
+        return Err(Requantize::NotIndexed);
+    }
+    let (width, height) = (info.width, info.height);
+    let bit_depth = info.bit_depth as usize;
+    let alphas = info.trns.as_deref().unwrap_or_default();
+    let entries: Vec<[u8; 4]> = info
+        .palette
+        .as_deref()
+        .ok_or(Requantize::NotIndexed)?
+        .chunks_exact(3)
+        .enumerate()
+        .map(|(i, rgb)| {
+            [
+                rgb[0],
+                rgb[1],
+                rgb[2],
+                alphas.get(i).copied().unwrap_or(255),
+            ]
+        })
+        .collect();
+    let mapping = match_palette(&entries, display)?;
+
+    let mut raw = vec![0; reader.output_buffer_size()];
+    let frame = reader
+        .next_frame(&mut raw)
+        .map_err(|error| not_png(&error))?;
+
+    // Rows are packed to whole bytes, high bits first
+    let per_byte = 8 / bit_depth;
+    let mask = ((1u16 << bit_depth) - 1) as u8;
+    let mut indices = Vec::with_capacity(width as usize * height as usize);
+    for row in raw.chunks_exact(frame.line_size).take(height as usize) {
+        for x in 0..width as usize {
+            let shift = 8 - bit_depth * (x % per_byte + 1);
+            let entry = row[x / per_byte] >> shift & mask;
+            // An index past the end of the palette, which decoders show as black
+            let index = mapping.get(entry as usize).ok_or(Requantize::Palette {
+                entry: entry as usize,
+                color: [0, 0, 0, 255],
+            })?;
+            indices.push(*index);
+        }
+    }
+
+    return Ok((width, height, indices));
+}
+
+/** The file at `path` as display palette indices, if it is a paletted PNG in the panel's
+ * colors and needn't be quantized. In `strict` mode any other file fails instead, as does one
+ * that isn't `width` × `height`, so a generator that drifted is noticed. */
+pub fn passthrough(
+    path: &Path,
+    display: &[imagequant::RGBA],
+    (width, height): (u32, u32),
+    strict: bool,
+) -> Result<Option<(u32, u32, Vec<u8>)>, QuantizeError> {
+    let indexed = load_indexed(path, display).and_then(|indexed| {
+        let (image_width, image_height, _) = indexed;
+        if strict && (image_width, image_height) != (width, height) {
+            return Err(Requantize::Size {
+                image: (image_width, image_height),
+                panel: (width, height),
+            });
+        }
+        Ok(indexed)
+    });
+    match indexed {
+        Ok(indexed) => return Ok(Some(indexed)),
+        Err(reason) if strict => {
+            return Err(QuantizeError::Passthrough(path.to_path_buf(), reason))
+        }
+        Err(reason) => {
+            debug!("Quantizing {}, as {reason}", path.display());
+            return Ok(None);
+        }
+    }
+}
+
+/** Bring palette indices to the given size like [crate::quantize::resize], or without enlarging
+ * them like [shrink_with] if not `upscale`, but with nearest neighbor sampling so every pixel
+ * keeps an index of the original. Bands added by fitting or centering are palette index
+ * `background`. */
+pub fn resize_indices(
+    (width, height, indices): (u32, u32, Vec<u8>),
+    target_width: u32,
+    target_height: u32,
+    fit: Fit,
+    upscale: bool,
+    background: u8,
+) -> Vec<u8> {
+    if (width, height) == (target_width, target_height) {
+        return indices;
+    }
+
+    let image = DynamicImage::from(GrayImage::from_raw(width, height, indices).unwrap());
+    let fill = Rgba([background, background, background, 255]);
+    let resize = if upscale { resize_with } else { shrink_with };
+    let resized = resize(
+        fit,
+        target_width,
+        target_height,
+        fill,
+        &image,
+        FilterType::Nearest,
+    );
+    resized.to_rgba8().pixels().map(|px| px[0]).collect()
+}
+
+impl Display for Requantize {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Requantize::NotPng(error) => write!(f, "it can't be read as a PNG: {error}"),
+            Requantize::NotIndexed => write!(f, "it is not a paletted PNG"),
+            Requantize::Palette {
+                entry,
+                color: [r, g, b, a],
+            } => write!(
+                f,
+                "entry {entry} of its palette, #{r:02X}{g:02X}{b:02X} with alpha {a}, is not one \
+                 of the panel's colors"
+            ),
+            Requantize::Size {
+                image: (image_width, image_height),
+                panel: (panel_width, panel_height),
+            } => write!(
+                f,
+                "it is {image_width}x{image_height}, but the panel is {panel_width}x{panel_height}"
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+    /** Black, white, green, blue, red, yellow, orange and transparent. */
+    const DISPLAY: [[u8; 4]; 8] = [
+        [0, 0, 0, 255],
+        [255, 255, 255, 255],
+        [0, 255, 0, 255],
+        [0, 0, 255, 255],
+        [255, 0, 0, 255],
+        [255, 255, 0, 255],
+        [255, 140, 0, 255],
+        [0, 0, 0, 0],
+    ];
+
+    fn display() -> Vec<imagequant::RGBA> {
+        DISPLAY
+            .iter()
+            .map(|&[r, g, b, a]| imagequant::RGBA::new(r, g, b, a))
+            .collect()
+    }
+
+    /** Pack `indices` into rows of whole bytes at `bit_depth`, high bits first. */
+    fn pack(indices: &[u8], width: usize, bit_depth: usize) -> Vec<u8> {
+        let per_byte = 8 / bit_depth;
+        let mut packed = Vec::new();
+        for row in indices.chunks(width) {
+            for pixels in row.chunks(per_byte) {
+                let byte = pixels
+                    .iter()
+                    .enumerate()
+                    .fold(0, |byte, (i, &px)| byte | px << (8 - bit_depth * (i + 1)));
+                packed.push(byte);
+            }
+        }
+        packed
+    }
+
+    /** Write a paletted PNG of `indices` into `palette`, with the alpha of each entry. */
+    fn write_indexed(
+        path: &Path,
+        width: u32,
+        bit_depth: png::BitDepth,
+        palette: &[[u8; 4]],
+        indices: &[u8],
+    ) {
+        let height = indices.len() as u32 / width;
+        let mut encoder = png::Encoder::new(File::create(path).unwrap(), width, height);
+        encoder.set_color(png::ColorType::Indexed);
+        encoder.set_depth(bit_depth);
+        encoder.set_palette(
+            palette
+                .iter()
+                .flat_map(|c| [c[0], c[1], c[2]])
+                .collect::<Vec<_>>(),
+        );
+        encoder.set_trns(palette.iter().map(|c| c[3]).collect::<Vec<_>>());
+        let mut writer = encoder.write_header().unwrap();
+        let packed = pack(indices, width as usize, bit_depth as usize);
+        writer.write_image_data(&packed).unwrap();
+    }
+
+    fn fixture(dir: &TempDir, name: &str) -> PathBuf {
+        dir.path().join(name)
+    }
+
+    fn strict_reason(path: &Path, size: (u32, u32)) -> Requantize {
+        match passthrough(path, &display(), size, true) {
+            Err(QuantizeError::Passthrough(failed, reason)) => {
+                assert_eq!(failed, path);
+                reason
+            }
+            Ok(_) => panic!("{} passed through", path.display()),
+            Err(error) => panic!("unexpected error: {error}"),
+        }
+    }
+
+    #[test]
+    fn strict_passthrough_accepts_an_exact_image() {
+        let dir = TempDir::new().unwrap();
+        let path = fixture(&dir, "exact.png");
+        let indices = [0, 1, 2, 3, 4, 5, 6, 1];
+        write_indexed(&path, 4, png::BitDepth::Four, &DISPLAY, &indices);
+
+        let passed = passthrough(&path, &display(), (4, 2), true).ok().flatten();
+        assert_eq!(passed, Some((4, 2, indices.to_vec())));
+    }
+
+    #[test]
+    fn strict_passthrough_rejects_other_formats() {
+        let dir = TempDir::new().unwrap();
+        let path = fixture(&dir, "photo.jpg");
+        std::fs::write(&path, b"\xFF\xD8\xFF\xE0 not a png").unwrap();
+
+        assert!(matches!(
+            strict_reason(&path, (4, 2)),
+            Requantize::NotPng(_)
+        ));
+    }
+
+    #[test]
+    fn strict_passthrough_rejects_truecolor() {
+        let dir = TempDir::new().unwrap();
+        let path = fixture(&dir, "rgb.png");
+        image::RgbImage::new(4, 2).save(&path).unwrap();
+
+        assert!(matches!(
+            strict_reason(&path, (4, 2)),
+            Requantize::NotIndexed
+        ));
+    }
+
+    #[test]
+    fn strict_passthrough_rejects_a_drifted_palette() {
+        let dir = TempDir::new().unwrap();
+        let path = fixture(&dir, "drifted.png");
+        let mut palette = DISPLAY;
+        palette[4] = [200, 30, 30, 255];
+        write_indexed(&path, 4, png::BitDepth::Four, &palette, &[4; 8]);
+
+        match strict_reason(&path, (4, 2)) {
+            Requantize::Palette { entry, color } => {
+                assert_eq!(entry, 4);
+                assert_eq!(color, [200, 30, 30, 255]);
+            }
+            reason => panic!("unexpected reason: {reason}"),
+        }
+    }
+
+    #[test]
+    fn strict_passthrough_rejects_an_index_past_the_palette() {
+        let dir = TempDir::new().unwrap();
+        let path = fixture(&dir, "past.png");
+        write_indexed(
+            &path,
+            4,
+            png::BitDepth::Four,
+            &DISPLAY[..4],
+            &[0, 1, 2, 9, 0, 0, 0, 0],
+        );
+
+        match strict_reason(&path, (4, 2)) {
+            Requantize::Palette { entry, .. } => assert_eq!(entry, 9),
+            reason => panic!("unexpected reason: {reason}"),
+        }
+    }
+
+    #[test]
+    fn strict_passthrough_rejects_another_size() {
+        let dir = TempDir::new().unwrap();
+        let path = fixture(&dir, "small.png");
+        write_indexed(&path, 4, png::BitDepth::Four, &DISPLAY, &[1; 8]);
+
+        match strict_reason(&path, (8, 4)) {
+            Requantize::Size { image, panel } => {
+                assert_eq!(image, (4, 2));
+                assert_eq!(panel, (8, 4));
+            }
+            reason => panic!("unexpected reason: {reason}"),
+        }
+    }
+
+    #[test]
+    fn lenient_passthrough_falls_back_to_quantizing() {
+        let dir = TempDir::new().unwrap();
+        let path = fixture(&dir, "rgb.png");
+        image::RgbImage::new(4, 2).save(&path).unwrap();
+
+        assert!(matches!(
+            passthrough(&path, &display(), (4, 2), false),
+            Ok(None)
+        ));
+
+        let path = fixture(&dir, "small.png");
+        write_indexed(&path, 4, png::BitDepth::Four, &DISPLAY, &[1; 8]);
+        let passed = passthrough(&path, &display(), (8, 4), false).ok().flatten();
+        assert_eq!(passed, Some((4, 2, vec![1; 8])));
+    }
+
+    #[test]
+    fn match_palette_maps_an_exact_palette_to_itself() {
+        let mapping = match_palette(&DISPLAY, &display()).unwrap();
+        assert_eq!(mapping, (0..8).collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn match_palette_follows_a_permuted_palette() {
+        let entries = [DISPLAY[6], DISPLAY[0], DISPLAY[3], DISPLAY[1]];
+        assert_eq!(match_palette(&entries, &display()).unwrap(), [6, 0, 3, 1]);
+    }
+
+    #[test]
+    fn match_palette_accepts_a_subset_within_tolerance() {
+        let entries = [[4, 250, 3, 255], [255, 136, 6, 255], [9, 200, 40, 0]];
+        assert_eq!(match_palette(&entries, &display()).unwrap(), [2, 6, 7]);
+    }
+
+    #[test]
+    fn match_palette_rejects_a_color_off_the_palette() {
+        let entries = [DISPLAY[0], [128, 128, 128, 255]];
+        match match_palette(&entries, &display()) {
+            Err(Requantize::Palette { entry, color }) => {
+                assert_eq!(entry, 1);
+                assert_eq!(color, [128, 128, 128, 255]);
+            }
+            other => panic!("unexpected result: {other:?}"),
+        }
+
+        let just_outside = [[255, 140 + TOLERANCE + 1, 0, 255]];
+        assert!(match_palette(&just_outside, &display()).is_err());
+    }
+
+    #[test]
+    fn load_indexed_reads_every_bit_depth() {
+        let dir = TempDir::new().unwrap();
+        for (bit_depth, colors) in [
+            (png::BitDepth::One, 2),
+            (png::BitDepth::Two, 4),
+            (png::BitDepth::Four, 8),
+            (png::BitDepth::Eight, 8),
+        ] {
+            // An odd width, so rows end part way through a byte
+            let (width, height) = (13, 3);
+            let indices: Vec<u8> = (0..width * height)
+                .map(|i| (i * 5 % colors) as u8)
+                .collect();
+            let path = fixture(&dir, &format!("{}-bit.png", bit_depth as u8));
+            write_indexed(&path, width as u32, bit_depth, &DISPLAY[..colors], &indices);
+
+            let loaded = load_indexed(&path, &display()).unwrap();
+            assert_eq!(
+                loaded,
+                (width as u32, height as u32, indices),
+                "{bit_depth:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn load_indexed_maps_entries_through_the_palette() {
+        let dir = TempDir::new().unwrap();
+        let path = fixture(&dir, "permuted.png");
+        let palette = [DISPLAY[5], DISPLAY[2], DISPLAY[7]];
+        write_indexed(
+            &path,
+            3,
+            png::BitDepth::Eight,
+            &palette,
+            &[0, 1, 2, 2, 1, 0],
+        );
+
+        let (_, _, indices) = load_indexed(&path, &display()).unwrap();
+        assert_eq!(indices, [5, 2, 7, 7, 2, 5]);
+    }
+}
@@ -1,14 +1,234 @@
-use image::{imageops, DynamicImage, ImageBuffer, RgbaImage};
-use std::cmp::Ordering;
+use clap::ValueEnum;
+use image::{imageops, DynamicImage, ImageBuffer, RgbImage, Rgba, RgbaImage};
+use std::{cmp::Ordering, mem::MaybeUninit};
 
+pub mod adjust;
+pub mod caption;
+pub mod collage;
+pub mod decode;
+pub mod dither;
 pub mod error;
+pub mod fidelity;
+pub mod indexed;
+pub mod rotate;
+pub mod text;
+pub mod xmp;
 
-pub fn fit_resize(width: u32, height: u32, image: &DynamicImage) -> DynamicImage {
+/** Gamma of the input images. Passing 0 would let the library pick its default, which is the
+ * same value today but not guaranteed to stay so. */
+const INPUT_GAMMA: f64 = 0.45455;
+
+/** Images are averaged down to between this many and twice this many times the target size
+ * before the actual resize. */
+const PREREDUCE_MARGIN: u32 = 2;
+
+/** Shrink an image much larger than `width` × `height` by an integer factor with cheap area
+ * averaging, so the expensive filter only has to handle a few times the target size. The detail
+ * this averages away would be lost in the final resize anyway. Returns `None` if the image is
+ * small enough already, or for nearest neighbor resizes, where averaging would mix colors. */
+fn prereduce(
+    width: u32,
+    height: u32,
+    image: &DynamicImage,
+    filter: imageops::FilterType,
+) -> Option<DynamicImage> {
+    if filter == imageops::FilterType::Nearest {
+        return None;
+    }
+    let ratio = (image.width() / width.max(1)).min(image.height() / height.max(1));
+    let factor = ratio / PREREDUCE_MARGIN;
+    if factor < 2 {
+        return None;
+    }
+    Some(image.thumbnail_exact(image.width() / factor, image.height() / factor))
+}
+
+/** Check that a saturation is between 0 (the desaturated palette) and 1 (the saturated one).
+ * Values outside, or NaN, would blend the palettes into colors the panel doesn't have. */
+pub fn check_saturation(saturation: f64) -> Result<f64, String> {
+    if !(0.0..=1.0).contains(&saturation) {
+        return Err(format!(
+            "saturation must be between 0.0 and 1.0, not {saturation}"
+        ));
+    }
+    return Ok(saturation);
+}
+
+/** How images are brought to the size of the panel. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Fit {
+    /// Fill the panel, cropping what sticks out
+    Cover,
+    /// Show the whole image, leaving bands of the background color
+    Contain,
+    /// Fill the panel, distorting the image if its aspect ratio differs
+    Stretch,
+    /// Show the image unscaled in the middle, cropping what sticks out
+    Center,
+}
+
+/** Bring an image to `width` × `height` as `fit` says. Bands left by [Fit::Contain] and
+ * [Fit::Center] are filled with `background`. */
+pub fn resize(
+    fit: Fit,
+    width: u32,
+    height: u32,
+    background: Rgba<u8>,
+    image: &DynamicImage,
+) -> DynamicImage {
+    resize_with(
+        fit,
+        width,
+        height,
+        background,
+        image,
+        imageops::FilterType::Lanczos3,
+    )
+}
+
+/** Like [resize], with the given filter. */
+pub fn resize_with(
+    fit: Fit,
+    width: u32,
+    height: u32,
+    background: Rgba<u8>,
+    image: &DynamicImage,
+    filter: imageops::FilterType,
+) -> DynamicImage {
+    match fit {
+        Fit::Cover => crop_resize_with(width, height, image, filter),
+        Fit::Contain => fit_resize_with(width, height, background, image, filter),
+        Fit::Stretch => stretch_resize_with(width, height, image, filter),
+        Fit::Center => center_resize(width, height, background, image),
+    }
+}
+
+/** Like [resize], but never enlarging the image, see [shrink_with]. */
+pub fn shrink(
+    fit: Fit,
+    width: u32,
+    height: u32,
+    background: Rgba<u8>,
+    image: &DynamicImage,
+) -> DynamicImage {
+    shrink_with(
+        fit,
+        width,
+        height,
+        background,
+        image,
+        imageops::FilterType::Lanczos3,
+    )
+}
+
+/** Like [resize_with], but never enlarging the image, for `--no-upscale`. An image smaller than
+ * `width` × `height` both ways is centered at its own size over `background`. One that is
+ * smaller only one way keeps its size that way and is shrunk the other way as far as `fit` needs,
+ * see [shrink_fit]. */
+pub fn shrink_with(
+    fit: Fit,
+    width: u32,
+    height: u32,
+    background: Rgba<u8>,
+    image: &DynamicImage,
+    filter: imageops::FilterType,
+) -> DynamicImage {
+    let (image_width, image_height) = (image.width(), image.height());
+    match shrink_fit(fit, width, height, image_width, image_height) {
+        Fit::Stretch if image_width < width || image_height < height => {
+            let (squeezed_width, squeezed_height) =
+                (image_width.min(width), image_height.min(height));
+            let squeezed = stretch_resize_with(squeezed_width, squeezed_height, image, filter);
+            center_resize(width, height, background, &squeezed)
+        }
+        fit => resize_with(fit, width, height, background, image, filter),
+    }
+}
+
+/** How [shrink_with] fits an `image_width` × `image_height` image into `width` × `height`:
+ * [Fit::Center] wherever `fit` would enlarge it, except that [Fit::Contain] still shrinks an
+ * image larger one way and [Fit::Stretch] still squeezes it that way. */
+pub fn shrink_fit(fit: Fit, width: u32, height: u32, image_width: u32, image_height: u32) -> Fit {
+    let smaller = image_width < width || image_height < height;
+    let larger = image_width > width || image_height > height;
+    match fit {
+        Fit::Cover if smaller => Fit::Center,
+        Fit::Contain | Fit::Stretch if !larger => Fit::Center,
+        fit => fit,
+    }
+}
+
+/** The part of an `image_width` × `image_height` image that ends up on a `width` × `height`
+ * canvas when fitted as `fit` says, as `(x, y, width, height)`. */
+pub fn visible_region(
+    fit: Fit,
+    width: u32,
+    height: u32,
+    image_width: u32,
+    image_height: u32,
+) -> (u32, u32, u32, u32) {
+    match fit {
+        Fit::Cover => cover_crop(width, height, image_width, image_height),
+        Fit::Contain | Fit::Stretch => (0, 0, image_width, image_height),
+        Fit::Center => {
+            let (visible_width, visible_height) =
+                (image_width.min(width), image_height.min(height));
+            (
+                (image_width - visible_width) / 2,
+                (image_height - visible_height) / 2,
+                visible_width,
+                visible_height,
+            )
+        }
+    }
+}
+
+/** The largest centered rectangle of an `image_width` × `image_height` image with the aspect
+ * ratio of `width` × `height`, as `(x, y, width, height)`. */
+fn cover_crop(
+    width: u32,
+    height: u32,
+    image_width: u32,
+    image_height: u32,
+) -> (u32, u32, u32, u32) {
+    let image_aspect_ratio = image_width as f64 / image_height as f64;
+    let target_aspect_ratio = width as f64 / height as f64;
+
+    let (crop_width, crop_height) = match image_aspect_ratio.total_cmp(&target_aspect_ratio) {
+        Ordering::Less => (
+            image_width,
+            (image_width as f64 / target_aspect_ratio) as u32,
+        ),
+        Ordering::Equal => (image_width, image_height),
+        Ordering::Greater => (
+            (image_height as f64 * target_aspect_ratio) as u32,
+            image_height,
+        ),
+    };
+
+    let crop_x = (image_width - crop_width) / 2;
+    let crop_y = (image_height - crop_height) / 2;
+    return (crop_x, crop_y, crop_width, crop_height);
+}
+
+/** Resize a [DynamicImage] to fit within the given width and height without distortion,
+ * filling the rest with `background`. */
+pub fn fit_resize_with(
+    width: u32,
+    height: u32,
+    background: Rgba<u8>,
+    image: &DynamicImage,
+    filter: imageops::FilterType,
+) -> DynamicImage {
     let image_width = image.width() as f64;
     let image_height = image.height() as f64;
     let image_aspect_ratio = image_width / image_height;
     let target_aspect_ratio = width as f64 / height as f64;
-    let resized = image.resize(width, height, imageops::FilterType::Lanczos3);
+    let reduced = prereduce(width, height, image, filter);
+    let resized = reduced
+        .as_ref()
+        .unwrap_or(image)
+        .resize(width, height, filter);
 
     let (overlay_x, overlay_y) = match image_aspect_ratio.total_cmp(&target_aspect_ratio) {
         Ordering::Less => ((width - resized.width()) / 2, 0),
@@ -16,31 +236,81 @@ pub fn fit_resize(width: u32, height: u32, image: &DynamicImage) -> DynamicImage
         Ordering::Greater => (0, (height - resized.height()) / 2),
     };
 
-    let mut new_image = RgbaImage::new(width, height);
+    let mut new_image = RgbaImage::from_pixel(width, height, background);
     imageops::overlay(&mut new_image, &resized, overlay_x as i64, overlay_y as i64);
 
     return new_image.into();
 }
 
-/** Resize a [DynamicImage] into the given width and height without distortion. */
-pub fn crop_resize(width: u32, height: u32, image: &DynamicImage) -> DynamicImage {
-    let image_width = image.width() as f64;
-    let image_height = image.height() as f64;
-    let image_aspect_ratio = image_width / image_height;
-    let target_aspect_ratio = width as f64 / height as f64;
+/** Resize a [DynamicImage] into the given width and height without distortion, cropping
+ * what sticks out. */
+pub fn crop_resize_with(
+    width: u32,
+    height: u32,
+    image: &DynamicImage,
+    filter: imageops::FilterType,
+) -> DynamicImage {
+    let (crop_x, crop_y, crop_width, crop_height) =
+        cover_crop(width, height, image.width(), image.height());
+    let cropped = image.crop_imm(crop_x, crop_y, crop_width, crop_height);
+    let cropped = prereduce(width, height, &cropped, filter).unwrap_or(cropped);
+    return cropped.resize_exact(width, height, filter);
+}
 
-    let (crop_width, crop_height) = match image_aspect_ratio.total_cmp(&target_aspect_ratio) {
-        Ordering::Less => (image.width(), (image_width / target_aspect_ratio) as u32),
-        Ordering::Equal => (image.width(), image.height()),
-        Ordering::Greater => ((image_height * target_aspect_ratio) as u32, image.height()),
-    };
+/** Resize a [DynamicImage] to exactly the given width and height, ignoring its aspect
+ * ratio. */
+pub fn stretch_resize_with(
+    width: u32,
+    height: u32,
+    image: &DynamicImage,
+    filter: imageops::FilterType,
+) -> DynamicImage {
+    let reduced = prereduce(width, height, image, filter);
+    return reduced
+        .as_ref()
+        .unwrap_or(image)
+        .resize_exact(width, height, filter);
+}
+
+/** Place a [DynamicImage] unscaled in the middle of a `width` × `height` canvas filled with
+ * `background`, cropping it evenly on each side where it is larger. */
+pub fn center_resize(
+    width: u32,
+    height: u32,
+    background: Rgba<u8>,
+    image: &DynamicImage,
+) -> DynamicImage {
+    let mut new_image = RgbaImage::from_pixel(width, height, background);
+    let x = (width as i64 - image.width() as i64) / 2;
+    let y = (height as i64 - image.height() as i64) / 2;
+    imageops::overlay(&mut new_image, image, x, y);
+
+    return new_image.into();
+}
 
-    let crop_x = (image.width() - crop_width) / 2;
-    let crop_y = (image.height() - crop_height) / 2;
+/** Estimate the sharpness of an image as the variance of the Laplacian of its luminance. */
+pub fn sharpness(image: &DynamicImage) -> f64 {
+    let luma = image.to_luma8();
+    let (width, height) = luma.dimensions();
+    if width < 3 || height < 3 {
+        return 0.0;
+    }
+
+    let px = |x: u32, y: u32| luma.get_pixel(x, y)[0] as f64;
+    let mut sum = 0.0;
+    let mut sum_sq = 0.0;
+    for y in 1..height - 1 {
+        for x in 1..width - 1 {
+            let laplacian =
+                px(x - 1, y) + px(x + 1, y) + px(x, y - 1) + px(x, y + 1) - 4.0 * px(x, y);
+            sum += laplacian;
+            sum_sq += laplacian * laplacian;
+        }
+    }
 
-    return image
-        .crop_imm(crop_x, crop_y, crop_width, crop_height)
-        .resize_exact(width, height, imageops::FilterType::Lanczos3);
+    let n = ((width - 2) * (height - 2)) as f64;
+    let mean = sum / n;
+    return sum_sq / n - mean * mean;
 }
 
 /** Convert an RGBA [ImageBuffer] into a vector of [imagequant::RGBA] pixels. */
@@ -50,26 +320,97 @@ pub fn image_buffer_into_vec(
     bytemuck::allocation::cast_vec(image.into_raw())
 }
 
-/** Quantize an image (as a boxed slice of pixels) according to a palette of max. 256 colors. */
+pub fn rgb_image_into_vec(image: RgbImage) -> Vec<rgb::RGB8> {
+    bytemuck::allocation::cast_vec(image.into_raw())
+}
+
+/** Quantizer settings that can be tuned on the command line. */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Settings {
+    /** How much of each pixel's color error is spread to its neighbors, from 0 for flat areas
+     * of the nearest palette color to 1 for full error diffusion. */
+    pub dither_strength: f32,
+    /** libimagequant's speed, from 1 (slowest, best quality) to 10. */
+    pub speed: u8,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            dither_strength: 1.0,
+            speed: 1,
+        }
+    }
+}
+
+/** A quantizer with every setting that affects the output pinned, rather than left to the
+ * library's defaults and heuristics. */
+fn new_quantizer(
+    palette: &[imagequant::RGBA],
+    settings: Settings,
+) -> Result<imagequant::Attributes, imagequant::Error> {
+    let mut quantizer = imagequant::new();
+    quantizer.set_max_colors(palette.len() as u32)?;
+    quantizer.set_speed(settings.speed as i32)?;
+    quantizer.set_quality(0, 100)?;
+    quantizer.set_min_posterization(0)?;
+    quantizer.set_last_index_transparent(false);
+    Ok(quantizer)
+}
+
+/** Quantize an image (as a boxed slice of pixels) according to a palette of max. 256 colors.
+ *
+ * The library runs on as many threads as rayon gives it, which doesn't change the result:
+ * every palette color is fixed, so there is no palette to search for, and the dithered remap
+ * goes through the pixels in order. The tests check this with 1 to 4 threads. */
 pub fn quantize(
     palette: &[imagequant::RGBA],
+    settings: Settings,
     width: usize,
     height: usize,
     buffer: Box<[imagequant::RGBA]>,
 ) -> Result<Vec<u8>, imagequant::Error> {
-    // Initialize the quantizer
-    let mut quantizer = imagequant::new();
-    quantizer.set_max_colors(palette.len() as u32)?;
-    quantizer.set_speed(1)?;
+    let mut quantizer = new_quantizer(palette, settings)?;
+    let image = quantizer.new_image(buffer, width, height, INPUT_GAMMA)?;
+    remap_to_palette(palette, settings, &mut quantizer, image)
+}
 
+/** Like [quantize], for images without an alpha channel. Rows are widened to RGBA as the
+ * quantizer reads them, instead of converting the whole image up front. */
+pub fn quantize_rgb(
+    palette: &[imagequant::RGBA],
+    settings: Settings,
+    width: usize,
+    height: usize,
+    buffer: &[rgb::RGB8],
+) -> Result<Vec<u8>, imagequant::Error> {
+    let mut quantizer = new_quantizer(palette, settings)?;
+    let rows = |row: &mut [MaybeUninit<imagequant::RGBA>], y: usize| {
+        let pixels = &buffer[y * width..(y + 1) * width];
+        for (out, px) in row.iter_mut().zip(pixels) {
+            out.write(imagequant::RGBA::new(px.r, px.g, px.b, 255));
+        }
+    };
+    let image = imagequant::Image::new_fn(&quantizer, rows, width, height, INPUT_GAMMA)?;
+    remap_to_palette(palette, settings, &mut quantizer, image)
+}
+
+/** Quantize to exactly the palette colors and return the index of each pixel's color. */
+fn remap_to_palette(
+    palette: &[imagequant::RGBA],
+    settings: Settings,
+    quantizer: &mut imagequant::Attributes,
+    mut image: imagequant::Image<'_>,
+) -> Result<Vec<u8>, imagequant::Error> {
     // Force the quantizer to only use palette colors
-    let mut image = quantizer.new_image(buffer, width, height, 0.0)?;
     for color in palette {
         image.add_fixed_color(color.clone())?;
     }
 
     // Quantize
     let mut quantization = quantizer.quantize(&mut image)?;
+    quantization.set_dithering_level(settings.dither_strength)?;
+    quantization.set_output_gamma(INPUT_GAMMA)?;
     let (out_palette, mut outbuf) = quantization.remapped(&mut image)?;
 
     // The order of the palette is not necessarily preserved,
@@ -84,3 +425,525 @@ pub fn quantize(
 
     return Ok(outbuf);
 }
+
+#[cfg(test)]
+mod tests {
+    use image::GenericImageView;
+
+    use super::*;
+
+    const WIDTH: usize = 48;
+    const HEIGHT: usize = 32;
+
+    /** Gradients with some texture, which dithering has plenty to do on. */
+    fn fixture() -> Vec<imagequant::RGBA> {
+        (0..WIDTH * HEIGHT)
+            .map(|ix| {
+                let (x, y) = (ix % WIDTH, ix / WIDTH);
+                let texture = ((x * 7 + y * 13) % 17) as u8;
+                imagequant::RGBA::new(
+                    (x * 255 / WIDTH) as u8 ^ texture,
+                    (y * 255 / HEIGHT) as u8,
+                    ((x + y) * 3) as u8,
+                    255,
+                )
+            })
+            .collect()
+    }
+
+    /** `run` on a pool of each number of threads from 1 to 4. */
+    fn on_threads<T: Send>(run: impl Fn() -> T + Sync) -> Vec<T> {
+        (1..=4)
+            .map(|threads| {
+                let pool = rayon::ThreadPoolBuilder::new()
+                    .num_threads(threads)
+                    .build()
+                    .unwrap();
+                pool.install(&run)
+            })
+            .collect()
+    }
+
+    const PANEL: (u32, u32) = (80, 48);
+    const BACKGROUND: Rgba<u8> = Rgba([1, 2, 3, 255]);
+    const RED: Rgba<u8> = Rgba([255, 0, 0, 255]);
+
+    fn solid(width: u32, height: u32) -> DynamicImage {
+        RgbaImage::from_pixel(width, height, RED).into()
+    }
+
+    /** Image sizes around the panel's: the same shape, wider, taller, smaller, much larger. */
+    const SHAPES: [(u32, u32); 10] = [
+        (80, 48),
+        (160, 96),
+        (40, 24),
+        (300, 50),
+        (50, 300),
+        (79, 48),
+        (20, 10),
+        (7, 300),
+        (2000, 1200),
+        (3, 3),
+    ];
+
+    const FITS: [Fit; 4] = [Fit::Cover, Fit::Contain, Fit::Stretch, Fit::Center];
+
+    fn pixel(image: &DynamicImage, x: u32, y: u32) -> Rgba<u8> {
+        image.to_rgba8().get_pixel(x, y).to_owned()
+    }
+
+    #[test]
+    fn every_fit_fills_the_panel_exactly() {
+        let (width, height) = PANEL;
+        for filter in [
+            imageops::FilterType::Lanczos3,
+            imageops::FilterType::Nearest,
+        ] {
+            for (image_width, image_height) in SHAPES {
+                let image = solid(image_width, image_height);
+                for fit in FITS {
+                    let resized = resize_with(fit, width, height, BACKGROUND, &image, filter);
+                    assert_eq!(
+                        resized.dimensions(),
+                        PANEL,
+                        "{fit:?} of {image_width}x{image_height} with {filter:?}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn contain_leaves_bands_across_the_short_side() {
+        let (width, height) = PANEL;
+        let filter = imageops::FilterType::Triangle;
+
+        // Wider than the panel: bands above and below
+        let resized = resize_with(
+            Fit::Contain,
+            width,
+            height,
+            BACKGROUND,
+            &solid(160, 48),
+            filter,
+        );
+        assert_eq!(pixel(&resized, 40, 0), BACKGROUND);
+        assert_eq!(pixel(&resized, 40, 24), RED);
+        assert_eq!(pixel(&resized, 40, 47), BACKGROUND);
+        assert_eq!(pixel(&resized, 0, 24), RED);
+
+        // Taller: bands on the left and right
+        let resized = resize_with(
+            Fit::Contain,
+            width,
+            height,
+            BACKGROUND,
+            &solid(24, 48),
+            filter,
+        );
+        assert_eq!(pixel(&resized, 0, 24), BACKGROUND);
+        assert_eq!(pixel(&resized, 40, 24), RED);
+        assert_eq!(pixel(&resized, 79, 24), BACKGROUND);
+        assert_eq!(pixel(&resized, 40, 0), RED);
+
+        // Smaller with the same shape: enlarged, no bands
+        let resized = resize_with(
+            Fit::Contain,
+            width,
+            height,
+            BACKGROUND,
+            &solid(40, 24),
+            filter,
+        );
+        assert_eq!(pixel(&resized, 0, 0), RED);
+        assert_eq!(pixel(&resized, 79, 47), RED);
+    }
+
+    #[test]
+    fn center_keeps_the_size_of_the_image() {
+        let (width, height) = PANEL;
+        let resized = center_resize(width, height, BACKGROUND, &solid(20, 10));
+        assert_eq!(resized.dimensions(), PANEL);
+        let red: Vec<(u32, u32)> = resized
+            .to_rgba8()
+            .enumerate_pixels()
+            .filter(|(_, _, px)| **px == RED)
+            .map(|(x, y, _)| (x, y))
+            .collect();
+        assert_eq!(red.len(), 20 * 10);
+        assert_eq!((red[0], red[red.len() - 1]), ((30, 19), (49, 28)));
+    }
+
+    #[test]
+    fn center_crops_evenly() {
+        let (width, height) = PANEL;
+        // Every pixel tells where it was
+        let image: DynamicImage =
+            RgbaImage::from_fn(100, 60, |x, y| Rgba([x as u8, y as u8, 0, 255])).into();
+        let resized = center_resize(width, height, BACKGROUND, &image);
+        assert_eq!(pixel(&resized, 0, 0), Rgba([10, 6, 0, 255]));
+        assert_eq!(pixel(&resized, 79, 47), Rgba([89, 53, 0, 255]));
+
+        // Larger one way only: cropped that way, bands the other
+        let image: DynamicImage =
+            RgbaImage::from_fn(100, 20, |x, y| Rgba([x as u8, y as u8, 0, 255])).into();
+        let resized = center_resize(width, height, BACKGROUND, &image);
+        assert_eq!(pixel(&resized, 0, 0), BACKGROUND);
+        assert_eq!(pixel(&resized, 0, 14), Rgba([10, 0, 0, 255]));
+        assert_eq!(pixel(&resized, 79, 33), Rgba([89, 19, 0, 255]));
+        assert_eq!(pixel(&resized, 79, 34), BACKGROUND);
+    }
+
+    #[test]
+    fn stretch_keeps_every_part_of_the_image() {
+        let (width, height) = PANEL;
+        let filter = imageops::FilterType::Nearest;
+        for (image_width, image_height) in SHAPES {
+            // Red on the left half, blue on the right
+            let image: DynamicImage = RgbaImage::from_fn(image_width, image_height, |x, _| match x
+                < image_width / 2
+            {
+                true => RED,
+                false => Rgba([0, 0, 255, 255]),
+            })
+            .into();
+            let stretched = stretch_resize_with(width, height, &image, filter);
+            assert_eq!(stretched.dimensions(), PANEL);
+            assert_eq!(pixel(&stretched, 0, 0), RED, "{image_width}x{image_height}");
+            assert_eq!(pixel(&stretched, 79, 47), Rgba([0, 0, 255, 255]));
+        }
+    }
+
+    #[test]
+    fn shrink_fit_never_enlarges() {
+        use Fit::*;
+
+        let (width, height) = PANEL;
+        // Smaller, the same, larger, and larger one way only, both ways round
+        let cases = [
+            ((40, 24), [Center, Center, Center, Center]),
+            ((80, 48), [Cover, Center, Center, Center]),
+            ((160, 96), [Cover, Contain, Stretch, Center]),
+            ((160, 24), [Center, Contain, Stretch, Center]),
+            ((40, 96), [Center, Contain, Stretch, Center]),
+        ];
+        for ((image_width, image_height), expected) in cases {
+            let fits = FITS.map(|fit| shrink_fit(fit, width, height, image_width, image_height));
+            assert_eq!(fits, expected, "{image_width}x{image_height}");
+        }
+    }
+
+    /** The bounding box of the red pixels, as `(x, y, width, height)`. */
+    fn red_area(image: &DynamicImage) -> (u32, u32, u32, u32) {
+        let image = image.to_rgba8();
+        let red: Vec<(u32, u32)> = image
+            .enumerate_pixels()
+            .filter(|(_, _, px)| px[0] > 128 && px[2] < 128)
+            .map(|(x, y, _)| (x, y))
+            .collect();
+        let (left, top) = (red.iter().map(|p| p.0).min(), red.iter().map(|p| p.1).min());
+        let (right, bottom) = (red.iter().map(|p| p.0).max(), red.iter().map(|p| p.1).max());
+        let (left, top, right, bottom) =
+            (left.unwrap(), top.unwrap(), right.unwrap(), bottom.unwrap());
+        (left, top, right - left + 1, bottom - top + 1)
+    }
+
+    #[test]
+    fn shrink_keeps_small_images_at_their_size() {
+        let (width, height) = PANEL;
+        let filter = imageops::FilterType::Triangle;
+        for fit in FITS {
+            let shrunk = shrink_with(fit, width, height, BACKGROUND, &solid(40, 24), filter);
+            assert_eq!(shrunk.dimensions(), PANEL);
+            assert_eq!(red_area(&shrunk), (20, 12, 40, 24), "{fit:?}");
+        }
+    }
+
+    #[test]
+    fn shrink_shrinks_large_images_like_resize() {
+        let (width, height) = PANEL;
+        let filter = imageops::FilterType::Triangle;
+        for fit in FITS {
+            for (image_width, image_height) in [(160, 96), (400, 100), (100, 400)] {
+                let image = solid(image_width, image_height);
+                let shrunk = shrink_with(fit, width, height, BACKGROUND, &image, filter);
+                let resized = resize_with(fit, width, height, BACKGROUND, &image, filter);
+                assert!(shrunk == resized, "{fit:?} of {image_width}x{image_height}");
+            }
+        }
+    }
+
+    #[test]
+    fn shrink_only_shrinks_the_larger_side() {
+        let (width, height) = PANEL;
+        let filter = imageops::FilterType::Triangle;
+        // Twice as wide as the panel, half as tall
+        let image = solid(160, 24);
+        let shrink = |fit| red_area(&shrink_with(fit, width, height, BACKGROUND, &image, filter));
+        // Cropped at the sides, as it isn't enlarged to cover
+        assert_eq!(shrink(Fit::Cover), (0, 12, 80, 24));
+        // Shrunk to fit, keeping its shape
+        assert_eq!(shrink(Fit::Contain), (0, 18, 80, 12));
+        // Squeezed to the panel's width, keeping its height
+        assert_eq!(shrink(Fit::Stretch), (0, 12, 80, 24));
+        assert_eq!(shrink(Fit::Center), (0, 12, 80, 24));
+
+        // Half as wide, twice as tall
+        let image = solid(40, 96);
+        let shrink = |fit| red_area(&shrink_with(fit, width, height, BACKGROUND, &image, filter));
+        assert_eq!(shrink(Fit::Cover), (20, 0, 40, 48));
+        assert_eq!(shrink(Fit::Contain), (30, 0, 20, 48));
+        assert_eq!(shrink(Fit::Stretch), (20, 0, 40, 48));
+    }
+
+    /** Black and white squares with a diagonal line through them, full of sharp edges. */
+    fn sharp_fixture() -> DynamicImage {
+        let image = image::GrayImage::from_fn(64, 48, |x, y| {
+            let on = (x / 8 + y / 8) % 2 == 0 || x == y;
+            image::Luma([if on { 255 } else { 0 }])
+        });
+        DynamicImage::ImageLuma8(image)
+    }
+
+    #[test]
+    fn blur_lowers_sharpness() {
+        let sharp = sharp_fixture();
+        let scores: Vec<f64> = [0.5, 1.0, 2.0, 4.0]
+            .iter()
+            .map(|&sigma| sharpness(&sharp.blur(sigma)))
+            .collect();
+        let sharp = sharpness(&sharp);
+        assert!(sharp > 2.0 * scores[0], "{sharp} vs {scores:?}");
+        assert!(scores.windows(2).all(|w| w[0] > w[1]), "{scores:?}");
+    }
+
+    #[test]
+    fn sharpness_goes_by_luminance() {
+        let sharp = sharp_fixture();
+        let color = DynamicImage::ImageRgb8(sharp.to_rgb8());
+        assert_eq!(sharpness(&color), sharpness(&sharp));
+    }
+
+    #[test]
+    fn flat_and_tiny_images_have_no_sharpness() {
+        assert_eq!(sharpness(&solid(40, 30)), 0.0);
+        // Too small to have a pixel with neighbors on every side
+        let tiny = sharp_fixture().crop_imm(0, 0, 2, 40);
+        assert_eq!(sharpness(&tiny), 0.0);
+    }
+
+    /** Mean structural similarity of the luminance of two images of the same size, over 8x8
+     * windows: 1 for identical images, lower the more their local structure differs. */
+    fn ssim(a: &DynamicImage, b: &DynamicImage) -> f64 {
+        const C1: f64 = (0.01 * 255.0) * (0.01 * 255.0);
+        const C2: f64 = (0.03 * 255.0) * (0.03 * 255.0);
+        let (a, b) = (a.to_luma8(), b.to_luma8());
+        assert_eq!(a.dimensions(), b.dimensions());
+        let (width, height) = a.dimensions();
+        let mut total = 0.0;
+        let mut windows = 0;
+        for y in (0..height - 7).step_by(4) {
+            for x in (0..width - 7).step_by(4) {
+                let pairs: Vec<(f64, f64)> = (0..64)
+                    .map(|i| (x + i % 8, y + i / 8))
+                    .map(|(x, y)| (a.get_pixel(x, y)[0] as f64, b.get_pixel(x, y)[0] as f64))
+                    .collect();
+                let n = pairs.len() as f64;
+                let mean_a = pairs.iter().map(|p| p.0).sum::<f64>() / n;
+                let mean_b = pairs.iter().map(|p| p.1).sum::<f64>() / n;
+                let var_a = pairs.iter().map(|p| (p.0 - mean_a).powi(2)).sum::<f64>() / n;
+                let var_b = pairs.iter().map(|p| (p.1 - mean_b).powi(2)).sum::<f64>() / n;
+                let cov = pairs
+                    .iter()
+                    .map(|p| (p.0 - mean_a) * (p.1 - mean_b))
+                    .sum::<f64>()
+                    / n;
+                total += ((2.0 * mean_a * mean_b + C1) * (2.0 * cov + C2))
+                    / ((mean_a.powi(2) + mean_b.powi(2) + C1) * (var_a + var_b + C2));
+                windows += 1;
+            }
+        }
+        total / windows as f64
+    }
+
+    /** Large images for the two-stage resize: a photo-like gradient with fine texture, sharp
+     * shapes, and stripes finer than the panel can show. */
+    fn large_fixtures() -> Vec<(&'static str, DynamicImage)> {
+        let gradient = RgbImage::from_fn(800, 500, |x, y| {
+            let texture = ((x * 7 + y * 13) % 17) as u8;
+            image::Rgb([
+                (x * 255 / 800) as u8 ^ texture,
+                (y * 255 / 500) as u8,
+                ((x + y) / 11) as u8,
+            ])
+        });
+        let shapes = RgbImage::from_fn(750, 600, |x, y| {
+            let (dx, dy) = (x as i64 - 375, y as i64 - 300);
+            let inside = dx * dx + dy * dy < 200 * 200;
+            let band = (x / 75 + y / 75) % 2 == 0;
+            match (inside, band) {
+                (true, _) => image::Rgb([230, 40, 30]),
+                (false, true) => image::Rgb([250, 250, 250]),
+                (false, false) => image::Rgb([10, 10, 40]),
+            }
+        });
+        let stripes = RgbImage::from_fn(600, 900, |x, y| {
+            let level = if (x / 3 + y / 5) % 2 == 0 { 255 } else { 0 };
+            image::Rgb([level, level, (x / 5) as u8])
+        });
+        vec![
+            ("gradient", DynamicImage::ImageRgb8(gradient)),
+            ("shapes", DynamicImage::ImageRgb8(shapes)),
+            ("stripes", DynamicImage::ImageRgb8(stripes)),
+        ]
+    }
+
+    #[test]
+    fn two_stage_resize_matches_a_single_pass() {
+        let (width, height) = PANEL;
+        let filter = imageops::FilterType::Lanczos3;
+        for (name, image) in large_fixtures() {
+            assert!(prereduce(width, height, &image, filter).is_some(), "{name}");
+
+            let two_stage = stretch_resize_with(width, height, &image, filter);
+            let single = image.resize_exact(width, height, filter);
+            let similarity = ssim(&two_stage, &single);
+            assert!(similarity > 0.97, "{name} stretched: SSIM {similarity}");
+
+            let two_stage = crop_resize_with(width, height, &image, filter);
+            let (x, y, crop_width, crop_height) =
+                visible_region(Fit::Cover, width, height, image.width(), image.height());
+            let single = image
+                .crop_imm(x, y, crop_width, crop_height)
+                .resize_exact(width, height, filter);
+            let similarity = ssim(&two_stage, &single);
+            assert!(similarity > 0.97, "{name} covered: SSIM {similarity}");
+        }
+    }
+
+    #[test]
+    fn ssim_tells_images_apart() {
+        let stripes = large_fixtures()[2].1.crop_imm(0, 0, 160, 100);
+        assert!((ssim(&stripes, &stripes) - 1.0).abs() < 1e-9);
+        assert!(ssim(&stripes, &stripes.blur(2.0)) < 0.5);
+        // Nearest neighbor drops the detail the two-stage resize averages
+        let (width, height) = PANEL;
+        let large = &large_fixtures()[2].1;
+        let nearest = large.resize_exact(width, height, imageops::FilterType::Nearest);
+        let single = large.resize_exact(width, height, imageops::FilterType::Lanczos3);
+        assert!(ssim(&nearest, &single) < 0.5);
+    }
+
+    #[test]
+    fn prereduce_leaves_room_for_the_filter() {
+        let (width, height) = PANEL;
+        let filter = imageops::FilterType::Lanczos3;
+        for (name, image) in large_fixtures() {
+            let reduced = prereduce(width, height, &image, filter).unwrap();
+            let ratio = (reduced.width() / width).min(reduced.height() / height);
+            assert!(
+                (PREREDUCE_MARGIN..PREREDUCE_MARGIN * 3).contains(&ratio),
+                "{name}: {}x{}",
+                reduced.width(),
+                reduced.height()
+            );
+        }
+        // Not for small images, nor for nearest neighbor
+        assert!(prereduce(width, height, &solid(300, 200), filter).is_none());
+        let large = &large_fixtures()[0].1;
+        let nearest = imageops::FilterType::Nearest;
+        assert!(prereduce(width, height, large, nearest).is_none());
+    }
+
+    #[test]
+    fn saturation_is_between_0_and_1() {
+        for saturation in [0.0, 0.25, 1.0] {
+            assert_eq!(check_saturation(saturation), Ok(saturation));
+        }
+        for saturation in [f64::NAN, -1.0, -0.0001, 1.0001, 3.0, f64::INFINITY] {
+            assert!(check_saturation(saturation).is_err(), "{saturation}");
+        }
+        assert_eq!(
+            check_saturation(3.0),
+            Err("saturation must be between 0.0 and 1.0, not 3".to_string())
+        );
+    }
+
+    #[test]
+    fn quantize_is_repeatable() {
+        let palette = crate::get_palette(0.5);
+        let pixels = fixture();
+        let run = || {
+            let buffer = pixels.clone().into_boxed_slice();
+            quantize(&palette, Settings::default(), WIDTH, HEIGHT, buffer).unwrap()
+        };
+
+        let expected = run();
+        assert_eq!(expected.len(), WIDTH * HEIGHT);
+        for _ in 0..20 {
+            assert!(run() == expected);
+        }
+        for (threads, indices) in on_threads(run).iter().enumerate() {
+            assert!(
+                *indices == expected,
+                "different with {} threads",
+                threads + 1
+            );
+        }
+    }
+
+    #[test]
+    fn quantize_rgb_matches_quantize_on_opaque_images() {
+        let pixels = fixture();
+        let rgb: Vec<rgb::RGB8> = pixels
+            .iter()
+            .map(|px| rgb::RGB8::new(px.r, px.g, px.b))
+            .collect();
+        for saturation in [0.0, 0.5, 1.0] {
+            let palette = crate::get_palette(saturation);
+            for settings in [
+                Settings::default(),
+                Settings {
+                    dither_strength: 0.0,
+                    speed: 10,
+                },
+                Settings {
+                    dither_strength: 0.5,
+                    speed: 5,
+                },
+            ] {
+                let buffer = pixels.clone().into_boxed_slice();
+                let expected = quantize(&palette, settings, WIDTH, HEIGHT, buffer).unwrap();
+                let indices = quantize_rgb(&palette, settings, WIDTH, HEIGHT, &rgb).unwrap();
+                assert!(
+                    indices == expected,
+                    "different at saturation {saturation} with {settings:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn dither_is_repeatable() {
+        let palette = crate::get_palette(0.5);
+        let pixels = fixture();
+        let run = || dither::dither(&palette, WIDTH, HEIGHT, 0.8, |ix| pixels[ix]);
+
+        let expected = run();
+        // Dithering mixes colors even in the smooth parts
+        let mut used = expected.clone();
+        used.sort();
+        used.dedup();
+        assert!(used.len() > 3, "{used:?}");
+        for _ in 0..20 {
+            assert!(run() == expected);
+        }
+        for (threads, indices) in on_threads(run).iter().enumerate() {
+            assert!(
+                *indices == expected,
+                "different with {} threads",
+                threads + 1
+            );
+        }
+    }
+}
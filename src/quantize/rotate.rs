@@ -0,0 +1,196 @@
+use clap::ValueEnum;
+use image::{DynamicImage, GrayImage};
+
+/** How far images are turned clockwise on the panel, for frames that hang it in another
+ * orientation. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Rotation {
+    #[default]
+    #[value(name = "0")]
+    None,
+    #[value(name = "90")]
+    Quarter,
+    #[value(name = "180")]
+    Half,
+    #[value(name = "270")]
+    ThreeQuarters,
+}
+
+impl Rotation {
+    /** Whether width and height trade places. */
+    pub fn swaps_axes(self) -> bool {
+        matches!(self, Rotation::Quarter | Rotation::ThreeQuarters)
+    }
+
+    /** The size of the frame as seen upright, for a panel area of `width` × `height`. Images are
+     * fit or cropped to this size, so the aspect ratio is that of the hanging frame. */
+    pub fn upright_size<T>(self, width: T, height: T) -> (T, T) {
+        if self.swaps_axes() {
+            return (height, width);
+        }
+        return (width, height);
+    }
+
+    /** The rotation that undoes this one. */
+    pub fn inverse(self) -> Rotation {
+        match self {
+            Rotation::Quarter => Rotation::ThreeQuarters,
+            Rotation::ThreeQuarters => Rotation::Quarter,
+            other => other,
+        }
+    }
+
+    /** Turn an upright image into the panel's orientation. */
+    pub fn apply(self, image: DynamicImage) -> DynamicImage {
+        match self {
+            Rotation::None => image,
+            Rotation::Quarter => image.rotate90(),
+            Rotation::Half => image.rotate180(),
+            Rotation::ThreeQuarters => image.rotate270(),
+        }
+    }
+}
+
+/** Where images end up on the panel: turned by `rotation` first, then mirrored along the
+ * panel's own axes, e.g. for a panel seen through mirror film. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Orientation {
+    pub rotation: Rotation,
+    pub hflip: bool,
+    pub vflip: bool,
+}
+
+impl Orientation {
+    /** Turn and mirror an upright image into the panel's orientation. */
+    pub fn apply(self, image: DynamicImage) -> DynamicImage {
+        let mut image = self.rotation.apply(image);
+        if self.hflip {
+            image = image.fliph();
+        }
+        if self.vflip {
+            image = image.flipv();
+        }
+        return image;
+    }
+
+    /** Like [Orientation::apply], for `width` × `height` palette indices. Returns the new width
+     * and height along with the indices. */
+    pub fn apply_indices(self, width: u32, height: u32, indices: Vec<u8>) -> (u32, u32, Vec<u8>) {
+        if self == Orientation::default() {
+            return (width, height, indices);
+        }
+        let image = GrayImage::from_raw(width, height, indices).unwrap();
+        let turned = self.apply(DynamicImage::from(image)).into_luma8();
+        return (turned.width(), turned.height(), turned.into_raw());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROTATIONS: [Rotation; 4] = [
+        Rotation::None,
+        Rotation::Quarter,
+        Rotation::Half,
+        Rotation::ThreeQuarters,
+    ];
+
+    /** 3 × 2 indices, each different:
+     * ```text
+     * 0 1 2
+     * 3 4 5
+     * ``` */
+    fn upright() -> (u32, u32, Vec<u8>) {
+        (3, 2, vec![0, 1, 2, 3, 4, 5])
+    }
+
+    fn turned(rotation: Rotation) -> Orientation {
+        Orientation {
+            rotation,
+            ..Orientation::default()
+        }
+    }
+
+    fn apply(
+        orientation: Orientation,
+        (width, height, indices): (u32, u32, Vec<u8>),
+    ) -> (u32, u32, Vec<u8>) {
+        orientation.apply_indices(width, height, indices)
+    }
+
+    #[test]
+    fn quarter_turns_swap_the_axes() {
+        assert_eq!(Rotation::None.upright_size(800, 480), (800, 480));
+        assert_eq!(Rotation::Quarter.upright_size(800, 480), (480, 800));
+        assert_eq!(Rotation::Half.upright_size(800, 480), (800, 480));
+        assert_eq!(Rotation::ThreeQuarters.upright_size(800, 480), (480, 800));
+    }
+
+    #[test]
+    fn indices_turn_clockwise() {
+        assert_eq!(apply(turned(Rotation::None), upright()), upright());
+        assert_eq!(
+            apply(turned(Rotation::Quarter), upright()),
+            (2, 3, vec![3, 0, 4, 1, 5, 2])
+        );
+        assert_eq!(
+            apply(turned(Rotation::Half), upright()),
+            (3, 2, vec![5, 4, 3, 2, 1, 0])
+        );
+        assert_eq!(
+            apply(turned(Rotation::ThreeQuarters), upright()),
+            (2, 3, vec![2, 5, 1, 4, 0, 3])
+        );
+    }
+
+    #[test]
+    fn inverse_turns_back() {
+        for rotation in ROTATIONS {
+            let panel = apply(turned(rotation), upright());
+            assert_eq!((panel.0, panel.1), rotation.upright_size(3, 2));
+            assert_eq!(
+                apply(turned(rotation.inverse()), panel),
+                upright(),
+                "{rotation:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn flips_follow_the_turn_along_the_panel_axes() {
+        // Mirrored left to right on the panel after a quarter turn, which is top to bottom as
+        // the frame hangs
+        let panel = Orientation {
+            rotation: Rotation::Quarter,
+            hflip: true,
+            vflip: false,
+        };
+        let panel = apply(panel, upright());
+        assert_eq!(panel, (2, 3, vec![0, 3, 1, 4, 2, 5]));
+        // Turning back as upright() does keeps the flip, now along the other axis
+        let upright_flipped = (3, 2, vec![3, 4, 5, 0, 1, 2]);
+        assert_eq!(
+            apply(turned(Rotation::ThreeQuarters), panel),
+            upright_flipped
+        );
+
+        // Without a quarter turn the flips stay on their axes
+        let panel = Orientation {
+            rotation: Rotation::Half,
+            hflip: true,
+            vflip: true,
+        };
+        assert_eq!(apply(panel, upright()), upright());
+        let panel = Orientation {
+            rotation: Rotation::Half,
+            hflip: true,
+            vflip: false,
+        };
+        let panel = apply(panel, upright());
+        assert_eq!(
+            apply(turned(Rotation::Half), panel),
+            (3, 2, vec![2, 1, 0, 5, 4, 3])
+        );
+    }
+}
@@ -0,0 +1,153 @@
+use std::path::Path;
+
+use image::{DynamicImage, GrayImage, RgbImage};
+use log::{info, warn};
+
+use crate::quantize::{caption::CaptionFont, error::QuantizeError};
+
+/** Fonts tried for text files when none is given, as Raspberry Pi OS and other distributions
+ * install them. The built-in font only covers ASCII. */
+const SYSTEM_FONTS: &[&str] = &[
+    "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/TTF/DejaVuSans.ttf",
+    "/usr/share/fonts/dejavu/DejaVuSans.ttf",
+    "/usr/share/fonts/truetype/liberation/LiberationSans-Regular.ttf",
+    "/usr/share/fonts/truetype/noto/NotoSans-Regular.ttf",
+];
+
+/** Columns between tab stops. */
+const TAB_WIDTH: usize = 4;
+
+/** Whether the file at `path` is drawn as text rather than decoded as an image: with
+ * `--as-text`, or for a `.txt` extension. */
+pub fn is_text(as_text: bool, path: &Path) -> bool {
+    as_text
+        || path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("txt"))
+}
+
+/** The font for text files: the one given, or else the first of [SYSTEM_FONTS] installed, or
+ * else the built-in one. */
+pub fn font(path: Option<&Path>) -> Result<CaptionFont, QuantizeError> {
+    if path.is_some() {
+        return CaptionFont::load(path);
+    }
+    let Some(system) = SYSTEM_FONTS
+        .iter()
+        .map(Path::new)
+        .find(|path| path.is_file())
+    else {
+        return Ok(CaptionFont::Builtin);
+    };
+    info!("Drawing text in {}", system.display());
+    return CaptionFont::load(Some(system));
+}
+
+/** Draw `text` in black on white at `width` × `height`, `size` pixels tall, wrapped at word
+ * boundaries. Text that doesn't fit is cut short with an ellipsis. The image has no shades of
+ * gray, so it should be quantized without dithering. */
+pub fn render(text: &str, font: &CaptionFont, size: u32, width: u32, height: u32) -> DynamicImage {
+    if matches!(font, CaptionFont::Builtin) && !text.is_ascii() {
+        warn!("The built-in font only has ASCII characters, give a font with --caption-font");
+    }
+    let margin = size / 2;
+    let max_width = width.saturating_sub(2 * margin);
+    let line_height = font.line_height(size);
+    let max_lines = (height.saturating_sub(2 * margin) / line_height).max(1) as usize;
+
+    let mut lines = wrap(text, font, size, max_width);
+    if lines.len() > max_lines {
+        lines.truncate(max_lines);
+        let last = lines.pop().unwrap_or_default();
+        lines.push(with_ellipsis(&last, font, size, max_width));
+    }
+
+    let mut coverage = GrayImage::new(width, height);
+    for (i, line) in lines.iter().enumerate() {
+        if line.is_empty() {
+            continue;
+        }
+        let mask = font.rasterize(line, size);
+        let top = margin + i as u32 * line_height;
+        for (x, y, pixel) in mask.enumerate_pixels() {
+            let (px, py) = (margin + x, top + y);
+            if px < width && py < height {
+                coverage.put_pixel(px, py, *pixel);
+            }
+        }
+    }
+    // Gray edges would come out in whichever color is nearest, so every pixel is black or white
+    let image = RgbImage::from_fn(width, height, |x, y| match coverage.get_pixel(x, y).0[0] {
+        0..128 => image::Rgb([255, 255, 255]),
+        _ => image::Rgb([0, 0, 0]),
+    });
+    return DynamicImage::from(image);
+}
+
+/** Break `text` into lines at most `max_width` wide, at spaces where possible. Line breaks in
+ * the text are kept and tabs become spaces up to the next tab stop. */
+fn wrap(text: &str, font: &CaptionFont, size: u32, max_width: u32) -> Vec<String> {
+    let fits = |line: &str| font.width(line, size) <= max_width;
+    let mut lines = Vec::new();
+    for paragraph in text.lines() {
+        let paragraph = expand_tabs(paragraph);
+        let mut line = String::new();
+        for (i, word) in paragraph.split(' ').enumerate() {
+            let joined = match i {
+                0 => word.to_string(),
+                _ => format!("{line} {word}"),
+            };
+            if fits(&joined) {
+                line = joined;
+                continue;
+            }
+            if i > 0 {
+                lines.push(std::mem::take(&mut line));
+            }
+            // A word wider than a whole line is broken wherever it has to be
+            for c in word.chars() {
+                line.push(c);
+                if !fits(&line) && line.chars().count() > 1 {
+                    line.pop();
+                    lines.push(std::mem::replace(&mut line, c.to_string()));
+                }
+            }
+        }
+        lines.push(line);
+    }
+    // Blank lines at the end would only push the ellipsis up
+    while lines.last().is_some_and(String::is_empty) {
+        lines.pop();
+    }
+    return lines;
+}
+
+fn expand_tabs(line: &str) -> String {
+    let mut expanded = String::with_capacity(line.len());
+    let mut column = 0;
+    for c in line.chars() {
+        if c == '\t' {
+            let spaces = TAB_WIDTH - column % TAB_WIDTH;
+            expanded.extend(std::iter::repeat_n(' ', spaces));
+            column += spaces;
+        } else {
+            expanded.push(c);
+            column += 1;
+        }
+    }
+    return expanded;
+}
+
+/** `line` followed by an ellipsis, shortened until both fit in `max_width`. */
+pub fn with_ellipsis(line: &str, font: &CaptionFont, size: u32, max_width: u32) -> String {
+    let mut chars: Vec<char> = line.trim_end().chars().collect();
+    loop {
+        let shortened: String = chars.iter().collect::<String>().trim_end().to_string();
+        let shortened = shortened + font.ellipsis();
+        if chars.is_empty() || font.width(&shortened, size) <= max_width {
+            return shortened;
+        }
+        chars.pop();
+    }
+}
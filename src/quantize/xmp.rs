@@ -0,0 +1,267 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use image::DynamicImage;
+use log::{debug, warn};
+
+/** A crop rectangle as fractions of the image size, as Camera Raw and Lightroom store it. */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Crop {
+    pub top: f64,
+    pub left: f64,
+    pub bottom: f64,
+    pub right: f64,
+}
+
+impl Crop {
+    /** The rectangle in pixels of a `width` × `height` image as `(x, y, width, height)`.
+     * Editors write values a rounding error outside of 0–1, so they are clamped first. Returns
+     * `None` if nothing would be left of the image. */
+    pub fn pixels(self, width: u32, height: u32) -> Option<(u32, u32, u32, u32)> {
+        let scale = |fraction: f64, size: u32| (fraction.clamp(0.0, 1.0) * size as f64).round();
+        let left = scale(self.left, width);
+        let right = scale(self.right, width);
+        let top = scale(self.top, height);
+        let bottom = scale(self.bottom, height);
+        if right <= left || bottom <= top {
+            return None;
+        }
+        return Some((
+            left as u32,
+            top as u32,
+            (right - left) as u32,
+            (bottom - top) as u32,
+        ));
+    }
+}
+
+/** Sidecars an editor may have written next to `path`: `photo.xmp`, as Lightroom names them,
+ * and `photo.jpg.xmp`, as darktable and digiKam do. */
+fn sidecar_paths(path: &Path) -> [PathBuf; 2] {
+    let mut appended = path.as_os_str().to_owned();
+    appended.push(".xmp");
+    [path.with_extension("xmp"), appended.into()]
+}
+
+/** Find the value of an XMP property written either as an attribute, `crs:CropTop="0.1"`, or
+ * as an element, `<crs:CropTop>0.1</crs:CropTop>`. */
+fn property<'a>(xmp: &'a str, name: &str) -> Option<&'a str> {
+    let attribute = format!("{name}=");
+    if let Some(start) = xmp.find(&attribute) {
+        let rest = &xmp[start + attribute.len()..];
+        let quote = rest.chars().next().filter(|c| *c == '"' || *c == '\'')?;
+        let rest = &rest[1..];
+        return Some(rest[..rest.find(quote)?].trim());
+    }
+
+    let open = format!("<{name}>");
+    let start = xmp.find(&open)? + open.len();
+    let rest = &xmp[start..];
+    return Some(rest[..rest.find('<')?].trim());
+}
+
+/** Read the crop out of the text of an XMP packet. Returns `None` if it has no crop, or one
+ * that can't be applied as a plain rectangle. */
+pub fn parse_crop(xmp: &str) -> Option<Crop> {
+    if property(xmp, "crs:HasCrop").is_some_and(|value| !value.eq_ignore_ascii_case("true")) {
+        return None;
+    }
+    let number = |name| property(xmp, name)?.parse::<f64>().ok();
+    let crop = Crop {
+        top: number("crs:CropTop")?,
+        left: number("crs:CropLeft")?,
+        bottom: number("crs:CropBottom")?,
+        right: number("crs:CropRight")?,
+    };
+
+    let angle = number("crs:CropAngle").unwrap_or(0.0);
+    if angle != 0.0 {
+        warn!("Ignoring a crop rotated by {angle}°, only straight crops are supported");
+        return None;
+    }
+    return Some(crop);
+}
+
+/** Apply the crop from the XMP sidecar of the image at `path`, if there is one. Missing or
+ * unreadable sidecars leave the image as it is. Also returns the part of the image that was
+ * kept, as `(x, y, width, height)`. */
+pub fn apply_sidecar_crop(
+    path: &Path,
+    image: DynamicImage,
+) -> (DynamicImage, (u32, u32, u32, u32)) {
+    let whole = (0, 0, image.width(), image.height());
+    let Some(crop) = sidecar_paths(path)
+        .iter()
+        .find_map(|sidecar| fs::read_to_string(sidecar).ok())
+        .and_then(|xmp| parse_crop(&xmp))
+    else {
+        return (image, whole);
+    };
+    let Some((x, y, width, height)) = crop.pixels(image.width(), image.height()) else {
+        warn!(
+            "Ignoring an empty crop in the sidecar of {}",
+            path.display()
+        );
+        return (image, whole);
+    };
+
+    debug!(
+        "Cropping {} to {width}x{height}+{x}+{y} from its sidecar",
+        path.display()
+    );
+    return (image.crop_imm(x, y, width, height), (x, y, width, height));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /** A sidecar as Lightroom writes it, with the crop as attributes. */
+    const LIGHTROOM: &str = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/" x:xmptk="Adobe XMP Core 7.0-c000">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about=""
+    xmlns:crs="http://ns.adobe.com/camera-raw-settings/1.0/"
+   crs:Version="15.0"
+   crs:CropTop="0.1"
+   crs:CropLeft="0.25"
+   crs:CropBottom="0.9"
+   crs:CropRight="0.75"
+   crs:CropAngle="0"
+   crs:CropConstrainToWarp="0"
+   crs:HasCrop="True">
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>"#;
+
+    /** The same crop written as elements, as other tools do, and a rounding error out of
+     * range. */
+    const ELEMENTS: &str = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about="" xmlns:crs="http://ns.adobe.com/camera-raw-settings/1.0/">
+   <crs:HasCrop>true</crs:HasCrop>
+   <crs:CropTop>-0.0000001</crs:CropTop>
+   <crs:CropLeft> 0.5 </crs:CropLeft>
+   <crs:CropBottom>1.0000002</crs:CropBottom>
+   <crs:CropRight>1.00001</crs:CropRight>
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>"#;
+
+    fn crop(top: f64, left: f64, bottom: f64, right: f64) -> Crop {
+        Crop {
+            top,
+            left,
+            bottom,
+            right,
+        }
+    }
+
+    #[test]
+    fn crops_are_read_from_attributes_and_elements() {
+        assert_eq!(parse_crop(LIGHTROOM), Some(crop(0.1, 0.25, 0.9, 0.75)));
+        assert_eq!(
+            parse_crop(ELEMENTS),
+            Some(crop(-0.0000001, 0.5, 1.0000002, 1.00001))
+        );
+        let quoted = LIGHTROOM.replace('"', "'");
+        assert_eq!(parse_crop(&quoted), Some(crop(0.1, 0.25, 0.9, 0.75)));
+    }
+
+    #[test]
+    fn sidecars_without_a_usable_crop_are_ignored() {
+        // Turned off, with the rectangle left behind
+        let off = LIGHTROOM.replace(r#"crs:HasCrop="True""#, r#"crs:HasCrop="False""#);
+        assert_eq!(parse_crop(&off), None);
+        // Rotated
+        let rotated = LIGHTROOM.replace(r#"crs:CropAngle="0""#, r#"crs:CropAngle="1.5""#);
+        assert_eq!(parse_crop(&rotated), None);
+        // Cut short
+        let partial = LIGHTROOM.replace(r#"crs:CropRight="0.75""#, "");
+        assert_eq!(parse_crop(&partial), None);
+        let garbled = LIGHTROOM.replace("0.75", "three quarters");
+        assert_eq!(parse_crop(&garbled), None);
+        assert_eq!(parse_crop(""), None);
+        // Without HasCrop at all, the rectangle is taken
+        let unflagged = LIGHTROOM.replace(r#"crs:HasCrop="True""#, "");
+        assert!(parse_crop(&unflagged).is_some());
+    }
+
+    #[test]
+    fn crops_become_pixels() {
+        let crop = parse_crop(LIGHTROOM).unwrap();
+        assert_eq!(crop.pixels(400, 300), Some((100, 30, 200, 240)));
+        // Rounded to the nearest pixel
+        assert_eq!(crop.pixels(3, 3), Some((1, 0, 1, 3)));
+    }
+
+    #[test]
+    fn crops_slightly_out_of_range_are_clamped() {
+        let crop = parse_crop(ELEMENTS).unwrap();
+        assert_eq!(crop.pixels(400, 300), Some((200, 0, 200, 300)));
+        let everything = Crop {
+            top: -0.001,
+            left: -0.001,
+            bottom: 1.001,
+            right: 1.001,
+        };
+        assert_eq!(everything.pixels(640, 480), Some((0, 0, 640, 480)));
+    }
+
+    #[test]
+    fn empty_crops_are_none() {
+        assert_eq!(crop(0.5, 0.2, 0.5, 0.8).pixels(100, 100), None);
+        assert_eq!(crop(0.2, 0.8, 0.6, 0.2).pixels(100, 100), None);
+        // Entirely out of range, so clamped to nothing
+        assert_eq!(crop(1.1, 0.0, 1.2, 1.0).pixels(100, 100), None);
+        // Less than half a pixel
+        assert_eq!(crop(0.0, 0.0, 1.0, 0.004).pixels(100, 100), None);
+    }
+
+    /** A 40x20 image in `dir` with the sidecars named. */
+    fn photo(dir: &Path, sidecars: &[(&str, &str)]) -> (PathBuf, DynamicImage) {
+        let path = dir.join("photo.jpg");
+        for (name, xmp) in sidecars {
+            fs::write(dir.join(name), xmp).unwrap();
+        }
+        return (path, DynamicImage::new_rgb8(40, 20));
+    }
+
+    #[test]
+    fn sidecar_crops_are_applied() {
+        let dir = tempfile::tempdir().unwrap();
+        let (path, image) = photo(dir.path(), &[("photo.xmp", LIGHTROOM)]);
+        let (cropped, kept) = apply_sidecar_crop(&path, image);
+        assert_eq!(kept, (10, 2, 20, 16));
+        assert_eq!((cropped.width(), cropped.height()), (20, 16));
+    }
+
+    #[test]
+    fn sidecars_are_found_by_either_name() {
+        let dir = tempfile::tempdir().unwrap();
+        let (path, image) = photo(dir.path(), &[("photo.jpg.xmp", ELEMENTS)]);
+        assert_eq!(apply_sidecar_crop(&path, image).1, (20, 0, 20, 20));
+
+        // Lightroom's name first
+        let (path, image) = photo(dir.path(), &[("photo.xmp", LIGHTROOM)]);
+        assert_eq!(apply_sidecar_crop(&path, image).1, (10, 2, 20, 16));
+    }
+
+    #[test]
+    fn images_without_a_usable_sidecar_are_left_whole() {
+        let dir = tempfile::tempdir().unwrap();
+        let (path, image) = photo(dir.path(), &[]);
+        assert_eq!(apply_sidecar_crop(&path, image).1, (0, 0, 40, 20));
+
+        let rotated = LIGHTROOM.replace(r#"crs:CropAngle="0""#, r#"crs:CropAngle="-3""#);
+        let (path, image) = photo(dir.path(), &[("photo.xmp", &rotated)]);
+        let (image, kept) = apply_sidecar_crop(&path, image);
+        assert_eq!(kept, (0, 0, 40, 20));
+        assert_eq!((image.width(), image.height()), (40, 20));
+
+        let empty = LIGHTROOM.replace(r#"crs:CropBottom="0.9""#, r#"crs:CropBottom="0.1""#);
+        let (path, image) = photo(dir.path(), &[("photo.xmp", &empty)]);
+        assert_eq!(apply_sidecar_crop(&path, image).1, (0, 0, 40, 20));
+    }
+}
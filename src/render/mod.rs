@@ -0,0 +1,410 @@
+pub mod font;
+
+use std::str::FromStr;
+
+use clap::ValueEnum;
+use font::{glyph, GLYPH_HEIGHT, GLYPH_WIDTH};
+use image::{imageops, Rgb, RgbImage, Rgba};
+
+/** Width of the bars between the panes of a [side_by_side] comparison. */
+const SEPARATOR_WIDTH: u32 = 4;
+const SEPARATOR_COLOR: Rgb<u8> = Rgb([64, 64, 64]);
+
+/** Space around and between the cells of a [contact_sheet]. */
+const SHEET_GAP: usize = 24;
+const LABEL_SCALE: usize = 3;
+
+/** The panel's colors, numbered like the palettes in `main.rs`. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+#[repr(u8)]
+pub enum Color {
+    Black = 0,
+    White = 1,
+    Green = 2,
+    Blue = 3,
+    Red = 4,
+    Yellow = 5,
+    Orange = 6,
+}
+
+impl Color {
+    /** Index of the color in the palette. */
+    pub fn index(self) -> u8 {
+        self as u8
+    }
+}
+
+/** What the `color` command fills the panel with: one of its colors by name, or the last index
+ * of the controller's palette. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+#[repr(u8)]
+pub enum Fill {
+    Black = 0,
+    White = 1,
+    Green = 2,
+    Blue = 3,
+    Red = 4,
+    Yellow = 5,
+    Orange = 6,
+    /// Index 7, meant for transparency. The AC073TC1 has no such color and shows it as white,
+    /// so the driver sends white in its place
+    Transparent = 7,
+}
+
+impl Fill {
+    /** Index of the color in the palette. */
+    pub fn index(self) -> u8 {
+        self as u8
+    }
+}
+
+/** What fills the bands left around an image by `--fit contain` or `center`: one of the
+ * panel's colors, or any `#RRGGBB` color, which is dithered along with the image. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Background {
+    Color(Color),
+    Rgb([u8; 3]),
+}
+
+impl Background {
+    /** The background as a pixel, with named colors taken from `palette` so they come out
+     * as exactly that color. */
+    pub fn rgba(self, palette: &[imagequant::RGBA]) -> Rgba<u8> {
+        match self {
+            Background::Color(color) => {
+                let c = palette[color.index() as usize];
+                Rgba([c.r, c.g, c.b, 255])
+            }
+            Background::Rgb([r, g, b]) => Rgba([r, g, b, 255]),
+        }
+    }
+
+    /** The palette index of the background, the nearest opaque one for `#RRGGBB` colors, for
+     * images that aren't quantized. */
+    pub fn index(self, palette: &[imagequant::RGBA]) -> u8 {
+        let rgb = match self {
+            Background::Color(color) => return color.index(),
+            Background::Rgb(rgb) => rgb.map(|c| c as i32),
+        };
+        let distance = |c: &imagequant::RGBA| {
+            let channels = [c.r, c.g, c.b].map(|v| v as i32);
+            (0..3).map(|i| (channels[i] - rgb[i]).pow(2)).sum::<i32>()
+        };
+        palette
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| c.a == 255)
+            .min_by_key(|(_, c)| distance(c))
+            .map_or(Color::White.index(), |(i, _)| i as u8)
+    }
+}
+
+/** Parse a color name, like the values of [Color], or `#RRGGBB`. */
+impl FromStr for Background {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(hex) = s.strip_prefix('#').filter(|hex| hex.len() == 6) {
+            let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+            if let (Some(r), Some(g), Some(b)) = (channel(0), channel(2), channel(4)) {
+                return Ok(Background::Rgb([r, g, b]));
+            }
+        }
+        <Color as ValueEnum>::from_str(s, true)
+            .map(Background::Color)
+            .map_err(|_| {
+                format!(
+                    "`{s}` is not a panel color such as white or black, or a color like #RRGGBB"
+                )
+            })
+    }
+}
+
+/** An image made of palette indices, for generated screens that don't need quantization. */
+#[derive(Clone)]
+pub struct Canvas {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+impl Canvas {
+    pub fn new(width: usize, height: usize, background: u8) -> Canvas {
+        Canvas {
+            width,
+            height,
+            pixels: vec![background; width * height],
+        }
+    }
+
+    /** Fill a rectangle, clipped to the canvas. */
+    pub fn fill_rect(&mut self, x: usize, y: usize, width: usize, height: usize, color: u8) {
+        for row in y..(y + height).min(self.height) {
+            for column in x..(x + width).min(self.width) {
+                self.pixels[row * self.width + column] = color;
+            }
+        }
+    }
+
+    /** Copy another canvas onto this one with its top left corner at `(x, y)`, clipped. */
+    pub fn blit(&mut self, x: usize, y: usize, other: &Canvas) {
+        for row in 0..other.height.min(self.height.saturating_sub(y)) {
+            let columns = other.width.min(self.width.saturating_sub(x));
+            let source = row * other.width;
+            let target = (y + row) * self.width + x;
+            self.pixels[target..target + columns]
+                .copy_from_slice(&other.pixels[source..source + columns]);
+        }
+    }
+
+    /** Turn the palette indices into colors, e.g. for saving a preview. */
+    pub fn to_rgb(&self, palette: &[[u8; 4]]) -> RgbImage {
+        RgbImage::from_fn(self.width as u32, self.height as u32, |x, y| {
+            let [r, g, b, _] = palette[self.pixels[y as usize * self.width + x as usize] as usize];
+            Rgb([r, g, b])
+        })
+    }
+
+    /** Draw a line of text with its top left corner at `(x, y)`, every font pixel scaled up to
+     * a `scale` sized square. Text beyond the edges is clipped. */
+    pub fn draw_text(&mut self, x: usize, y: usize, text: &str, scale: usize, color: u8) {
+        for (i, c) in text.chars().enumerate() {
+            let left = x + i * (GLYPH_WIDTH + 1) * scale;
+            if left >= self.width {
+                break;
+            }
+            for (column, bits) in glyph(c).iter().enumerate() {
+                for row in 0..GLYPH_HEIGHT {
+                    if bits >> row & 1 == 1 {
+                        let px = left + column * scale;
+                        self.fill_rect(px, y + row * scale, scale, scale, color);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/** Vertical bars of every color the panel can show, for checking the wiring and the palette. */
+pub fn color_bars(width: usize, height: usize) -> Canvas {
+    let mut canvas = Canvas::new(width, height, Color::White.index());
+    let colors = Color::value_variants();
+    for (i, color) in colors.iter().enumerate() {
+        let left = i * width / colors.len();
+        let right = (i + 1) * width / colors.len();
+        canvas.fill_rect(left, 0, right - left, height, color.index());
+    }
+    return canvas;
+}
+
+/** Diagnostic patterns for checking a new panel or its wiring. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Pattern {
+    /// Vertical bars of the seven colors
+    Bars,
+    /// Black and white checkerboards of 1 pixel squares above and 8 pixel squares below
+    Checker,
+    /// Strips of gradients, quantized like photos to show how they are dithered
+    Gradient,
+}
+
+/** Black and white checkerboards, of 1 pixel squares in the top half and 8 pixel squares in
+ * the bottom half, to check that every pixel can be set on its own. */
+pub fn checker(width: usize, height: usize) -> Canvas {
+    let mut canvas = Canvas::new(width, height, Color::White.index());
+    for y in 0..height {
+        let pitch = if y < height / 2 { 1 } else { 8 };
+        for x in 0..width {
+            if (x / pitch + y / pitch) % 2 == 0 {
+                canvas.pixels[y * width + x] = Color::Black.index();
+            }
+        }
+    }
+    return canvas;
+}
+
+/** Horizontal strips of gradients from left to right: gray from black to white, red, green
+ * and blue from black to full, and all hues. They are meant to be quantized, unlike the
+ * other patterns. */
+pub fn gradient(width: usize, height: usize) -> RgbImage {
+    const STRIPS: u32 = 5;
+    let (width, height) = (width as u32, height as u32);
+    RgbImage::from_fn(width, height, |x, y| {
+        let t = x as f64 / (width.max(2) - 1) as f64;
+        let level = (t * 255.0).round() as u8;
+        match y * STRIPS / height.max(1) {
+            0 => Rgb([level; 3]),
+            1 => Rgb([level, 0, 0]),
+            2 => Rgb([0, level, 0]),
+            3 => Rgb([0, 0, level]),
+            _ => hue(t),
+        }
+    })
+}
+
+/** The fully saturated color at `t` of the way around the color wheel, from red back to red. */
+fn hue(t: f64) -> Rgb<u8> {
+    let sector = t.clamp(0.0, 1.0) * 6.0;
+    // The end of the last sector rather than the start of a seventh
+    let (sector, fraction) = if sector >= 6.0 {
+        (5, 1.0)
+    } else {
+        (sector as u32, sector.fract())
+    };
+    let rising = (fraction * 255.0).round() as u8;
+    let falling = 255 - rising;
+    match sector {
+        0 => Rgb([255, rising, 0]),
+        1 => Rgb([falling, 255, 0]),
+        2 => Rgb([0, 255, rising]),
+        3 => Rgb([0, falling, 255]),
+        4 => Rgb([rising, 0, 255]),
+        _ => Rgb([255, 0, falling]),
+    }
+}
+
+/** Lay out canvases of equal size in a grid, `columns` wide, each with its label underneath.
+ * Labels wider than their canvas are drawn smaller. */
+pub fn contact_sheet(cells: &[(Canvas, String)], columns: usize) -> Canvas {
+    let Some((first, _)) = cells.first() else {
+        return Canvas::new(0, 0, Color::White.index());
+    };
+    let columns = columns.clamp(1, cells.len());
+    let rows = cells.len().div_ceil(columns);
+    let cell_width = first.width + SHEET_GAP;
+    let cell_height = first.height + text_height(LABEL_SCALE) + 2 * SHEET_GAP;
+
+    let mut sheet = Canvas::new(
+        columns * cell_width + SHEET_GAP,
+        rows * cell_height + SHEET_GAP,
+        Color::White.index(),
+    );
+    for (i, (canvas, label)) in cells.iter().enumerate() {
+        let x = SHEET_GAP + i % columns * cell_width;
+        let y = SHEET_GAP + i / columns * cell_height;
+        sheet.blit(x, y, canvas);
+        let mut scale = LABEL_SCALE;
+        while scale > 1 && text_width(label, scale) > canvas.width {
+            scale -= 1;
+        }
+        let label_x = x + canvas.width.saturating_sub(text_width(label, scale)) / 2;
+        let label_y = y + canvas.height + SHEET_GAP / 2;
+        sheet.draw_text(label_x, label_y, label, scale, Color::Black.index());
+    }
+
+    return sheet;
+}
+
+/** Put images next to each other from left to right, separated by thin dark bars. */
+pub fn side_by_side(panes: &[RgbImage]) -> RgbImage {
+    let height = panes.iter().map(RgbImage::height).max().unwrap_or(0);
+    let separators = panes.len().saturating_sub(1) as u32 * SEPARATOR_WIDTH;
+    let width = panes.iter().map(RgbImage::width).sum::<u32>() + separators;
+
+    let mut sheet = RgbImage::from_pixel(width, height, SEPARATOR_COLOR);
+    let mut x = 0;
+    for pane in panes {
+        imageops::replace(&mut sheet, pane, x as i64, 0);
+        x += pane.width() + SEPARATOR_WIDTH;
+    }
+    return sheet;
+}
+
+/** Width in pixels of a line of text drawn at the given scale. */
+pub fn text_width(text: &str, scale: usize) -> usize {
+    let count = text.chars().count();
+    (count * (GLYPH_WIDTH + 1)).saturating_sub(1) * scale
+}
+
+/** Height in pixels of a line of text drawn at the given scale. */
+pub fn text_height(scale: usize) -> usize {
+    GLYPH_HEIGHT * scale
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /** The part of `canvas` at `(x, y)` as text, a line per row, with `#` for black, `.` for
+     * white and the palette index for anything else. */
+    fn ascii(canvas: &Canvas, x: usize, y: usize, width: usize, height: usize) -> String {
+        let mut text = String::new();
+        for row in y..y + height {
+            for column in x..x + width {
+                text.push(match canvas.pixels[row * canvas.width + column] {
+                    0 => '#',
+                    1 => '.',
+                    index => char::from(b'0' + index),
+                });
+            }
+            text.push('\n');
+        }
+        return text;
+    }
+
+    fn cell(width: usize, height: usize, color: Color, label: &str) -> (Canvas, String) {
+        return (Canvas::new(width, height, color.index()), label.to_string());
+    }
+
+    #[test]
+    fn contact_sheet_lays_cells_out_in_rows() {
+        let cells = [
+            cell(40, 30, Color::Red, "0"),
+            cell(40, 30, Color::Green, "0.5"),
+            cell(40, 30, Color::Blue, "1"),
+        ];
+        let sheet = contact_sheet(&cells, 2);
+
+        // Two columns of 40 + a gap, two rows of 30 + a label 24 high + a gap either side
+        let mut expected = Canvas::new(152, 228, Color::White.index());
+        expected.fill_rect(24, 24, 40, 30, Color::Red.index());
+        expected.fill_rect(88, 24, 40, 30, Color::Green.index());
+        expected.fill_rect(24, 126, 40, 30, Color::Blue.index());
+        // Centered half a gap below each cell, "0.5" at scale 2 to fit in 40
+        expected.draw_text(36, 66, "0", 3, Color::Black.index());
+        expected.draw_text(91, 66, "0.5", 2, Color::Black.index());
+        expected.draw_text(36, 168, "1", 3, Color::Black.index());
+
+        assert_eq!((sheet.width, sheet.height), (152, 228));
+        assert!(sheet.pixels == expected.pixels);
+    }
+
+    #[test]
+    fn contact_sheet_shrinks_labels_to_fit() {
+        let sheet = contact_sheet(&[cell(20, 4, Color::Yellow, "0.5")], 1);
+        assert_eq!((sheet.width, sheet.height), (68, 100));
+        // The bottom of the cell, and the label centered under it at scale 1
+        let golden = "\
+            .55555555555555555555.\n\
+            .55555555555555555555.\n\
+            ......................\n\
+            ......................\n\
+            ......................\n\
+            ......................\n\
+            ......................\n\
+            ......................\n\
+            ......................\n\
+            ......................\n\
+            ......................\n\
+            ......................\n\
+            ......................\n\
+            ......................\n\
+            ...###........#####...\n\
+            ..#...#.......#.......\n\
+            ..#..##.......####....\n\
+            ..#.#.#...........#...\n\
+            ..##..#...........#...\n\
+            ..#...#..##...#...#...\n\
+            ...###...##....###....\n\
+            ......................\n";
+        assert_eq!(ascii(&sheet, 23, 26, 22, 22), golden);
+    }
+
+    #[test]
+    fn contact_sheet_keeps_columns_within_the_cells() {
+        let cells = [cell(8, 8, Color::Black, ""), cell(8, 8, Color::Black, "")];
+        assert_eq!(contact_sheet(&cells, 0).width, 8 + 2 * 24);
+        assert_eq!(contact_sheet(&cells, 5).width, 2 * (8 + 24) + 24);
+        assert_eq!(contact_sheet(&[], 3).pixels.len(), 0);
+    }
+}
@@ -0,0 +1,208 @@
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use clap::ValueEnum;
+
+use crate::{epd::inky::displayed_index, quantize::Fit, render::Color, select::criteria::Scores};
+
+/** Summary of what a single run displayed, logged once the refresh is done. */
+pub struct RunReport {
+    pub file: PathBuf,
+    /** The other files of a collage, after `file`. */
+    pub collage: Vec<PathBuf>,
+    pub scores: Scores,
+    pub schedule_window: Option<String>,
+    pub refresh_duration: Option<Duration>,
+    pub time_budget: TimeBudget,
+    /** The image the frame was made from. */
+    pub source: Option<Source>,
+    /** Number of pixels of each color in the image, by palette index. */
+    pub histogram: [usize; 7],
+    /** Width and height of the whole panel, margins included. */
+    pub panel: (usize, usize),
+    /** The saturation of the palette, after the schedule's overrides. */
+    pub saturation: f64,
+    pub fit: Fit,
+}
+
+/** The decoded image a frame was made from, and how it was brought to the panel's size. */
+#[derive(Debug, Clone, Copy)]
+pub struct Source {
+    pub width: u32,
+    pub height: u32,
+    /** The part of the image on the panel as `(x, y, width, height)`, after the crop from an
+     * XMP sidecar and fitting it to the panel. */
+    pub crop: (u32, u32, u32, u32),
+    /** Time to read and decode the image. */
+    pub decode: Duration,
+    pub resize: Duration,
+}
+
+/** Count the pixels of each color in `pixels`, palette indices in the panel's or the
+ * quantizer's numbering. */
+pub fn histogram(pixels: &[u8]) -> [usize; 7] {
+    let mut histogram = [0; 7];
+    for &px in pixels {
+        histogram[displayed_index(px) as usize] += 1;
+    }
+    return histogram;
+}
+
+impl RunReport {
+    pub fn new(file: PathBuf) -> RunReport {
+        RunReport {
+            file,
+            collage: Vec::new(),
+            scores: Scores::default(),
+            schedule_window: None,
+            refresh_duration: None,
+            time_budget: TimeBudget::default(),
+            source: None,
+            histogram: [0; 7],
+            panel: (0, 0),
+            saturation: 0.0,
+            fit: Fit::Cover,
+        }
+    }
+}
+
+impl RunReport {
+    /** Every file shown, more than one for a collage. */
+    pub fn files(&self) -> impl Iterator<Item = &PathBuf> {
+        std::iter::once(&self.file).chain(&self.collage)
+    }
+}
+
+/** `file` followed by the other files of a collage, for messages. */
+fn list_files(file: &Path, collage: &[PathBuf]) -> String {
+    let files: Vec<String> = std::iter::once(file)
+        .chain(collage.iter().map(PathBuf::as_path))
+        .map(|path| path.display().to_string())
+        .collect();
+    return files.join(", ");
+}
+
+impl Display for RunReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Displayed {}", list_files(&self.file, &self.collage))?;
+        if !self.scores.is_empty() {
+            write!(f, " ({})", self.scores)?;
+        }
+        if let Some(window) = &self.schedule_window {
+            write!(f, " using the {window} schedule window")?;
+        }
+        if let Some(duration) = self.refresh_duration {
+            write!(f, " in {:.1} s", duration.as_secs_f64())?;
+        }
+        Ok(())
+    }
+}
+
+/** What a run did, printed on stdout for people running it by hand: the image and the part of
+ * it that was used, its colors on the panel and where the time went. */
+pub struct Summary<'a>(pub &'a RunReport);
+
+impl Display for Summary<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let report = self.0;
+        write!(f, "{report}")?;
+        if let Some(source) = &report.source {
+            write!(f, "\nSource: {}x{}", source.width, source.height)?;
+            let (x, y, width, height) = source.crop;
+            if (width, height) == (source.width, source.height) {
+                write!(f, ", shown whole")?;
+            } else {
+                write!(f, ", cropped to {width}x{height}+{x}+{y}")?;
+            }
+        }
+        let total = report.histogram.iter().sum::<usize>().max(1) as f64;
+        write!(f, "\nColors:")?;
+        for (i, color) in Color::value_variants().iter().enumerate() {
+            let separator = if i == 0 { " " } else { ", " };
+            let count = report.histogram[color.index() as usize];
+            write!(
+                f,
+                "{separator}{color:?} {:.1}%",
+                count as f64 * 100.0 / total
+            )?;
+        }
+        write!(f, "\nTime: {}", report.time_budget)
+    }
+}
+
+/** What a `--dry-run` would have displayed. */
+pub struct DryRun {
+    pub file: PathBuf,
+    /** The other files of a collage, after `file`. */
+    pub collage: Vec<PathBuf>,
+    pub scores: Scores,
+    pub width: usize,
+    pub height: usize,
+    /** Number of pixels of each color, by palette index. */
+    pub histogram: [usize; 7],
+}
+
+impl DryRun {
+    pub fn new(file: PathBuf, scores: Scores, width: usize, height: usize, frame: &[u8]) -> DryRun {
+        DryRun {
+            file,
+            collage: Vec::new(),
+            scores,
+            width,
+            height,
+            histogram: histogram(frame),
+        }
+    }
+}
+
+impl Display for DryRun {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Would display {}", list_files(&self.file, &self.collage))?;
+        if !self.scores.is_empty() {
+            write!(f, " ({})", self.scores)?;
+        }
+        write!(f, "\nFrame: {}x{}", self.width, self.height)?;
+        let total = (self.width * self.height).max(1) as f64;
+        for color in Color::value_variants() {
+            let count = self.histogram[color.index() as usize];
+            write!(
+                f,
+                "\n{:<8} {:>5.1}%  {count} px",
+                format!("{color:?}"),
+                count as f64 * 100.0 / total
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/** Where the time of a run went, logged with `--time-budget`. */
+#[derive(Default)]
+pub struct TimeBudget {
+    pub steps: Vec<(String, Duration)>,
+    /** Steps that ran at the same time as the ones before them. */
+    pub parallel: Vec<(String, Duration)>,
+    /** Time from the start of the run until the panel started refreshing. */
+    pub until_refresh: Duration,
+}
+
+impl Display for TimeBudget {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{:.2} s until the refresh started (",
+            self.until_refresh.as_secs_f64()
+        )?;
+        for (i, (step, duration)) in self.steps.iter().enumerate() {
+            let separator = if i == 0 { "" } else { ", " };
+            write!(f, "{separator}{step} {:.2} s", duration.as_secs_f64())?;
+        }
+        for (step, duration) in &self.parallel {
+            write!(f, ", {step} {:.2} s in parallel", duration.as_secs_f64())?;
+        }
+        write!(f, ")")
+    }
+}
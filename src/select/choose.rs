@@ -0,0 +1,370 @@
+use std::{
+    mem,
+    path::{Path, PathBuf},
+};
+
+use image::DynamicImage;
+use log::{info, warn};
+use rand::Rng;
+
+use crate::{
+    quantize::error::QuantizeError,
+    select::{
+        criteria::{Best, Criteria, Scores},
+        error::SelectError,
+        take, Candidate, Selection,
+    },
+};
+
+/** How files are taken from the candidates until one will do: picked as `selection` says, and
+ * given up on after `max_attempts`. */
+#[derive(Debug, Clone, Copy)]
+pub struct Chooser {
+    pub selection: Selection,
+    pub max_attempts: u32,
+}
+
+impl Chooser {
+    /** Take candidates until one is loaded with `load`. A file picked from others that can't be
+     * read or decoded, such as a corrupt JPEG, is added to `failed` and another one taken
+     * instead, until `max_attempts` files have failed or none are left. The error then lists
+     * them all. */
+    pub fn take_readable<T>(
+        &self,
+        candidates: &mut Vec<Candidate>,
+        rng: &mut impl Rng,
+        failed: &mut Vec<(PathBuf, QuantizeError)>,
+        load: impl Fn(&Path) -> Result<T, QuantizeError>,
+    ) -> Result<(PathBuf, T), QuantizeError> {
+        loop {
+            let path = take(candidates, self.selection, rng);
+            let error = match load(&path) {
+                Ok(loaded) => return Ok((path, loaded)),
+                Err(error) if !error.is_unreadable() => return Err(error),
+                Err(error) => error,
+            };
+            if candidates.is_empty() || failed.len() + 1 >= self.max_attempts as usize {
+                failed.push((path, error));
+                if failed.len() == 1 {
+                    return Err(failed.pop().unwrap().1);
+                }
+                return Err(QuantizeError::Unreadable(mem::take(failed)));
+            }
+            warn!(
+                "Could not read {}, trying another file ({}/{}): {error}",
+                path.display(),
+                failed.len() + 1,
+                self.max_attempts
+            );
+            failed.push((path, error));
+        }
+    }
+
+    /** Take a candidate, re-rolling ones whose `score` misses the `criteria`, if any. A single
+     * candidate is used as it is. Files that can't be read are skipped as
+     * [Chooser::take_readable] does. */
+    pub fn choose<T>(
+        &self,
+        criteria: Criteria,
+        candidates: &mut Vec<Candidate>,
+        rng: &mut impl Rng,
+        load: impl Fn(&Path) -> Result<(DynamicImage, T), QuantizeError>,
+        score: impl Fn(&DynamicImage) -> Scores,
+    ) -> Result<(PathBuf, DynamicImage, Scores, T), QuantizeError> {
+        let mut failed = Vec::new();
+        if !criteria.is_active() || candidates.len() <= 1 {
+            let (path, (image, loaded)) = self.take_readable(candidates, rng, &mut failed, load)?;
+            return Ok((path, image, Scores::default(), loaded));
+        }
+
+        let attempts = (self.max_attempts as usize).min(candidates.len());
+        let mut best = Best::new(criteria);
+        let mut offered = false;
+        for attempt in 1..=attempts {
+            if candidates.is_empty() {
+                break;
+            }
+            let (path, (image, loaded)) =
+                match self.take_readable(candidates, rng, &mut failed, &load) {
+                    Ok(taken) => taken,
+                    // One that was read but missed the thresholds still beats none
+                    Err(error) if offered && error.is_unreadable() => {
+                        warn!("{error}");
+                        break;
+                    }
+                    Err(error) => return Err(error),
+                };
+            let scores = score(&image);
+            let Some(reason) = criteria.rejection(&scores) else {
+                info!("Scores of {}: {scores}", path.display());
+                return Ok((path, image, scores, loaded));
+            };
+
+            warn!(
+                "Skipping {}: {reason} (attempt {attempt}/{attempts})",
+                path.display()
+            );
+            best.offer((path, image, loaded), scores);
+            offered = true;
+        }
+
+        let ((path, image, loaded), scores) = best.into_inner().unwrap();
+        warn!("No candidate met the thresholds, using the closest one ({scores})");
+        return Ok((path, image, scores, loaded));
+    }
+
+    /** Take distinct files for the `cells` of a collage and load them, skipping ones that can't
+     * be read as [Chooser::take_readable] does. With too few files, they are to be repeated if
+     * `repeat`, otherwise it is an error. */
+    pub fn take_cells<T, E: From<SelectError> + From<QuantizeError>>(
+        &self,
+        cells: usize,
+        repeat: bool,
+        candidates: &mut Vec<Candidate>,
+        rng: &mut impl Rng,
+        load: impl Fn(&Path) -> Result<T, QuantizeError>,
+    ) -> Result<(Vec<PathBuf>, Vec<T>), E> {
+        if candidates.len() < cells && !repeat {
+            return Err(SelectError::TooFewForCollage {
+                cells,
+                candidates: candidates.len(),
+            }
+            .into());
+        }
+        let mut failed = Vec::new();
+        let mut files = Vec::new();
+        let mut loaded = Vec::new();
+        while files.len() < cells && !candidates.is_empty() {
+            let (path, cell) = match self.take_readable(candidates, rng, &mut failed, &load) {
+                Ok(taken) => taken,
+                // The files read so far still make a collage, repeated or not
+                Err(error) if !files.is_empty() && error.is_unreadable() => {
+                    warn!("{error}");
+                    break;
+                }
+                Err(error) => return Err(error.into()),
+            };
+            files.push(path);
+            loaded.push(cell);
+        }
+        // Files that couldn't be read may have left too few
+        if files.len() < cells {
+            if !repeat {
+                return Err(SelectError::TooFewForCollage {
+                    cells,
+                    candidates: files.len(),
+                }
+                .into());
+            }
+            warn!(
+                "Repeating files, as there are only {} for {cells} cells",
+                files.len()
+            );
+        }
+        return Ok((files, loaded));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use image::{GrayImage, Luma};
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use super::*;
+
+    const CHOOSER: Chooser = Chooser {
+        selection: Selection::Alphabetical,
+        max_attempts: 3,
+    };
+
+    const MIN_SHARPNESS: Criteria = Criteria {
+        min_sharpness: Some(50.0),
+        max_quant_error: None,
+    };
+
+    fn candidates(names: &[&str]) -> Vec<Candidate> {
+        names
+            .iter()
+            .map(|name| Candidate::new(PathBuf::from(name)))
+            .collect()
+    }
+
+    fn rng() -> StdRng {
+        StdRng::seed_from_u64(0)
+    }
+
+    /** Files named `*-unreadable` can't be read and `*-caption` can't be drawn on. The others
+     * are images whose one pixel is the number after the dash, and are loaded with their name. */
+    fn load(path: &Path) -> Result<(DynamicImage, String), QuantizeError> {
+        let name = path.file_stem().unwrap().to_str().unwrap();
+        if name.ends_with("-unreadable") {
+            return Err(io::Error::from(io::ErrorKind::InvalidData).into());
+        }
+        if name.ends_with("-caption") {
+            return Err(QuantizeError::Caption("not a font".to_string()));
+        }
+        let value = name.rsplit('-').next().unwrap().parse().unwrap_or(0);
+        let image = GrayImage::from_pixel(1, 1, Luma([value]));
+        return Ok((DynamicImage::from(image), name.to_string()));
+    }
+
+    /** The sharpness is the pixel that [load] set. */
+    fn score(image: &DynamicImage) -> Scores {
+        Scores {
+            sharpness: Some(image.to_luma8()[(0, 0)].0[0] as f64),
+            quant_error: None,
+        }
+    }
+
+    fn names(paths: &[PathBuf]) -> Vec<&str> {
+        paths.iter().map(|path| path.to_str().unwrap()).collect()
+    }
+
+    #[test]
+    fn unreadable_files_are_skipped() {
+        let mut candidates = candidates(&["a-unreadable.jpg", "b.jpg", "c.jpg"]);
+        let mut failed = Vec::new();
+        let taken = CHOOSER.take_readable(&mut candidates, &mut rng(), &mut failed, load);
+        let (path, (_, name)) = taken.unwrap_or_else(|error| panic!("{error}"));
+        assert_eq!((path, name.as_str()), (PathBuf::from("b.jpg"), "b"));
+        assert_eq!(names(&[failed[0].0.clone()]), ["a-unreadable.jpg"]);
+        assert_eq!(candidates, self::candidates(&["c.jpg"]));
+    }
+
+    #[test]
+    fn other_errors_are_not_skipped() {
+        let mut candidates = candidates(&["a-caption.jpg", "b.jpg"]);
+        let taken = CHOOSER.take_readable(&mut candidates, &mut rng(), &mut Vec::new(), load);
+        assert!(matches!(taken, Err(QuantizeError::Caption(_))));
+        assert_eq!(candidates, self::candidates(&["b.jpg"]));
+    }
+
+    #[test]
+    fn unreadable_files_are_given_up_on_after_max_attempts() {
+        let mut candidates = candidates(&[
+            "a-unreadable.jpg",
+            "b-unreadable.jpg",
+            "c-unreadable.jpg",
+            "d.jpg",
+        ]);
+        let taken = CHOOSER.take_readable(&mut candidates, &mut rng(), &mut Vec::new(), load);
+        let Err(QuantizeError::Unreadable(failed)) = taken else {
+            panic!("expected every file to be unreadable");
+        };
+        let paths: Vec<PathBuf> = failed.into_iter().map(|(path, _)| path).collect();
+        assert_eq!(
+            names(&paths),
+            ["a-unreadable.jpg", "b-unreadable.jpg", "c-unreadable.jpg"]
+        );
+        assert_eq!(candidates, self::candidates(&["d.jpg"]));
+    }
+
+    #[test]
+    fn a_single_unreadable_file_keeps_its_error() {
+        let mut candidates = candidates(&["a-unreadable.jpg"]);
+        let taken = CHOOSER.take_readable(&mut candidates, &mut rng(), &mut Vec::new(), load);
+        assert!(matches!(taken, Err(QuantizeError::Io(_))));
+    }
+
+    #[test]
+    fn candidates_missing_the_thresholds_are_rerolled() {
+        let mut candidates = candidates(&["a-10.jpg", "b-60.jpg", "c-90.jpg"]);
+        let chosen = CHOOSER.choose(MIN_SHARPNESS, &mut candidates, &mut rng(), load, score);
+        let (path, _, scores, name) = chosen.unwrap_or_else(|error| panic!("{error}"));
+        assert_eq!((path, name.as_str()), (PathBuf::from("b-60.jpg"), "b-60"));
+        assert_eq!(scores.sharpness, Some(60.0));
+    }
+
+    #[test]
+    fn the_closest_candidate_is_used_when_none_meet_the_thresholds() {
+        let mut candidates = candidates(&["a-10.jpg", "b-40.jpg", "c-20.jpg", "d-90.jpg"]);
+        let chosen = CHOOSER.choose(MIN_SHARPNESS, &mut candidates, &mut rng(), load, score);
+        let (path, ..) = chosen.unwrap_or_else(|error| panic!("{error}"));
+        // Only as many as --max-attempts are tried
+        assert_eq!(path, PathBuf::from("b-40.jpg"));
+        assert_eq!(candidates, self::candidates(&["d-90.jpg"]));
+    }
+
+    #[test]
+    fn a_candidate_that_was_read_beats_unreadable_ones() {
+        let mut candidates = candidates(&["a-10.jpg", "b-unreadable.jpg"]);
+        let chosen = CHOOSER.choose(MIN_SHARPNESS, &mut candidates, &mut rng(), load, score);
+        let (path, ..) = chosen.unwrap_or_else(|error| panic!("{error}"));
+        assert_eq!(path, PathBuf::from("a-10.jpg"));
+    }
+
+    #[test]
+    fn thresholds_are_not_checked_without_a_choice() {
+        let mut candidates = candidates(&["a-10.jpg"]);
+        let chosen = CHOOSER.choose(MIN_SHARPNESS, &mut candidates, &mut rng(), load, score);
+        let (path, _, scores, _) = chosen.unwrap_or_else(|error| panic!("{error}"));
+        assert_eq!(path, PathBuf::from("a-10.jpg"));
+        assert_eq!(scores, Scores::default());
+    }
+
+    #[derive(derive_more::From)]
+    enum CellError {
+        Select(SelectError),
+        Quantize(QuantizeError),
+    }
+
+    /** The files taken for the `cells`, and the names they were loaded with. */
+    fn take_cells(
+        cells: usize,
+        repeat: bool,
+        names: &[&str],
+    ) -> Result<(Vec<PathBuf>, Vec<String>), CellError> {
+        let mut candidates = candidates(names);
+        let load = |path: &Path| load(path).map(|(_, name)| name);
+        return CHOOSER.take_cells(cells, repeat, &mut candidates, &mut rng(), load);
+    }
+
+    #[test]
+    fn collages_take_distinct_files() {
+        let Ok((files, loaded)) = take_cells(2, false, &["a.jpg", "b.jpg", "c.jpg"]) else {
+            panic!("expected enough files");
+        };
+        assert_eq!(names(&files), ["a.jpg", "b.jpg"]);
+        assert_eq!(loaded, ["a", "b"]);
+    }
+
+    #[test]
+    fn collages_need_enough_files_unless_repeated() {
+        let result = take_cells(4, false, &["a.jpg", "b.jpg"]);
+        assert!(matches!(
+            result,
+            Err(CellError::Select(SelectError::TooFewForCollage {
+                cells: 4,
+                candidates: 2
+            }))
+        ));
+        let Ok((files, _)) = take_cells(4, true, &["a.jpg", "b.jpg"]) else {
+            panic!("expected the files to be repeated");
+        };
+        assert_eq!(names(&files), ["a.jpg", "b.jpg"]);
+    }
+
+    #[test]
+    fn unreadable_files_can_leave_too_few_for_a_collage() {
+        let paths = ["a.jpg", "b.jpg", "c-unreadable.jpg"];
+        let result = take_cells(3, false, &paths);
+        assert!(matches!(
+            result,
+            Err(CellError::Select(SelectError::TooFewForCollage {
+                cells: 3,
+                candidates: 2
+            }))
+        ));
+        let Ok((files, _)) = take_cells(3, true, &paths) else {
+            panic!("expected the readable files to be repeated");
+        };
+        assert_eq!(names(&files), ["a.jpg", "b.jpg"]);
+        let result = take_cells(2, false, &["a-caption.jpg", "b.jpg"]);
+        assert!(matches!(
+            result,
+            Err(CellError::Quantize(QuantizeError::Caption(_)))
+        ));
+    }
+}
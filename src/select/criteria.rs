@@ -0,0 +1,259 @@
+use std::fmt::Display;
+
+/** Thresholds a candidate has to meet to be displayed without trying another one. */
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Criteria {
+    pub min_sharpness: Option<f64>,
+    pub max_quant_error: Option<f64>,
+}
+
+/** What was measured of a candidate. Only the scores a threshold is set for are measured. */
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Scores {
+    pub sharpness: Option<f64>,
+    pub quant_error: Option<f64>,
+}
+
+impl Criteria {
+    /** Whether there is anything to check at all. */
+    pub fn is_active(&self) -> bool {
+        self.min_sharpness.is_some() || self.max_quant_error.is_some()
+    }
+
+    /** Why a candidate with these scores should be skipped, or `None` to accept it. Scores
+     * that weren't measured don't count against it. */
+    pub fn rejection(&self, scores: &Scores) -> Option<String> {
+        let mut reasons = Vec::new();
+        if let (Some(min), Some(sharpness)) = (self.min_sharpness, scores.sharpness) {
+            if sharpness < min {
+                reasons.push(format!("sharpness {sharpness:.1} is below {min}"));
+            }
+        }
+        if let (Some(max), Some(error)) = (self.max_quant_error, scores.quant_error) {
+            if error > max {
+                reasons.push(format!("color error {error:.1} is above {max}"));
+            }
+        }
+        (!reasons.is_empty()).then(|| reasons.join(" and "))
+    }
+
+    /** How far a candidate is from meeting every threshold, each as a fraction of the
+     * threshold, summed. 0 for candidates that are accepted. */
+    pub fn shortfall(&self, scores: &Scores) -> f64 {
+        let relative =
+            |missing: f64, threshold: f64| (missing / threshold.abs().max(f64::EPSILON)).max(0.0);
+        let mut shortfall = 0.0;
+        if let (Some(min), Some(sharpness)) = (self.min_sharpness, scores.sharpness) {
+            shortfall += relative(min - sharpness, min);
+        }
+        if let (Some(max), Some(error)) = (self.max_quant_error, scores.quant_error) {
+            shortfall += relative(error - max, max);
+        }
+        return shortfall;
+    }
+}
+
+/** The rejected candidate that came closest to the [Criteria], to fall back on once every
+ * attempt is used up. On a tie the earlier one is kept. */
+pub struct Best<T> {
+    criteria: Criteria,
+    best: Option<(T, Scores, f64)>,
+}
+
+impl<T> Best<T> {
+    pub fn new(criteria: Criteria) -> Best<T> {
+        Best {
+            criteria,
+            best: None,
+        }
+    }
+
+    pub fn offer(&mut self, item: T, scores: Scores) {
+        let shortfall = self.criteria.shortfall(&scores);
+        if self
+            .best
+            .as_ref()
+            .is_none_or(|(_, _, best)| shortfall < *best)
+        {
+            self.best = Some((item, scores, shortfall));
+        }
+    }
+
+    pub fn into_inner(self) -> Option<(T, Scores)> {
+        self.best.map(|(item, scores, _)| (item, scores))
+    }
+}
+
+impl Scores {
+    pub fn is_empty(&self) -> bool {
+        self.sharpness.is_none() && self.quant_error.is_none()
+    }
+}
+
+impl Display for Scores {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut separator = "";
+        if let Some(sharpness) = self.sharpness {
+            write!(f, "sharpness {sharpness:.1}")?;
+            separator = ", ";
+        }
+        if let Some(error) = self.quant_error {
+            write!(f, "{separator}color error {error:.1}")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sharpness(sharpness: f64) -> Scores {
+        Scores {
+            sharpness: Some(sharpness),
+            quant_error: None,
+        }
+    }
+
+    const MIN_SHARPNESS: Criteria = Criteria {
+        min_sharpness: Some(100.0),
+        max_quant_error: None,
+    };
+
+    #[test]
+    fn sharpness_below_the_threshold_is_rejected() {
+        assert!(MIN_SHARPNESS.is_active());
+        assert_eq!(MIN_SHARPNESS.rejection(&sharpness(150.0)), None);
+        assert_eq!(MIN_SHARPNESS.rejection(&sharpness(100.0)), None);
+        assert_eq!(
+            MIN_SHARPNESS.rejection(&sharpness(42.0)),
+            Some("sharpness 42.0 is below 100".to_string())
+        );
+        // Not measured, so not held against it
+        assert_eq!(MIN_SHARPNESS.rejection(&Scores::default()), None);
+    }
+
+    #[test]
+    fn no_thresholds_accept_anything() {
+        let criteria = Criteria::default();
+        assert!(!criteria.is_active());
+        assert_eq!(criteria.rejection(&sharpness(0.0)), None);
+        assert_eq!(criteria.shortfall(&sharpness(0.0)), 0.0);
+    }
+
+    #[test]
+    fn shortfall_is_relative_to_the_threshold() {
+        assert_eq!(MIN_SHARPNESS.shortfall(&sharpness(150.0)), 0.0);
+        assert_eq!(MIN_SHARPNESS.shortfall(&sharpness(75.0)), 0.25);
+        assert_eq!(MIN_SHARPNESS.shortfall(&sharpness(0.0)), 1.0);
+    }
+
+    #[test]
+    fn best_keeps_the_sharpest_rejection() {
+        let mut best = Best::new(MIN_SHARPNESS);
+        for (name, score) in [("a", 20.0), ("b", 60.0), ("c", 10.0), ("d", 60.0)] {
+            best.offer(name, sharpness(score));
+        }
+        // d ties with b, which came first
+        assert_eq!(best.into_inner(), Some(("b", sharpness(60.0))));
+    }
+
+    #[test]
+    fn best_of_nothing_is_none() {
+        let best: Best<&str> = Best::new(MIN_SHARPNESS);
+        assert_eq!(best.into_inner(), None);
+    }
+
+    fn scores(sharpness: f64, quant_error: f64) -> Scores {
+        Scores {
+            sharpness: Some(sharpness),
+            quant_error: Some(quant_error),
+        }
+    }
+
+    fn quant_error(quant_error: f64) -> Scores {
+        Scores {
+            sharpness: None,
+            quant_error: Some(quant_error),
+        }
+    }
+
+    const BOTH: Criteria = Criteria {
+        min_sharpness: Some(100.0),
+        max_quant_error: Some(20.0),
+    };
+
+    #[test]
+    fn color_error_above_the_threshold_is_rejected() {
+        let criteria = Criteria {
+            min_sharpness: None,
+            max_quant_error: Some(20.0),
+        };
+        assert!(criteria.is_active());
+        assert_eq!(criteria.rejection(&quant_error(5.0)), None);
+        assert_eq!(criteria.rejection(&quant_error(20.0)), None);
+        assert_eq!(
+            criteria.rejection(&quant_error(31.25)),
+            Some("color error 31.2 is above 20".to_string())
+        );
+        assert_eq!(criteria.shortfall(&quant_error(30.0)), 0.5);
+        // The sharpness it wasn't asked about doesn't matter
+        assert_eq!(criteria.rejection(&scores(0.0, 5.0)), None);
+    }
+
+    #[test]
+    fn every_reason_is_given() {
+        assert_eq!(BOTH.rejection(&scores(150.0, 10.0)), None);
+        assert_eq!(
+            BOTH.rejection(&scores(50.0, 10.0)),
+            Some("sharpness 50.0 is below 100".to_string())
+        );
+        assert_eq!(
+            BOTH.rejection(&scores(50.0, 40.0)),
+            Some("sharpness 50.0 is below 100 and color error 40.0 is above 20".to_string())
+        );
+    }
+
+    #[test]
+    fn shortfalls_add_up() {
+        assert_eq!(BOTH.shortfall(&scores(150.0, 10.0)), 0.0);
+        assert_eq!(BOTH.shortfall(&scores(50.0, 10.0)), 0.5);
+        assert_eq!(BOTH.shortfall(&scores(150.0, 30.0)), 0.5);
+        assert_eq!(BOTH.shortfall(&scores(50.0, 30.0)), 1.0);
+        // Being well within one threshold doesn't make up for missing the other
+        assert_eq!(BOTH.shortfall(&scores(1000.0, 30.0)), 0.5);
+    }
+
+    #[test]
+    fn shortfall_of_a_zero_threshold_is_finite() {
+        let criteria = Criteria {
+            min_sharpness: None,
+            max_quant_error: Some(0.0),
+        };
+        let shortfall = criteria.shortfall(&quant_error(1.0));
+        assert!(shortfall.is_finite() && shortfall > 0.0);
+        assert_eq!(criteria.shortfall(&quant_error(0.0)), 0.0);
+    }
+
+    #[test]
+    fn best_keeps_the_attempt_closest_to_both_thresholds() {
+        let mut best = Best::new(BOTH);
+        best.offer(1, scores(10.0, 25.0)); // 0.9 + 0.25
+        best.offer(2, scores(200.0, 36.0)); // 0.8
+        best.offer(3, scores(80.0, 22.0)); // 0.2 + 0.1
+        best.offer(4, scores(40.0, 10.0)); // 0.6
+        assert_eq!(best.into_inner(), Some((3, scores(80.0, 22.0))));
+    }
+
+    #[test]
+    fn scores_show_what_was_measured() {
+        assert_eq!(
+            scores(123.456, 7.06).to_string(),
+            "sharpness 123.5, color error 7.1"
+        );
+        assert_eq!(quant_error(7.06).to_string(), "color error 7.1");
+        assert_eq!(sharpness(123.456).to_string(), "sharpness 123.5");
+        assert!(Scores::default().is_empty());
+        assert!(!sharpness(0.0).is_empty());
+    }
+}
@@ -0,0 +1,84 @@
+use std::{fmt::Display, io, path::PathBuf};
+
+use crate::select::Rejected;
+
+#[derive(derive_more::From)]
+pub enum SelectError {
+    Io(io::Error),
+    #[from(ignore)]
+    NotFound(PathBuf),
+    /** Nothing to choose from in the directories, after the filter left out `rejected` files.
+     * `patterns` are the filter's patterns, for the message. */
+    #[from(ignore)]
+    NoCandidates {
+        dirs: Vec<PathBuf>,
+        rejected: Rejected,
+        patterns: Vec<String>,
+    },
+    SeasonMap(toml::de::Error),
+    /** The file given on the command line is over `--max-file-size`. */
+    #[from(ignore)]
+    TooLarge {
+        path: PathBuf,
+        size: u64,
+        limit: u64,
+    },
+    /** `--collage` needs more files than there are to choose from. */
+    #[from(ignore)]
+    TooFewForCollage {
+        cells: usize,
+        candidates: usize,
+    },
+}
+
+impl Display for SelectError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SelectError::Io(error) => write!(f, "Directory error: {error}"),
+            SelectError::NotFound(path) => write!(f, "{} does not exist", path.display()),
+            SelectError::NoCandidates {
+                dirs,
+                rejected,
+                patterns,
+            } => {
+                let dirs: Vec<String> = dirs.iter().map(|dir| dir.display().to_string()).collect();
+                write!(f, "No displayable files found in {}", dirs.join(", "))?;
+                if rejected.extension > 0 {
+                    let count = rejected.extension;
+                    write!(
+                        f,
+                        ", {count} were skipped for their extension (see --all-files)"
+                    )?;
+                }
+                if rejected.patterns > 0 {
+                    let count = rejected.patterns;
+                    write!(f, ", {count} were left out by {}", patterns.join(" "))?;
+                }
+                if rejected.size > 0 {
+                    let count = rejected.size;
+                    write!(f, ", {count} were larger than --max-file-size")?;
+                }
+                if rejected.resolution > 0 {
+                    let count = rejected.resolution;
+                    write!(f, ", {count} were smaller than --min-resolution")?;
+                }
+                if rejected.weight > 0 {
+                    let count = rejected.weight;
+                    write!(f, ", {count} have a weight of 0")?;
+                }
+                Ok(())
+            }
+            SelectError::SeasonMap(error) => write!(f, "Season map error: {error}"),
+            SelectError::TooLarge { path, size, limit } => write!(
+                f,
+                "{} is {size} bytes, larger than --max-file-size {limit}",
+                path.display()
+            ),
+            SelectError::TooFewForCollage { cells, candidates } => write!(
+                f,
+                "The collage has {cells} cells but there are only {candidates} to choose from \
+                 (see --collage-repeat)"
+            ),
+        }
+    }
+}
@@ -0,0 +1,574 @@
+use std::{
+    collections::HashSet,
+    fs, io,
+    path::{Path, PathBuf},
+    slice,
+};
+
+use chrono::Weekday;
+use log::{debug, info, warn};
+
+use crate::{
+    fetch,
+    select::{
+        error::SelectError,
+        list_candidates,
+        season::{MonthDay, SeasonMap},
+        weekday,
+        weight::{self, Weight},
+        Candidate, Filter, Listing, Rejected, STDIN,
+    },
+    state::sources::SourceCursor,
+};
+
+/** Where the files to choose from are gathered from and which of them are kept, as the command
+ * line sets it up. */
+#[derive(Debug, Clone)]
+pub struct Sources {
+    /** Files and directories, URLs, or [STDIN]. */
+    pub paths: Vec<String>,
+    /** A command that stands in for a file, and is run once it is chosen. */
+    pub exec: Option<String>,
+    pub listing: Listing,
+    pub filter: Filter,
+    pub weights: Vec<Weight>,
+    /** The season map file, read every time candidates are gathered. */
+    pub season_map: Option<PathBuf>,
+    /** The day the seasons are checked on. */
+    pub today: MonthDay,
+    /** The day whose subdirectory is chosen from, for `--by-weekday`. */
+    pub weekday: Option<Weekday>,
+}
+
+impl Sources {
+    /** Collect the pool of files to choose from all the paths: files as they are and the
+     * entries of directories, without duplicates. A path that can't be read is skipped with a
+     * warning, unless none of them can. */
+    pub fn candidates(&self) -> Result<Vec<Candidate>, SelectError> {
+        if let Some(command) = &self.exec {
+            return Ok(vec![Candidate::new(PathBuf::from(command))]);
+        }
+        return self.gather(&self.paths);
+    }
+
+    /** The candidates from the next of the paths in the round of `--rotate-sources`, going by
+     * the state file at `cursor`, which is moved on if `advance`. Paths without anything to
+     * choose from are skipped with a warning. */
+    pub fn next_source(&self, cursor: &Path, advance: bool) -> Result<Vec<Candidate>, SelectError> {
+        let mut state = SourceCursor::load(cursor);
+        for index in state.order(&self.paths) {
+            let source = &self.paths[index];
+            match self.gather(slice::from_ref(source)) {
+                Ok(candidates) => {
+                    info!(
+                        "Choosing from {source} ({}/{})",
+                        index + 1,
+                        self.paths.len()
+                    );
+                    if advance {
+                        state.last = Some(source.clone());
+                        state.index = index;
+                        if let Err(error) = state.save(cursor) {
+                            warn!("Could not write {}: {error}", cursor.display());
+                        }
+                    }
+                    return Ok(candidates);
+                }
+                Err(error) => warn!("{error}, going on to the next source"),
+            }
+        }
+        // Fails like choosing from all of them at once would
+        return self.candidates();
+    }
+
+    /** The candidates from `paths`, as for [Sources::candidates]. */
+    pub fn gather(&self, paths: &[String]) -> Result<Vec<Candidate>, SelectError> {
+        let season_map = self
+            .season_map
+            .as_deref()
+            .map(SeasonMap::load)
+            .transpose()?;
+        if let Some(season_map) = &season_map {
+            season_map.log_active(self.today);
+        }
+
+        let mut scanned = HashSet::new();
+        let mut dirs = Vec::new();
+        let mut candidates = Vec::new();
+        let mut rejected = Rejected::default();
+        let mut failures = Vec::new();
+        let mut readable = false;
+        for source in paths {
+            let path = Path::new(source);
+            // The same path may be given twice, spelled differently
+            if !scanned.insert(fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())) {
+                continue;
+            }
+            match self.gather_path(path, season_map.as_ref()) {
+                Ok((found, left_out)) => {
+                    readable = true;
+                    if path.is_dir() {
+                        dirs.push(path.to_path_buf());
+                    }
+                    candidates.extend(found);
+                    rejected += left_out;
+                }
+                Err(error) => failures.push(error),
+            }
+        }
+        if !readable {
+            return Err(failures.swap_remove(0));
+        }
+        for error in failures {
+            warn!("{error}, choosing from the other paths");
+        }
+        if rejected != Rejected::default() {
+            debug!("Left out {rejected:?}");
+        }
+
+        // Directories can overlap, such as a directory and one of its subdirectories
+        let mut listed = HashSet::new();
+        candidates.retain(|candidate| listed.insert(candidate.path.clone()));
+        if candidates.is_empty() {
+            return Err(SelectError::NoCandidates {
+                dirs,
+                rejected,
+                patterns: self.filter.patterns(),
+            });
+        }
+
+        return Ok(candidates);
+    }
+
+    /** The candidates from one path: the file alone, or the entries of the directory, or of its
+     * subdirectory for the weekday if given, that pass the filter and the season map. Also
+     * returns how many the filter left out. */
+    fn gather_path(
+        &self,
+        path: &Path,
+        season_map: Option<&SeasonMap>,
+    ) -> Result<(Vec<Candidate>, Rejected), SelectError> {
+        if path == Path::new(STDIN) || path.to_str().is_some_and(fetch::is_url) {
+            return Ok((
+                vec![Candidate::new(path.to_path_buf())],
+                Rejected::default(),
+            ));
+        }
+        let metadata = path.metadata().map_err(|error| match error.kind() {
+            io::ErrorKind::NotFound => SelectError::NotFound(path.to_path_buf()),
+            _ => error.into(),
+        })?;
+        if !metadata.is_dir() {
+            let max_file_size = self.filter.max_file_size;
+            if let Some(limit) = max_file_size.filter(|&limit| metadata.len() > limit) {
+                return Err(SelectError::TooLarge {
+                    path: path.to_path_buf(),
+                    size: metadata.len(),
+                    limit,
+                });
+            }
+            return Ok((
+                vec![Candidate::new(path.to_path_buf())],
+                Rejected::default(),
+            ));
+        }
+
+        let listing = self.listing;
+        let mut candidates = list_candidates(path, listing)?;
+        if let Some(weekday) = self.weekday {
+            candidates = weekday::narrow(path, weekday, listing, candidates);
+        }
+        if let Some(season_map) = season_map {
+            season_map.extend_candidates(self.today, path, listing, &mut candidates);
+        }
+        let mut rejected = self.filter.apply(path, &mut candidates);
+        if let Some(season_map) = season_map {
+            candidates = season_map.apply(self.today, path, candidates);
+        }
+        weight::apply(&self.weights, path, &mut candidates);
+        // Never chosen anyway, and a pool of only these would leave nothing to sample
+        let before = candidates.len();
+        candidates.retain(|candidate| candidate.weight > 0.0);
+        rejected.weight = before - candidates.len();
+
+        return Ok((candidates, rejected));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::symlink;
+
+    use super::*;
+    use crate::select::glob::Glob;
+
+    fn sources(paths: &[&Path]) -> Sources {
+        Sources {
+            paths: paths
+                .iter()
+                .map(|path| path.display().to_string())
+                .collect(),
+            exec: None,
+            listing: Listing::default(),
+            filter: Filter::default(),
+            weights: Vec::new(),
+            season_map: None,
+            today: MonthDay::new(1, 1).unwrap(),
+            weekday: None,
+        }
+    }
+
+    /** Relative to `dir`, sorted. */
+    fn relative(dir: &Path, candidates: &[Candidate]) -> Vec<String> {
+        let mut found: Vec<String> = candidates
+            .iter()
+            .map(|candidate| candidate.path.strip_prefix(dir).unwrap())
+            .map(|path| path.to_str().unwrap().to_string())
+            .collect();
+        found.sort();
+        return found;
+    }
+
+    /** A directory of images to gather from, with hidden files, a file that isn't an image,
+     * symlinks to a file and to a directory outside, one leading back up, and a dangling one.
+     * The second directory holds what the symlinked directory leads to. */
+    fn symlink_fixture() -> (tempfile::TempDir, tempfile::TempDir) {
+        let dir = tempfile::tempdir().unwrap();
+        let outside = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        for file in [
+            "a.jpg",
+            ".hidden.jpg",
+            "notes.txt",
+            "sub/b.jpg",
+            ".hiddendir/c.jpg",
+        ] {
+            let path = root.join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, b"").unwrap();
+        }
+        fs::write(outside.path().join("d.jpg"), b"").unwrap();
+        symlink(root.join("a.jpg"), root.join("link.jpg")).unwrap();
+        symlink(root.join("missing.jpg"), root.join("broken.jpg")).unwrap();
+        symlink(outside.path(), root.join("linked")).unwrap();
+        symlink(root, root.join("sub/loop")).unwrap();
+        return (dir, outside);
+    }
+
+    /** The files found in `dir` with the sources set up by `configure`, relative to it. */
+    fn gathered(dir: &Path, configure: impl FnOnce(&mut Sources)) -> Vec<String> {
+        let mut sources = sources(&[dir]);
+        configure(&mut sources);
+        match sources.candidates() {
+            Ok(candidates) => relative(dir, &candidates),
+            Err(error) => panic!("{error}"),
+        }
+    }
+
+    fn recursive(sources: &mut Sources) {
+        sources.listing.max_depth = usize::MAX;
+    }
+
+    #[test]
+    fn gather_skips_dotfiles_and_dangling_symlinks() {
+        let (dir, _outside) = symlink_fixture();
+        // Symlinks to files are followed, but this doesn't look into directories
+        assert_eq!(gathered(dir.path(), |_| {}), ["a.jpg", "link.jpg"]);
+        assert_eq!(
+            gathered(dir.path(), |s| s.listing.include_hidden = true),
+            [".hidden.jpg", "a.jpg", "link.jpg"]
+        );
+        assert_eq!(
+            gathered(dir.path(), |s| s.filter.all_files = true),
+            ["a.jpg", "link.jpg", "notes.txt"]
+        );
+    }
+
+    #[test]
+    fn gather_follows_directory_symlinks_only_when_asked() {
+        let (dir, _outside) = symlink_fixture();
+        assert_eq!(
+            gathered(dir.path(), recursive),
+            ["a.jpg", "link.jpg", "sub/b.jpg"]
+        );
+        // The symlink back up doesn't list the directory a second time
+        assert_eq!(
+            gathered(dir.path(), |s| {
+                recursive(s);
+                s.listing.follow_symlinks = true;
+            }),
+            ["a.jpg", "link.jpg", "linked/d.jpg", "sub/b.jpg"]
+        );
+        assert_eq!(
+            gathered(dir.path(), |s| {
+                recursive(s);
+                s.listing.follow_symlinks = true;
+                s.listing.include_hidden = true;
+            }),
+            [
+                ".hidden.jpg",
+                ".hiddendir/c.jpg",
+                "a.jpg",
+                "link.jpg",
+                "linked/d.jpg",
+                "sub/b.jpg"
+            ]
+        );
+    }
+
+    #[test]
+    fn gather_follows_a_loop_once() {
+        let (dir, _outside) = symlink_fixture();
+        // Started below the loop, it leads up to the rest of the tree once
+        let sub = dir.path().join("sub");
+        let found = gathered(&sub, |s| {
+            recursive(s);
+            s.listing.follow_symlinks = true;
+        });
+        assert_eq!(
+            found,
+            ["b.jpg", "loop/a.jpg", "loop/link.jpg", "loop/linked/d.jpg"]
+        );
+    }
+
+    #[test]
+    fn gather_rejects_a_dangling_symlink_given_as_a_path() {
+        let (dir, _outside) = symlink_fixture();
+        let path = dir.path().join("broken.jpg");
+        let result = sources(&[&path]).candidates();
+        assert!(matches!(result, Err(SelectError::NotFound(missing)) if missing == path));
+    }
+
+    #[test]
+    fn paths_are_gathered_once() {
+        let (dir, _outside) = symlink_fixture();
+        let root = dir.path();
+        let mut sources = sources(&[root, &root.join("sub"), &root.join("a.jpg")]);
+        sources.paths.push(format!("{}/./sub/", root.display()));
+        recursive(&mut sources);
+        let candidates = sources
+            .candidates()
+            .unwrap_or_else(|error| panic!("{error}"));
+        assert_eq!(
+            relative(root, &candidates),
+            ["a.jpg", "link.jpg", "sub/b.jpg"]
+        );
+    }
+
+    #[test]
+    fn unreadable_paths_are_skipped_unless_all_are() {
+        let (dir, _outside) = symlink_fixture();
+        let missing = dir.path().join("missing");
+        let sources = sources(&[&missing, dir.path()]);
+        let candidates = sources
+            .candidates()
+            .unwrap_or_else(|error| panic!("{error}"));
+        assert_eq!(relative(dir.path(), &candidates), ["a.jpg", "link.jpg"]);
+
+        let result = sources.gather(&sources.paths[..1]);
+        assert!(matches!(result, Err(SelectError::NotFound(path)) if path == missing));
+        // A directory without anything to choose from
+        let empty = dir.path().join("empty");
+        fs::create_dir(&empty).unwrap();
+        let result = sources.gather(&[empty.display().to_string()]);
+        assert!(matches!(result, Err(SelectError::NoCandidates { .. })));
+    }
+
+    #[test]
+    fn files_given_as_paths_are_checked_for_size() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("large.jpg");
+        fs::write(&path, [0; 100]).unwrap();
+        let mut sources = sources(&[&path]);
+        sources.filter.max_file_size = Some(99);
+        let result = sources.candidates();
+        assert!(matches!(
+            result,
+            Err(SelectError::TooLarge {
+                size: 100,
+                limit: 99,
+                ..
+            })
+        ));
+        sources.filter.max_file_size = Some(100);
+        assert_eq!(
+            sources.candidates().unwrap_or_else(|e| panic!("{e}")).len(),
+            1
+        );
+    }
+
+    #[test]
+    fn stdin_urls_and_commands_stand_for_themselves() {
+        let sources = sources(&[Path::new(STDIN), Path::new("https://example.com/a.jpg")]);
+        let candidates = sources
+            .candidates()
+            .unwrap_or_else(|error| panic!("{error}"));
+        let paths: Vec<_> = candidates
+            .iter()
+            .map(|c| c.path.to_str().unwrap())
+            .collect();
+        assert_eq!(paths, ["-", "https://example.com/a.jpg"]);
+
+        let sources = Sources {
+            exec: Some("fetch-photo --today".to_string()),
+            ..sources
+        };
+        let candidates = sources
+            .candidates()
+            .unwrap_or_else(|error| panic!("{error}"));
+        assert_eq!(
+            candidates,
+            [Candidate::new(PathBuf::from("fetch-photo --today"))]
+        );
+    }
+
+    #[test]
+    fn sources_are_taken_in_turn() {
+        let (first, second) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        fs::write(first.path().join("a.jpg"), b"").unwrap();
+        fs::write(second.path().join("b.jpg"), b"").unwrap();
+        let empty = tempfile::tempdir().unwrap();
+        let sources = sources(&[first.path(), empty.path(), second.path()]);
+        let state = tempfile::tempdir().unwrap();
+        let cursor = state.path().join("sources.json");
+        let next = |advance| match sources.next_source(&cursor, advance) {
+            Ok(candidates) => candidates[0].path.file_name().unwrap().to_owned(),
+            Err(error) => panic!("{error}"),
+        };
+
+        assert_eq!(next(true), "a.jpg");
+        // The empty directory is skipped
+        assert_eq!(next(true), "b.jpg");
+        // Without advancing, the same source comes next again
+        assert_eq!(next(false), "a.jpg");
+        assert_eq!(next(false), "a.jpg");
+        assert_eq!(next(true), "a.jpg");
+        assert_eq!(next(true), "b.jpg");
+    }
+
+    /** A tree of photos from trips and work, with a private folder, a screenshot and notes. */
+    fn album() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for file in [
+            "vacation-2023/a.jpg",
+            "vacation-2023/b.png",
+            "vacation-2023/private/c.jpg",
+            "vacation-2023/itinerary.txt",
+            "vacation-2024/day 1/d.jpg",
+            "work/e.jpg",
+            "screenshot.png",
+            "notes.txt",
+            "f.jpg",
+        ] {
+            let path = dir.path().join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, b"").unwrap();
+        }
+        return dir;
+    }
+
+    fn globs(patterns: &[&str]) -> Vec<Glob> {
+        patterns
+            .iter()
+            .map(|pattern| pattern.parse().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn includes_are_narrowed_by_excludes_at_any_depth() {
+        let dir = album();
+        let found = gathered(dir.path(), |s| {
+            recursive(s);
+            s.filter.include = globs(&["vacation-*/**"]);
+            s.filter.exclude = globs(&["**/private/**", "*.png"]);
+        });
+        assert_eq!(found, ["vacation-2023/a.jpg", "vacation-2024/day 1/d.jpg"]);
+
+        // Without recursion only the top level is looked at
+        let found = gathered(dir.path(), |s| {
+            s.filter.include = globs(&["**/*.jpg"]);
+        });
+        assert_eq!(found, ["f.jpg"]);
+        let found = gathered(dir.path(), |s| {
+            recursive(s);
+            s.filter.include = globs(&["**/*.jpg"]);
+            s.filter.exclude = globs(&["vacation-*/**"]);
+        });
+        assert_eq!(found, ["f.jpg", "work/e.jpg"]);
+    }
+
+    #[test]
+    fn patterns_apply_after_the_extension_filter() {
+        let dir = album();
+        // The notes were left out for their extension before the pattern could keep them
+        let mut sources = sources(&[dir.path()]);
+        sources.filter.include = globs(&["*.txt"]);
+        assert!(matches!(
+            sources.candidates(),
+            Err(SelectError::NoCandidates { .. })
+        ));
+        let found = gathered(dir.path(), |s| {
+            s.filter.all_files = true;
+            s.filter.include = globs(&["*.txt"]);
+        });
+        assert_eq!(found, ["notes.txt"]);
+    }
+
+    #[test]
+    fn a_weight_of_zero_leaves_a_subtree_out() {
+        let dir = album();
+        let mut sources = sources(&[dir.path()]);
+        recursive(&mut sources);
+        sources.weights = vec![
+            "vacation-2023=0".parse().unwrap(),
+            "work=3".parse().unwrap(),
+        ];
+        let (candidates, rejected) = sources
+            .gather_path(dir.path(), None)
+            .unwrap_or_else(|error| panic!("{error}"));
+        assert_eq!(
+            relative(dir.path(), &candidates),
+            [
+                "f.jpg",
+                "screenshot.png",
+                "vacation-2024/day 1/d.jpg",
+                "work/e.jpg"
+            ]
+        );
+        let work = candidates.iter().find(|c| c.path.ends_with("work/e.jpg"));
+        assert_eq!(work.map(|c| c.weight), Some(3.0));
+        // The pictures of 2023, the notes having been left out for their extension already
+        assert_eq!(rejected.weight, 3);
+        assert_eq!(rejected.extension, 2);
+
+        sources.filter.include = globs(&["vacation-2023/**"]);
+        let Err(error) = sources.candidates() else {
+            panic!("expected nothing to be left");
+        };
+        assert!(
+            error.to_string().contains(", 3 have a weight of 0"),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn filtering_everything_out_names_the_patterns() {
+        let dir = album();
+        let mut sources = sources(&[dir.path()]);
+        recursive(&mut sources);
+        sources.filter.include = globs(&["vacation-*/**"]);
+        sources.filter.exclude = globs(&["**/*.jpg", "*.png"]);
+        let error = match sources.candidates() {
+            Ok(candidates) => panic!("expected nothing to be left, got {candidates:?}"),
+            Err(error) => error,
+        };
+        assert!(matches!(error, SelectError::NoCandidates { .. }));
+        let expected = format!(
+            "No displayable files found in {}, 2 were skipped for their extension (see \
+             --all-files), 7 were left out by --include 'vacation-*/**' --exclude '**/*.jpg' \
+             --exclude '*.png'",
+            dir.path().display()
+        );
+        assert_eq!(error.to_string(), expected);
+    }
+}
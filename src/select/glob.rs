@@ -0,0 +1,211 @@
+use std::{fmt::Display, path::Path, str::FromStr};
+
+/** A shell style pattern for paths relative to the source directory.
+ *
+ * `*` matches any characters but `/`, `?` any one of them and `[a-z]` or `[!a-z]` one from a
+ * set. A `**` segment matches any number of directories, including none. Patterns without a
+ * `/` are matched against the file name alone, so `*.jpg` matches at any depth. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Glob {
+    pattern: String,
+}
+
+impl Glob {
+    pub fn matches(&self, path: &Path) -> bool {
+        let components: Vec<Vec<char>> = path
+            .components()
+            .map(|c| c.as_os_str().to_string_lossy().chars().collect())
+            .collect();
+        if !self.pattern.contains('/') {
+            let pattern: Vec<char> = self.pattern.chars().collect();
+            return components
+                .last()
+                .is_some_and(|name| match_segment(&pattern, name));
+        }
+
+        let segments: Vec<Vec<char>> = self
+            .pattern
+            .split('/')
+            .filter(|s| !s.is_empty())
+            .map(|s| s.chars().collect())
+            .collect();
+        match_segments(&segments, &components)
+    }
+}
+
+/** Match path components against pattern segments, where `**` stands for any number of
+ * components. */
+fn match_segments(segments: &[Vec<char>], components: &[Vec<char>]) -> bool {
+    match segments.split_first() {
+        None => components.is_empty(),
+        Some((segment, rest)) if segment[..] == ['*', '*'] => {
+            (0..=components.len()).any(|skip| match_segments(rest, &components[skip..]))
+        }
+        Some((segment, rest)) => components.split_first().is_some_and(|(component, others)| {
+            match_segment(segment, component) && match_segments(rest, others)
+        }),
+    }
+}
+
+/** Match a single file or directory name against a pattern without `/`, a character at a
+ * time so `?` and sets match one whole character rather than one byte of it. */
+fn match_segment(pattern: &[char], name: &[char]) -> bool {
+    match pattern.split_first() {
+        None => name.is_empty(),
+        Some(('*', rest)) => (0..=name.len()).any(|skip| match_segment(rest, &name[skip..])),
+        Some(('?', rest)) => !name.is_empty() && match_segment(rest, &name[1..]),
+        Some(('[', rest)) => {
+            // A `]` right after `[` or `[!` is part of the set, as in the shell
+            let skip = if rest.first() == Some(&'!') { 2 } else { 1 };
+            let Some(end) = rest.iter().skip(skip).position(|&c| c == ']') else {
+                return false;
+            };
+            let end = end + skip;
+            let Some((&c, others)) = name.split_first() else {
+                return false;
+            };
+            match_class(&rest[..end], c) && match_segment(&rest[end + 1..], others)
+        }
+        Some((&p, rest)) => name.first() == Some(&p) && match_segment(rest, &name[1..]),
+    }
+}
+
+/** Whether `c` is in a bracket expression like `a-z0`, or not in it if it starts with `!`. */
+fn match_class(class: &[char], c: char) -> bool {
+    let (negated, class) = match class.split_first() {
+        Some(('!', rest)) => (true, rest),
+        _ => (false, class),
+    };
+    let mut found = false;
+    let mut i = 0;
+    while i < class.len() {
+        if i + 2 < class.len() && class[i + 1] == '-' {
+            found |= (class[i]..=class[i + 2]).contains(&c);
+            i += 3;
+        } else {
+            found |= class[i] == c;
+            i += 1;
+        }
+    }
+    found != negated
+}
+
+impl FromStr for Glob {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.is_empty() {
+            return Err("the pattern is empty".to_string());
+        }
+        let mut rest = s;
+        while let Some(start) = rest.find('[') {
+            let after = &rest[start + 1..];
+            // The first character of the set, after any `!`, may be `]`
+            let skip = if after.starts_with('!') { 2 } else { 1 };
+            let set = after
+                .char_indices()
+                .nth(skip)
+                .map(|(index, _)| &after[index..]);
+            match set.and_then(|set| set.find(']').map(|end| &set[end + 1..])) {
+                Some(others) => rest = others,
+                None => return Err(format!("unclosed '[' in {s}")),
+            }
+        }
+        Ok(Glob {
+            pattern: s.to_string(),
+        })
+    }
+}
+
+impl Display for Glob {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.pattern)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, path: &str) -> bool {
+        let glob: Glob = pattern.parse().unwrap();
+        return glob.matches(Path::new(path));
+    }
+
+    #[test]
+    fn patterns_without_a_slash_match_the_file_name_at_any_depth() {
+        assert!(matches("*.jpg", "a.jpg"));
+        assert!(matches("*.jpg", "2023/summer/a.jpg"));
+        assert!(!matches("*.jpg", "a.jpg.png"));
+        assert!(!matches("*.jpg", "a.JPG"));
+        assert!(matches("IMG_????.*", "camera/IMG_0042.heic"));
+        assert!(!matches("IMG_????.*", "camera/IMG_42.heic"));
+    }
+
+    #[test]
+    fn double_stars_span_any_number_of_directories() {
+        assert!(matches("**/*.jpg", "a.jpg"));
+        assert!(matches("**/*.jpg", "2023/summer/a.jpg"));
+        assert!(!matches("**/*.jpg", "2023/summer/a.png"));
+        assert!(matches("vacation-*/**", "vacation-2023/a.jpg"));
+        assert!(matches("vacation-*/**", "vacation-2023/day 1/a.jpg"));
+        assert!(!matches("vacation-*/**", "work/vacation-2023/a.jpg"));
+        assert!(!matches("vacation-*/**", "vacation.jpg"));
+        assert!(matches("**/private/**", "2023/private/a.jpg"));
+        assert!(matches("**/private/**", "private/a.jpg"));
+        assert!(!matches("**/private/**", "2023/private.jpg"));
+    }
+
+    #[test]
+    fn single_stars_stay_within_a_directory() {
+        assert!(matches("2023/*.jpg", "2023/a.jpg"));
+        assert!(!matches("2023/*.jpg", "2023/summer/a.jpg"));
+        assert!(!matches("2023/*.jpg", "a.jpg"));
+    }
+
+    #[test]
+    fn sets_match_one_character_from_them() {
+        assert!(matches("[abc].jpg", "b.jpg"));
+        assert!(!matches("[abc].jpg", "d.jpg"));
+        assert!(matches("[0-9]*.jpg", "7 dwarfs.jpg"));
+        assert!(!matches("[0-9]*.jpg", "dwarfs.jpg"));
+        assert!(matches("[a-cx]?.jpg", "x1.jpg"));
+        // A `]` first in the set is part of it
+        assert!(matches("[]]*.jpg", "].jpg"));
+        assert!(matches("[!]]*.jpg", "a.jpg"));
+        assert!(!matches("[!]]*.jpg", "].jpg"));
+    }
+
+    #[test]
+    fn negated_sets_match_anything_else() {
+        assert!(matches("[!0-9]*.jpg", "dwarfs.jpg"));
+        assert!(!matches("[!0-9]*.jpg", "7 dwarfs.jpg"));
+        assert!(!matches("[!abc].jpg", "a.jpg"));
+        assert!(!matches("[!abc].jpg", ".jpg"));
+    }
+
+    #[test]
+    fn characters_beyond_ascii_are_matched_whole() {
+        assert!(matches("caf?.jpg", "café.jpg"));
+        assert!(matches("caf[éè].jpg", "café.jpg"));
+        assert!(!matches("caf[!é].jpg", "café.jpg"));
+        assert!(matches("[à-ÿ]t[à-ÿ].jpg", "été.jpg"));
+        assert!(!matches("[à-ÿ]*.jpg", "ete.jpg"));
+        assert!(matches("???.jpg", "日本語.jpg"));
+        assert!(matches("**/*.jpg", "Ürlaub/ß.jpg"));
+    }
+
+    #[test]
+    fn malformed_patterns_are_refused() {
+        assert_eq!(
+            "".parse::<Glob>().unwrap_err(),
+            "the pattern is empty".to_string()
+        );
+        assert_eq!(
+            "[a-z.jpg".parse::<Glob>().unwrap_err(),
+            "unclosed '[' in [a-z.jpg".to_string()
+        );
+        assert!("[]".parse::<Glob>().is_err());
+        assert_eq!("[]]".parse::<Glob>().unwrap().to_string(), "[]]");
+    }
+}
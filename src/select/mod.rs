@@ -0,0 +1,290 @@
+use std::{
+    collections::HashSet,
+    ffi::OsStr,
+    fs, io,
+    ops::AddAssign,
+    path::{Path, PathBuf},
+};
+
+use clap::ValueEnum;
+use glob::Glob;
+use log::{debug, warn};
+use rand::{
+    distr::{weighted::WeightedIndex, Distribution},
+    Rng,
+};
+
+use crate::quantize::decode;
+
+pub mod choose;
+pub mod criteria;
+pub mod error;
+pub mod gather;
+pub mod glob;
+pub mod on_this_day;
+pub mod pin;
+pub mod season;
+pub mod weekday;
+pub mod weight;
+
+/** Path given to read the image from stdin instead of a file. */
+pub const STDIN: &str = "-";
+
+/** Extensions of the formats the image crate decodes, in lower case. */
+pub const IMAGE_EXTENSIONS: &[&str] = &[
+    "jpg", "jpeg", "png", "gif", "webp", "bmp", "tif", "tiff", "tga", "pnm", "pbm", "pgm", "ppm",
+    "qoi", "ico", "heic", "heif", "avif",
+];
+
+/** Whether the extension of `path` is one of [IMAGE_EXTENSIONS], in any case. */
+pub fn is_image(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            IMAGE_EXTENSIONS
+                .iter()
+                .any(|known| extension.eq_ignore_ascii_case(known))
+        })
+}
+
+/** Which of the files found in the source directory may be chosen. */
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    /** Keep files without an image extension. */
+    pub all_files: bool,
+    /** If any, only files matching one of these are kept. */
+    pub include: Vec<Glob>,
+    /** Files matching one of these are left out, even if included. */
+    pub exclude: Vec<Glob>,
+    /** Files larger than this many bytes are left out. */
+    pub max_file_size: Option<u64>,
+    /** Images narrower or lower than this width and height, as shown upright, are left out. */
+    pub min_resolution: Option<(u32, u32)>,
+}
+
+/** How many candidates a [Filter] left out, and why. */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Rejected {
+    pub extension: usize,
+    pub patterns: usize,
+    pub size: usize,
+    pub resolution: usize,
+    /** Left out for a weight of 0 rather than by the filter itself. */
+    pub weight: usize,
+}
+
+impl AddAssign for Rejected {
+    fn add_assign(&mut self, other: Rejected) {
+        self.extension += other.extension;
+        self.patterns += other.patterns;
+        self.size += other.size;
+        self.resolution += other.resolution;
+        self.weight += other.weight;
+    }
+}
+
+impl Filter {
+    /** Remove the candidates the filter doesn't allow. Patterns are matched against paths
+     * relative to `dir`. */
+    pub fn apply(&self, dir: &Path, candidates: &mut Vec<Candidate>) -> Rejected {
+        let mut rejected = Rejected::default();
+        candidates.retain(|candidate| {
+            if !self.all_files && !is_image(&candidate.path) {
+                rejected.extension += 1;
+                return false;
+            }
+            let relative = candidate.path.strip_prefix(dir).unwrap_or(&candidate.path);
+            let included =
+                self.include.is_empty() || self.include.iter().any(|g| g.matches(relative));
+            if !included || self.exclude.iter().any(|g| g.matches(relative)) {
+                rejected.patterns += 1;
+                return false;
+            }
+            // Files whose size can't be read are kept, decoding them will tell what's wrong
+            if let Some(limit) = self.max_file_size {
+                let size = fs::metadata(&candidate.path).map_or(0, |metadata| metadata.len());
+                if size > limit {
+                    debug!(
+                        "Leaving out {}, which is {size} bytes, over --max-file-size {limit}",
+                        candidate.path.display()
+                    );
+                    rejected.size += 1;
+                    return false;
+                }
+            }
+            if let Some((min_width, min_height)) = self.min_resolution {
+                match decode::dimensions(&candidate.path) {
+                    Ok((width, height)) if width >= min_width && height >= min_height => {}
+                    Ok((width, height)) => {
+                        debug!(
+                            "Leaving out {}, which is {width}x{height}, below --min-resolution \
+                             {min_width}x{min_height}",
+                            candidate.path.display()
+                        );
+                        rejected.resolution += 1;
+                        return false;
+                    }
+                    Err(error) => {
+                        debug!(
+                            "Leaving out {}, whose size can't be read: {error}",
+                            candidate.path.display()
+                        );
+                        rejected.resolution += 1;
+                        return false;
+                    }
+                }
+            }
+            true
+        });
+        return rejected;
+    }
+
+    /** The patterns as they were given on the command line. */
+    pub fn patterns(&self) -> Vec<String> {
+        let include = self.include.iter().map(|g| format!("--include '{g}'"));
+        let exclude = self.exclude.iter().map(|g| format!("--exclude '{g}'"));
+        include.chain(exclude).collect()
+    }
+}
+
+/** A file that may be chosen for display, along with its relative selection weight. */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Candidate {
+    pub path: PathBuf,
+    pub weight: f64,
+}
+
+impl Candidate {
+    pub fn new(path: PathBuf) -> Candidate {
+        Candidate { path, weight: 1.0 }
+    }
+}
+
+/** How a directory is walked to list candidates. */
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Listing {
+    /** How many levels of subdirectories to look into. With 0 only the directory itself is
+     * listed. */
+    pub max_depth: usize,
+    /** Look into symlinked subdirectories too. Symlinks to files are always followed. */
+    pub follow_symlinks: bool,
+    /** Keep files and subdirectories whose name starts with a dot. */
+    pub include_hidden: bool,
+}
+
+impl Listing {
+    /** The same listing with `max_depth` replaced. */
+    pub fn depth(self, max_depth: usize) -> Listing {
+        Listing { max_depth, ..self }
+    }
+}
+
+/** List the files in a directory as equally weighted candidates, walking it as `listing` says.
+ * The directory itself may be a symlink. The list is sorted by path, as the order of directory
+ * entries depends on the file system. */
+pub fn list_candidates(dir: &Path, listing: Listing) -> Result<Vec<Candidate>, error::SelectError> {
+    let mut candidates = Vec::new();
+    walk(dir, listing, &mut HashSet::new(), &mut candidates)?;
+    candidates.sort_by(|a, b| a.path.cmp(&b.path));
+
+    return Ok(candidates);
+}
+
+/** Add the files in `dir` to `candidates` and descend into its subdirectories. Subdirectories
+ * that can't be read are skipped with a warning, and dangling symlinks with a debug log. */
+fn walk(
+    dir: &Path,
+    listing: Listing,
+    visited: &mut HashSet<PathBuf>,
+    candidates: &mut Vec<Candidate>,
+) -> io::Result<()> {
+    // Symlinks can lead back to a directory that is already being walked
+    if !visited.insert(fs::canonicalize(dir)?) {
+        return Ok(());
+    }
+
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let path = entry.path();
+        let hidden = entry.file_name().as_encoded_bytes().starts_with(b".");
+        if hidden && !listing.include_hidden {
+            continue;
+        }
+        let symlink = entry.file_type()?.is_symlink();
+        // Unlike the entry's file type, this follows symlinks, and fails for dangling ones
+        let metadata = match fs::metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(error) => {
+                debug!("Skipping {}: {error}", path.display());
+                continue;
+            }
+        };
+        if metadata.is_file() {
+            candidates.push(Candidate::new(path));
+        } else if metadata.is_dir() && listing.max_depth > 0 {
+            if symlink && !listing.follow_symlinks {
+                debug!("Skipping {}, a symlinked directory", path.display());
+                continue;
+            }
+            let below = listing.depth(listing.max_depth - 1);
+            if let Err(error) = walk(&path, below, visited, candidates) {
+                warn!("Skipping {}: {error}", path.display());
+            }
+        }
+    }
+
+    return Ok(());
+}
+
+/** How the next file is picked from the pool. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Selection {
+    /// At random, respecting weights
+    Random,
+    /// The most recently modified file, every time
+    Newest,
+    /// The least recently modified file not shown yet, so files go by in date order
+    Oldest,
+    /// The first file by name not shown yet, so files go by in name order
+    Alphabetical,
+}
+
+/** Remove the candidate `selection` picks from the pool and return its path. Files whose
+ * modification time can't be read come last when picking by date. */
+pub fn take(candidates: &mut Vec<Candidate>, selection: Selection, rng: &mut impl Rng) -> PathBuf {
+    let modified = |candidate: &Candidate| match fs::metadata(&candidate.path)
+        .and_then(|metadata| metadata.modified())
+    {
+        Ok(time) => Some(time),
+        Err(error) => {
+            warn!(
+                "Could not read the date of {}: {error}",
+                candidate.path.display()
+            );
+            None
+        }
+    };
+    let indexed = candidates.iter().enumerate();
+    let chosen = match selection {
+        Selection::Random => return take_random(candidates, rng),
+        Selection::Newest => indexed.max_by_key(|(_, c)| modified(c)),
+        Selection::Oldest => indexed.min_by_key(|(_, c)| {
+            let time = modified(c);
+            (time.is_none(), time)
+        }),
+        Selection::Alphabetical => {
+            indexed.min_by_key(|(_, c)| (c.path.file_name().map(OsStr::to_owned), &c.path))
+        }
+    };
+    let index = chosen.map(|(index, _)| index).unwrap();
+    candidates.swap_remove(index).path
+}
+
+/** Remove a candidate from the pool at random, respecting weights, and return its path. */
+pub fn take_random(candidates: &mut Vec<Candidate>, rng: &mut impl Rng) -> PathBuf {
+    let index = match WeightedIndex::new(candidates.iter().map(|c| c.weight)) {
+        Ok(distribution) => distribution.sample(rng),
+        Err(_) => rng.random_range(0..candidates.len()),
+    };
+    candidates.swap_remove(index).path
+}
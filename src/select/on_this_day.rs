@@ -0,0 +1,243 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufReader},
+    path::Path,
+};
+
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    select::{season::MonthDay, Candidate},
+    state::{read_json, shuffle::FileKey, write_atomic},
+};
+
+pub const FILE_NAME: &str = "exif-dates.json";
+
+/** The day of the year each photo was taken, so that the EXIF data of a large library isn't read
+ * again on every run. A file that is edited or moved gets a new key and is read again. */
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Index {
+    files: Vec<Entry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Entry {
+    #[serde(flatten)]
+    file: FileKey,
+    /** `None` for a file without an EXIF date, which is remembered as well. */
+    taken: Option<MonthDay>,
+}
+
+impl Index {
+    /** Load the index. A missing or unreadable file counts as an empty index. */
+    fn load(path: &Path) -> Index {
+        read_json(path)
+    }
+
+    fn save(&self, path: &Path) -> io::Result<()> {
+        write_atomic(path, serde_json::to_string(self)?.as_bytes())
+    }
+}
+
+/** The month and day of `DateTimeOriginal` in the EXIF data of the file at `path`. */
+fn read_taken(path: &Path) -> Option<MonthDay> {
+    let file = File::open(path).ok()?;
+    let exif = exif::Reader::new()
+        .read_from_container(&mut BufReader::new(file))
+        .ok()?;
+    let field = exif.get_field(exif::Tag::DateTimeOriginal, exif::In::PRIMARY)?;
+    let exif::Value::Ascii(values) = &field.value else {
+        return None;
+    };
+    let taken = exif::DateTime::from_ascii(values.first()?).ok()?;
+    return MonthDay::new(taken.month.into(), taken.day.into());
+}
+
+/** Narrow the `candidates` down to the photos taken on `today` in any year, going by their EXIF
+ * dates. Files without one are left out. The dates are kept in the index at `cache`, if given.
+ * If no photo was taken on this day, all candidates are returned so there is still something to
+ * show. */
+pub fn narrow(cache: Option<&Path>, today: MonthDay, candidates: Vec<Candidate>) -> Vec<Candidate> {
+    let mut known: HashMap<FileKey, Option<MonthDay>> = cache
+        .map(Index::load)
+        .unwrap_or_default()
+        .files
+        .into_iter()
+        .map(|entry| (entry.file, entry.taken))
+        .collect();
+    let mut read = 0;
+    let mut taken_today = Vec::new();
+    for candidate in &candidates {
+        let key = FileKey::of(&candidate.path);
+        let taken = match key.as_ref().and_then(|key| known.get(key)) {
+            Some(&taken) => taken,
+            None => {
+                let taken = read_taken(&candidate.path);
+                if let Some(key) = key {
+                    known.insert(key, taken);
+                }
+                read += 1;
+                taken
+            }
+        };
+        if taken == Some(today) {
+            taken_today.push(candidate.clone());
+        }
+    }
+    debug!("Read the EXIF dates of {read} files");
+
+    if let Some(path) = cache.filter(|_| read > 0) {
+        // Files that have been deleted or changed since are dropped, so the index doesn't grow
+        // forever
+        let files = known
+            .into_iter()
+            .filter(|(key, _)| FileKey::of(&key.path).as_ref() == Some(key))
+            .map(|(file, taken)| Entry { file, taken })
+            .collect();
+        if let Err(error) = (Index { files }).save(path) {
+            warn!("Could not write {}: {error}", path.display());
+        }
+    }
+
+    if taken_today.is_empty() {
+        info!("No photos were taken on {today} in any year, choosing from all of them");
+        return candidates;
+    }
+    info!(
+        "{} of {} files were taken on {today} in some year",
+        taken_today.len(),
+        candidates.len()
+    );
+    return taken_today;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf};
+
+    use super::*;
+
+    fn today() -> MonthDay {
+        MonthDay::new(3, 14).unwrap()
+    }
+
+    /** Empty files named `names` in `dir`, which have no EXIF date of their own. */
+    fn photos(dir: &Path, names: &[&str]) -> Vec<Candidate> {
+        names
+            .iter()
+            .map(|name| {
+                let path = dir.join(name);
+                fs::write(&path, b"").unwrap();
+                Candidate::new(path)
+            })
+            .collect()
+    }
+
+    fn entry(path: &Path, taken: Option<MonthDay>) -> Entry {
+        Entry {
+            file: FileKey::of(path).unwrap(),
+            taken,
+        }
+    }
+
+    fn names(candidates: &[Candidate]) -> Vec<&str> {
+        candidates
+            .iter()
+            .map(|c| c.path.file_name().unwrap().to_str().unwrap())
+            .collect()
+    }
+
+    /** The files in the index at `path` by name, with the day they were taken. */
+    fn indexed(path: &Path) -> Vec<(String, Option<MonthDay>)> {
+        let mut files: Vec<(String, Option<MonthDay>)> = Index::load(path)
+            .files
+            .into_iter()
+            .map(|entry| {
+                let name = entry.file.path.file_name().unwrap().to_str().unwrap();
+                (name.to_string(), entry.taken)
+            })
+            .collect();
+        files.sort_by(|a, b| a.0.cmp(&b.0));
+        return files;
+    }
+
+    #[test]
+    fn dates_in_the_cache_are_not_read_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let candidates = photos(dir.path(), &["a.jpg", "b.jpg"]);
+        let cache = dir.path().join(FILE_NAME);
+        let files = vec![
+            entry(&candidates[0].path, Some(today())),
+            entry(&candidates[1].path, MonthDay::new(7, 1)),
+        ];
+        Index { files }.save(&cache).unwrap();
+        let before = fs::read(&cache).unwrap();
+
+        // a.jpg has no EXIF date, so only the cache can say it was taken today
+        let narrowed = narrow(Some(&cache), today(), candidates);
+        assert_eq!(names(&narrowed), ["a.jpg"]);
+        // Nothing was read, so the cache is left as it was
+        assert_eq!(fs::read(&cache).unwrap(), before);
+    }
+
+    #[test]
+    fn dates_missing_from_the_cache_are_read_and_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let candidates = photos(dir.path(), &["a.jpg", "b.jpg"]);
+        let cache = dir.path().join(FILE_NAME);
+        Index {
+            files: vec![entry(&candidates[0].path, Some(today()))],
+        }
+        .save(&cache)
+        .unwrap();
+
+        let narrowed = narrow(Some(&cache), today(), candidates);
+        assert_eq!(names(&narrowed), ["a.jpg"]);
+        // b.jpg was read and has no date, which is remembered too
+        assert_eq!(
+            indexed(&cache),
+            [
+                ("a.jpg".to_string(), Some(today())),
+                ("b.jpg".to_string(), None)
+            ]
+        );
+    }
+
+    #[test]
+    fn stale_entries_are_pruned() {
+        let dir = tempfile::tempdir().unwrap();
+        let candidates = photos(dir.path(), &["a.jpg", "edited.jpg", "deleted.jpg"]);
+        let cache = dir.path().join(FILE_NAME);
+        let files = candidates
+            .iter()
+            .map(|c| entry(&c.path, Some(today())))
+            .collect();
+        Index { files }.save(&cache).unwrap();
+        fs::write(&candidates[1].path, b"edited").unwrap();
+        fs::remove_file(&candidates[2].path).unwrap();
+
+        let candidates: Vec<Candidate> = candidates[..2].to_vec();
+        let narrowed = narrow(Some(&cache), today(), candidates);
+        assert_eq!(names(&narrowed), ["a.jpg"]);
+        // The edited file was read again, and the deleted one is gone
+        assert_eq!(
+            indexed(&cache),
+            [
+                ("a.jpg".to_string(), Some(today())),
+                ("edited.jpg".to_string(), None)
+            ]
+        );
+    }
+
+    #[test]
+    fn without_photos_from_today_all_are_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let candidates = photos(dir.path(), &["a.jpg", "b.jpg"]);
+        let paths: Vec<PathBuf> = candidates.iter().map(|c| c.path.clone()).collect();
+        let narrowed = narrow(None, today(), candidates);
+        let narrowed: Vec<PathBuf> = narrowed.into_iter().map(|c| c.path).collect();
+        assert_eq!(narrowed, paths);
+    }
+}
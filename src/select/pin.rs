@@ -0,0 +1,179 @@
+use std::{
+    ffi::OsStr,
+    fs,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use log::warn;
+
+const PIN_NAME: &str = "pinned";
+
+/** A `pinned.<ext>` file or `pinned` symlink in the source directory. While it exists, it is
+ * displayed instead of a randomly chosen file. */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pin {
+    pub path: PathBuf,
+    /** Modification time of the pinned file, so that replacing it counts as a new pin. */
+    modified: Option<SystemTime>,
+}
+
+/** Look for a pin in `dir`. Pins that can't be read, such as dangling symlinks, are ignored
+ * with a warning. */
+pub fn find(dir: &Path) -> Option<Pin> {
+    let mut paths: Vec<PathBuf> = fs::read_dir(dir)
+        .ok()?
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.file_stem() == Some(OsStr::new(PIN_NAME)))
+        .collect();
+    paths.sort();
+
+    for path in paths {
+        // Follows symlinks, so a dangling one fails here
+        match fs::metadata(&path) {
+            Ok(metadata) if metadata.is_file() => {
+                let modified = metadata.modified().ok();
+                return Some(Pin { path, modified });
+            }
+            Ok(_) => warn!("Ignoring pin {}: not a file", path.display()),
+            Err(error) => warn!("Ignoring pin {}: {error}", path.display()),
+        }
+    }
+
+    return None;
+}
+
+/** What a run does about pins. */
+#[derive(Debug, PartialEq, Eq)]
+pub enum Pinned {
+    /** Nothing is pinned, so an image is chosen as usual. */
+    None,
+    /** A pin that isn't on the panel yet, shown instead of a chosen image. */
+    Show(Pin),
+    /** The pin that is already on the panel, which is left as it is. */
+    Unchanged(Pin),
+}
+
+/** Look for a pin in each of `dirs` in turn, the first one found taking precedence, and compare
+ * it with `shown`, the pin on the panel if there is one. */
+pub fn check<S: AsRef<Path>>(dirs: &[S], shown: Option<&Pin>) -> Pinned {
+    match dirs.iter().find_map(|dir| find(dir.as_ref())) {
+        Some(pin) if shown == Some(&pin) => Pinned::Unchanged(pin),
+        Some(pin) => Pinned::Show(pin),
+        None => Pinned::None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{os::unix::fs::symlink, time::Duration};
+
+    use super::*;
+
+    fn touch(path: &Path) {
+        fs::write(path, b"").unwrap();
+    }
+
+    fn pinned(dirs: &[&Path], shown: Option<&Pin>) -> Option<PathBuf> {
+        match check(dirs, shown) {
+            Pinned::Show(pin) => Some(pin.path),
+            Pinned::Unchanged(pin) => panic!("{} was left as it is", pin.path.display()),
+            Pinned::None => None,
+        }
+    }
+
+    #[test]
+    fn nothing_is_pinned_without_a_pin() {
+        let dir = tempfile::tempdir().unwrap();
+        touch(&dir.path().join("beach.jpg"));
+        touch(&dir.path().join("unpinned.jpg"));
+        touch(&dir.path().join("pinned-old.jpg"));
+        assert_eq!(check(&[dir.path()], None), Pinned::None);
+        assert_eq!(check(&[dir.path().join("missing")], None), Pinned::None);
+    }
+
+    #[test]
+    fn a_pinned_file_is_shown() {
+        let dir = tempfile::tempdir().unwrap();
+        touch(&dir.path().join("beach.jpg"));
+        touch(&dir.path().join("pinned.jpg"));
+        let pin = pinned(&[dir.path()], None);
+        assert_eq!(pin, Some(dir.path().join("pinned.jpg")));
+    }
+
+    #[test]
+    fn a_pinned_symlink_is_shown() {
+        let (dir, photos) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        touch(&photos.path().join("guests.jpg"));
+        symlink(photos.path().join("guests.jpg"), dir.path().join("pinned")).unwrap();
+        let pin = pinned(&[dir.path()], None);
+        assert_eq!(pin, Some(dir.path().join("pinned")));
+    }
+
+    #[test]
+    fn broken_pins_are_ignored() {
+        let dir = tempfile::tempdir().unwrap();
+        symlink(dir.path().join("gone.jpg"), dir.path().join("pinned")).unwrap();
+        fs::create_dir(dir.path().join("pinned.d")).unwrap();
+        assert_eq!(check(&[dir.path()], None), Pinned::None);
+
+        // A pin that can be read takes their place
+        touch(&dir.path().join("pinned.png"));
+        let pin = pinned(&[dir.path()], None);
+        assert_eq!(pin, Some(dir.path().join("pinned.png")));
+    }
+
+    #[test]
+    fn the_first_pin_takes_precedence() {
+        let (first, second) = (tempfile::tempdir().unwrap(), tempfile::tempdir().unwrap());
+        touch(&second.path().join("pinned.jpg"));
+        let dirs = [first.path(), second.path()];
+        assert_eq!(pinned(&dirs, None), Some(second.path().join("pinned.jpg")));
+
+        // Across directories by their order on the command line, within one by name
+        touch(&first.path().join("pinned.png"));
+        touch(&first.path().join("pinned.jpg"));
+        assert_eq!(pinned(&dirs, None), Some(first.path().join("pinned.jpg")));
+    }
+
+    #[test]
+    fn a_pin_on_the_panel_is_left_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pinned.jpg");
+        touch(&path);
+        let Pinned::Show(shown) = check(&[dir.path()], None) else {
+            panic!("nothing was pinned");
+        };
+        assert_eq!(
+            check(&[dir.path()], Some(&shown)),
+            Pinned::Unchanged(shown.clone())
+        );
+
+        // Replacing the file pins the new one
+        let later = SystemTime::now() + Duration::from_secs(60);
+        fs::File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        assert_eq!(pinned(&[dir.path()], Some(&shown)), Some(path));
+    }
+
+    #[test]
+    fn removing_the_pin_resumes_selection() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("pinned.jpg");
+        touch(&path);
+        let Pinned::Show(shown) = check(&[dir.path()], None) else {
+            panic!("nothing was pinned");
+        };
+        fs::remove_file(&path).unwrap();
+        assert_eq!(check(&[dir.path()], Some(&shown)), Pinned::None);
+
+        // Pinning it again shows it again, as something else was chosen in the meantime
+        touch(&path);
+        assert_eq!(pinned(&[dir.path()], None), Some(path));
+    }
+}
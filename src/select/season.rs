@@ -0,0 +1,396 @@
+use std::{
+    collections::HashSet,
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use chrono::Datelike;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::select::{error::SelectError, Candidate, Listing};
+
+const DEFAULT_WEIGHT: f64 = 4.0;
+
+/** A day of the year without a year, written as `MM-DD` in the season map. */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Deserialize, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct MonthDay {
+    month: u32,
+    day: u32,
+}
+
+impl MonthDay {
+    pub fn new(month: u32, day: u32) -> Option<MonthDay> {
+        let days_in_month = match month {
+            2 => 29,
+            4 | 6 | 9 | 11 => 30,
+            1..=12 => 31,
+            _ => return None,
+        };
+        (1..=days_in_month)
+            .contains(&day)
+            .then_some(MonthDay { month, day })
+    }
+
+    pub fn from_date(date: &impl Datelike) -> MonthDay {
+        MonthDay {
+            month: date.month(),
+            day: date.day(),
+        }
+    }
+}
+
+impl Display for MonthDay {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:02}-{:02}", self.month, self.day)
+    }
+}
+
+impl From<MonthDay> for String {
+    fn from(value: MonthDay) -> String {
+        value.to_string()
+    }
+}
+
+impl TryFrom<String> for MonthDay {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value
+            .split_once('-')
+            .and_then(|(month, day)| MonthDay::new(month.parse().ok()?, day.parse().ok()?))
+            .ok_or_else(|| format!("`{value}` is not a valid MM-DD date"))
+    }
+}
+
+/** A subdirectory that is preferred between two days of the year, both inclusive. */
+#[derive(Debug, Deserialize)]
+pub struct Season {
+    pub dir: String,
+    pub from: MonthDay,
+    pub to: MonthDay,
+    #[serde(default = "default_weight")]
+    pub weight: f64,
+}
+
+fn default_weight() -> f64 {
+    DEFAULT_WEIGHT
+}
+
+impl Season {
+    /** Whether the season covers the given day, wrapping around the new year if `to < from`. */
+    pub fn contains(&self, day: MonthDay) -> bool {
+        if self.from <= self.to {
+            self.from <= day && day <= self.to
+        } else {
+            day >= self.from || day <= self.to
+        }
+    }
+}
+
+/** Contents of the `--season-map` file. */
+#[derive(Debug, Deserialize)]
+pub struct SeasonMap {
+    /** Only show files from matching seasons instead of merely boosting them */
+    #[serde(default)]
+    pub strict: bool,
+    #[serde(default, rename = "season")]
+    pub seasons: Vec<Season>,
+}
+
+impl SeasonMap {
+    pub fn load(path: &Path) -> Result<SeasonMap, SelectError> {
+        let contents = fs::read_to_string(path)?;
+        return Ok(toml::from_str(&contents)?);
+    }
+
+    /** Seasons that cover the given day. */
+    pub fn active(&self, day: MonthDay) -> Vec<&Season> {
+        self.seasons.iter().filter(|s| s.contains(day)).collect()
+    }
+
+    /** Weigh candidates by the seasons active on `day`.
+     *
+     * Candidates inside an active season's subdirectory of `dir` get that season's weight
+     * (the largest one if several overlap). In strict mode, all other candidates are dropped.
+     * The pool is returned unchanged if nothing is in season. */
+    pub fn apply(&self, day: MonthDay, dir: &Path, candidates: Vec<Candidate>) -> Vec<Candidate> {
+        let active = self.active(day);
+        if active.is_empty() {
+            return candidates;
+        }
+
+        let weighted: Vec<(Candidate, Option<f64>)> = candidates
+            .into_iter()
+            .map(|candidate| {
+                let weight = active
+                    .iter()
+                    .filter(|s| candidate.path.starts_with(dir.join(&s.dir)))
+                    .map(|s| s.weight)
+                    .reduce(f64::max);
+                (candidate, weight)
+            })
+            .collect();
+
+        if self.strict && weighted.iter().any(|(_, weight)| weight.is_some()) {
+            return weighted
+                .into_iter()
+                .filter_map(|(candidate, weight)| {
+                    weight.map(|weight| Candidate {
+                        weight,
+                        ..candidate
+                    })
+                })
+                .collect();
+        }
+
+        return weighted
+            .into_iter()
+            .map(|(candidate, weight)| Candidate {
+                weight: weight.unwrap_or(candidate.weight),
+                ..candidate
+            })
+            .collect();
+    }
+
+    /** Add the contents of the subdirectories of `dir` for the seasons active on `day` to the
+     * pool, listed as `listing` says but without their own subdirectories. */
+    pub fn extend_candidates(
+        &self,
+        day: MonthDay,
+        dir: &Path,
+        listing: Listing,
+        candidates: &mut Vec<Candidate>,
+    ) {
+        let mut dirs: Vec<&str> = self.active(day).iter().map(|s| s.dir.as_str()).collect();
+        dirs.sort();
+        dirs.dedup();
+
+        // Season directories are already in the pool when the source directory is walked
+        // recursively
+        let mut known: HashSet<PathBuf> = candidates.iter().map(|c| c.path.clone()).collect();
+        for season_dir in dirs {
+            match super::list_candidates(&dir.join(season_dir), listing.depth(0)) {
+                Ok(files) => {
+                    candidates.extend(files.into_iter().filter(|c| known.insert(c.path.clone())))
+                }
+                Err(error) => warn!("Skipping season directory {season_dir}: {error}"),
+            }
+        }
+    }
+
+    /** Log which seasons are active on `day`. */
+    pub fn log_active(&self, day: MonthDay) {
+        let active: Vec<&str> = self.active(day).iter().map(|s| s.dir.as_str()).collect();
+        if active.is_empty() {
+            info!("No season is active, choosing from the whole pool");
+        } else {
+            info!("Active seasons: {}", active.join(", "));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn day(text: &str) -> MonthDay {
+        MonthDay::try_from(text.to_string()).unwrap()
+    }
+
+    fn season(dir: &str, from: &str, to: &str, weight: f64) -> Season {
+        Season {
+            dir: dir.to_string(),
+            from: day(from),
+            to: day(to),
+            weight,
+        }
+    }
+
+    /** Winter across the new year, and Christmas within it. */
+    fn season_map(strict: bool) -> SeasonMap {
+        SeasonMap {
+            strict,
+            seasons: vec![
+                season("winter", "12-01", "02-29", 2.0),
+                season("christmas", "12-20", "12-26", 8.0),
+                season("summer", "06-21", "09-22", 4.0),
+            ],
+        }
+    }
+
+    fn active<'a>(map: &'a SeasonMap, today: &str) -> Vec<&'a str> {
+        map.active(day(today))
+            .iter()
+            .map(|s| s.dir.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn month_days_parse_and_print() {
+        assert_eq!(day("2-29").to_string(), "02-29");
+        assert_eq!(day("12-01"), MonthDay::new(12, 1).unwrap());
+        for bad in ["02-30", "13-01", "00-10", "04-31", "1201", "12-xx", ""] {
+            assert!(MonthDay::try_from(bad.to_string()).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn seasons_wrap_around_the_new_year() {
+        let winter = season("winter", "12-01", "02-29", 1.0);
+        for today in ["12-01", "12-31", "01-01", "02-28", "02-29"] {
+            assert!(winter.contains(day(today)), "{today}");
+        }
+        for today in ["11-30", "03-01", "07-15"] {
+            assert!(!winter.contains(day(today)), "{today}");
+        }
+
+        let summer = season("summer", "06-21", "09-22", 1.0);
+        assert!(summer.contains(day("06-21")) && summer.contains(day("09-22")));
+        assert!(!summer.contains(day("06-20")) && !summer.contains(day("09-23")));
+
+        let one_day = season("birthday", "04-01", "04-01", 1.0);
+        assert!(one_day.contains(day("04-01")) && !one_day.contains(day("04-02")));
+    }
+
+    #[test]
+    fn overlapping_seasons_are_all_active() {
+        let map = season_map(false);
+        assert_eq!(active(&map, "12-10"), ["winter"]);
+        assert_eq!(active(&map, "12-24"), ["winter", "christmas"]);
+        assert_eq!(active(&map, "01-05"), ["winter"]);
+        assert!(active(&map, "04-01").is_empty());
+    }
+
+    fn candidates(dir: &Path, files: &[&str]) -> Vec<Candidate> {
+        files
+            .iter()
+            .map(|file| Candidate::new(dir.join(file)))
+            .collect()
+    }
+
+    fn weights(dir: &Path, candidates: &[Candidate]) -> Vec<(String, f64)> {
+        candidates
+            .iter()
+            .map(|c| {
+                let path = c.path.strip_prefix(dir).unwrap();
+                (path.to_str().unwrap().to_string(), c.weight)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn overlapping_seasons_weigh_with_the_largest() {
+        let dir = Path::new("/photos");
+        let files = ["a.jpg", "winter/b.jpg", "christmas/c.jpg", "summer/d.jpg"];
+        let map = season_map(false);
+
+        let weighed = map.apply(day("12-24"), dir, candidates(dir, &files));
+        assert_eq!(
+            weights(dir, &weighed),
+            [
+                ("a.jpg".to_string(), 1.0),
+                ("winter/b.jpg".to_string(), 2.0),
+                ("christmas/c.jpg".to_string(), 8.0),
+                ("summer/d.jpg".to_string(), 1.0),
+            ]
+        );
+
+        // Christmas nested in winter gets the larger of the two
+        let nested = SeasonMap {
+            strict: false,
+            seasons: vec![
+                season("winter", "12-01", "02-29", 2.0),
+                season("winter/christmas", "12-20", "12-26", 8.0),
+            ],
+        };
+        let files = ["winter/b.jpg", "winter/christmas/c.jpg"];
+        let weighed = nested.apply(day("12-24"), dir, candidates(dir, &files));
+        assert_eq!(weighed[1].weight, 8.0);
+        let weighed = nested.apply(day("01-02"), dir, candidates(dir, &files));
+        assert_eq!(weighed[1].weight, 2.0);
+    }
+
+    #[test]
+    fn strict_seasons_drop_the_rest() {
+        let dir = Path::new("/photos");
+        let files = ["a.jpg", "winter/b.jpg", "christmas/c.jpg", "summer/d.jpg"];
+        let map = season_map(true);
+
+        let weighed = map.apply(day("01-05"), dir, candidates(dir, &files));
+        assert_eq!(weights(dir, &weighed), [("winter/b.jpg".to_string(), 2.0)]);
+        // Out of season, nothing is dropped
+        let weighed = map.apply(day("04-01"), dir, candidates(dir, &files));
+        assert_eq!(weighed.len(), files.len());
+        // Nor when the active season has no files
+        let weighed = map.apply(day("07-01"), dir, candidates(dir, &["a.jpg"]));
+        assert_eq!(weights(dir, &weighed), [("a.jpg".to_string(), 1.0)]);
+    }
+
+    #[test]
+    fn only_active_seasons_extend_the_pool() {
+        let dir = tempfile::tempdir().unwrap();
+        for file in ["a.jpg", "winter/b.jpg", "christmas/c.jpg", "summer/d.jpg"] {
+            let path = dir.path().join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, b"").unwrap();
+        }
+        let map = season_map(false);
+        let extended = |today: &str| {
+            let mut pool = candidates(dir.path(), &["a.jpg"]);
+            map.extend_candidates(day(today), dir.path(), Listing::default(), &mut pool);
+            let mut files: Vec<String> = weights(dir.path(), &pool)
+                .into_iter()
+                .map(|(file, _)| file)
+                .collect();
+            files.sort();
+            files
+        };
+        assert_eq!(
+            extended("12-24"),
+            ["a.jpg", "christmas/c.jpg", "winter/b.jpg"]
+        );
+        assert_eq!(extended("01-05"), ["a.jpg", "winter/b.jpg"]);
+        assert_eq!(extended("04-01"), ["a.jpg"]);
+
+        // Files already in the pool from walking recursively aren't added twice
+        let mut pool = candidates(dir.path(), &["a.jpg", "winter/b.jpg"]);
+        map.extend_candidates(day("01-05"), dir.path(), Listing::default(), &mut pool);
+        assert_eq!(pool.len(), 2);
+    }
+
+    #[test]
+    fn season_maps_load_from_toml() {
+        let map: SeasonMap = toml::from_str(
+            r#"
+            strict = true
+
+            [[season]]
+            dir = "winter"
+            from = "12-01"
+            to = "02-29"
+
+            [[season]]
+            dir = "summer"
+            from = "06-21"
+            to = "09-22"
+            weight = 10
+            "#,
+        )
+        .unwrap();
+        assert!(map.strict);
+        assert_eq!(map.seasons[0].weight, DEFAULT_WEIGHT);
+        assert_eq!(map.seasons[1].weight, 10.0);
+        assert_eq!(map.seasons[0].to, day("02-29"));
+
+        let bad = toml::from_str::<SeasonMap>(
+            "[[season]]\ndir = \"x\"\nfrom = \"02-30\"\nto = \"03-01\"",
+        );
+        assert!(bad
+            .unwrap_err()
+            .to_string()
+            .contains("`02-30` is not a valid MM-DD date"));
+    }
+}
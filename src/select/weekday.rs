@@ -0,0 +1,140 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use chrono::Weekday;
+use log::{info, warn};
+
+use crate::select::{list_candidates, Candidate, Listing};
+
+/** Short and full names of the days of the week, from Monday. */
+const NAMES: [(&str, &str); 7] = [
+    ("mon", "monday"),
+    ("tue", "tuesday"),
+    ("wed", "wednesday"),
+    ("thu", "thursday"),
+    ("fri", "friday"),
+    ("sat", "saturday"),
+    ("sun", "sunday"),
+];
+
+/** Whether a directory name stands for `weekday`, short or in full and in any case. */
+fn matches(name: &str, weekday: Weekday) -> bool {
+    let (short, full) = NAMES[weekday.num_days_from_monday() as usize];
+    name.eq_ignore_ascii_case(short) || name.eq_ignore_ascii_case(full)
+}
+
+/** The subdirectory of `dir` named after `weekday`, if there is one. */
+fn find_dir(dir: &Path, weekday: Weekday) -> Option<PathBuf> {
+    let entries = fs::read_dir(dir).ok()?;
+    entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.is_dir())
+        .find(|path| {
+            let name = path.file_name().and_then(|name| name.to_str());
+            name.is_some_and(|name| matches(name, weekday))
+        })
+}
+
+/** Narrow the `candidates` listed from `dir` down to the files in its subdirectory for
+ * `weekday`, walking it as `listing` says for `dir`. If there is no such
+ * subdirectory or it has no files, the whole of `dir` is used, subdirectories included. */
+pub fn narrow(
+    dir: &Path,
+    weekday: Weekday,
+    listing: Listing,
+    candidates: Vec<Candidate>,
+) -> Vec<Candidate> {
+    let today = find_dir(dir, weekday).map(|subdir| {
+        let files = list_candidates(&subdir, listing.depth(listing.max_depth.saturating_sub(1)));
+        (subdir, files.unwrap_or_default())
+    });
+    match today {
+        Some((subdir, files)) if !files.is_empty() => {
+            info!("Choosing from {} for {weekday}", subdir.display());
+            return files;
+        }
+        Some((subdir, _)) => warn!(
+            "{} has no files, choosing from all of {}",
+            subdir.display(),
+            dir.display()
+        ),
+        None => warn!(
+            "{} has no subdirectory for {weekday}, choosing from all of it",
+            dir.display()
+        ),
+    }
+    // The weekday directories themselves are only listed when walking recursively
+    if listing.max_depth > 0 {
+        return candidates;
+    }
+    return list_candidates(dir, listing.depth(1)).unwrap_or(candidates);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_match_short_or_full_in_any_case() {
+        for name in ["mon", "Mon", "MON", "monday", "Monday", "MONDAY"] {
+            assert!(matches(name, Weekday::Mon), "{name}");
+        }
+        for name in ["mo", "mond", "mondays", "tue", "Tuesday", " mon", ""] {
+            assert!(!matches(name, Weekday::Mon), "{name}");
+        }
+        assert!(matches("Sun", Weekday::Sun));
+        assert!(matches("wednesday", Weekday::Wed));
+    }
+
+    /** A directory with a file of its own, one for Monday, a full name in capitals for Tuesday,
+     * and an empty one for Wednesday. */
+    fn fixture() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        for file in ["top.jpg", "mon/a.jpg", "mon/deeper/b.jpg", "TUESDAY/c.jpg"] {
+            let path = dir.path().join(file);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, b"").unwrap();
+        }
+        fs::create_dir(dir.path().join("Wed")).unwrap();
+        return dir;
+    }
+
+    fn narrowed(dir: &Path, weekday: Weekday, max_depth: usize) -> Vec<String> {
+        let listing = Listing::default().depth(max_depth);
+        let candidates = list_candidates(dir, listing).unwrap_or_else(|error| panic!("{error}"));
+        narrow(dir, weekday, listing, candidates)
+            .iter()
+            .map(|candidate| candidate.path.strip_prefix(dir).unwrap())
+            .map(|path| path.to_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn narrows_to_the_directory_for_the_day() {
+        let dir = fixture();
+        assert_eq!(narrowed(dir.path(), Weekday::Mon, 0), ["mon/a.jpg"]);
+        assert_eq!(
+            narrowed(dir.path(), Weekday::Mon, usize::MAX),
+            ["mon/a.jpg", "mon/deeper/b.jpg"]
+        );
+        assert_eq!(narrowed(dir.path(), Weekday::Tue, 0), ["TUESDAY/c.jpg"]);
+    }
+
+    #[test]
+    fn falls_back_to_the_whole_directory() {
+        let dir = fixture();
+        let everything = ["TUESDAY/c.jpg", "mon/a.jpg", "mon/deeper/b.jpg", "top.jpg"];
+        // Without a directory for the day, or with an empty one, the weekday directories are
+        // listed too, if only one level deep without --recursive
+        for weekday in [Weekday::Wed, Weekday::Thu] {
+            assert_eq!(
+                narrowed(dir.path(), weekday, 0),
+                ["TUESDAY/c.jpg", "mon/a.jpg", "top.jpg"]
+            );
+            assert_eq!(narrowed(dir.path(), weekday, usize::MAX), everything);
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use std::{
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::select::Candidate;
+
+/** A `--weight` rule: files under `dir`, a subdirectory of the directory they were found in,
+ * are chosen `factor` times as often. */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Weight {
+    pub dir: PathBuf,
+    pub factor: f64,
+}
+
+/** Multiply the weight of each candidate found in `dir` by the factor of every rule whose
+ * subdirectory it is in. */
+pub fn apply(weights: &[Weight], dir: &Path, candidates: &mut [Candidate]) {
+    for candidate in candidates {
+        let relative = candidate.path.strip_prefix(dir).unwrap_or(&candidate.path);
+        for weight in weights.iter().filter(|w| relative.starts_with(&w.dir)) {
+            candidate.weight *= weight.factor;
+        }
+    }
+}
+
+/** Parse `subdir=factor`, such as `favorites=5`. */
+impl FromStr for Weight {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (dir, factor) = s
+            .split_once('=')
+            .filter(|(dir, _)| !dir.is_empty())
+            .ok_or_else(|| format!("`{s}` is not a weight like favorites=5"))?;
+        match factor.parse::<f64>() {
+            Ok(factor) if factor >= 0.0 && factor.is_finite() => Ok(Weight {
+                dir: PathBuf::from(dir),
+                factor,
+            }),
+            _ => Err(format!("`{factor}` is not a factor of 0 or more")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn weight(dir: &str, factor: f64) -> Weight {
+        Weight {
+            dir: PathBuf::from(dir),
+            factor,
+        }
+    }
+
+    #[test]
+    fn weights_parse() {
+        assert_eq!("favorites=5".parse(), Ok(weight("favorites", 5.0)));
+        assert_eq!("old/scans=0.5".parse(), Ok(weight("old/scans", 0.5)));
+        assert_eq!("private=0".parse(), Ok(weight("private", 0.0)));
+    }
+
+    #[test]
+    fn weights_need_a_directory_and_a_factor() {
+        for invalid in ["=3", "favorites", ""] {
+            assert_eq!(
+                invalid.parse::<Weight>(),
+                Err(format!("`{invalid}` is not a weight like favorites=5"))
+            );
+        }
+    }
+
+    #[test]
+    fn factors_are_finite_and_not_negative() {
+        for (invalid, factor) in [("x=-1", "-1"), ("x=inf", "inf"), ("x=NaN", "NaN")] {
+            assert_eq!(
+                invalid.parse::<Weight>(),
+                Err(format!("`{factor}` is not a factor of 0 or more"))
+            );
+        }
+    }
+
+    #[test]
+    fn matching_rules_multiply() {
+        let weights = [
+            weight("photos", 2.0),
+            weight("photos/best", 5.0),
+            weight("other", 3.0),
+            weight("other", 0.5),
+        ];
+        let mut candidates: Vec<Candidate> = [
+            "photos/a.jpg",
+            "photos/best/b.jpg",
+            "other/c.jpg",
+            "d.jpg",
+            // Matched by whole directories, not the start of their name
+            "photos-old/e.jpg",
+        ]
+        .iter()
+        .map(|file| Candidate::new(Path::new("/album").join(file)))
+        .collect();
+        apply(&weights, Path::new("/album"), &mut candidates);
+        let applied: Vec<f64> = candidates.iter().map(|c| c.weight).collect();
+        assert_eq!(applied, [2.0, 10.0, 1.5, 1.0, 1.0]);
+    }
+}
@@ -0,0 +1,434 @@
+use std::{fmt::Display, time::Instant};
+
+use rppal::{gpio::Gpio, i2c::I2c};
+
+use crate::{
+    epd::{self, diagnose::diagnose, error::InkyError, inky::Inky, wiring::Wiring, EPDType},
+    render,
+};
+
+/** Result of one check. */
+pub enum Outcome {
+    Pass(String),
+    Fail {
+        error: String,
+        hint: Option<String>,
+    },
+    /** Not attempted because a check it depends on failed. */
+    Skip(String),
+}
+
+impl Outcome {
+    fn from_error(error: InkyError) -> Outcome {
+        Outcome::Fail {
+            hint: diagnose(&error).map(|diagnosis| diagnosis.to_string()),
+            error: error.to_string(),
+        }
+    }
+
+    fn fail(error: &str, hint: &str) -> Outcome {
+        Outcome::Fail {
+            error: error.to_string(),
+            hint: Some(hint.to_string()),
+        }
+    }
+}
+
+/** The hardware the checks probe. */
+pub trait Backend {
+    fn read_eeprom(&mut self, bus: u8, address: u16) -> Result<EPDType, InkyError>;
+    /** Claim a GPIO pin and release it again. */
+    fn claim_pin(&mut self, pin: u8) -> Result<(), InkyError>;
+    fn open(&mut self, eeprom_address: u16, wiring: Wiring) -> Result<Inky, InkyError>;
+}
+
+/** The real hardware, through rppal. */
+pub struct Hardware;
+
+impl Backend for Hardware {
+    fn read_eeprom(&mut self, bus: u8, address: u16) -> Result<EPDType, InkyError> {
+        let mut i2c = I2c::with_bus(bus)?;
+        return Ok(epd::read_eeprom(&mut i2c, address)?);
+    }
+
+    fn claim_pin(&mut self, pin: u8) -> Result<(), InkyError> {
+        Gpio::new()?.get(pin)?;
+        return Ok(());
+    }
+
+    fn open(&mut self, eeprom_address: u16, wiring: Wiring) -> Result<Inky, InkyError> {
+        Inky::new(eeprom_address, wiring)
+    }
+}
+
+/** What a check does. */
+pub enum Step {
+    Eeprom {
+        bus: u8,
+        address: u16,
+    },
+    Pin(u8),
+    /** Open the panel for the checks after it. */
+    Open {
+        eeprom_address: u16,
+        wiring: Wiring,
+    },
+    /** Reset the controller and watch the busy line, which is on `busy_pin`. */
+    BusyResponds {
+        busy_pin: u8,
+    },
+    SpiWrite,
+    TestPattern,
+}
+
+/** A named step of the self-test. */
+pub struct Check {
+    pub name: String,
+    pub step: Step,
+}
+
+impl Check {
+    fn new(name: &str, step: Step) -> Check {
+        Check {
+            name: name.to_string(),
+            step,
+        }
+    }
+}
+
+/** The checks in the order they run, from the bottom of the stack up. Failures don't stop the
+ * later checks; those that need the panel are skipped if it couldn't be opened. With `full`,
+ * the last check refreshes the panel with a test pattern. */
+pub fn checks(full: bool, eeprom_address: u16, wiring: Wiring) -> Vec<Check> {
+    let pins = Inky::pins(&wiring);
+    let mut checks = vec![Check::new(
+        "EEPROM",
+        Step::Eeprom {
+            bus: wiring.i2c_bus,
+            address: eeprom_address,
+        },
+    )];
+    for (name, pin) in [
+        ("reset", pins.reset),
+        ("busy", pins.busy),
+        ("data/command", pins.data_command),
+        ("chip select", pins.chip_select),
+    ] {
+        checks.push(Check::new(&format!("GPIO {pin} ({name})"), Step::Pin(pin)));
+    }
+    checks.push(Check::new(
+        "Open the panel",
+        Step::Open {
+            eeprom_address,
+            wiring,
+        },
+    ));
+    checks.push(Check::new(
+        "BUSY responds to reset",
+        Step::BusyResponds {
+            busy_pin: pins.busy,
+        },
+    ));
+    checks.push(Check::new("SPI write", Step::SpiWrite));
+    if full {
+        checks.push(Check::new("Test pattern refresh", Step::TestPattern));
+    }
+
+    return checks;
+}
+
+fn outcome<T>(result: Result<T, InkyError>, pass: impl FnOnce(T) -> String) -> Outcome {
+    match result {
+        Ok(value) => Outcome::Pass(pass(value)),
+        Err(error) => Outcome::from_error(error),
+    }
+}
+
+/** Run one step. The panel it opens, or the one it needs, is kept in `inky`. */
+fn run_step(step: &Step, backend: &mut dyn Backend, inky: &mut Option<Inky>) -> Outcome {
+    let needs_panel = matches!(
+        step,
+        Step::BusyResponds { .. } | Step::SpiWrite | Step::TestPattern
+    );
+    if needs_panel && inky.is_none() {
+        return Outcome::Skip("the panel could not be opened".to_string());
+    }
+
+    match *step {
+        Step::Eeprom { bus, address } => {
+            outcome(backend.read_eeprom(bus, address), |eeprom| eeprom.to_string())
+        }
+        Step::Pin(pin) => outcome(backend.claim_pin(pin), |()| "available".to_string()),
+        Step::Open {
+            eeprom_address,
+            wiring,
+        } => outcome(backend.open(eeprom_address, wiring), |opened| {
+            *inky = Some(opened);
+            "claimed SPI and GPIO".to_string()
+        }),
+        Step::BusyResponds { busy_pin } => match inky.as_mut().unwrap().reset() {
+            Ok(true) => Outcome::Pass("the controller signaled busy".to_string()),
+            Ok(false) => Outcome::fail(
+                &format!("GPIO {busy_pin} never went low"),
+                "Check that the panel is seated on the header and that nothing else drives the busy pin.",
+            ),
+            Err(error) => Outcome::from_error(error),
+        },
+        Step::SpiWrite => outcome(inky.as_mut().unwrap().prepare(), |()| {
+            "sent the initialization sequence".to_string()
+        }),
+        Step::TestPattern => {
+            let inky = inky.as_mut().unwrap();
+            let (width, height) = inky.dimensions();
+            let canvas = render::color_bars(width, height);
+            for (ix, px) in canvas.pixels.iter().enumerate() {
+                inky.set_pixel(ix % width, ix / width, *px);
+            }
+            let started = Instant::now();
+            outcome(inky.show(), |()| {
+                format!(
+                    "refreshed in {:.1} s, expect bars of all seven colors",
+                    started.elapsed().as_secs_f64()
+                )
+            })
+        }
+    }
+}
+
+/** Run every check, printing each outcome as it comes in. Returns whether all of them passed. */
+pub fn run(checks: &[Check], backend: &mut dyn Backend) -> bool {
+    run_each(checks, backend, |name, outcome| {
+        println!("{}", Report(name, outcome))
+    })
+}
+
+/** Run every check, handing each outcome to `report` as it comes in. */
+fn run_each(
+    checks: &[Check],
+    backend: &mut dyn Backend,
+    mut report: impl FnMut(&str, &Outcome),
+) -> bool {
+    let mut inky = None;
+    let mut passed = true;
+    for check in checks {
+        let outcome = run_step(&check.step, backend, &mut inky);
+        passed &= matches!(outcome, Outcome::Pass(_));
+        report(&check.name, &outcome);
+    }
+    return passed;
+}
+
+struct Report<'a>(&'a str, &'a Outcome);
+
+impl Display for Report<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let Report(name, outcome) = self;
+        match outcome {
+            Outcome::Pass(detail) => write!(f, "[PASS] {name}: {detail}"),
+            Outcome::Skip(reason) => write!(f, "[SKIP] {name}: {reason}"),
+            Outcome::Fail { error, hint } => {
+                write!(f, "[FAIL] {name}: {error}")?;
+                if let Some(hint) = hint {
+                    write!(f, "\n       {hint}")?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, io};
+
+    use rppal::{gpio, i2c};
+
+    use super::*;
+    use crate::epd::{
+        mock::{FakeClock, MockPanel},
+        Model,
+    };
+
+    /** Hardware where the EEPROM, some pins or the panel can be missing. */
+    struct MockBackend {
+        eeprom: bool,
+        busy_pins: HashSet<u8>,
+        panel: Option<MockPanel>,
+        clock: FakeClock,
+        opened: Vec<u16>,
+    }
+
+    impl MockBackend {
+        fn new(panel: impl FnOnce(&FakeClock) -> MockPanel) -> MockBackend {
+            let clock = FakeClock::new();
+            MockBackend {
+                eeprom: true,
+                busy_pins: HashSet::new(),
+                panel: Some(panel(&clock)),
+                clock,
+                opened: Vec::new(),
+            }
+        }
+    }
+
+    impl Backend for MockBackend {
+        fn read_eeprom(&mut self, _bus: u8, _address: u16) -> Result<EPDType, InkyError> {
+            if !self.eeprom {
+                return Err(i2c::Error::Io(io::Error::other("no acknowledgement")).into());
+            }
+            return Ok(Model::Ac073tc1.epd_type(None, None));
+        }
+
+        fn claim_pin(&mut self, pin: u8) -> Result<(), InkyError> {
+            if self.busy_pins.contains(&pin) {
+                return Err(gpio::Error::PinUsed(pin).into());
+            }
+            return Ok(());
+        }
+
+        fn open(&mut self, eeprom_address: u16, wiring: Wiring) -> Result<Inky, InkyError> {
+            self.opened.push(eeprom_address);
+            let eeprom = self.read_eeprom(wiring.i2c_bus, eeprom_address)?;
+            let Some(panel) = &self.panel else {
+                return Err(gpio::Error::PinUsed(wiring.cs_pin).into());
+            };
+            Inky::with_connector(eeprom, wiring, panel.clone(), self.clock.clone())
+        }
+    }
+
+    fn outcomes(full: bool, backend: &mut MockBackend) -> (bool, Vec<(String, String)>) {
+        let mut outcomes = Vec::new();
+        let checks = checks(full, 0x52, Wiring::default());
+        let passed = run_each(&checks, backend, |name, outcome| {
+            let kind = match outcome {
+                Outcome::Pass(_) => "pass",
+                Outcome::Fail { .. } => "fail",
+                Outcome::Skip(_) => "skip",
+            };
+            outcomes.push((name.to_string(), kind.to_string()));
+        });
+        return (passed, outcomes);
+    }
+
+    fn kinds(outcomes: &[(String, String)]) -> Vec<&str> {
+        outcomes.iter().map(|(_, kind)| kind.as_str()).collect()
+    }
+
+    #[test]
+    fn checks_run_from_the_bottom_up() {
+        let wiring = Wiring::default();
+        let pins = Inky::pins(&wiring);
+        let checks = checks(false, 0x52, wiring);
+        let names: Vec<&str> = checks.iter().map(|check| check.name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "EEPROM",
+                &format!("GPIO {} (reset)", pins.reset),
+                &format!("GPIO {} (busy)", pins.busy),
+                &format!("GPIO {} (data/command)", pins.data_command),
+                &format!("GPIO {} (chip select)", pins.chip_select),
+                "Open the panel",
+                "BUSY responds to reset",
+                "SPI write",
+            ]
+        );
+        assert!(matches!(
+            checks[0].step,
+            Step::Eeprom {
+                bus: 1,
+                address: 0x52
+            }
+        ));
+    }
+
+    #[test]
+    fn full_adds_the_refresh_last() {
+        let checks = checks(true, 0x50, Wiring::default());
+        assert_eq!(checks.len(), 9);
+        assert_eq!(checks[8].name, "Test pattern refresh");
+    }
+
+    #[test]
+    fn a_working_panel_passes_everything() {
+        let mut backend = MockBackend::new(MockPanel::responsive);
+        let (passed, outcomes) = outcomes(true, &mut backend);
+        assert!(passed);
+        assert!(kinds(&outcomes).iter().all(|kind| *kind == "pass"));
+        assert_eq!(backend.opened, [0x52]);
+
+        let panel = backend.panel.unwrap();
+        let commands = panel.command_bytes();
+        assert_eq!(commands.last(), Some(&0x02));
+        assert!(commands.contains(&0x12));
+    }
+
+    #[test]
+    fn checks_that_need_the_panel_are_skipped_without_it() {
+        let mut backend = MockBackend::new(MockPanel::responsive);
+        backend.panel = None;
+        let (passed, outcomes) = outcomes(true, &mut backend);
+        assert!(!passed);
+        assert_eq!(
+            kinds(&outcomes),
+            ["pass", "pass", "pass", "pass", "pass", "fail", "skip", "skip", "skip"]
+        );
+    }
+
+    #[test]
+    fn failures_do_not_stop_later_checks() {
+        let wiring = Wiring::default();
+        let mut backend = MockBackend::new(MockPanel::responsive);
+        backend.eeprom = false;
+        backend.busy_pins.insert(wiring.dc_pin);
+        let (passed, outcomes) = outcomes(false, &mut backend);
+        assert!(!passed);
+        // Opening reads the EEPROM too
+        assert_eq!(
+            kinds(&outcomes),
+            ["fail", "pass", "pass", "fail", "pass", "fail", "skip", "skip"]
+        );
+        assert_eq!(backend.opened, [0x52]);
+    }
+
+    #[test]
+    fn a_silent_busy_line_fails_with_a_hint() {
+        let mut backend = MockBackend::new(MockPanel::new);
+        let checks = checks(false, 0x50, Wiring::default());
+        let mut busy = None;
+        let passed = run_each(&checks, &mut backend, |name, outcome| {
+            if name == "BUSY responds to reset" {
+                busy = Some(Report(name, outcome).to_string());
+            }
+        });
+        assert!(!passed);
+        let busy_pin = Wiring::default().busy_pin;
+        assert_eq!(
+            busy.unwrap(),
+            format!(
+                "[FAIL] BUSY responds to reset: GPIO {busy_pin} never went low\n       \
+                 Check that the panel is seated on the header and that nothing else drives the \
+                 busy pin."
+            )
+        );
+    }
+
+    #[test]
+    fn report_shows_every_outcome() {
+        let pass = Outcome::Pass("available".to_string());
+        assert_eq!(
+            Report("GPIO 17", &pass).to_string(),
+            "[PASS] GPIO 17: available"
+        );
+        let skip = Outcome::Skip("the panel could not be opened".to_string());
+        assert_eq!(
+            Report("SPI write", &skip).to_string(),
+            "[SKIP] SPI write: the panel could not be opened"
+        );
+        let fail = Outcome::Fail {
+            error: "busy".to_string(),
+            hint: None,
+        };
+        assert_eq!(Report("GPIO 8", &fail).to_string(), "[FAIL] GPIO 8: busy");
+    }
+}
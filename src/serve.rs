@@ -0,0 +1,565 @@
+use std::{
+    fmt::Display,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc, Condvar, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::{epd::error::InkyError, error::RunError, report::RunReport};
+
+/** The file in the state directory that uploads are written to before they are displayed. */
+pub const UPLOAD_FILE_NAME: &str = "upload";
+
+/** Seconds a client is asked to wait before trying again after a 503. */
+#[cfg_attr(not(feature = "serve"), allow(dead_code))]
+const RETRY_AFTER: u64 = 60;
+
+/** Requests handled at the same time. Uploads wait for their refresh, and status requests are
+ * quick, so this only turns clients away when something floods the server. */
+#[cfg_attr(not(feature = "serve"), allow(dead_code))]
+const MAX_HANDLERS: usize = 16;
+
+#[cfg_attr(not(feature = "serve"), allow(dead_code))]
+pub enum ServeError {
+    /** Listening on the address failed, e.g. because it is in use. */
+    Listen(String),
+    /** Built without the `serve` feature. */
+    #[cfg(not(feature = "serve"))]
+    Unsupported,
+}
+
+/** The answer to an upload, sent as JSON. */
+#[derive(Debug, Serialize)]
+#[cfg_attr(not(feature = "serve"), allow(dead_code))]
+pub struct Outcome {
+    #[serde(skip)]
+    status: u16,
+    /** `displayed`, `deferred` until quiet hours end, or `failed` */
+    outcome: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    refresh_ms: Option<u64>,
+    /** From receiving the upload until the panel finished refreshing or it failed. */
+    elapsed_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl Outcome {
+    fn failed(status: u16, error: impl Display, received: Instant) -> Outcome {
+        Outcome {
+            status,
+            outcome: "failed",
+            refresh_ms: None,
+            elapsed_ms: received.elapsed().as_millis() as u64,
+            error: Some(error.to_string()),
+        }
+    }
+}
+
+/** An upload waiting to be displayed. */
+pub struct Job {
+    body: Vec<u8>,
+    /** Who sent it, as shown by `/status`. */
+    source: String,
+    received: Instant,
+    /** Gone once the client has been answered. */
+    reply: Option<mpsc::Sender<Outcome>>,
+}
+
+impl Job {
+    /** The uploaded bytes, leaving the job without them. */
+    pub fn take_body(&mut self) -> Vec<u8> {
+        return std::mem::take(&mut self.body);
+    }
+
+    /** Answer the client with the result of displaying the upload. Bad images are the
+     * client's fault, anything else is the server's. */
+    pub fn finish(&mut self, result: &Result<RunReport, RunError>) {
+        let outcome = match result {
+            Ok(report) => Outcome {
+                status: 200,
+                outcome: "displayed",
+                refresh_ms: report.refresh_duration.map(|d| d.as_millis() as u64),
+                elapsed_ms: self.received.elapsed().as_millis() as u64,
+                error: None,
+            },
+            Err(error @ RunError::Quantize(_)) => Outcome::failed(422, error, self.received),
+            Err(error @ RunError::Display(InkyError::Interrupted(_))) => {
+                Outcome::failed(503, error, self.received)
+            }
+            Err(error) => Outcome::failed(500, error, self.received),
+        };
+        self.answer(outcome);
+    }
+
+    /** Answer the client that the upload is held back until quiet hours end. It is displayed
+     * then without telling the client again. */
+    pub fn defer(&mut self) {
+        self.answer(Outcome {
+            status: 202,
+            outcome: "deferred",
+            refresh_ms: None,
+            elapsed_ms: self.received.elapsed().as_millis() as u64,
+            error: None,
+        });
+    }
+
+    fn answer(&mut self, outcome: Outcome) {
+        if let Some(reply) = self.reply.take() {
+            // The client may have hung up in the meantime
+            let _ = reply.send(outcome);
+        }
+    }
+}
+
+/** What `GET /status` answers. */
+#[derive(Debug, Clone, Default, Serialize)]
+#[cfg_attr(not(feature = "serve"), allow(dead_code))]
+pub struct Status {
+    /** When the panel last finished refreshing with an upload. */
+    last_refresh: Option<DateTime<Utc>>,
+    /** Who sent the upload on the panel. */
+    last_source: Option<String>,
+    /** Size of the panel from its EEPROM, once it has been opened. */
+    width: Option<usize>,
+    height: Option<usize>,
+    /** Whether an upload is being rendered or displayed right now. */
+    refreshing: bool,
+}
+
+/** The one upload the queue holds besides the one on its way to the panel. */
+#[derive(Default)]
+enum Pending {
+    #[default]
+    Empty,
+    /** Its body is still being received. */
+    Receiving,
+    Waiting(Job),
+}
+
+/** Uploads waiting for the panel, and what is on it. It only holds one upload, as the panel
+ * can only do one refresh at a time: while one is waiting, others are turned away before their
+ * body is read, so at most two uploads are in memory. The status has a lock of its own, so it
+ * can be read during a refresh. */
+#[derive(Default)]
+pub struct Queue {
+    pending: Mutex<Pending>,
+    ready: Condvar,
+    /** Requests being handled, at most [MAX_HANDLERS]. */
+    handlers: AtomicUsize,
+    status: Mutex<Status>,
+    /** The frame on the panel as a PNG, made once after each refresh rather than per request. */
+    preview: Mutex<Option<Vec<u8>>>,
+}
+
+/** The right to send the next upload to the panel, held while its body is received. Dropping
+ * it without [Reservation::submit] lets the next upload in. */
+#[cfg_attr(not(feature = "serve"), allow(dead_code))]
+struct Reservation<'a>(&'a Queue);
+
+#[cfg_attr(not(feature = "serve"), allow(dead_code))]
+impl Reservation<'_> {
+    /** Queue `body` from `source` for display. The outcome is sent on the returned channel. */
+    fn submit(self, body: Vec<u8>, source: String) -> mpsc::Receiver<Outcome> {
+        let (reply, outcome) = mpsc::channel();
+        let job = Job {
+            body,
+            source,
+            received: Instant::now(),
+            reply: Some(reply),
+        };
+        *self.0.pending.lock().unwrap() = Pending::Waiting(job);
+        self.0.ready.notify_one();
+        return outcome;
+    }
+}
+
+impl Drop for Reservation<'_> {
+    fn drop(&mut self) {
+        let mut pending = self.0.pending.lock().unwrap();
+        if let Pending::Receiving = *pending {
+            *pending = Pending::Empty;
+        }
+    }
+}
+
+/** A request being handled, counted in [Queue::handlers] until it is dropped. */
+#[cfg_attr(not(feature = "serve"), allow(dead_code))]
+struct Handler(Arc<Queue>);
+
+impl Drop for Handler {
+    fn drop(&mut self) {
+        self.0.handlers.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Queue {
+    /** Reserve the place of the next upload, unless another one is already waiting or being
+     * received. */
+    #[cfg_attr(not(feature = "serve"), allow(dead_code))]
+    fn reserve(&self) -> Option<Reservation<'_>> {
+        let mut pending = self.pending.lock().unwrap();
+        if !matches!(*pending, Pending::Empty) {
+            return None;
+        }
+        *pending = Pending::Receiving;
+        return Some(Reservation(self));
+    }
+
+    /** Count a new request, unless [MAX_HANDLERS] are being handled already. */
+    #[cfg_attr(not(feature = "serve"), allow(dead_code))]
+    fn handler(self: &Arc<Self>) -> Option<Handler> {
+        let admitted = self
+            .handlers
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |handlers| {
+                (handlers < MAX_HANDLERS).then_some(handlers + 1)
+            });
+        return admitted.is_ok().then(|| Handler(self.clone()));
+    }
+
+    /** The next upload to display, waiting up to `timeout` for one. The status says the panel
+     * is refreshing until [Queue::done] is called for it. */
+    pub fn next(&self, timeout: Duration) -> Option<Job> {
+        let pending = self.pending.lock().unwrap();
+        let (mut pending, _) = self
+            .ready
+            .wait_timeout_while(pending, timeout, |pending| {
+                !matches!(pending, Pending::Waiting(_))
+            })
+            .unwrap();
+        let Pending::Waiting(job) = std::mem::take(&mut *pending) else {
+            return None;
+        };
+        self.status.lock().unwrap().refreshing = true;
+        return Some(job);
+    }
+
+    /** Note the size of the panel for the status. */
+    pub fn set_panel(&self, (width, height): (usize, usize)) {
+        let mut status = self.status.lock().unwrap();
+        status.width = Some(width);
+        status.height = Some(height);
+    }
+
+    /** Note that the panel is done with `job`, and now shows the frame in `preview`, a PNG, if
+     * the upload was displayed. */
+    pub fn done(&self, job: &Job, preview: Option<Vec<u8>>) {
+        let mut status = self.status.lock().unwrap();
+        status.refreshing = false;
+        if let Some(preview) = preview {
+            status.last_refresh = Some(Utc::now());
+            status.last_source = Some(job.source.clone());
+            *self.preview.lock().unwrap() = Some(preview);
+        }
+    }
+
+    #[cfg_attr(not(feature = "serve"), allow(dead_code))]
+    fn status(&self) -> Status {
+        self.status.lock().unwrap().clone()
+    }
+
+    #[cfg_attr(not(feature = "serve"), allow(dead_code))]
+    fn preview(&self) -> Option<Vec<u8>> {
+        self.preview.lock().unwrap().clone()
+    }
+}
+
+/** Listen for uploads and requests for the status on `address` on another thread, handling
+ * each request on its own so that clients waiting for their refresh don't hold up others.
+ * Beyond [MAX_HANDLERS] at once, requests are answered with 503 right away. */
+#[cfg(feature = "serve")]
+pub fn start(address: std::net::SocketAddr) -> Result<Arc<Queue>, ServeError> {
+    use std::thread;
+
+    use log::info;
+
+    let server =
+        tiny_http::Server::http(address).map_err(|error| ServeError::Listen(error.to_string()))?;
+    info!("Listening on http://{address}");
+    let queue = Arc::new(Queue::default());
+    let handler_queue = queue.clone();
+    thread::spawn(move || {
+        for request in server.incoming_requests() {
+            let Some(handler) = handler_queue.handler() else {
+                let error = "Too many requests at once";
+                respond(request, Outcome::failed(503, error, Instant::now()).into());
+                continue;
+            };
+            thread::spawn(move || handle(&handler.0, request));
+        }
+    });
+    return Ok(queue);
+}
+
+#[cfg(not(feature = "serve"))]
+pub fn start(_address: std::net::SocketAddr) -> Result<Arc<Queue>, ServeError> {
+    Err(ServeError::Unsupported)
+}
+
+/** An answer to a request. */
+#[cfg(feature = "serve")]
+struct Reply {
+    status: u16,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+#[cfg(feature = "serve")]
+impl Reply {
+    fn json(status: u16, value: &impl Serialize) -> Reply {
+        Reply {
+            status,
+            content_type: "application/json",
+            body: serde_json::to_vec(value).unwrap(),
+        }
+    }
+}
+
+#[cfg(feature = "serve")]
+impl From<Outcome> for Reply {
+    fn from(outcome: Outcome) -> Reply {
+        Reply::json(outcome.status, &outcome)
+    }
+}
+
+#[cfg(feature = "serve")]
+fn handle(queue: &Queue, mut request: tiny_http::Request) {
+    use log::debug;
+    use tiny_http::Method;
+
+    let received = Instant::now();
+    let (method, url) = (request.method().clone(), request.url().to_string());
+    let reply = match (&method, url.as_str()) {
+        (Method::Post, "/display") => {
+            let source = match request.remote_addr() {
+                Some(address) => format!("upload from {}", address.ip()),
+                None => "upload".to_string(),
+            };
+            Reply::from(upload(queue, &mut request, source, received))
+        }
+        (Method::Get, "/status") => Reply::json(200, &queue.status()),
+        (Method::Get, "/preview.png") => match queue.preview() {
+            Some(png) => Reply {
+                status: 200,
+                content_type: "image/png",
+                body: png,
+            },
+            None => Outcome::failed(404, "Nothing has been displayed yet", received).into(),
+        },
+        (_, "/display") => Outcome::failed(405, "Use POST to upload an image", received).into(),
+        (_, "/status" | "/preview.png") => Outcome::failed(405, "Use GET", received).into(),
+        (_, url) => Outcome::failed(404, format!("There is nothing at {url}"), received).into(),
+    };
+    debug!("{method} {url} answered {}", reply.status);
+    respond(request, reply);
+}
+
+/** Read the image POSTed in `request` and wait until it has been displayed. While another
+ * upload waits for the panel, the body is left unread and the client is asked to come back. */
+#[cfg(feature = "serve")]
+fn upload(
+    queue: &Queue,
+    request: &mut tiny_http::Request,
+    source: String,
+    received: Instant,
+) -> Outcome {
+    use std::io::Read;
+
+    use crate::fetch::MAX_BYTES;
+
+    let Some(reservation) = queue.reserve() else {
+        let error = "Another upload is waiting to be displayed";
+        return Outcome::failed(503, error, received);
+    };
+    let mut body = Vec::new();
+    let read = request
+        .as_reader()
+        .take(MAX_BYTES + 1)
+        .read_to_end(&mut body);
+    match read {
+        Err(error) => Outcome::failed(400, error, received),
+        Ok(_) if body.len() as u64 > MAX_BYTES => {
+            let error = format!("The image is larger than {} MiB", MAX_BYTES / 1024 / 1024);
+            Outcome::failed(413, error, received)
+        }
+        Ok(_) if body.is_empty() => Outcome::failed(400, "The body is empty", received),
+        Ok(_) => reservation
+            .submit(body, source)
+            .recv()
+            .unwrap_or_else(|_| Outcome::failed(503, "The server is shutting down", received)),
+    }
+}
+
+#[cfg(feature = "serve")]
+fn respond(request: tiny_http::Request, reply: Reply) {
+    use log::debug;
+    use tiny_http::Header;
+
+    let content_type = format!("Content-Type: {}", reply.content_type);
+    let mut response = tiny_http::Response::from_data(reply.body)
+        .with_status_code(reply.status)
+        .with_header(content_type.parse::<Header>().unwrap());
+    if reply.status == 503 {
+        let retry_after = format!("Retry-After: {RETRY_AFTER}");
+        response.add_header(retry_after.parse::<Header>().unwrap());
+    }
+    if let Err(error) = request.respond(response) {
+        debug!("Could not answer a request: {error}");
+    }
+}
+
+impl Display for ServeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ServeError::Listen(error) => write!(f, "{error}"),
+            #[cfg(not(feature = "serve"))]
+            ServeError::Unsupported => {
+                write!(
+                    f,
+                    "this build has no HTTP server, rebuild with --features serve"
+                )
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io, path::PathBuf};
+
+    use super::*;
+
+    fn body(queue: &Queue) -> Option<Vec<u8>> {
+        queue.next(Duration::ZERO).map(|job| job.body)
+    }
+
+    #[test]
+    fn one_upload_is_received_at_a_time() {
+        let queue = Queue::default();
+        let reservation = queue.reserve().unwrap();
+        assert!(queue.reserve().is_none());
+        assert_eq!(body(&queue), None);
+
+        let _outcome = reservation.submit(b"first".to_vec(), "test".to_string());
+        // Waiting counts as well
+        assert!(queue.reserve().is_none());
+        assert_eq!(body(&queue).as_deref(), Some(b"first".as_slice()));
+        assert!(queue.status().refreshing);
+
+        // Taken to the panel, so the next one may come in
+        let reservation = queue.reserve().unwrap();
+        let _outcome = reservation.submit(b"second".to_vec(), "test".to_string());
+        assert_eq!(body(&queue).as_deref(), Some(b"second".as_slice()));
+    }
+
+    #[test]
+    fn failed_uploads_give_their_place_up() {
+        let queue = Queue::default();
+        drop(queue.reserve().unwrap());
+        let reservation = queue.reserve().unwrap();
+        let _outcome = reservation.submit(b"image".to_vec(), "test".to_string());
+        assert!(body(&queue).is_some());
+    }
+
+    #[test]
+    fn outcomes_reach_the_client() {
+        let queue = Queue::default();
+        let outcome = queue
+            .reserve()
+            .unwrap()
+            .submit(b"image".to_vec(), "test".to_string());
+        let mut job = queue.next(Duration::ZERO).unwrap();
+        let error = RunError::Upload(PathBuf::from("/upload"), io::Error::other("disk full"));
+        job.finish(&Err(error));
+        let outcome = outcome.recv().unwrap();
+        assert_eq!((outcome.status, outcome.outcome), (500, "failed"));
+        assert_eq!(
+            outcome.error.as_deref(),
+            Some("Could not write /upload: disk full")
+        );
+    }
+
+    #[test]
+    fn deferred_uploads_are_answered_once() {
+        let queue = Queue::default();
+        let outcome = queue
+            .reserve()
+            .unwrap()
+            .submit(b"image".to_vec(), "test".to_string());
+        let mut job = queue.next(Duration::ZERO).unwrap();
+        job.defer();
+        let answer = outcome.recv().unwrap();
+        assert_eq!((answer.status, answer.outcome), (202, "deferred"));
+
+        // Displayed once quiet hours end, but the client has its answer already
+        job.finish(&Err(RunError::Usage("too late".to_string())));
+        assert!(outcome.recv().is_err());
+    }
+
+    #[test]
+    fn handlers_are_capped() {
+        let queue = Arc::new(Queue::default());
+        let handlers: Vec<Handler> = (0..MAX_HANDLERS)
+            .map(|_| queue.handler().unwrap())
+            .collect();
+        assert!(queue.handler().is_none());
+        drop(handlers);
+        assert_eq!(queue.handlers.load(Ordering::SeqCst), 0);
+        assert!(queue.handler().is_some());
+    }
+
+    /** Send `request` to the server at `address` and read the answer until it hangs up. */
+    #[cfg(feature = "serve")]
+    fn exchange(address: std::net::SocketAddr, request: &str) -> String {
+        use std::{
+            io::{Read, Write},
+            net::TcpStream,
+        };
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(10)))
+            .unwrap();
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut answer = Vec::new();
+        let _ = stream.read_to_end(&mut answer);
+        return String::from_utf8_lossy(&answer).into_owned();
+    }
+
+    #[cfg(feature = "serve")]
+    fn free_address() -> std::net::SocketAddr {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        return listener.local_addr().unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "serve")]
+    fn uploads_are_turned_away_unread_while_one_waits() {
+        let address = free_address();
+        let Ok(queue) = start(address) else {
+            panic!("could not listen on {address}");
+        };
+        let _waiting = queue.reserve().unwrap();
+
+        // The body is announced but never sent, so reading it would hang
+        let answer = exchange(
+            address,
+            "POST /display HTTP/1.1\r\nHost: test\r\nContent-Length: 33554432\r\n\
+             Connection: close\r\n\r\n",
+        );
+        assert!(answer.starts_with("HTTP/1.1 503"), "{answer}");
+        assert!(answer.contains("Retry-After: 60"), "{answer}");
+        assert!(answer.contains("Another upload is waiting to be displayed"));
+
+        let answer = exchange(
+            address,
+            "GET /status HTTP/1.1\r\nHost: test\r\nConnection: close\r\n\r\n",
+        );
+        assert!(answer.starts_with("HTTP/1.1 200"), "{answer}");
+    }
+}
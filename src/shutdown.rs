@@ -0,0 +1,94 @@
+use std::{
+    process::{self, ExitCode},
+    sync::{
+        atomic::{AtomicBool, AtomicI32, Ordering},
+        Condvar, Mutex, Once,
+    },
+    thread,
+    time::Duration,
+};
+
+use log::{info, warn};
+use signal_hook::{
+    consts::{SIGINT, SIGTERM},
+    iterator::Signals,
+};
+
+/** The signal that asked the program to stop, or 0 if none has. */
+static SIGNAL: AtomicI32 = AtomicI32::new(0);
+/** Set by [interrupt] until [interrupted] is asked. */
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+/** Wakes [sleep] when a signal arrives or it is interrupted. */
+static WAKE: (Mutex<()>, Condvar) = (Mutex::new(()), Condvar::new());
+static INSTALL: Once = Once::new();
+
+/** Handle SIGINT and SIGTERM from now on. The first one only asks the program to stop once the
+ * panel is safe, that is after the current refresh or before powering it on, and a second one
+ * exits right away. */
+pub fn install() {
+    INSTALL.call_once(|| {
+        let mut signals = match Signals::new([SIGINT, SIGTERM]) {
+            Ok(signals) => signals,
+            Err(error) => {
+                warn!("Could not handle signals: {error}");
+                return;
+            }
+        };
+        thread::spawn(move || {
+            for signal in signals.forever() {
+                if SIGNAL.swap(signal, Ordering::SeqCst) != 0 {
+                    eprintln!("Stopping immediately");
+                    process::exit(128 + signal);
+                }
+                info!("Stopping once the panel is idle, signal again to stop immediately");
+                let _guard = WAKE.0.lock().unwrap();
+                WAKE.1.notify_all();
+            }
+        });
+    });
+}
+
+/** Whether a signal asked the program to stop. */
+pub fn requested() -> bool {
+    SIGNAL.load(Ordering::SeqCst) != 0
+}
+
+/** The exit code after stopping for a signal: 128 plus its number, as shells report it. */
+pub fn exit_code() -> u8 {
+    (128 + SIGNAL.load(Ordering::SeqCst)) as u8
+}
+
+/** The code to exit with if a signal asked the program to stop. */
+pub fn stop_code() -> Option<ExitCode> {
+    if !requested() {
+        return None;
+    }
+    info!("Stopped by a signal");
+    return Some(ExitCode::from(exit_code()));
+}
+
+/** Sleep for `duration`, or until a signal asks the program to stop or [interrupt] is called.
+ * Returns whether a signal did. */
+pub fn sleep(duration: Duration) -> bool {
+    let guard = WAKE.0.lock().unwrap();
+    let _ = WAKE
+        .1
+        .wait_timeout_while(guard, duration, |_| {
+            !requested() && !INTERRUPTED.load(Ordering::SeqCst)
+        })
+        .unwrap();
+    return requested();
+}
+
+/** End the [sleep] going on, or the next one, early without stopping the program, such as for a
+ * button press. */
+pub fn interrupt() {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+    let _guard = WAKE.0.lock().unwrap();
+    WAKE.1.notify_all();
+}
+
+/** Whether [interrupt] was called since this was last asked. */
+pub fn interrupted() -> bool {
+    INTERRUPTED.swap(false, Ordering::SeqCst)
+}
@@ -0,0 +1,283 @@
+use std::{collections::HashSet, fs, io, path::Path, path::PathBuf};
+
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{select::Candidate, state::write_atomic};
+
+pub const FILE_NAME: &str = "history.jsonl";
+
+/** Oldest entries are dropped beyond this many. */
+const MAX_ENTRIES: usize = 1000;
+
+/** A file that was displayed, one JSON object per line in the history file. */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    pub time: DateTime<Utc>,
+    pub path: PathBuf,
+    #[serde(default)]
+    pub refresh_secs: Option<f64>,
+}
+
+/** Displayed files, oldest first.
+ *
+ * Positions in the history are given as the number of steps back from the newest entry, so
+ * that they stay valid when entries are appended or the oldest ones are dropped. */
+#[derive(Debug, Default)]
+pub struct History {
+    pub entries: Vec<HistoryEntry>,
+}
+
+impl History {
+    /** Load the history file. A missing file is an empty history, and lines that can't be
+     * parsed (such as one cut short by a crash) are skipped. */
+    pub fn load(path: &Path) -> History {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return History::default(),
+            Err(error) => {
+                warn!("Could not read {}: {error}", path.display());
+                return History::default();
+            }
+        };
+
+        let entries = contents
+            .lines()
+            .filter_map(|line| serde_json::from_str(line).ok())
+            .collect();
+        return History { entries };
+    }
+
+    pub fn push(&mut self, entry: HistoryEntry) {
+        self.entries.push(entry);
+        if self.entries.len() > MAX_ENTRIES {
+            self.entries.drain(..self.entries.len() - MAX_ENTRIES);
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut contents = String::new();
+        for entry in &self.entries {
+            contents.push_str(&serde_json::to_string(entry)?);
+            contents.push('\n');
+        }
+        write_atomic(path, contents.as_bytes())
+    }
+
+    fn back(&self, steps: usize) -> Option<&HistoryEntry> {
+        let index = self.entries.len().checked_sub(steps + 1)?;
+        self.entries.get(index)
+    }
+
+    /** The closest entry older than `cursor` whose file still exists. */
+    pub fn step_back(&self, cursor: usize, exists: impl Fn(&Path) -> bool) -> Option<usize> {
+        (cursor + 1..self.entries.len())
+            .find(|&steps| self.back(steps).is_some_and(|e| exists(&e.path)))
+    }
+
+    /** The closest entry newer than `cursor` whose file still exists. */
+    pub fn step_forward(&self, cursor: usize, exists: impl Fn(&Path) -> bool) -> Option<usize> {
+        (0..cursor.min(self.entries.len()))
+            .rev()
+            .find(|&steps| self.back(steps).is_some_and(|e| exists(&e.path)))
+    }
+
+    pub fn path_at(&self, cursor: usize) -> Option<&Path> {
+        self.back(cursor).map(|entry| entry.path.as_path())
+    }
+
+    /** The files of the newest `count` entries, with symlinks resolved so they can be compared
+     * with candidates however the directory was given. Files that no longer exist are left
+     * out. */
+    pub fn recent(&self, count: usize) -> HashSet<PathBuf> {
+        let skip = self.entries.len().saturating_sub(count);
+        self.entries[skip..]
+            .iter()
+            .filter_map(|entry| fs::canonicalize(&entry.path).ok())
+            .collect()
+    }
+}
+
+/** The file holding the history cursor, next to the history file: `history.cursor` for
+ * `history.jsonl`. */
+pub fn cursor_path(history_path: &Path) -> PathBuf {
+    history_path.with_extension("cursor")
+}
+
+/** The candidates that aren't among the newest `count` files in the history at `path`. If
+ * that would leave none, all of them are returned: repeating a recent file is better than
+ * showing nothing. */
+pub fn exclude_recent(path: &Path, count: usize, candidates: Vec<Candidate>) -> Vec<Candidate> {
+    let recent = History::load(path).recent(count);
+    if recent.is_empty() {
+        return candidates;
+    }
+    let (older, recent): (Vec<Candidate>, Vec<Candidate>) = candidates
+        .into_iter()
+        .partition(|c| fs::canonicalize(&c.path).map_or(true, |path| !recent.contains(&path)));
+    if older.is_empty() {
+        warn!("Every file was among the last {count} displayed, ignoring --history");
+        return recent;
+    }
+    info!("Leaving out {} files displayed recently", recent.len());
+    return older;
+}
+
+/** Load how far back in the history the displayed image is. Defaults to the newest entry. */
+pub fn load_cursor(path: &Path) -> usize {
+    fs::read_to_string(path)
+        .ok()
+        .and_then(|contents| contents.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+pub fn save_cursor(path: &Path, cursor: usize) -> io::Result<()> {
+    write_atomic(path, format!("{cursor}\n").as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /** A history of `files`, oldest first. */
+    fn history(files: &[&str]) -> History {
+        History {
+            entries: files
+                .iter()
+                .map(|file| HistoryEntry {
+                    time: Utc::now(),
+                    path: PathBuf::from(file),
+                    refresh_secs: None,
+                })
+                .collect(),
+        }
+    }
+
+    fn all(_: &Path) -> bool {
+        true
+    }
+
+    /** Every file but `gone`. */
+    fn except<'a>(gone: &'a [&str]) -> impl Fn(&Path) -> bool + 'a {
+        move |path| !gone.iter().any(|gone| path == Path::new(gone))
+    }
+
+    #[test]
+    fn the_cursor_counts_back_from_the_newest() {
+        let history = history(&["a", "b", "c"]);
+        assert_eq!(history.path_at(0), Some(Path::new("c")));
+        assert_eq!(history.path_at(2), Some(Path::new("a")));
+        assert_eq!(history.path_at(3), None);
+    }
+
+    #[test]
+    fn stepping_back_and_forward_retraces() {
+        let history = history(&["a", "b", "c", "d"]);
+        let mut cursor = 0;
+        let mut seen = vec![history.path_at(cursor).unwrap()];
+        while let Some(back) = history.step_back(cursor, all) {
+            cursor = back;
+            seen.push(history.path_at(cursor).unwrap());
+        }
+        assert_eq!(seen, ["d", "c", "b", "a"].map(Path::new));
+        assert_eq!(cursor, 3);
+
+        let mut seen = Vec::new();
+        while let Some(forward) = history.step_forward(cursor, all) {
+            cursor = forward;
+            seen.push(history.path_at(cursor).unwrap());
+        }
+        assert_eq!(seen, ["b", "c", "d"].map(Path::new));
+        assert_eq!(cursor, 0);
+    }
+
+    #[test]
+    fn stepping_stops_at_the_ends() {
+        let history = history(&["a", "b"]);
+        assert_eq!(history.step_forward(0, all), None);
+        assert_eq!(history.step_back(1, all), None);
+    }
+
+    #[test]
+    fn an_empty_history_has_nowhere_to_go() {
+        let empty = History::default();
+        assert_eq!(empty.step_back(0, all), None);
+        assert_eq!(empty.step_forward(0, all), None);
+        assert_eq!(empty.path_at(0), None);
+        // Only the newest entry
+        let one = history(&["a"]);
+        assert_eq!(one.step_back(0, all), None);
+        assert_eq!(one.step_forward(0, all), None);
+    }
+
+    #[test]
+    fn deleted_files_are_skipped_both_ways() {
+        let history = history(&["a", "b", "c", "d", "e"]);
+        let exists = except(&["d", "b"]);
+        assert_eq!(history.step_back(0, &exists), Some(2));
+        assert_eq!(history.step_back(2, &exists), Some(4));
+        assert_eq!(history.step_forward(4, &exists), Some(2));
+        assert_eq!(history.step_forward(2, &exists), Some(0));
+        // Nothing older is left
+        let exists = except(&["a", "b", "c", "d"]);
+        assert_eq!(history.step_back(0, &exists), None);
+        // The cursor itself may point at a deleted file
+        assert_eq!(history.step_back(1, &exists), None);
+        assert_eq!(history.step_forward(1, &exists), Some(0));
+    }
+
+    #[test]
+    fn a_stale_cursor_is_brought_back_into_the_history() {
+        // Such as after the history file was replaced by a shorter one
+        let history = history(&["a", "b", "c"]);
+        assert_eq!(history.step_back(10, all), None);
+        assert_eq!(history.step_forward(10, all), Some(2));
+        assert_eq!(history.path_at(10), None);
+    }
+
+    #[test]
+    fn positions_survive_dropping_the_oldest() {
+        let mut history = history(&["a", "b", "c"]);
+        for n in 0..MAX_ENTRIES {
+            history.push(HistoryEntry {
+                time: Utc::now(),
+                path: PathBuf::from(format!("{n}")),
+                refresh_secs: None,
+            });
+        }
+        assert_eq!(history.entries.len(), MAX_ENTRIES);
+        assert_eq!(history.path_at(0), Some(Path::new("999")));
+        assert_eq!(history.path_at(MAX_ENTRIES - 1), Some(Path::new("0")));
+        assert_eq!(history.step_back(MAX_ENTRIES - 1, all), None);
+    }
+
+    #[test]
+    fn cursors_are_kept_next_to_the_history() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = cursor_path(&dir.path().join(FILE_NAME));
+        assert_eq!(path, dir.path().join("history.cursor"));
+
+        // Missing or unreadable, it is at the newest entry
+        assert_eq!(load_cursor(&path), 0);
+        fs::write(&path, "back\n").unwrap();
+        assert_eq!(load_cursor(&path), 0);
+        fs::write(&path, "-1").unwrap();
+        assert_eq!(load_cursor(&path), 0);
+
+        save_cursor(&path, 7).unwrap();
+        assert_eq!(load_cursor(&path), 7);
+        fs::write(&path, " 3 ").unwrap();
+        assert_eq!(load_cursor(&path), 3);
+    }
+
+    #[test]
+    fn histories_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(FILE_NAME);
+        history(&["a", "b"]).save(&path).unwrap();
+        let loaded = History::load(&path);
+        assert_eq!(loaded.path_at(0), Some(Path::new("b")));
+        assert_eq!(loaded.entries.len(), 2);
+    }
+}
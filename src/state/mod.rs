@@ -0,0 +1,336 @@
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+    process,
+    time::Duration,
+};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use log::warn;
+use serde::de::DeserializeOwned;
+
+use crate::report::RunReport;
+
+pub mod history;
+pub mod showing;
+pub mod shuffle;
+pub mod sources;
+pub mod stats;
+
+const APP_DIR: &str = "inky-rs";
+
+/** Directory for files kept between runs: `$XDG_STATE_HOME/inky-rs`, or
+ * `~/.local/state/inky-rs`. */
+pub fn default_dir() -> PathBuf {
+    if let Some(dir) = env::var_os("XDG_STATE_HOME").filter(|dir| !dir.is_empty()) {
+        return PathBuf::from(dir).join(APP_DIR);
+    }
+    if let Some(home) = env::var_os("HOME").filter(|home| !home.is_empty()) {
+        return PathBuf::from(home).join(".local/state").join(APP_DIR);
+    }
+    return env::temp_dir().join(APP_DIR);
+}
+
+/** Replace the contents of `path` by writing a temporary file next to it and renaming it,
+ * so that readers (and concurrent runs) never see a partially written file. */
+pub fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(format!(".{}.tmp", process::id()));
+    fs::write(&temp, contents)?;
+    fs::rename(&temp, path)
+}
+
+/** Read the JSON state file at `path`. A missing file gives the default, and so does one that
+ * can't be read or parsed, with a warning. */
+pub fn read_json<T: Default + DeserializeOwned>(path: &Path) -> T {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return T::default(),
+        Err(error) => {
+            warn!("Could not read {}: {error}", path.display());
+            return T::default();
+        }
+    };
+    return serde_json::from_str(&contents).unwrap_or_else(|error| {
+        warn!("Ignoring {}, which is corrupt: {error}", path.display());
+        T::default()
+    });
+}
+
+/** How the image of a successful run was chosen. */
+#[derive(Debug, Clone, Copy)]
+pub enum Shown {
+    New,
+    /** Shown again from this position in the history. */
+    Revisit(usize),
+    /** A pinned file, which is kept out of the history. */
+    Pinned,
+}
+
+/** Record a successful run in the state files, with the history at `history_path`. Failures
+ * are only logged. */
+pub fn record(dir: &Path, history_path: &Path, report: &RunReport, shown: Shown) {
+    match shown {
+        Shown::New => record_refresh(dir, history_path, report),
+        Shown::Revisit(cursor) => record_revisit(dir, history_path, report, cursor),
+        Shown::Pinned => count_refresh(dir, report),
+    }
+}
+
+/** Add a successful run to the history and stats files, and point the history cursor at it.
+ * Each file of a collage gets an entry of its own. */
+fn record_refresh(dir: &Path, history_path: &Path, report: &RunReport) {
+    let mut history = history::History::load(history_path);
+    for file in report.files() {
+        history.push(history::HistoryEntry {
+            time: Utc::now(),
+            path: file.clone(),
+            refresh_secs: report.refresh_duration.map(|d| d.as_secs_f64()),
+        });
+    }
+    if let Err(error) = history.save(history_path) {
+        warn!("Could not write {}: {error}", history_path.display());
+    }
+    set_cursor(history_path, 0);
+    count_refresh(dir, report);
+}
+
+/** Count a run that showed an image from the history again, without adding a duplicate
+ * history entry, and move the history cursor to it. */
+fn record_revisit(dir: &Path, history_path: &Path, report: &RunReport, cursor: usize) {
+    set_cursor(history_path, cursor);
+    count_refresh(dir, report);
+}
+
+fn count_refresh(dir: &Path, report: &RunReport) {
+    let stats_path = dir.join(stats::FILE_NAME);
+    let mut stats = stats::Stats::load(&stats_path).unwrap_or_default();
+    stats.record_refresh(report.refresh_duration);
+    if let Err(error) = stats.save(&stats_path) {
+        warn!("Could not write {}: {error}", stats_path.display());
+    }
+}
+
+fn set_cursor(history_path: &Path, cursor: usize) {
+    let cursor_path = history::cursor_path(history_path);
+    if let Err(error) = history::save_cursor(&cursor_path, cursor) {
+        warn!("Could not write {}: {error}", cursor_path.display());
+    }
+}
+
+/** Count a failed run in the stats file. Failures to write it are only logged. */
+pub fn record_failure(dir: &Path, class: &str) {
+    let stats_path = dir.join(stats::FILE_NAME);
+    let mut stats = stats::Stats::load(&stats_path).unwrap_or_default();
+    stats.record_failure(class, Utc::now());
+    if let Err(error) = stats.save(&stats_path) {
+        warn!("Could not write {}: {error}", stats_path.display());
+    }
+}
+
+/** Direction to move the history cursor in. */
+#[derive(Debug, Clone, Copy)]
+pub enum Step {
+    Back,
+    Forward,
+}
+
+/** Find the image to show again when stepping through the history from the one currently
+ * displayed, skipping files that no longer exist. Returns the new cursor and the file. */
+pub fn step(history_path: &Path, step: Step) -> Option<(usize, PathBuf)> {
+    let history = history::History::load(history_path);
+    let cursor = history::load_cursor(&history::cursor_path(history_path));
+    let cursor = match step {
+        Step::Back => history.step_back(cursor, Path::exists)?,
+        Step::Forward => history.step_forward(cursor, Path::exists)?,
+    };
+
+    return Some((cursor, history.path_at(cursor)?.to_path_buf()));
+}
+
+/** The image the history cursor is at, which is the one on the panel unless something else was
+ * displayed since, if its file still exists. Returns the cursor and the file. */
+pub fn current(history_path: &Path) -> Option<(usize, PathBuf)> {
+    let history = history::History::load(history_path);
+    let cursor = history::load_cursor(&history::cursor_path(history_path));
+    let path = history.path_at(cursor)?.to_path_buf();
+    return path.exists().then_some((cursor, path));
+}
+
+/** Summarize the state files, counting only what happened within `since` if given. */
+pub fn summarize(
+    dir: &Path,
+    history_path: &Path,
+    since: Option<Duration>,
+    last: usize,
+) -> stats::Summary {
+    let history = history::History::load(history_path);
+    let stats = stats::Stats::load(&dir.join(stats::FILE_NAME));
+    let since = since.map(|since| {
+        let since = TimeDelta::from_std(since).unwrap_or(TimeDelta::MAX);
+        Utc::now()
+            .checked_sub_signed(since)
+            .unwrap_or(DateTime::<Utc>::MIN_UTC)
+    });
+
+    return stats::summarize(stats.as_ref(), &history, since, last);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{quantize::Fit, report::TimeBudget, select::criteria::Scores, select::Candidate};
+
+    fn report(file: &Path) -> RunReport {
+        RunReport {
+            file: file.to_path_buf(),
+            collage: Vec::new(),
+            scores: Scores::default(),
+            schedule_window: None,
+            refresh_duration: Some(Duration::from_secs(30)),
+            time_budget: TimeBudget::default(),
+            source: None,
+            histogram: [0; 7],
+            panel: (800, 480),
+            saturation: 0.5,
+            fit: Fit::Cover,
+        }
+    }
+
+    #[test]
+    fn json_state_files_fall_back_to_the_default() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        assert_eq!(read_json::<Vec<u32>>(&path), Vec::<u32>::new());
+
+        fs::write(&path, "[1, 2, 3]").unwrap();
+        assert_eq!(read_json::<Vec<u32>>(&path), [1, 2, 3]);
+
+        // Such as cut short by a crash
+        fs::write(&path, "[1, 2").unwrap();
+        assert_eq!(read_json::<Vec<u32>>(&path), Vec::<u32>::new());
+
+        // A directory can't be read as a file
+        assert_eq!(read_json::<Vec<u32>>(dir.path()), Vec::<u32>::new());
+    }
+
+    /** A state directory, and a directory of images a to d that were displayed in that order. */
+    fn displayed() -> (tempfile::TempDir, tempfile::TempDir) {
+        let state = tempfile::tempdir().unwrap();
+        let photos = tempfile::tempdir().unwrap();
+        let history_path = state.path().join(history::FILE_NAME);
+        for name in ["a.jpg", "b.jpg", "c.jpg", "d.jpg"] {
+            let file = photos.path().join(name);
+            fs::write(&file, b"").unwrap();
+            record(state.path(), &history_path, &report(&file), Shown::New);
+        }
+        return (state, photos);
+    }
+
+    fn name(step: Option<(usize, PathBuf)>) -> Option<(usize, String)> {
+        step.map(|(cursor, path)| {
+            let name = path.file_name().unwrap().to_str().unwrap().to_string();
+            (cursor, name)
+        })
+    }
+
+    /** Step like `previous` or `next` do, recording the revisit. */
+    fn go(state: &Path, direction: Step) -> Option<(usize, String)> {
+        let history_path = state.join(history::FILE_NAME);
+        let (cursor, path) = step(&history_path, direction)?;
+        record(state, &history_path, &report(&path), Shown::Revisit(cursor));
+        return name(Some((cursor, path)));
+    }
+
+    #[test]
+    fn previous_and_next_move_through_the_history() {
+        let (state, _photos) = displayed();
+        let state = state.path();
+        assert_eq!(go(state, Step::Forward), None);
+        assert_eq!(go(state, Step::Back), Some((1, "c.jpg".to_string())));
+        assert_eq!(go(state, Step::Back), Some((2, "b.jpg".to_string())));
+        assert_eq!(go(state, Step::Forward), Some((1, "c.jpg".to_string())));
+        assert_eq!(go(state, Step::Back), Some((2, "b.jpg".to_string())));
+        assert_eq!(go(state, Step::Back), Some((3, "a.jpg".to_string())));
+        assert_eq!(go(state, Step::Back), None);
+        let history_path = state.join(history::FILE_NAME);
+        assert_eq!(name(current(&history_path)), Some((3, "a.jpg".to_string())));
+    }
+
+    #[test]
+    fn going_back_adds_nothing_to_the_history() {
+        let (state, photos) = displayed();
+        let history_path = state.path().join(history::FILE_NAME);
+        go(state.path(), Step::Back);
+        go(state.path(), Step::Back);
+        assert_eq!(history::History::load(&history_path).entries.len(), 4);
+
+        // So the files left out as shown recently are still the last ones chosen
+        let candidates: Vec<Candidate> = ["a.jpg", "b.jpg", "c.jpg", "d.jpg"]
+            .iter()
+            .map(|name| Candidate::new(photos.path().join(name)))
+            .collect();
+        let left = history::exclude_recent(&history_path, 2, candidates);
+        let left: Vec<&Path> = left.iter().map(|c| c.path.as_path()).collect();
+        assert_eq!(
+            left,
+            [photos.path().join("a.jpg"), photos.path().join("b.jpg")]
+        );
+
+        // Revisits still count as refreshes
+        let stats = stats::Stats::load(&state.path().join(stats::FILE_NAME)).unwrap();
+        assert_eq!(stats.refreshes, 6);
+    }
+
+    #[test]
+    fn a_new_image_moves_the_cursor_back_to_the_newest() {
+        let (state, photos) = displayed();
+        let history_path = state.path().join(history::FILE_NAME);
+        go(state.path(), Step::Back);
+        go(state.path(), Step::Back);
+        let file = photos.path().join("e.jpg");
+        fs::write(&file, b"").unwrap();
+        record(state.path(), &history_path, &report(&file), Shown::New);
+        assert_eq!(name(current(&history_path)), Some((0, "e.jpg".to_string())));
+        assert_eq!(go(state.path(), Step::Back), Some((1, "d.jpg".to_string())));
+    }
+
+    #[test]
+    fn deleted_files_are_stepped_over() {
+        let (state, photos) = displayed();
+        fs::remove_file(photos.path().join("c.jpg")).unwrap();
+        assert_eq!(go(state.path(), Step::Back), Some((2, "b.jpg".to_string())));
+        fs::remove_file(photos.path().join("d.jpg")).unwrap();
+        // Nothing newer is left to go forward to
+        assert_eq!(go(state.path(), Step::Forward), None);
+        let history_path = state.path().join(history::FILE_NAME);
+        assert_eq!(name(current(&history_path)), Some((2, "b.jpg".to_string())));
+
+        // Once the displayed file is gone too, there is no current image
+        fs::remove_file(photos.path().join("b.jpg")).unwrap();
+        assert_eq!(current(&history_path), None);
+    }
+
+    #[test]
+    fn without_a_history_there_is_nowhere_to_go() {
+        let state = tempfile::tempdir().unwrap();
+        assert_eq!(go(state.path(), Step::Back), None);
+        assert_eq!(go(state.path(), Step::Forward), None);
+        assert_eq!(current(&state.path().join(history::FILE_NAME)), None);
+    }
+
+    #[test]
+    fn pinned_files_are_kept_out_of_the_history() {
+        let (state, photos) = displayed();
+        let history_path = state.path().join(history::FILE_NAME);
+        go(state.path(), Step::Back);
+        let pinned = photos.path().join("pinned.jpg");
+        fs::write(&pinned, b"").unwrap();
+        record(state.path(), &history_path, &report(&pinned), Shown::Pinned);
+        assert_eq!(history::History::load(&history_path).entries.len(), 4);
+        assert_eq!(name(current(&history_path)), Some((1, "c.jpg".to_string())));
+    }
+}
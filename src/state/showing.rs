@@ -0,0 +1,53 @@
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use log::warn;
+use serde::Serialize;
+
+use crate::{report::RunReport, state::write_atomic};
+
+pub const FILE_NAME: &str = "showing.json";
+/** Raised whenever a field of [Showing] changes meaning or is removed. Fields may be added
+ * without raising it, so readers should ignore the ones they don't know. */
+pub const SCHEMA_VERSION: u32 = 1;
+
+/** The image on the panel, described for other programs such as a status LED or a web page.
+ * The file is replaced atomically after every refresh. */
+#[derive(Debug, Serialize)]
+struct Showing {
+    /** [SCHEMA_VERSION] */
+    schema_version: u32,
+    /** The file displayed, or the URL or `-` it was read from. */
+    source: String,
+    /** When the refresh finished, in RFC 3339. */
+    time: DateTime<Utc>,
+    /** The saturation of the palette, from 0 to 1. */
+    saturation: f64,
+    /** How the image was brought to the panel's size, as `--fit` names it. */
+    fit: String,
+    /** Pixels of each color by palette index: black, white, green, blue, red, yellow and
+     * orange. */
+    histogram: [usize; 7],
+}
+
+/** Describe the image of a successful run at `path`. Failures are only logged. */
+pub fn write(path: &Path, report: &RunReport) {
+    let showing = Showing {
+        schema_version: SCHEMA_VERSION,
+        source: report.file.display().to_string(),
+        time: Utc::now(),
+        saturation: report.saturation,
+        fit: report
+            .fit
+            .to_possible_value()
+            .unwrap()
+            .get_name()
+            .to_string(),
+        histogram: report.histogram,
+    };
+    let contents = serde_json::to_vec_pretty(&showing).unwrap();
+    if let Err(error) = write_atomic(path, &contents) {
+        warn!("Could not write {}: {error}", path.display());
+    }
+}
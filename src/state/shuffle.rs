@@ -0,0 +1,101 @@
+use std::{
+    collections::HashSet,
+    fs, io,
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    select::Candidate,
+    state::{read_json, write_atomic},
+};
+
+pub const FILE_NAME: &str = "shown.json";
+
+/** Identifies a version of a file: an edited file counts as new, and so does one moved to a
+ * different path. */
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct FileKey {
+    pub path: PathBuf,
+    pub size: u64,
+    /** Modification time in seconds since the epoch, if the file system has one. */
+    pub modified: Option<u64>,
+}
+
+impl FileKey {
+    /** The key of the file at `path` as it is now, or `None` if it can't be read. */
+    pub fn of(path: &Path) -> Option<FileKey> {
+        let path = fs::canonicalize(path).ok()?;
+        let metadata = fs::metadata(&path).ok()?;
+        let modified = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|duration| duration.as_secs());
+        Some(FileKey {
+            path,
+            size: metadata.len(),
+            modified,
+        })
+    }
+}
+
+/** The files shown since every file was last shown, so selection can go through them all
+ * before repeating any. */
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Shuffle {
+    pub shown: HashSet<FileKey>,
+}
+
+impl Shuffle {
+    /** Load the state file. A missing or unreadable file counts as nothing shown yet. */
+    pub fn load(path: &Path) -> Shuffle {
+        read_json(path)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        write_atomic(path, serde_json::to_string(self)?.as_bytes())
+    }
+}
+
+/** The candidates that haven't been shown yet. Once all of them have, the state file is
+ * cleared and every candidate is returned, to start another round. */
+pub fn unseen(path: &Path, candidates: Vec<Candidate>) -> Vec<Candidate> {
+    let shuffle = Shuffle::load(path);
+    let (unseen, seen): (Vec<Candidate>, Vec<Candidate>) = candidates
+        .into_iter()
+        .partition(|c| FileKey::of(&c.path).is_none_or(|key| !shuffle.shown.contains(&key)));
+    if !unseen.is_empty() {
+        info!(
+            "{} of {} files not shown yet",
+            unseen.len(),
+            unseen.len() + seen.len()
+        );
+        return unseen;
+    }
+
+    info!("Every file has been shown, starting over");
+    if let Err(error) = Shuffle::default().save(path) {
+        warn!("Could not write {}: {error}", path.display());
+    }
+    return seen;
+}
+
+/** Add `file` to the files shown in this round. Files that have been deleted or changed since
+ * they were recorded are dropped, so the state file doesn't grow forever. Failures are only
+ * logged. */
+pub fn mark_shown(path: &Path, file: &Path) {
+    let mut shuffle = Shuffle::load(path);
+    shuffle
+        .shown
+        .retain(|key| FileKey::of(&key.path).as_ref() == Some(key));
+    if let Some(key) = FileKey::of(file) {
+        shuffle.shown.insert(key);
+    }
+    if let Err(error) = shuffle.save(path) {
+        warn!("Could not write {}: {error}", path.display());
+    }
+}
@@ -0,0 +1,74 @@
+use std::{io, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::state::{read_json, write_atomic};
+
+pub const FILE_NAME: &str = "sources.json";
+
+/** Where `--rotate-sources` is in its round of the paths on the command line. */
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct SourceCursor {
+    /** The path the last image was chosen from, as given on the command line. */
+    pub last: Option<String>,
+    /** Its position among the paths, to carry on from if it has been removed since. */
+    pub index: usize,
+}
+
+impl SourceCursor {
+    /** Load the state file. A missing or unreadable file starts with the first path. */
+    pub fn load(path: &Path) -> SourceCursor {
+        read_json(path)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        write_atomic(path, serde_json::to_string(self)?.as_bytes())
+    }
+
+    /** Positions in `sources` in the order to try them, once round from the one after the last
+     * path used. If that path is gone, the one that took its place comes first. */
+    pub fn order(&self, sources: &[String]) -> impl Iterator<Item = usize> {
+        let last = self.last.as_ref();
+        let next = match last.and_then(|last| sources.iter().position(|s| s == last)) {
+            Some(position) => position + 1,
+            None => self.index,
+        };
+        let count = sources.len();
+        return (0..count).map(move |i| (next + i) % count);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cursor(last: Option<&str>, index: usize) -> SourceCursor {
+        SourceCursor {
+            last: last.map(str::to_string),
+            index,
+        }
+    }
+
+    fn order(cursor: &SourceCursor, sources: &[&str]) -> Vec<usize> {
+        let sources: Vec<String> = sources.iter().map(|s| s.to_string()).collect();
+        return cursor.order(&sources).collect();
+    }
+
+    #[test]
+    fn rounds_start_after_the_last_source() {
+        assert_eq!(order(&SourceCursor::default(), &["a", "b", "c"]), [0, 1, 2]);
+        assert_eq!(order(&cursor(Some("b"), 1), &["a", "b", "c"]), [2, 0, 1]);
+        assert_eq!(order(&cursor(Some("c"), 2), &["a", "b", "c"]), [0, 1, 2]);
+    }
+
+    #[test]
+    fn rounds_follow_a_source_list_that_changed() {
+        // Moved to the front with another added, so it is found by name
+        assert_eq!(order(&cursor(Some("b"), 1), &["b", "d", "a"]), [1, 2, 0]);
+        // Removed, so the one that took its place comes first
+        assert_eq!(order(&cursor(Some("b"), 1), &["a", "c", "d"]), [1, 2, 0]);
+        // Removed along with the ones after it, going round to the start
+        assert_eq!(order(&cursor(Some("e"), 4), &["a", "b"]), [0, 1]);
+        assert_eq!(order(&cursor(Some("b"), 1), &[]), Vec::<usize>::new());
+    }
+}
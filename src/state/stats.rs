@@ -0,0 +1,375 @@
+use std::{collections::BTreeMap, fmt::Display, io, path::Path, time::Duration};
+
+use chrono::{DateTime, Local, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::state::{
+    history::{History, HistoryEntry},
+    read_json, write_atomic,
+};
+
+pub const FILE_NAME: &str = "stats.json";
+const VERSION: u32 = 1;
+
+/** Only this many individual failures are kept for `--since` filtering. */
+const MAX_RECENT_FAILURES: usize = 500;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Failure {
+    pub time: DateTime<Utc>,
+    pub class: String,
+}
+
+/** Running totals kept in the stats file. Every field is optional on disk, so files written
+ * by older versions still load. */
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Stats {
+    pub version: u32,
+    pub refreshes: u64,
+    pub timed_refreshes: u64,
+    pub refresh_secs: f64,
+    pub failures: BTreeMap<String, u64>,
+    pub recent_failures: Vec<Failure>,
+}
+
+impl Stats {
+    /** Load the stats file, or `None` if it is missing or unreadable. */
+    pub fn load(path: &Path) -> Option<Stats> {
+        read_json(path)
+    }
+
+    pub fn save(&mut self, path: &Path) -> io::Result<()> {
+        self.version = VERSION;
+        write_atomic(path, &serde_json::to_vec_pretty(self)?)
+    }
+
+    pub fn record_refresh(&mut self, duration: Option<Duration>) {
+        self.refreshes += 1;
+        if let Some(duration) = duration {
+            self.timed_refreshes += 1;
+            self.refresh_secs += duration.as_secs_f64();
+        }
+    }
+
+    pub fn record_failure(&mut self, class: &str, time: DateTime<Utc>) {
+        *self.failures.entry(class.to_string()).or_default() += 1;
+        self.recent_failures.push(Failure {
+            time,
+            class: class.to_string(),
+        });
+        if self.recent_failures.len() > MAX_RECENT_FAILURES {
+            self.recent_failures
+                .drain(..self.recent_failures.len() - MAX_RECENT_FAILURES);
+        }
+    }
+}
+
+/** What the `stats` subcommand prints. */
+#[derive(Debug, Serialize)]
+pub struct Summary {
+    pub refreshes: u64,
+    pub average_refresh_secs: Option<f64>,
+    pub failures: BTreeMap<String, u64>,
+    pub recent: Vec<HistoryEntry>,
+}
+
+/** Aggregate the stats and history files, either all time or only since a given time.
+ *
+ * All-time totals come from the stats file when it exists. Totals since a given time, or
+ * without a stats file, are counted from the history instead. `recent` holds up to `last`
+ * history entries, newest first. */
+pub fn summarize(
+    stats: Option<&Stats>,
+    history: &History,
+    since: Option<DateTime<Utc>>,
+    last: usize,
+) -> Summary {
+    let entries: Vec<&HistoryEntry> = history
+        .entries
+        .iter()
+        .filter(|entry| since.is_none_or(|since| entry.time >= since))
+        .collect();
+
+    let (refreshes, timed, secs) = match (stats, since) {
+        (Some(stats), None) => (stats.refreshes, stats.timed_refreshes, stats.refresh_secs),
+        _ => {
+            let timings: Vec<f64> = entries.iter().filter_map(|e| e.refresh_secs).collect();
+            (
+                entries.len() as u64,
+                timings.len() as u64,
+                timings.iter().sum(),
+            )
+        }
+    };
+
+    let failures = match (stats, since) {
+        (Some(stats), None) => stats.failures.clone(),
+        (Some(stats), Some(since)) => {
+            let mut failures = BTreeMap::new();
+            for failure in stats.recent_failures.iter().filter(|f| f.time >= since) {
+                *failures.entry(failure.class.clone()).or_default() += 1;
+            }
+            failures
+        }
+        (None, _) => BTreeMap::new(),
+    };
+
+    Summary {
+        refreshes,
+        average_refresh_secs: (timed > 0).then(|| secs / timed as f64),
+        failures,
+        recent: entries
+            .iter()
+            .rev()
+            .take(last)
+            .map(|&e| e.clone())
+            .collect(),
+    }
+}
+
+/** Format a duration for people, e.g. `41.3 s`, `2m 05s` or `1h 20m`. */
+pub fn format_duration(secs: f64) -> String {
+    let whole = secs.round() as u64;
+    if secs < 60.0 {
+        format!("{secs:.1} s")
+    } else if whole < 3600 {
+        format!("{}m {:02}s", whole / 60, whole % 60)
+    } else {
+        format!("{}h {:02}m", whole / 3600, whole / 60 % 60)
+    }
+}
+
+impl Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        writeln!(f, "Refreshes: {}", self.refreshes)?;
+        match self.average_refresh_secs {
+            Some(secs) => writeln!(f, "Average refresh: {}", format_duration(secs))?,
+            None => writeln!(f, "Average refresh: unknown")?,
+        }
+
+        if self.failures.is_empty() {
+            writeln!(f, "Failures: none")?;
+        } else {
+            let failures: Vec<String> = self
+                .failures
+                .iter()
+                .map(|(class, count)| format!("{class} {count}"))
+                .collect();
+            writeln!(f, "Failures: {}", failures.join(", "))?;
+        }
+
+        if self.recent.is_empty() {
+            return writeln!(f, "No images displayed yet");
+        }
+        writeln!(f, "Recently displayed:")?;
+        for entry in &self.recent {
+            let time = entry.time.with_timezone(&Local).format("%Y-%m-%d %H:%M");
+            writeln!(f, "  {time}  {}", entry.path.display())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use chrono::TimeZone;
+
+    use super::*;
+
+    fn at(hour: u32) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 5, 1, hour, 0, 0).unwrap()
+    }
+
+    /** Write `contents` to a state file named `name` in `dir`. */
+    fn state_file(dir: &tempfile::TempDir, name: &str, contents: &str) -> std::path::PathBuf {
+        let path = dir.path().join(name);
+        fs::write(&path, contents).unwrap();
+        return path;
+    }
+
+    /** A history as it is on disk: one entry from before refresh times were kept, one cut short
+     * by a crash, and three with times, at 1, 2, 3, 4 and 5 o'clock. */
+    const HISTORY: &str = r#"{"time":"2024-05-01T01:00:00Z","path":"/photos/a.jpg"}
+{"time":"2024-05-01T02:00:00Z","path":"/photos/b.jpg","refresh_secs":30.0}
+{"time":"2024-05-01T03:00:00Z","path":"/photos/c.jpg","refre
+{"time":"2024-05-01T04:00:00Z","path":"/photos/d.jpg","refresh_secs":40.0}
+{"time":"2024-05-01T05:00:00Z","path":"/photos/e.jpg","refresh_secs":20.0}
+"#;
+
+    fn paths(summary: &Summary) -> Vec<&str> {
+        summary
+            .recent
+            .iter()
+            .map(|entry| entry.path.to_str().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn stats_from_older_versions_load() {
+        let dir = tempfile::tempdir().unwrap();
+        // Before the version, refresh times and individual failures were kept
+        let path = state_file(&dir, FILE_NAME, r#"{"refreshes":12,"failures":{"busy":2}}"#);
+        let stats = Stats::load(&path).unwrap();
+        assert_eq!(stats.version, 0);
+        assert_eq!(stats.refreshes, 12);
+        assert_eq!(stats.timed_refreshes, 0);
+        assert_eq!(stats.failures["busy"], 2);
+        assert!(stats.recent_failures.is_empty());
+
+        let path = state_file(&dir, "empty.json", "{}");
+        assert_eq!(Stats::load(&path).unwrap().refreshes, 0);
+        // Fields from newer versions are ignored
+        let path = state_file(
+            &dir,
+            "newer.json",
+            r#"{"version":9,"refreshes":3,"later":[1]}"#,
+        );
+        assert_eq!(Stats::load(&path).unwrap().refreshes, 3);
+    }
+
+    #[test]
+    fn missing_or_corrupt_stats_are_none() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(Stats::load(&dir.path().join(FILE_NAME)).is_none());
+        let path = state_file(&dir, FILE_NAME, r#"{"refreshes":"#);
+        assert!(Stats::load(&path).is_none());
+        let path = state_file(&dir, "wrong.json", r#"{"refreshes":"many"}"#);
+        assert!(Stats::load(&path).is_none());
+    }
+
+    #[test]
+    fn stats_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(FILE_NAME);
+        let mut stats = Stats::default();
+        stats.record_refresh(Some(Duration::from_secs(30)));
+        stats.record_refresh(None);
+        stats.record_failure("busy", at(1));
+        stats.save(&path).unwrap();
+
+        let loaded = Stats::load(&path).unwrap();
+        assert_eq!(loaded.version, VERSION);
+        assert_eq!((loaded.refreshes, loaded.timed_refreshes), (2, 1));
+        assert_eq!(loaded.refresh_secs, 30.0);
+        assert_eq!(loaded.failures["busy"], 1);
+        assert_eq!(loaded.recent_failures[0].time, at(1));
+    }
+
+    #[test]
+    fn only_the_latest_failures_are_kept() {
+        let mut stats = Stats::default();
+        for n in 0..MAX_RECENT_FAILURES + 10 {
+            stats.record_failure(if n < 10 { "old" } else { "new" }, at(1));
+        }
+        assert_eq!(stats.recent_failures.len(), MAX_RECENT_FAILURES);
+        assert!(stats.recent_failures.iter().all(|f| f.class == "new"));
+        // The totals still count every one
+        assert_eq!(stats.failures["old"], 10);
+    }
+
+    #[test]
+    fn all_time_totals_come_from_the_stats() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = History::load(&state_file(&dir, "history.jsonl", HISTORY));
+        let stats = Stats {
+            refreshes: 100,
+            timed_refreshes: 50,
+            refresh_secs: 1500.0,
+            failures: BTreeMap::from([("busy".to_string(), 3)]),
+            ..Stats::default()
+        };
+        let summary = summarize(Some(&stats), &history, None, 2);
+        assert_eq!(summary.refreshes, 100);
+        assert_eq!(summary.average_refresh_secs, Some(30.0));
+        assert_eq!(summary.failures["busy"], 3);
+        assert_eq!(paths(&summary), ["/photos/e.jpg", "/photos/d.jpg"]);
+    }
+
+    #[test]
+    fn totals_since_a_time_come_from_the_history() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = History::load(&state_file(&dir, "history.jsonl", HISTORY));
+        let mut stats = Stats {
+            refreshes: 100,
+            ..Stats::default()
+        };
+        stats.record_failure("busy", at(1));
+        stats.record_failure("decode", at(4));
+        stats.record_failure("busy", at(5));
+
+        let summary = summarize(Some(&stats), &history, Some(at(2)), 10);
+        assert_eq!(summary.refreshes, 3);
+        assert_eq!(summary.average_refresh_secs, Some(30.0));
+        assert_eq!(
+            summary.failures,
+            BTreeMap::from([("busy".to_string(), 1), ("decode".to_string(), 1)])
+        );
+        assert_eq!(
+            paths(&summary),
+            ["/photos/e.jpg", "/photos/d.jpg", "/photos/b.jpg"]
+        );
+    }
+
+    #[test]
+    fn history_without_stats() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = History::load(&state_file(&dir, "history.jsonl", HISTORY));
+        let summary = summarize(None, &history, None, 10);
+        // The line cut short isn't counted, and the entry without a time isn't averaged
+        assert_eq!(summary.refreshes, 4);
+        assert_eq!(summary.average_refresh_secs, Some(30.0));
+        assert!(summary.failures.is_empty());
+        assert_eq!(paths(&summary).len(), 4);
+    }
+
+    #[test]
+    fn stats_without_history() {
+        let dir = tempfile::tempdir().unwrap();
+        let history = History::load(&dir.path().join("history.jsonl"));
+        let stats = Stats {
+            refreshes: 7,
+            ..Stats::default()
+        };
+        let summary = summarize(Some(&stats), &history, None, 10);
+        assert_eq!(summary.refreshes, 7);
+        assert_eq!(summary.average_refresh_secs, None);
+        assert!(summary.recent.is_empty());
+        assert_eq!(
+            summary.to_string(),
+            "Refreshes: 7\nAverage refresh: unknown\nFailures: none\nNo images displayed yet\n"
+        );
+    }
+
+    #[test]
+    fn summaries_print_and_serialize() {
+        let summary = Summary {
+            refreshes: 4,
+            average_refresh_secs: Some(125.0),
+            failures: BTreeMap::from([("busy".to_string(), 2), ("decode".to_string(), 1)]),
+            recent: Vec::new(),
+        };
+        let text = summary.to_string();
+        assert!(text.starts_with("Refreshes: 4\nAverage refresh: 2m 05s\n"));
+        assert!(text.contains("Failures: busy 2, decode 1\n"));
+
+        let json: serde_json::Value = serde_json::to_value(&summary).unwrap();
+        assert_eq!(json["refreshes"], 4);
+        assert_eq!(json["average_refresh_secs"], 125.0);
+        assert_eq!(json["failures"]["busy"], 2);
+    }
+
+    #[test]
+    fn durations_are_formatted_for_people() {
+        assert_eq!(format_duration(0.0), "0.0 s");
+        assert_eq!(format_duration(41.25), "41.2 s");
+        assert_eq!(format_duration(59.9), "59.9 s");
+        assert_eq!(format_duration(60.0), "1m 00s");
+        assert_eq!(format_duration(125.4), "2m 05s");
+        assert_eq!(format_duration(3599.0), "59m 59s");
+        assert_eq!(format_duration(4800.0), "1h 20m");
+        assert_eq!(format_duration(90000.0), "25h 00m");
+    }
+}
@@ -0,0 +1,411 @@
+use std::{
+    fs, io,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use log::warn;
+
+use crate::{
+    epd::version::CRATE_VERSION,
+    render::{text_height, text_width, Canvas, Color},
+};
+
+/** Layout used without `--template`. */
+pub const DEFAULT_TEMPLATE: &str = "\
+# {hostname}
+{ips}
+
+Uptime: {uptime}
+Photos: {disk}
+Panel: {panel}
+inky-rs {version}
+";
+
+const MARGIN: usize = 32;
+const HEADING_SCALE: usize = 8;
+const TEXT_SCALE: usize = 4;
+
+/** Everything shown on the dashboard. Fields that could not be read are `None`. */
+pub struct SystemInfo {
+    pub hostname: Option<String>,
+    pub interfaces: Vec<(String, Vec<IpAddr>)>,
+    pub uptime: Option<Duration>,
+    pub disk_usage: Option<DiskUsage>,
+    pub version: String,
+    pub panel: String,
+}
+
+pub struct DiskUsage {
+    pub bytes: u64,
+    pub files: u64,
+}
+
+impl SystemInfo {
+    /** Collect the dashboard contents. Nothing here fails: unreadable values are left out. */
+    pub fn gather(photo_dir: Option<&Path>, panel: String) -> SystemInfo {
+        SystemInfo {
+            hostname: read_hostname(),
+            interfaces: read_interfaces(),
+            uptime: read_uptime(),
+            disk_usage: photo_dir.and_then(|dir| match disk_usage(dir) {
+                Ok(usage) => Some(usage),
+                Err(error) => {
+                    warn!("Could not measure {}: {error}", dir.display());
+                    None
+                }
+            }),
+            version: CRATE_VERSION.to_string(),
+            panel,
+        }
+    }
+
+    /** Value of a template placeholder such as `{hostname}` or `{ip eth0}`. */
+    fn lookup(&self, name: &str, argument: Option<&str>) -> Option<String> {
+        let value = match (name, argument) {
+            ("hostname", None) => self.hostname.clone().unwrap_or("unknown host".to_string()),
+            ("ips", None) if self.interfaces.is_empty() => "No network".to_string(),
+            ("ips", None) => self
+                .interfaces
+                .iter()
+                .map(|(name, addrs)| format!("{name}: {}", join_addrs(addrs)))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            ("ip", Some(interface)) => match self.interfaces.iter().find(|(n, _)| n == interface) {
+                Some((_, addrs)) => join_addrs(addrs),
+                None => "down".to_string(),
+            },
+            ("uptime", None) => match self.uptime {
+                Some(uptime) => format_uptime(uptime),
+                None => "unknown".to_string(),
+            },
+            ("disk", None) => match &self.disk_usage {
+                Some(usage) => format!("{} in {} files", format_bytes(usage.bytes), usage.files),
+                None => "unknown".to_string(),
+            },
+            ("version", None) => self.version.clone(),
+            ("panel", None) => self.panel.clone(),
+            _ => return None,
+        };
+        return Some(value);
+    }
+
+    /** Replace the placeholders in a template. Unknown placeholders are left as they are. */
+    pub fn fill_template(&self, template: &str) -> String {
+        let mut out = String::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            out.push_str(&rest[..start]);
+            rest = &rest[start..];
+            let Some(end) = rest.find('}') else {
+                break;
+            };
+            let placeholder = &rest[..=end];
+            let words: Vec<&str> = placeholder[1..end].split_whitespace().collect();
+            let value = match words[..] {
+                [name] => self.lookup(name, None),
+                [name, argument] => self.lookup(name, Some(argument)),
+                _ => None,
+            };
+            out.push_str(value.as_deref().unwrap_or(placeholder));
+            rest = &rest[end + 1..];
+        }
+        out.push_str(rest);
+        return out;
+    }
+}
+
+/** Draw filled in template text on a white canvas. Lines starting with `# ` are headings, and
+ * lines too wide for the panel are drawn smaller. */
+pub fn render(text: &str, width: usize, height: usize) -> Canvas {
+    let mut canvas = Canvas::new(width, height, Color::White.index());
+    let available = width.saturating_sub(2 * MARGIN);
+
+    let mut y = MARGIN;
+    for line in text.lines() {
+        let (line, mut scale, color) = match line.strip_prefix("# ") {
+            Some(heading) => (heading, HEADING_SCALE, Color::Blue.index()),
+            None => (line, TEXT_SCALE, Color::Black.index()),
+        };
+        while scale > 1 && text_width(line, scale) > available {
+            scale -= 1;
+        }
+        if y + text_height(scale) > height {
+            warn!("The dashboard does not fit on the panel, leaving out the rest");
+            break;
+        }
+
+        canvas.draw_text(MARGIN, y, line, scale, color);
+        y += text_height(scale) + 3 * scale;
+    }
+
+    return canvas;
+}
+
+fn read_hostname() -> Option<String> {
+    ["/proc/sys/kernel/hostname", "/etc/hostname"]
+        .iter()
+        .filter_map(|path| fs::read_to_string(path).ok())
+        .map(|name| name.trim().to_string())
+        .find(|name| !name.is_empty())
+}
+
+/** Addresses of every interface that is up, except loopback, grouped by interface. */
+fn read_interfaces() -> Vec<(String, Vec<IpAddr>)> {
+    let interfaces = match if_addrs::get_if_addrs() {
+        Ok(interfaces) => interfaces,
+        Err(error) => {
+            warn!("Could not list network interfaces: {error}");
+            return Vec::new();
+        }
+    };
+
+    let mut grouped: Vec<(String, Vec<IpAddr>)> = Vec::new();
+    for interface in interfaces.iter().filter(|i| !i.is_loopback()) {
+        match grouped.iter_mut().find(|(name, _)| *name == interface.name) {
+            Some((_, addrs)) => addrs.push(interface.ip()),
+            None => grouped.push((interface.name.clone(), vec![interface.ip()])),
+        }
+    }
+    for (_, addrs) in &mut grouped {
+        // IPv4 first, as that is what people usually type
+        addrs.sort_by_key(|addr| addr.is_ipv6());
+    }
+
+    return grouped;
+}
+
+fn read_uptime() -> Option<Duration> {
+    let contents = fs::read_to_string("/proc/uptime").ok()?;
+    let seconds: f64 = contents.split_whitespace().next()?.parse().ok()?;
+    Duration::try_from_secs_f64(seconds).ok()
+}
+
+/** Total size and number of files below a directory. */
+fn disk_usage(dir: &Path) -> io::Result<DiskUsage> {
+    let mut usage = DiskUsage { bytes: 0, files: 0 };
+    let mut pending: Vec<PathBuf> = vec![dir.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir)? {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if metadata.is_dir() {
+                pending.push(entry.path());
+            } else {
+                usage.bytes += metadata.len();
+                usage.files += 1;
+            }
+        }
+    }
+
+    return Ok(usage);
+}
+
+fn join_addrs(addrs: &[IpAddr]) -> String {
+    let addrs: Vec<String> = addrs.iter().map(|addr| addr.to_string()).collect();
+    addrs.join(", ")
+}
+
+fn format_uptime(uptime: Duration) -> String {
+    let minutes = uptime.as_secs() / 60;
+    let (days, hours, minutes) = (minutes / 1440, minutes / 60 % 24, minutes % 60);
+    if days > 0 {
+        format!("{days}d {hours:02}h {minutes:02}m")
+    } else {
+        format!("{hours}h {minutes:02}m")
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info() -> SystemInfo {
+        SystemInfo {
+            hostname: Some("frame".to_string()),
+            interfaces: vec![
+                ("eth0".to_string(), vec!["192.168.1.20".parse().unwrap()]),
+                (
+                    "wlan0".to_string(),
+                    vec!["10.0.0.5".parse().unwrap(), "fe80::1".parse().unwrap()],
+                ),
+            ],
+            uptime: Some(Duration::from_secs(3 * 86400 + 4 * 3600 + 5 * 60)),
+            disk_usage: Some(DiskUsage {
+                bytes: 3 * 1024 * 1024 * 1024 / 2,
+                files: 1234,
+            }),
+            version: "1.2.3".to_string(),
+            panel: "AC073TC1".to_string(),
+        }
+    }
+
+    /** Nothing could be read. */
+    fn unknown() -> SystemInfo {
+        SystemInfo {
+            hostname: None,
+            interfaces: Vec::new(),
+            uptime: None,
+            disk_usage: None,
+            version: "1.2.3".to_string(),
+            panel: "AC073TC1".to_string(),
+        }
+    }
+
+    /** The part of `canvas` at `(x, y)` as text, a line per row, with `#` for black, `.` for
+     * white and the palette index for anything else. */
+    fn ascii(canvas: &Canvas, x: usize, y: usize, width: usize, height: usize) -> String {
+        let mut text = String::new();
+        for row in y..y + height {
+            for column in x..x + width {
+                text.push(match canvas.pixels[row * canvas.width + column] {
+                    0 => '#',
+                    1 => '.',
+                    index => char::from(b'0' + index),
+                });
+            }
+            text.push('\n');
+        }
+        return text;
+    }
+
+    #[test]
+    fn the_default_template_is_filled_in() {
+        let text = info().fill_template(DEFAULT_TEMPLATE);
+        let expected = "\
+# frame
+eth0: 192.168.1.20
+wlan0: 10.0.0.5, fe80::1
+
+Uptime: 3d 04h 05m
+Photos: 1.5 GiB in 1234 files
+Panel: AC073TC1
+inky-rs 1.2.3
+";
+        assert_eq!(text, expected);
+    }
+
+    #[test]
+    fn missing_values_are_filled_in_as_unknown() {
+        let text = unknown().fill_template(DEFAULT_TEMPLATE);
+        let expected = "\
+# unknown host
+No network
+
+Uptime: unknown
+Photos: unknown
+Panel: AC073TC1
+inky-rs 1.2.3
+";
+        assert_eq!(text, expected);
+    }
+
+    #[test]
+    fn interfaces_that_are_down_say_so() {
+        let template = "{ip eth0} / {ip wlan0} / {ip usb0}";
+        assert_eq!(
+            info().fill_template(template),
+            "192.168.1.20 / 10.0.0.5, fe80::1 / down"
+        );
+        assert_eq!(unknown().fill_template("{ip eth0}"), "down");
+    }
+
+    #[test]
+    fn unknown_placeholders_are_left_as_they_are() {
+        let info = info();
+        assert_eq!(
+            info.fill_template("{nope} {ip} {a b c}"),
+            "{nope} {ip} {a b c}"
+        );
+        assert_eq!(
+            info.fill_template("{hostname} {unclosed"),
+            "frame {unclosed"
+        );
+        assert_eq!(info.fill_template("no placeholders"), "no placeholders");
+    }
+
+    #[test]
+    fn dashboard_is_drawn_line_by_line() {
+        let text = info().fill_template(DEFAULT_TEMPLATE);
+        let canvas = render(&text, 800, 480);
+
+        // Each line goes a gap of 3 pixels per scale below the one before
+        let mut expected = Canvas::new(800, 480, Color::White.index());
+        let black = Color::Black.index();
+        expected.draw_text(32, 32, "frame", 8, Color::Blue.index());
+        expected.draw_text(32, 120, "eth0: 192.168.1.20", 4, black);
+        expected.draw_text(32, 164, "wlan0: 10.0.0.5, fe80::1", 4, black);
+        expected.draw_text(32, 252, "Uptime: 3d 04h 05m", 4, black);
+        expected.draw_text(32, 296, "Photos: 1.5 GiB in 1234 files", 4, black);
+        expected.draw_text(32, 340, "Panel: AC073TC1", 4, black);
+        expected.draw_text(32, 384, "inky-rs 1.2.3", 4, black);
+
+        assert_eq!((canvas.width, canvas.height), (800, 480));
+        assert!(canvas.pixels == expected.pixels);
+    }
+
+    #[test]
+    fn dashboard_lines_shrink_to_fit() {
+        // 11 pixels between the margins: the blue heading drops to scale 2 and the text to 1, 6
+        // pixels below it
+        let canvas = render("# a\nbc", 75, 70);
+        let golden = "\
+            ...........\n\
+            ...........\n\
+            ...........\n\
+            ...........\n\
+            ..333333...\n\
+            ..333333...\n\
+            ........33.\n\
+            ........33.\n\
+            ..33333333.\n\
+            ..33333333.\n\
+            33......33.\n\
+            33......33.\n\
+            ..33333333.\n\
+            ..33333333.\n\
+            ...........\n\
+            ...........\n\
+            ...........\n\
+            ...........\n\
+            ...........\n\
+            ...........\n\
+            ...........\n\
+            ...........\n\
+            #..........\n\
+            #..........\n\
+            #.##...###.\n\
+            ##..#.#....\n\
+            #...#.#....\n\
+            #...#.#...#\n\
+            ####...###.\n\
+            ...........\n";
+        assert_eq!(ascii(&canvas, 32, 32, 11, 30), golden);
+    }
+
+    #[test]
+    fn dashboard_leaves_out_lines_below_the_panel() {
+        // The third line would need 32 more pixels from 120, but only 19 are left
+        let canvas = render("ab\ncd\nef", 120, 139);
+        let mut expected = Canvas::new(120, 139, Color::White.index());
+        expected.draw_text(32, 32, "ab", 4, Color::Black.index());
+        expected.draw_text(32, 76, "cd", 4, Color::Black.index());
+        assert!(canvas.pixels == expected.pixels);
+    }
+}
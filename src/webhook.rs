@@ -0,0 +1,184 @@
+use std::fmt::Display;
+
+use chrono::{DateTime, Utc};
+use log::{info, warn};
+use serde::Serialize;
+
+#[cfg(feature = "http")]
+use crate::fetch::{self, RequestError};
+use crate::report::RunReport;
+
+/** Attempts to deliver a notification before giving up. */
+#[cfg(feature = "http")]
+const ATTEMPTS: u32 = 3;
+/** Wait before the second attempt, doubled before each one after that. */
+#[cfg(feature = "http")]
+const BACKOFF: std::time::Duration = std::time::Duration::from_secs(1);
+/** A notification is small, so a server that takes longer is unlikely to answer at all. */
+#[cfg(feature = "http")]
+const TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+/** What is posted to the webhook after a run. */
+#[derive(Debug, Serialize)]
+pub struct Payload {
+    pub timestamp: DateTime<Utc>,
+    /** `displayed` or `failed`. */
+    pub outcome: &'static str,
+    /** The file or URL the image came from, unknown for some failures. */
+    pub source: Option<String>,
+    pub width: Option<usize>,
+    pub height: Option<usize>,
+    pub refresh_ms: Option<u64>,
+    pub error: Option<String>,
+}
+
+impl Payload {
+    pub fn displayed(report: &RunReport) -> Payload {
+        let (width, height) = report.panel;
+        Payload {
+            timestamp: Utc::now(),
+            outcome: "displayed",
+            source: Some(report.file.display().to_string()),
+            width: Some(width),
+            height: Some(height),
+            refresh_ms: report.refresh_duration.map(|d| d.as_millis() as u64),
+            error: None,
+        }
+    }
+
+    pub fn failed(error: &impl Display) -> Payload {
+        Payload {
+            timestamp: Utc::now(),
+            outcome: "failed",
+            source: None,
+            width: None,
+            height: None,
+            refresh_ms: None,
+            error: Some(error.to_string()),
+        }
+    }
+}
+
+enum WebhookError {
+    #[cfg(feature = "http")]
+    Request(RequestError),
+    /** Built without the `http` feature. */
+    #[cfg(not(feature = "http"))]
+    Unsupported,
+}
+
+impl WebhookError {
+    /** Whether trying again later may help, as for network errors and overloaded servers. */
+    #[cfg(feature = "http")]
+    fn is_transient(&self) -> bool {
+        match self {
+            WebhookError::Request(RequestError::Status(code, _)) => *code == 429 || *code >= 500,
+            WebhookError::Request(RequestError::Transport(_)) => true,
+        }
+    }
+}
+
+/** Post `payload` as JSON to `url`, trying again a couple of times on network errors. Failures
+ * are only logged, a notification never fails the run. */
+pub fn notify(url: &str, payload: &Payload) {
+    match post(url, payload) {
+        Ok(()) => info!("Notified {url}"),
+        Err(error) => warn!("Could not notify {url}: {error}"),
+    }
+}
+
+#[cfg(feature = "http")]
+fn post(url: &str, payload: &Payload) -> Result<(), WebhookError> {
+    let body = serde_json::to_string(payload).unwrap();
+    let agent = fetch::agent(TIMEOUT);
+    let mut backoff = BACKOFF;
+    let mut attempt = 1;
+    loop {
+        let result = agent
+            .post(url)
+            .set("Content-Type", "application/json")
+            .send_string(&body);
+        let error = match result {
+            Ok(_) => return Ok(()),
+            Err(error) => WebhookError::Request(error.into()),
+        };
+        if attempt == ATTEMPTS || !error.is_transient() {
+            return Err(error);
+        }
+        warn!("Could not notify {url}: {error}, trying again in {backoff:?}");
+        std::thread::sleep(backoff);
+        backoff *= 2;
+        attempt += 1;
+    }
+}
+
+#[cfg(not(feature = "http"))]
+fn post(_url: &str, _payload: &Payload) -> Result<(), WebhookError> {
+    Err(WebhookError::Unsupported)
+}
+
+impl Display for WebhookError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            #[cfg(feature = "http")]
+            WebhookError::Request(error) => write!(f, "{error}"),
+            #[cfg(not(feature = "http"))]
+            WebhookError::Unsupported => {
+                write!(
+                    f,
+                    "this build can't send webhooks, rebuild with --features http"
+                )
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::PathBuf, time::Duration};
+
+    use chrono::TimeZone;
+    use serde_json::json;
+
+    use super::*;
+
+    fn json(mut payload: Payload) -> serde_json::Value {
+        payload.timestamp = Utc.with_ymd_and_hms(2024, 5, 1, 6, 30, 0).unwrap();
+        return serde_json::to_value(&payload).unwrap();
+    }
+
+    #[test]
+    fn displayed_payload_keeps_its_shape() {
+        let mut report = RunReport::new(PathBuf::from("/photos/beach.jpg"));
+        report.panel = (800, 480);
+        report.refresh_duration = Some(Duration::from_millis(31_250));
+        assert_eq!(
+            json(Payload::displayed(&report)),
+            json!({
+                "timestamp": "2024-05-01T06:30:00Z",
+                "outcome": "displayed",
+                "source": "/photos/beach.jpg",
+                "width": 800,
+                "height": 480,
+                "refresh_ms": 31250,
+                "error": null,
+            })
+        );
+    }
+
+    #[test]
+    fn failed_payload_keeps_its_shape() {
+        assert_eq!(
+            json(Payload::failed(&"No displayable files found in /photos")),
+            json!({
+                "timestamp": "2024-05-01T06:30:00Z",
+                "outcome": "failed",
+                "source": null,
+                "width": null,
+                "height": null,
+                "refresh_ms": null,
+                "error": "No displayable files found in /photos",
+            })
+        );
+    }
+}
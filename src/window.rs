@@ -0,0 +1,75 @@
+use std::fmt::Display;
+
+#[cfg_attr(not(feature = "preview"), allow(dead_code))]
+pub enum WindowError {
+    /** The window couldn't be opened or drawn, e.g. without a display. */
+    Window(String),
+    /** Built without the `preview` feature. */
+    #[cfg(not(feature = "preview"))]
+    Unsupported,
+}
+
+/** Show a `width` × `height` frame of palette indices in a window at 1:1 scale, in the colors of
+ * `palette`, until a key is pressed or the window is closed. */
+#[cfg(feature = "preview")]
+pub fn show(
+    width: usize,
+    height: usize,
+    pixels: &[u8],
+    palette: &[imagequant::RGBA],
+) -> Result<(), WindowError> {
+    use minifb::{Window, WindowOptions};
+
+    let buffer: Vec<u32> = pixels
+        .iter()
+        .map(|&index| {
+            let color = palette
+                .get(index as usize)
+                .copied()
+                .unwrap_or(rgb::Rgba::new(0, 0, 0, 0));
+            (color.r as u32) << 16 | (color.g as u32) << 8 | color.b as u32
+        })
+        .collect();
+
+    let error = |error: minifb::Error| WindowError::Window(error.to_string());
+    let mut window = Window::new(
+        "inky-rs preview (press any key to close)",
+        width,
+        height,
+        WindowOptions::default(),
+    )
+    .map_err(error)?;
+    window.set_target_fps(30);
+    while window.is_open() && window.get_keys().is_empty() && !crate::shutdown::requested() {
+        window
+            .update_with_buffer(&buffer, width, height)
+            .map_err(error)?;
+    }
+
+    return Ok(());
+}
+
+#[cfg(not(feature = "preview"))]
+pub fn show(
+    _width: usize,
+    _height: usize,
+    _pixels: &[u8],
+    _palette: &[imagequant::RGBA],
+) -> Result<(), WindowError> {
+    Err(WindowError::Unsupported)
+}
+
+impl Display for WindowError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            WindowError::Window(error) => write!(f, "{error}"),
+            #[cfg(not(feature = "preview"))]
+            WindowError::Unsupported => {
+                write!(
+                    f,
+                    "this build has no window support, rebuild with --features preview"
+                )
+            }
+        }
+    }
+}